@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the gRPC service is generated from proto/chem_matcher.proto with a bundled protoc
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::compile_protos("proto/chem_matcher.proto")?;
+    }
+    Ok(())
+}
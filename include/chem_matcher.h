@@ -0,0 +1,81 @@
+#ifndef CHEM_MATCHER_H
+#define CHEM_MATCHER_H
+
+/* Generated by cbindgen from src/capi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A matcher loaded from a compiled dictionary
+typedef struct ChemMatcher ChemMatcher;
+
+// One match; strings are owned by the enclosing ChemMatches
+typedef struct ChemMatch {
+  // Dictionary key or identifier found
+  char *key;
+  // Text around the match, with the match masked
+  char *context;
+  // PubChem CID, or -1 when unknown or another kind of id
+  int64_t cid;
+  // name, cas, inchi, inchikey or formula
+  char *id_type;
+  // exact, inflected, salt, fuzzy:N or abbreviation
+  char *match_type;
+  // Confidence between 0 and 1
+  float score;
+  // Byte range of the match in the text
+  size_t start;
+  size_t end;
+  // Dictionary id, e.g. "2244" or "CHEBI:15377", empty when unknown
+  char *id;
+} ChemMatch;
+
+// Matches found in one buffer
+typedef struct ChemMatches {
+  struct ChemMatch *matches;
+  size_t len;
+} ChemMatches;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Load a dictionary written by compile-dict, searched with the --case-mode it was compiled with.
+// case_mode is NULL, or that mode's name, which is checked. Returns NULL on error; see
+// chem_last_error.
+//
+// # Safety
+// dict_path and case_mode must be NULL or point to NUL-terminated strings.
+struct ChemMatcher *chem_matcher_new(const char *dict_path, const char *case_mode);
+
+// Find chemicals in len bytes of UTF-8 text. Returns NULL on error; see chem_last_error.
+//
+// # Safety
+// matcher must come from chem_matcher_new and text must point to len readable bytes.
+struct ChemMatches *chem_matcher_match(const struct ChemMatcher *matcher,
+                                       const uint8_t *text,
+                                       size_t len);
+
+// Release matches returned by chem_matcher_match. NULL is ignored.
+//
+// # Safety
+// matches must come from chem_matcher_match and not be used afterwards.
+void chem_matches_free(struct ChemMatches *matches);
+
+// Release a matcher returned by chem_matcher_new. NULL is ignored.
+//
+// # Safety
+// matcher must come from chem_matcher_new and not be used afterwards.
+void chem_matcher_free(struct ChemMatcher *matcher);
+
+// Message of the last failed call on this thread, or NULL. Valid until the next failing call.
+const char *chem_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHEM_MATCHER_H */
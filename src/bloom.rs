@@ -0,0 +1,61 @@
+//! Bloom filter over strings, answering "certainly not present" or "maybe present" in a few
+//! bits per item; search uses one over the first words of dictionary keys to skip the tokens,
+//! and so most paragraphs, that start no key.
+
+use std::hash::{BuildHasher, RandomState};
+
+// Bits per item and hash functions for about 1% false positives
+const BITS_PER_ITEM: usize = 10;
+const HASHES: u64 = 7;
+
+// Positions of the bits of an item with hash among len bits, by double hashing
+fn positions(hash: u64, len: usize) -> impl Iterator<Item = usize> {
+    let step = hash.rotate_left(32) | 1;
+    (0..HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % len as u64) as usize)
+}
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hasher: RandomState,
+}
+
+impl BloomFilter {
+    /// Empty filter sized for up to items strings
+    pub fn new(items: usize) -> BloomFilter {
+        BloomFilter { bits: vec![0; (items * BITS_PER_ITEM).div_ceil(64).max(1)], hasher: RandomState::new() }
+    }
+
+    pub fn insert(&mut self, item: &str) {
+        for position in positions(self.hasher.hash_one(item), self.bits.len() * 64) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    /// False if item was never inserted, true if it probably was
+    pub fn contains(&self, item: &str) -> bool {
+        positions(self.hasher.hash_one(item), self.bits.len() * 64).all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+
+    /// Bytes allocated for the bits
+    pub fn heap_size(&self) -> usize {
+        self.bits.capacity() * std::mem::size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000 {
+            filter.insert(&format!("Compound{}", i));
+        }
+        assert!((0..1000).all(|i| filter.contains(&format!("Compound{}", i))));
+        let false_positives = (0..10000).filter(|i| filter.contains(&format!("Word{}", i))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert!(!BloomFilter::new(0).contains("Aspirin"));
+        assert_eq!(filter.heap_size(), 1256);
+    }
+}
@@ -0,0 +1,169 @@
+//! BRAT standoff annotations: `<id>.txt` documents with `<id>.ann` files of text-bound
+//! annotations, read as gold mentions and written from matches for review in BRAT.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::dictionary::Id;
+use crate::eval::{predicted_mentions, Documents, GoldMentions, Mention};
+use crate::matcher::Match;
+
+/// Entity type of the annotations written for matches
+pub const ENTITY_TYPE: &str = "Chemical";
+
+/// Mentions of the text-bound annotations (`T1<TAB>Chemical 4 11<TAB>aspirin`) in the content of an
+/// .ann file, optionally only those of entity_type. Ids come from normalizations such as
+/// `N1<TAB>Reference T1 PubChem:2244`, a CID, or `CHEBI:15377`, kept whole; a discontinuous
+/// annotation spans from its first to its last fragment.
+pub fn read_ann(content: &str, entity_type: Option<&str>) -> Result<Vec<Mention>, String> {
+    let mut mentions: Vec<(String, Mention)> = Vec::new();
+    let mut cids: HashMap<String, Id> = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bad_line = || format!("line {}: malformed annotation", i + 1);
+        if line.starts_with('T') {
+            let (kind, ranges) = fields.get(1).and_then(|field| field.split_once(' ')).ok_or_else(bad_line)?;
+            if entity_type.is_some_and(|entity_type| entity_type != kind) {
+                continue;
+            }
+            let mut offsets = Vec::new();
+            for range in ranges.split(';') {
+                let (start, end) = range.split_once(' ').ok_or_else(bad_line)?;
+                offsets.push((start.parse::<usize>().map_err(|_| bad_line())?, end.parse::<usize>().map_err(|_| bad_line())?));
+            }
+            let (start, end) = (offsets.iter().map(|range| range.0).min().unwrap(), offsets.iter().map(|range| range.1).max().unwrap());
+            mentions.push((fields[0].to_string(), Mention { start, end, cid: None }));
+        } else if line.starts_with('N') {
+            let reference: Vec<&str> = fields.get(1).ok_or_else(bad_line)?.split(' ').collect();
+            if let [_, target, id] = reference[..] {
+                let (database, cid) = id.split_once(':').ok_or_else(bad_line)?;
+                let cid = if ["pubchem", "cid", "id"].iter().any(|name| database.eq_ignore_ascii_case(name)) { cid } else { id };
+                cids.insert(target.to_string(), cid.parse().map_err(|_| bad_line())?);
+            }
+        }
+    }
+    Ok(mentions.into_iter().map(|(id, mention)| Mention { cid: cids.get(&id).cloned(), ..mention }).collect())
+}
+
+/// Gold mentions and texts of every `<id>.ann` with its `<id>.txt` in dir, by id
+pub fn read_brat_dir(dir: &Path, entity_type: Option<&str>) -> Result<(GoldMentions, Documents), Box<dyn Error>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ann"))
+        .collect();
+    paths.sort();
+    let (mut gold, mut texts) = (HashMap::new(), Vec::new());
+    for path in paths {
+        let id = path.file_stem().unwrap().to_string_lossy().to_string();
+        let mentions = read_ann(&fs::read_to_string(&path)?, entity_type).map_err(|e| format!("{}: {}", path.display(), e))?;
+        texts.push((id.clone(), fs::read_to_string(path.with_extension("txt"))?));
+        gold.insert(id, mentions);
+    }
+    Ok((gold, texts))
+}
+
+/// File name for the documents of id, e.g. a DOI: ASCII letters, digits, '-', '_' and '.' past
+/// the first character are kept and every other byte written as %XX, so distinct ids never share
+/// a file and none leaves the directory
+pub fn file_name(id: &str) -> String {
+    let mut name = String::new();
+    for (i, byte) in id.bytes().enumerate() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || (byte == b'.' && i > 0) {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name
+}
+
+// Character ranges and text of the lines of a mention covering covered, from character start, as
+// the fragments of a discontinuous annotation
+fn fragments(covered: &str, start: usize) -> (String, String) {
+    let (mut ranges, mut pieces) = (Vec::new(), Vec::new());
+    let mut from = start;
+    for line in covered.split('\n') {
+        let piece = line.trim_end_matches('\r');
+        if !piece.is_empty() {
+            ranges.push(format!("{} {}", from, from + piece.chars().count()));
+            pieces.push(piece);
+        }
+        from += line.chars().count() + 1;
+    }
+    (ranges.join(";"), pieces.join(" "))
+}
+
+/// Write text to `<dir>/<name>.txt` and its matches as Chemical annotations, normalized to their
+/// PubChem CIDs or other ids (`ID:` naming the database of ids without one), to `<dir>/<name>.ann`,
+/// where name is the file_name of the document's id. Matches spanning lines are split into a
+/// fragment per line.
+pub fn write_brat(dir: &Path, id: &str, text: &str, matches: &[Match]) -> Result<(), Box<dyn Error>> {
+    if id.is_empty() {
+        return Err("BRAT documents need an id".into());
+    }
+    let name = file_name(id);
+    fs::write(dir.join(format!("{}.txt", name)), text)?;
+    let mut writer = BufWriter::new(File::create(dir.join(format!("{}.ann", name)))?);
+    let mut normalized = 0;
+    for (i, (found, mention)) in matches.iter().zip(predicted_mentions(text, matches)).enumerate() {
+        let (ranges, covered) = fragments(&text[found.start..found.end], mention.start);
+        writeln!(writer, "T{}\t{} {}\t{}", i + 1, ENTITY_TYPE, ranges, covered)?;
+        if let Some(cid) = &found.cid {
+            normalized += 1;
+            let reference = match cid {
+                Id::Cid(cid) => format!("PubChem:{}", cid),
+                Id::Other(id) if id.contains(':') => id.to_string(),
+                Id::Other(id) => format!("ID:{}", id),
+            };
+            writeln!(writer, "N{}\tReference T{} {}\t{}", normalized, i + 1, reference, found.key)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{Matcher, SearchOptions};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_brat() {
+        let ann = "T1\tChemical 4 11\taspirin\nT2\tDisease 20 28\theadache\nT3\tChemical 15 17;18 20\tin wa\n#1\tAnnotatorNotes T1\tcheck\nN1\tReference T1 PubChem:2244\taspirin\nN2\tReference T3 CHEBI:15377\twater\n";
+        let mentions = read_ann(ann, Some("Chemical")).unwrap();
+        assert_eq!(mentions, vec![Mention { start: 4, end: 11, cid: Some(Id::Cid(2244)) }, Mention { start: 15, end: 20, cid: Some("CHEBI:15377".parse().unwrap()) }]);
+        assert_eq!(read_ann(ann, None).unwrap().len(), 3);
+        assert!(read_ann("T1\tChemical 4\tx\n", None).is_err());
+
+        let map: HashMap<String, Id> = [("Aspirin", Id::Cid(2244)), ("Daily", "DB00945".parse().unwrap())].into_iter().map(|(key, cid)| (key.to_string(), cid)).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let tmp_dir = TempDir::new("brat").unwrap();
+        let text = "Épi aspirin daily";
+        write_brat(tmp_dir.path(), "7", text, &matcher.search(text)).unwrap();
+        assert_eq!(fs::read_to_string(tmp_dir.path().join("7.txt")).unwrap(), text);
+        assert_eq!(
+            fs::read_to_string(tmp_dir.path().join("7.ann")).unwrap(),
+            "T1\tChemical 4 11\taspirin\nN1\tReference T1 PubChem:2244\tAspirin\nT2\tChemical 12 17\tdaily\nN2\tReference T2 ID:DB00945\tDaily\n"
+        );
+
+        let (gold, texts) = read_brat_dir(tmp_dir.path(), None).unwrap();
+        assert_eq!(texts, vec![("7".to_string(), text.to_string())]);
+        assert_eq!(gold["7"][0], Mention { start: 4, end: 11, cid: Some(Id::Cid(2244)) });
+        assert_eq!(gold["7"][1].cid, Some("DB00945".parse().unwrap()));
+
+        assert_eq!(file_name("10.1101/x"), "10.1101%2Fx");
+        assert_ne!(file_name("a/b"), file_name("a%2Fb"));
+        assert_eq!(file_name("../é"), "%2E.%2F%C3%A9");
+        assert!(write_brat(tmp_dir.path(), "", text, &[]).is_err());
+        let text = "lots of Daily\nAspirin";
+        let map: HashMap<String, Id> = [("Daily Aspirin".to_string(), Id::Cid(1))].into_iter().collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        write_brat(tmp_dir.path(), "10.1101/x", text, &matcher.search(text)).unwrap();
+        let ann = fs::read_to_string(tmp_dir.path().join("10.1101%2Fx.ann")).unwrap();
+        assert_eq!(ann, "T1\tChemical 8 13;14 21\tDaily Aspirin\nN1\tReference T1 PubChem:1\tDaily Aspirin\n");
+        assert_eq!(read_ann(&ann, None).unwrap(), vec![Mention { start: 8, end: 21, cid: Some(Id::Cid(1)) }]);
+    }
+}
@@ -0,0 +1,227 @@
+//! C ABI for embedding the matcher in other languages; see include/chem_matcher.h.
+//!
+//! Every string crossing the boundary is NUL-terminated UTF-8. Objects returned here are
+//! owned by the caller and must be released with the matching free function.
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::dictionary::{load_compiled_dict, Id};
+use crate::matcher::{Match, Matcher, MatcherBuilder};
+
+thread_local! {
+    // message of the last failed call on this thread
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// text with any interior NUL replaced, so it survives as a C string
+fn to_c_string(text: &str) -> *mut c_char {
+    CString::new(text.replace('\0', " ")).unwrap().into_raw()
+}
+
+/// A matcher loaded from a compiled dictionary
+pub struct ChemMatcher {
+    matcher: Matcher,
+}
+
+/// One match; strings are owned by the enclosing ChemMatches
+#[repr(C)]
+pub struct ChemMatch {
+    /// Dictionary key or identifier found
+    pub key: *mut c_char,
+    /// Text around the match, with the match masked
+    pub context: *mut c_char,
+    /// PubChem CID, or -1 when unknown or another kind of id
+    pub cid: i64,
+    /// name, cas, inchi, inchikey or formula
+    pub id_type: *mut c_char,
+    /// exact, inflected, salt, fuzzy:N or abbreviation
+    pub match_type: *mut c_char,
+    /// Confidence between 0 and 1
+    pub score: f32,
+    /// Byte range of the match in the text
+    pub start: usize,
+    pub end: usize,
+    /// Dictionary id, e.g. "2244" or "CHEBI:15377", empty when unknown
+    pub id: *mut c_char,
+}
+
+/// Matches found in one buffer
+#[repr(C)]
+pub struct ChemMatches {
+    pub matches: *mut ChemMatch,
+    pub len: usize,
+}
+
+impl ChemMatch {
+    fn new(found: &Match) -> ChemMatch {
+        ChemMatch {
+            key: to_c_string(&found.key),
+            context: to_c_string(&found.context),
+            cid: found.cid.as_ref().and_then(Id::cid).map_or(-1, i64::from),
+            id_type: to_c_string(&found.id_type.to_string()),
+            match_type: to_c_string(&found.match_type.to_string()),
+            score: found.score,
+            start: found.start,
+            end: found.end,
+            id: to_c_string(&found.cid.as_ref().map(Id::to_string).unwrap_or_default()),
+        }
+    }
+}
+
+unsafe fn str_arg<'a>(arg: *const c_char, name: &str) -> Result<&'a str, Box<dyn Error>> {
+    if arg.is_null() {
+        return Err(format!("{} is NULL", name).into());
+    }
+    Ok(CStr::from_ptr(arg).to_str()?)
+}
+
+unsafe fn new_matcher(dict_path: *const c_char, case_mode: *const c_char) -> Result<ChemMatcher, Box<dyn Error>> {
+    let dict_path = str_arg(dict_path, "dict_path")?;
+    let case_mode = if case_mode.is_null() { None } else { Some(str_arg(case_mode, "case_mode")?) };
+    let (header, (map, conflicts)) = load_compiled_dict(dict_path)?;
+    let case_mode = header.case_mode(case_mode)?;
+    let matcher = MatcherBuilder::new().case_mode(case_mode).conflicts(&conflicts).build(map)?;
+    Ok(ChemMatcher { matcher })
+}
+
+/// Load a dictionary written by compile-dict, searched with the --case-mode it was compiled with.
+/// case_mode is NULL, or that mode's name, which is checked. Returns NULL on error; see
+/// chem_last_error.
+///
+/// # Safety
+/// dict_path and case_mode must be NULL or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn chem_matcher_new(dict_path: *const c_char, case_mode: *const c_char) -> *mut ChemMatcher {
+    match new_matcher(dict_path, case_mode) {
+        Ok(matcher) => Box::into_raw(Box::new(matcher)),
+        Err(e) => {
+            set_last_error(e.to_string());
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Find chemicals in len bytes of UTF-8 text. Returns NULL on error; see chem_last_error.
+///
+/// # Safety
+/// matcher must come from chem_matcher_new and text must point to len readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chem_matcher_match(matcher: *const ChemMatcher, text: *const u8, len: usize) -> *mut ChemMatches {
+    if matcher.is_null() || (text.is_null() && len > 0) {
+        set_last_error("matcher or text is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let bytes = if len == 0 { &[] } else { std::slice::from_raw_parts(text, len) };
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => {
+            set_last_error(format!("text is not UTF-8: {}", e));
+            return ptr::null_mut();
+        }
+    };
+    let matches: Box<[ChemMatch]> = (*matcher).matcher.search(text).iter().map(ChemMatch::new).collect();
+    let len = matches.len();
+    let matches = Box::into_raw(matches) as *mut ChemMatch;
+    Box::into_raw(Box::new(ChemMatches { matches, len }))
+}
+
+/// Release matches returned by chem_matcher_match. NULL is ignored.
+///
+/// # Safety
+/// matches must come from chem_matcher_match and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chem_matches_free(matches: *mut ChemMatches) {
+    if matches.is_null() {
+        return;
+    }
+    let matches = Box::from_raw(matches);
+    let items = Box::from_raw(ptr::slice_from_raw_parts_mut(matches.matches, matches.len));
+    for item in items.iter() {
+        for text in [item.key, item.context, item.id_type, item.match_type, item.id] {
+            drop(CString::from_raw(text));
+        }
+    }
+}
+
+/// Release a matcher returned by chem_matcher_new. NULL is ignored.
+///
+/// # Safety
+/// matcher must come from chem_matcher_new and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn chem_matcher_free(matcher: *mut ChemMatcher) {
+    if !matcher.is_null() {
+        drop(Box::from_raw(matcher));
+    }
+}
+
+/// Message of the last failed call on this thread, or NULL. Valid until the next failing call.
+#[no_mangle]
+pub extern "C" fn chem_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use tempdir::TempDir;
+    use crate::dictionary::{write_compiled_dict, DictHeader, ParseOptions};
+    use crate::text::CaseMode;
+
+    #[test]
+    fn test_c_api() {
+        let tmp_dir = TempDir::new("capi").unwrap();
+        let dict_path = tmp_dir.path().join("dict.bin");
+        let map: HashMap<String, Id> = [("Aspirin".to_string(), Id::Cid(2244)), ("Water".to_string(), "CHEBI:15377".parse().unwrap())].into_iter().collect();
+        let header = DictHeader::new(&HashSet::new(), &ParseOptions::default());
+        write_compiled_dict(dict_path.to_str().unwrap(), &header, &map, &HashMap::new()).unwrap();
+        let dict_path = CString::new(dict_path.to_str().unwrap()).unwrap();
+
+        unsafe {
+            let matcher = chem_matcher_new(dict_path.as_ptr(), ptr::null());
+            assert!(!matcher.is_null());
+            let text = "Aspirin and water";
+            let matches = chem_matcher_match(matcher, text.as_ptr(), text.len());
+            assert_eq!((*matches).len, 2);
+            let found = &*(*matches).matches;
+            assert_eq!(CStr::from_ptr(found.key).to_str().unwrap(), "Aspirin");
+            assert_eq!((found.cid, CStr::from_ptr(found.id).to_str().unwrap()), (2244, "2244"));
+            let water = &*(*matches).matches.add(1);
+            assert_eq!((water.cid, CStr::from_ptr(water.id).to_str().unwrap()), (-1, "CHEBI:15377"));
+            assert_eq!((found.start, found.end), (0, 7));
+            assert_eq!(CStr::from_ptr(found.id_type).to_str().unwrap(), "name");
+            chem_matches_free(matches);
+
+            let invalid = b"Aspirin \xff";
+            assert!(chem_matcher_match(matcher, invalid.as_ptr(), invalid.len()).is_null());
+            assert!(CStr::from_ptr(chem_last_error()).to_str().unwrap().contains("UTF-8"));
+            chem_matcher_free(matcher);
+
+            // searched with the case mode the dictionary was compiled with, unless asked for another
+            let folded: HashMap<String, Id> = [("aspirin".to_string(), Id::Cid(2244))].into_iter().collect();
+            let header = DictHeader::new(&HashSet::new(), &ParseOptions { case_mode: CaseMode::Fold, ..Default::default() });
+            write_compiled_dict(tmp_dir.path().join("folded.bin").to_str().unwrap(), &header, &folded, &HashMap::new()).unwrap();
+            let folded_path = CString::new(tmp_dir.path().join("folded.bin").to_str().unwrap()).unwrap();
+            let matcher = chem_matcher_new(folded_path.as_ptr(), ptr::null());
+            let matches = chem_matcher_match(matcher, "ASPIRIN".as_ptr(), 7);
+            assert_eq!((*matches).len, 1);
+            chem_matches_free(matches);
+            chem_matcher_free(matcher);
+            let title = CString::new("title").unwrap();
+            assert!(chem_matcher_new(folded_path.as_ptr(), title.as_ptr()).is_null());
+            assert!(CStr::from_ptr(chem_last_error()).to_str().unwrap().contains("compiled with fold"));
+
+            let missing = CString::new(tmp_dir.path().join("missing.bin").to_str().unwrap()).unwrap();
+            assert!(chem_matcher_new(missing.as_ptr(), ptr::null()).is_null());
+            assert!(!chem_last_error().is_null());
+        }
+    }
+}
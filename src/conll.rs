@@ -0,0 +1,97 @@
+//! Token-per-line BIO tags of the paragraphs with matches, in the CoNLL format read by common NER
+//! training scripts.
+
+use std::io::{self, Write};
+use crate::matcher::{Match, SearchOptions};
+use crate::text::tokenize_with;
+
+/// Tag of the first token of a match
+pub const BEGIN: &str = "B-CHEM";
+/// Tag of the following tokens of a match
+pub const INSIDE: &str = "I-CHEM";
+/// Tag of tokens outside matches
+pub const OUTSIDE: &str = "O";
+
+// Byte offset of part in text, which it is a slice of
+fn offset_in(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+// Tokens of paragraph as the matcher splits them, plus every other non-whitespace character
+// (punctuation) as a token of its own
+fn conll_tokens<'a>(paragraph: &'a str, splits: &[char]) -> Vec<(usize, &'a str)> {
+    let mut tokens = Vec::new();
+    let mut covered = 0;
+    let push_characters = |tokens: &mut Vec<(usize, &'a str)>, from: usize, to: usize| {
+        for (i, c) in paragraph[from..to].char_indices().filter(|(_, c)| !c.is_whitespace()) {
+            tokens.push((from + i, &paragraph[from + i..from + i + c.len_utf8()]));
+        }
+    };
+    for (start, token) in tokenize_with(paragraph, splits) {
+        if start < covered {
+            continue;
+        }
+        push_characters(&mut tokens, covered, start);
+        // bracket groups may hold spaces, which would break a line
+        for part in token.split(char::is_whitespace).filter(|part| !part.is_empty()) {
+            tokens.push((offset_in(paragraph, part), part));
+        }
+        covered = start + token.len();
+    }
+    push_characters(&mut tokens, covered, paragraph.len());
+    tokens
+}
+
+/// Write `token<TAB>tag` lines for each paragraph of text holding a match, with a blank line after
+/// each paragraph. Tokens overlapping a match's byte range are tagged B-CHEM, then I-CHEM.
+pub fn write_conll<W: Write>(writer: &mut W, options: &SearchOptions, text: &str, matches: &[Match]) -> io::Result<()> {
+    let spans: Vec<(usize, usize)> = matches.iter().map(|found| (found.start, found.end)).collect();
+    write_conll_spans(writer, options, text, &spans)
+}
+
+/// write_conll for chemicals at byte ranges of text
+pub fn write_conll_spans<W: Write>(writer: &mut W, options: &SearchOptions, text: &str, spans: &[(usize, usize)]) -> io::Result<()> {
+    for paragraph in options.paragraph_re.split(text) {
+        let (start, end) = (offset_in(text, paragraph), offset_in(text, paragraph) + paragraph.len());
+        let found: Vec<&(usize, usize)> = spans.iter().filter(|found| found.0 >= start && found.0 < end).collect();
+        if found.is_empty() {
+            continue;
+        }
+        let mut previous = None;
+        for (offset, token) in conll_tokens(paragraph, &options.word_splits) {
+            let (token_start, token_end) = (start + offset, start + offset + token.len());
+            let inside = found.iter().position(|found| found.0 < token_end && token_start < found.1);
+            let tag = match inside {
+                Some(_) if inside == previous => INSIDE,
+                Some(_) => BEGIN,
+                None => OUTSIDE,
+            };
+            writeln!(writer, "{}\t{}", token, tag)?;
+            previous = inside;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::dictionary::Id;
+    use crate::matcher::Matcher;
+
+    #[test]
+    fn test_write_conll() {
+        let map: HashMap<String, Id> =
+            [("Acetylsalicylic acid", 2244), ("Water", 962)].iter().map(|(key, cid)| (key.to_string(), Id::Cid(*cid))).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let text = "Nothing here.\n\nTake acetylsalicylic acid (daily), water.";
+        let mut written = Vec::new();
+        write_conll(&mut written, matcher.options(), text, &matcher.search(text)).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "Take\tO\nacetylsalicylic\tB-CHEM\nacid\tI-CHEM\n(\tO\ndaily\tO\n)\tO\n,\tO\nwater\tB-CHEM\n.\tO\n\n"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Converting result files between the CSV written by a search, JSON lines and parquet, or into
+//! the NER training formats, without searching again.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde::Serialize;
+use crate::conll::write_conll_spans;
+use crate::eval::Mention;
+use crate::matcher::SearchOptions;
+use crate::report::{ner_record, parse_result_line, ResultRow};
+
+/// Representation of result rows
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResultFormat {
+    /// Lines written by a search
+    Csv,
+    /// One JSON object per row
+    Jsonl,
+    /// Columns of the export-dataset splits (needs the parquet feature)
+    Parquet,
+    /// One spaCy/Hugging Face record per row (output only)
+    NerJson,
+    /// BIO-tagged tokens of each row (output only)
+    Conll,
+}
+
+impl std::str::FromStr for ResultFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ResultFormat, String> {
+        match s {
+            "csv" => Ok(ResultFormat::Csv),
+            "jsonl" => Ok(ResultFormat::Jsonl),
+            "parquet" => Ok(ResultFormat::Parquet),
+            "ner-json" => Ok(ResultFormat::NerJson),
+            "conll" => Ok(ResultFormat::Conll),
+            _ => Err(format!("unknown result format: {}", s)),
+        }
+    }
+}
+
+impl ResultFormat {
+    /// Format of a file by its extension: .jsonl, .parquet and .conll, otherwise CSV
+    pub fn of_path(path: &str) -> ResultFormat {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => ResultFormat::Jsonl,
+            Some("parquet") => ResultFormat::Parquet,
+            Some("conll") => ResultFormat::Conll,
+            _ => ResultFormat::Csv,
+        }
+    }
+}
+
+/// The context of a row with its first mask replaced by the matched key, and the byte range of the
+/// key in it; contexts written without a mask are searched for the key instead
+pub fn unmask(row: &ResultRow, mask: &str) -> (String, Option<(usize, usize)>) {
+    match row.context.find(mask).filter(|_| !mask.is_empty()) {
+        Some(start) => (row.context.replacen(mask, &row.key, 1), Some((start, start + row.key.len()))),
+        None => (row.context.clone(), row.context.find(&row.key).map(|start| (start, start + row.key.len()))),
+    }
+}
+
+// Call f with each row of a result file in format
+fn for_each_row(path: &str, format: ResultFormat, mut f: impl FnMut(ResultRow) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    match format {
+        ResultFormat::Csv | ResultFormat::Jsonl => {
+            for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+                let line = line?;
+                if line.is_empty() {
+                    continue;
+                }
+                let row = if format == ResultFormat::Csv {
+                    parse_result_line(&line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?
+                } else {
+                    serde_json::from_str(&line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?
+                };
+                f(row)?;
+            }
+            Ok(())
+        }
+        #[cfg(feature = "parquet")]
+        ResultFormat::Parquet => crate::dataset::read_parquet(Path::new(path), f),
+        #[cfg(not(feature = "parquet"))]
+        ResultFormat::Parquet => Err("parquet needs chem-matcher built with --features parquet".into()),
+        ResultFormat::NerJson | ResultFormat::Conll => Err(format!("{}: NER formats can only be written", path).into()),
+    }
+}
+
+// Where converted rows go
+enum Sink {
+    Text(BufWriter<File>, ResultFormat),
+    #[cfg(feature = "parquet")]
+    Parquet(crate::dataset::ParquetWriter),
+}
+
+/// Write the rows of inputs, each in from or the format of its extension, to output in format to.
+/// The NER formats get the context of each row with its match unmasked (see unmask) as their
+/// text; CoNLL splits it into tokens and paragraphs as options do. Returns the rows converted.
+pub fn convert_results(
+    inputs: &[String],
+    from: Option<ResultFormat>,
+    output: &str,
+    to: ResultFormat,
+    options: &SearchOptions,
+    mask: &str,
+) -> Result<u64, Box<dyn Error>> {
+    let mut sink = match to {
+        #[cfg(feature = "parquet")]
+        ResultFormat::Parquet => Sink::Parquet(crate::dataset::ParquetWriter::new(Path::new(output))?),
+        #[cfg(not(feature = "parquet"))]
+        ResultFormat::Parquet => return Err("parquet needs chem-matcher built with --features parquet".into()),
+        _ => Sink::Text(BufWriter::new(File::create(output)?), to),
+    };
+    let mut rows = 0;
+    for input in inputs {
+        for_each_row(input, from.unwrap_or_else(|| ResultFormat::of_path(input)), |row| {
+            rows += 1;
+            match &mut sink {
+                Sink::Text(writer, ResultFormat::Jsonl) => writeln!(writer, "{}", serde_json::to_string(&row)?)?,
+                Sink::Text(writer, ResultFormat::NerJson) => {
+                    let (text, span) = unmask(&row, mask);
+                    let chars = |byte: usize| text[..byte].chars().count();
+                    let mentions: Vec<Mention> = span.map(|(start, end)| Mention { start: chars(start), end: chars(end), cid: row.cid.clone() }).into_iter().collect();
+                    writeln!(writer, "{}", ner_record(&row.paper_id, &text, &mentions))?;
+                }
+                Sink::Text(writer, ResultFormat::Conll) => {
+                    let (text, span) = unmask(&row, mask);
+                    write_conll_spans(writer, options, &text, &span.into_iter().collect::<Vec<_>>())?;
+                }
+                Sink::Text(writer, _) => writer.write_all(row.to_line().as_bytes())?,
+                #[cfg(feature = "parquet")]
+                Sink::Parquet(writer) => writer.push(row)?,
+            }
+            Ok(())
+        })?;
+    }
+    match sink {
+        Sink::Text(mut writer, _) => writer.flush()?,
+        #[cfg(feature = "parquet")]
+        Sink::Parquet(writer) => {
+            writer.close()?;
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::matcher::MASK;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_convert_results() {
+        let tmp_dir = TempDir::new("convert").unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        let lines = format!("\"Aspirin\",2244,\"Épi {} \\\"daily\\\"\",7,exact,name,1.000\n\"50-00-0\",,\"CAS 50-00-0\",8,exact,cas,0.900\n", MASK);
+        fs::write(path("out.csv"), &lines).unwrap();
+        let options = SearchOptions::default();
+
+        // through JSON lines and back
+        assert_eq!(convert_results(&[path("out.csv")], None, &path("out.jsonl"), ResultFormat::Jsonl, &options, MASK).unwrap(), 2);
+        assert!(fs::read_to_string(path("out.jsonl")).unwrap().starts_with("{\"key\":\"Aspirin\",\"cid\":2244,"));
+        convert_results(&[path("out.jsonl")], None, &path("back.txt"), ResultFormat::Csv, &options, MASK).unwrap();
+        assert_eq!(fs::read_to_string(path("back.txt")).unwrap(), lines);
+
+        convert_results(&[path("out.csv")], None, &path("ner.jsonl"), ResultFormat::NerJson, &options, MASK).unwrap();
+        let records: Vec<serde_json::Value> = fs::read_to_string(path("ner.jsonl")).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records[0], serde_json::json!({"id": "7", "text": "Épi Aspirin \"daily\"", "entities": [[4, 11, "CHEMICAL", 2244]]}));
+        assert_eq!(records[1]["entities"], serde_json::json!([[4, 11, "CHEMICAL", null]]));
+
+        convert_results(&[path("out.csv")], None, &path("out.conll"), ResultFormat::Conll, &options, MASK).unwrap();
+        assert!(fs::read_to_string(path("out.conll")).unwrap().starts_with("Épi\tO\nAspirin\tB-CHEM\n\"\tO\n"));
+        assert!(convert_results(&[path("out.conll")], None, &path("x.csv"), ResultFormat::Csv, &options, MASK).is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_convert_parquet() {
+        let tmp_dir = TempDir::new("convert").unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        let lines = "\"Aspirin\",2244,\"take it\",7,exact,name,1.000\n\"50-00-0\",,\"CAS\",8,exact,cas,0.900\n";
+        fs::write(path("out.csv"), lines).unwrap();
+        let options = SearchOptions::default();
+        convert_results(&[path("out.csv")], None, &path("out.parquet"), ResultFormat::Parquet, &options, MASK).unwrap();
+        convert_results(&[path("out.parquet")], None, &path("back.csv"), ResultFormat::Csv, &options, MASK).unwrap();
+        assert_eq!(fs::read_to_string(path("back.csv")).unwrap(), lines);
+    }
+}
@@ -0,0 +1,153 @@
+//! CIDs mentioned in the same paragraph, counted over a run and exported as a co-mention graph.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use serde::Serialize;
+use crate::dictionary::Id;
+use crate::matcher::Match;
+
+/// File format of a co-mention graph
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GraphFormat {
+    /// `cid<TAB>cid<TAB>paragraphs` lines, e.g. for networkx.read_weighted_edgelist(path, delimiter="\t")
+    EdgeList,
+    /// GraphML with CID nodes labelled by a name and edges weighted by paragraphs, e.g. for Gephi
+    GraphMl,
+}
+
+impl std::str::FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<GraphFormat, String> {
+        match s {
+            "edgelist" => Ok(GraphFormat::EdgeList),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            _ => Err(format!("unknown graph format: {}", s)),
+        }
+    }
+}
+
+// text with the characters XML reserves escaped
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Number of paragraphs mentioning each pair of CIDs, keyed with the lower CID first
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Cooccurrences {
+    pub pairs: HashMap<(Id, Id), u64>,
+}
+
+impl Cooccurrences {
+    /// Count the pairs of distinct CIDs among matches of text, within paragraphs split by paragraph_re
+    pub fn add_document(&mut self, paragraph_re: &regex::Regex, text: &str, matches: &[Match]) {
+        let starts: Vec<usize> = std::iter::once(0).chain(paragraph_re.find_iter(text).map(|delimiter| delimiter.end())).collect();
+        let mut paragraphs: HashMap<usize, BTreeSet<Id>> = HashMap::new();
+        for found in matches {
+            if let Some(cid) = &found.cid {
+                let paragraph = starts.partition_point(|&start| start <= found.start) - 1;
+                paragraphs.entry(paragraph).or_default().insert(cid.clone());
+            }
+        }
+        for cids in paragraphs.values() {
+            let cids: Vec<&Id> = cids.iter().collect();
+            for (i, a) in cids.iter().enumerate() {
+                for b in &cids[i + 1..] {
+                    *self.pairs.entry(((*a).clone(), (*b).clone())).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Cooccurrences) {
+        for (pair, count) in other.pairs {
+            *self.pairs.entry(pair).or_default() += count;
+        }
+    }
+
+    /// Pairs with their counts, most frequent first
+    pub fn ranked(&self) -> Vec<((Id, Id), u64)> {
+        let mut pairs: Vec<((Id, Id), u64)> = self.pairs.iter().map(|(pair, count)| (pair.clone(), *count)).collect();
+        pairs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pairs
+    }
+
+    /// CIDs in at least one pair
+    pub fn nodes(&self) -> BTreeSet<Id> {
+        self.pairs.keys().flat_map(|(a, b)| [a.clone(), b.clone()]).collect()
+    }
+
+    /// Write the pairs as a graph in format through a temporary file, most frequent pairs first.
+    /// GraphML nodes are labelled with their name in names, or their CID.
+    pub fn write_graph(&self, file_path: &str, format: GraphFormat, names: &HashMap<Id, String>) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", file_path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        match format {
+            GraphFormat::EdgeList => {
+                for ((a, b), count) in self.ranked() {
+                    writeln!(writer, "{}\t{}\t{}", a, b, count)?;
+                }
+            }
+            GraphFormat::GraphMl => {
+                writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+                writeln!(writer, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+                writeln!(writer, r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#)?;
+                writeln!(writer, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="long"/>"#)?;
+                writeln!(writer, r#"  <graph id="cooccurrence" edgedefault="undirected">"#)?;
+                for cid in self.nodes() {
+                    let id = xml_escape(&cid.to_string());
+                    let label = names.get(&cid).map_or(id.clone(), |name| xml_escape(name));
+                    writeln!(writer, r#"    <node id="{}"><data key="label">{}</data></node>"#, id, label)?;
+                }
+                for ((a, b), count) in self.ranked() {
+                    let (a, b) = (xml_escape(&a.to_string()), xml_escape(&b.to_string()));
+                    writeln!(writer, r#"    <edge source="{}" target="{}"><data key="weight">{}</data></edge>"#, a, b, count)?;
+                }
+                writeln!(writer, "  </graph>\n</graphml>")?;
+            }
+        }
+        writer.flush()?;
+        fs::rename(tmp, file_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{Matcher, SearchOptions};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_cooccurrences() {
+        let map: HashMap<String, Id> =
+            [("Aspirin", 2244), ("Water", 962), ("Caffeine", 2519)].iter().map(|(key, cid)| (key.to_string(), Id::Cid(*cid))).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let text = "Aspirin in water. Aspirin again.\n\nCaffeine and water.\n\nCaffeine only.";
+        let mut cooccurrences = Cooccurrences::default();
+        cooccurrences.add_document(&matcher.options().paragraph_re, text, &matcher.search(text));
+        assert_eq!(cooccurrences.ranked(), vec![((Id::Cid(962), Id::Cid(2244)), 1), ((Id::Cid(962), Id::Cid(2519)), 1)]);
+
+        let mut total = Cooccurrences::default();
+        total.merge(cooccurrences.clone());
+        total.merge(cooccurrences);
+        assert_eq!(total.pairs[&(Id::Cid(962), Id::Cid(2244))], 2);
+
+        let tmp_dir = TempDir::new("cooccurrences").unwrap();
+        let path = tmp_dir.path().join("pairs.tsv");
+        total.write_graph(path.to_str().unwrap(), GraphFormat::EdgeList, &HashMap::new()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "962\t2244\t2\n962\t2519\t2\n");
+
+        let path = tmp_dir.path().join("pairs.graphml");
+        let names = [(Id::Cid(2244), "Aspirin".to_string()), (Id::Cid(962), "H2O & <water>".to_string())].into_iter().collect();
+        total.write_graph(path.to_str().unwrap(), "graphml".parse().unwrap(), &names).unwrap();
+        let graphml = fs::read_to_string(&path).unwrap();
+        assert!(graphml.contains(r#"<node id="962"><data key="label">H2O &amp; &lt;water&gt;</data></node>"#));
+        assert!(graphml.contains(r#"<node id="2519"><data key="label">2519</data></node>"#));
+        assert!(graphml.contains(r#"<edge source="962" target="2244"><data key="weight">2</data></edge>"#));
+        assert!(graphml.trim_end().ends_with("</graphml>"));
+    }
+}
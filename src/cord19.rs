@@ -0,0 +1,54 @@
+//! Documents of CORD-19 `document_parses` (the `pdf_json` and `pmc_json` directories), one JSON
+//! file per paper with its paragraphs and their section names.
+
+use std::error::Error;
+use serde_json::Value;
+
+/// paper_id and text of a CORD-19 document parse: its title, then each paragraph of its abstract
+/// and body text, separated by blank lines, with a section name as a paragraph of its own where
+/// a new section starts
+pub fn cord19_document(json: &Value) -> Result<(String, String), Box<dyn Error>> {
+    let paper_id = json["paper_id"].as_str().ok_or("no paper_id")?;
+    let body_text = json["body_text"].as_array().ok_or("no body_text")?;
+    let mut paragraphs: Vec<&str> = json["metadata"]["title"].as_str().into_iter().collect();
+    // pmc_json parses have no abstract
+    let abstract_text = json["abstract"].as_array().map_or(&[][..], Vec::as_slice);
+    for part in [abstract_text, body_text] {
+        let mut section = "";
+        for paragraph in part {
+            let paragraph_section = paragraph["section"].as_str().unwrap_or_default().trim();
+            if paragraph_section != section {
+                section = paragraph_section;
+                paragraphs.push(section);
+            }
+            paragraphs.push(paragraph["text"].as_str().unwrap_or_default().trim());
+        }
+    }
+    paragraphs.retain(|paragraph| !paragraph.is_empty());
+    Ok((paper_id.to_string(), paragraphs.join("\n\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cord19_document() {
+        let json = serde_json::json!({
+            "paper_id": "0015023cc06b5362d332b3baf348d11567ca2fbb",
+            "metadata": {"title": "Aspirin and water", "authors": []},
+            "abstract": [{"text": "We dissolved aspirin.", "cite_spans": [], "ref_spans": [], "section": "Abstract"}],
+            "body_text": [
+                {"text": "Aspirin was used [1].", "cite_spans": [{"start": 17, "end": 20, "text": "[1]", "ref_id": "BIBREF0"}], "ref_spans": [], "section": "Introduction"},
+                {"text": "It dissolves in water.", "cite_spans": [], "ref_spans": [], "section": "Introduction"},
+                {"text": "Ethanol.", "cite_spans": [], "ref_spans": [], "section": ""}
+            ],
+            "bib_entries": {"BIBREF0": {"title": "Benzene"}},
+            "back_matter": [{"text": "We thank toluene.", "section": "Acknowledgments"}]
+        });
+        let (paper_id, text) = cord19_document(&json).unwrap();
+        assert_eq!(paper_id, "0015023cc06b5362d332b3baf348d11567ca2fbb");
+        assert_eq!(text, "Aspirin and water\n\nAbstract\n\nWe dissolved aspirin.\n\nIntroduction\n\nAspirin was used [1].\n\nIt dissolves in water.\n\nEthanol.");
+        assert!(cord19_document(&serde_json::json!({"paper_id": "PMC1"})).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Result files as a Hugging Face datasets directory: train, validation and test parquet splits
+//! with a dataset_infos.json, ready for `datasets.load_dataset` or pushing to the Hub.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, FloatType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+use serde::Serialize;
+use serde_json::json;
+use crate::dictionary::{hash_strings, Id};
+use crate::report::{parse_result_line, ResultRow};
+
+/// Names of the splits, in the order of their ratios
+pub const SPLITS: [&str; 3] = ["train", "validation", "test"];
+
+// rows of a split buffered before they are written as a row group
+const ROW_GROUP_ROWS: usize = 100_000;
+
+// columns of a result row, in the order they are written; ids that are not CIDs go in id
+const SCHEMA: &str = "message match {
+    required binary key (UTF8);
+    optional int64 cid;
+    required binary context (UTF8);
+    required binary paper_id (UTF8);
+    required binary match_type (UTF8);
+    required binary id_type (UTF8);
+    required float score;
+    optional binary id (UTF8);
+}";
+
+/// Shares of the papers in the train, validation and test splits, e.g. "0.8,0.1,0.1"
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct SplitRatios(pub [f64; 3]);
+
+impl std::str::FromStr for SplitRatios {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SplitRatios, String> {
+        let ratios = s.split(',').map(|ratio| ratio.trim().parse::<f64>().map_err(|e| format!("bad ratio {}: {}", ratio, e))).collect::<Result<Vec<f64>, String>>()?;
+        match ratios[..] {
+            [train, validation, test] if ratios.iter().all(|ratio| *ratio >= 0.0) && train + validation + test > 0.0 => {
+                Ok(SplitRatios([train, validation, test]))
+            }
+            _ => Err(format!("expected three non-negative train,validation,test ratios: {}", s)),
+        }
+    }
+}
+
+impl SplitRatios {
+    /// Index in SPLITS of the split holding the rows of paper_id, the same for every row of a
+    /// paper so none is both trained and tested on
+    pub fn split(&self, paper_id: &str, seed: u64) -> usize {
+        let position = (hash_strings([format!("{}\t{}", seed, paper_id)]) >> 11) as f64 / (1_u64 << 53) as f64;
+        let total: f64 = self.0.iter().sum();
+        let mut cumulative = 0.0;
+        for (i, ratio) in self.0.iter().enumerate() {
+            cumulative += ratio / total;
+            if position < cumulative {
+                return i;
+            }
+        }
+        // rounding left position past the last share
+        self.0.iter().rposition(|ratio| *ratio > 0.0).unwrap()
+    }
+}
+
+/// Parquet file of result rows, with the rows not written yet
+pub struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    rows: Vec<ResultRow>,
+    written: u64,
+}
+
+fn write_column<T: DataType>(row_group: &mut SerializedRowGroupWriter<'_, File>, values: &[T::T], def_levels: Option<&[i16]>) -> Result<(), Box<dyn Error>> {
+    let mut column = row_group.next_column()?.ok_or("more columns than the schema")?;
+    column.typed::<T>().write_batch(values, def_levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+impl ParquetWriter {
+    pub fn new(path: &Path) -> Result<ParquetWriter, Box<dyn Error>> {
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = SerializedFileWriter::new(File::create(path)?, Arc::new(parse_message_type(SCHEMA)?), Arc::new(properties))?;
+        Ok(ParquetWriter { writer, rows: Vec::new(), written: 0 })
+    }
+
+    pub fn push(&mut self, row: ResultRow) -> Result<(), Box<dyn Error>> {
+        self.rows.push(row);
+        if self.rows.len() == ROW_GROUP_ROWS {
+            self.write_rows()?;
+        }
+        Ok(())
+    }
+
+    // Write the buffered rows as a row group
+    fn write_rows(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let strings = |field: fn(&ResultRow) -> &str| rows.iter().map(|row| ByteArray::from(field(row))).collect::<Vec<ByteArray>>();
+        let mut row_group = self.writer.next_row_group()?;
+        write_column::<ByteArrayType>(&mut row_group, &strings(|row| &row.key), None)?;
+        let cids: Vec<Option<u32>> = rows.iter().map(|row| row.cid.as_ref().and_then(Id::cid)).collect();
+        let defined: Vec<i16> = cids.iter().map(|cid| cid.is_some() as i16).collect();
+        write_column::<Int64Type>(&mut row_group, &cids.iter().flatten().map(|&cid| i64::from(cid)).collect::<Vec<i64>>(), Some(&defined))?;
+        write_column::<ByteArrayType>(&mut row_group, &strings(|row| &row.context), None)?;
+        write_column::<ByteArrayType>(&mut row_group, &strings(|row| &row.paper_id), None)?;
+        write_column::<ByteArrayType>(&mut row_group, &strings(|row| &row.match_type), None)?;
+        write_column::<ByteArrayType>(&mut row_group, &strings(|row| &row.id_type), None)?;
+        write_column::<FloatType>(&mut row_group, &rows.iter().map(|row| row.score).collect::<Vec<f32>>(), None)?;
+        let ids: Vec<Option<&str>> = rows.iter().map(|row| match &row.cid { Some(Id::Other(id)) => Some(&**id), _ => None }).collect();
+        let defined: Vec<i16> = ids.iter().map(|id| id.is_some() as i16).collect();
+        write_column::<ByteArrayType>(&mut row_group, &ids.iter().flatten().map(|&id| ByteArray::from(id)).collect::<Vec<ByteArray>>(), Some(&defined))?;
+        row_group.close()?;
+        self.written += rows.len() as u64;
+        Ok(())
+    }
+
+    /// Write the remaining rows and the footer, returning the rows written
+    pub fn close(mut self) -> Result<u64, Box<dyn Error>> {
+        self.write_rows()?;
+        self.writer.close()?;
+        Ok(self.written)
+    }
+}
+
+/// Call f with each row of a parquet file written by ParquetWriter
+pub fn read_parquet(path: &Path, mut f: impl FnMut(ResultRow) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        f(ResultRow {
+            key: row.get_string(0)?.clone(),
+            // a null CID has no value to get, and files written before the id column have none
+            cid: row.get_long(1).ok().map(|cid| Id::Cid(cid as u32)).or_else(|| row.get_string(7).ok().map(|id| Id::Other(id.as_str().into()))),
+            context: row.get_string(2)?.clone(),
+            paper_id: row.get_string(3)?.clone(),
+            match_type: row.get_string(4)?.clone(),
+            id_type: row.get_string(5)?.clone(),
+            score: row.get_float(6)?,
+        })?;
+    }
+    Ok(())
+}
+
+/// Stream the rows of result files into `<dir>/train.parquet`, `validation.parquet` and
+/// `test.parquet`, splitting papers by ratios with seed, and describe them in
+/// `<dir>/dataset_infos.json`. Returns the rows of each split.
+pub fn export_dataset(results: &[String], dir: &Path, ratios: SplitRatios, seed: u64) -> Result<[u64; 3], Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    let paths = SPLITS.map(|split| dir.join(format!("{}.parquet", split)));
+    let mut writers = Vec::new();
+    for path in &paths {
+        writers.push(ParquetWriter::new(path)?);
+    }
+    for file_path in results {
+        for (i, line) in BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if !line.is_empty() {
+                let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+                writers[ratios.split(&row.paper_id, seed)].push(row)?;
+            }
+        }
+    }
+    let mut counts = [0; 3];
+    for (i, writer) in writers.into_iter().enumerate() {
+        counts[i] = writer.close()?;
+    }
+
+    let string = json!({"dtype": "string", "_type": "Value"});
+    let mut splits = serde_json::Map::new();
+    let mut size = 0;
+    for (i, split) in SPLITS.iter().enumerate() {
+        let bytes = fs::metadata(&paths[i])?.len();
+        size += bytes;
+        splits.insert(split.to_string(), json!({"name": split, "num_bytes": bytes, "num_examples": counts[i], "dataset_name": "chem-matcher"}));
+    }
+    let infos = json!({
+        "default": {
+            "description": "Chemical matches found by chem-matcher, one row per match",
+            "citation": "",
+            "homepage": "",
+            "license": "",
+            "features": {
+                "key": string,
+                "cid": {"dtype": "int64", "_type": "Value"},
+                "context": string,
+                "paper_id": string,
+                "match_type": string,
+                "id_type": string,
+                "score": {"dtype": "float32", "_type": "Value"},
+                "id": string,
+            },
+            "config_name": "default",
+            "splits": splits,
+            "download_size": size,
+            "dataset_size": size,
+        }
+    });
+    fs::write(dir.join("dataset_infos.json"), serde_json::to_string_pretty(&infos)?)?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_export_dataset() {
+        let ratios: SplitRatios = "0.5,0.25,0.25".parse().unwrap();
+        assert!("0.5,0.5".parse::<SplitRatios>().is_err());
+        assert!("1,-1,1".parse::<SplitRatios>().is_err());
+        let papers: Vec<String> = (0..1000).map(|paper| paper.to_string()).collect();
+        let mut sizes = [0; 3];
+        for paper in &papers {
+            sizes[ratios.split(paper, 7)] += 1;
+        }
+        assert!(sizes[0] > 430 && sizes[0] < 570 && sizes[2] > 180, "{:?}", sizes);
+        assert_eq!(SplitRatios([1.0, 0.0, 0.0]).split("7", 7), 0);
+
+        let tmp_dir = TempDir::new("dataset").unwrap();
+        let results = tmp_dir.path().join("out.csv");
+        let lines: Vec<String> = papers.iter().map(|paper| format!("\"Aspirin\",2244,\"take <|MOLECULE|>\",{},exact,name,1.000", paper)).collect();
+        fs::write(&results, lines.join("\n") + "\n\"50-00-0\",,\"x\",,exact,cas,0.900\n").unwrap();
+        let dir = tmp_dir.path().join("dataset");
+        let counts = export_dataset(&[results.to_str().unwrap().to_string()], &dir, ratios, 7).unwrap();
+        assert_eq!(counts.iter().sum::<u64>(), 1001);
+
+        let reader = SerializedFileReader::new(File::open(dir.join("train.parquet")).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows() as u64, counts[0]);
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!((row.get_string(0).unwrap().as_str(), row.get_long(1).unwrap()), ("Aspirin", 2244));
+        assert_eq!(row.get_string(2).unwrap(), "take <|MOLECULE|>");
+
+        let infos: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join("dataset_infos.json")).unwrap()).unwrap();
+        assert_eq!(infos["default"]["splits"]["validation"]["num_examples"], counts[1]);
+        assert_eq!(infos["default"]["features"]["cid"]["dtype"], "int64");
+    }
+}
@@ -0,0 +1,196 @@
+//! Removing duplicate contexts from result files larger than memory: row hashes are spread over
+//! bucket files on disk and each bucket is deduplicated on its own.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::dictionary::{hash_strings, Id};
+use crate::report::{parse_result_line, ResultRow};
+
+/// Hash of the id and context of a row. When near, contexts differing only in case, digits,
+/// punctuation or spacing hash the same.
+pub fn context_key(row: &ResultRow, near: bool) -> u64 {
+    let cid = row.cid.as_ref().map(Id::to_string).unwrap_or_default();
+    let context = if near {
+        let letters: String = row.context.chars().map(|c| if c.is_alphabetic() { c.to_ascii_lowercase() } else { ' ' }).collect();
+        letters.split_whitespace().collect::<Vec<&str>>().join(" ")
+    } else {
+        row.context.clone()
+    };
+    hash_strings([format!("{}\t{}", cid, context)])
+}
+
+// Read a little-endian u64, or None at the end of reader
+fn read_u64(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    let mut bytes = [0; 8];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(u64::from_le_bytes(bytes))),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Set of row keys kept on disk: each key is written with its row number to one of several bucket
+/// files, so finding repeated keys only needs one bucket in memory at a time
+pub struct DiskHashSet {
+    dir: PathBuf,
+    buckets: Vec<BufWriter<File>>,
+    rows: u64,
+}
+
+impl DiskHashSet {
+    /// Spread keys over buckets files in dir, which is created and removed when done
+    pub fn new(dir: &Path, buckets: usize) -> Result<DiskHashSet, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let buckets = (0..buckets.max(1))
+            .map(|bucket| File::create(dir.join(format!("{}.keys", bucket))).map(BufWriter::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(DiskHashSet { dir: dir.to_path_buf(), buckets, rows: 0 })
+    }
+
+    /// Add the key of the next row
+    pub fn insert(&mut self, key: u64) -> io::Result<()> {
+        let bucket = (key % self.buckets.len() as u64) as usize;
+        self.buckets[bucket].write_all(&key.to_le_bytes())?;
+        self.buckets[bucket].write_all(&self.rows.to_le_bytes())?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Numbers of the rows whose key an earlier row already had, in ascending order
+    pub fn repeated(self) -> Result<Repeated, Box<dyn Error>> {
+        let mut readers = Vec::new();
+        for (bucket, mut writer) in self.buckets.into_iter().enumerate() {
+            writer.flush()?;
+            drop(writer);
+            let mut entries = Vec::new();
+            let mut reader = BufReader::new(File::open(self.dir.join(format!("{}.keys", bucket)))?);
+            while let (Some(key), Some(row)) = (read_u64(&mut reader)?, read_u64(&mut reader)?) {
+                entries.push((key, row));
+            }
+            entries.sort_unstable();
+            let mut repeated: Vec<u64> = entries.windows(2).filter(|pair| pair[0].0 == pair[1].0).map(|pair| pair[1].1).collect();
+            repeated.sort_unstable();
+            let path = self.dir.join(format!("{}.repeated", bucket));
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for row in repeated {
+                writer.write_all(&row.to_le_bytes())?;
+            }
+            writer.flush()?;
+            readers.push(BufReader::new(File::open(path)?));
+        }
+        let mut next = BinaryHeap::new();
+        for (bucket, reader) in readers.iter_mut().enumerate() {
+            if let Some(row) = read_u64(reader)? {
+                next.push(Reverse((row, bucket)));
+            }
+        }
+        Ok(Repeated { dir: self.dir, readers, next })
+    }
+}
+
+/// Repeated row numbers merged from the buckets of a DiskHashSet
+pub struct Repeated {
+    dir: PathBuf,
+    readers: Vec<BufReader<File>>,
+    next: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl Repeated {
+    /// Whether row, asked for in ascending order, repeats an earlier key
+    pub fn contains(&mut self, row: u64) -> io::Result<bool> {
+        while let Some(&Reverse((next, bucket))) = self.next.peek() {
+            if next > row {
+                break;
+            }
+            self.next.pop();
+            if let Some(following) = read_u64(&mut self.readers[bucket])? {
+                self.next.push(Reverse((following, bucket)));
+            }
+            if next == row {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for Repeated {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Call f with each non-empty line of files and its parsed row
+fn for_each_row(files: &[String], mut f: impl FnMut(&str, &ResultRow) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    for file_path in files {
+        for (i, line) in BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if !line.is_empty() {
+                let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+                f(&line, &row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the rows of files to output, through a temporary file, without rows repeating the CID
+/// and context (see context_key) of an earlier row. Keys go through buckets files in
+/// `<output>.dedupe`, so memory holds one bucket. Returns the rows kept and dropped.
+pub fn dedupe_results(files: &[String], output: &str, near: bool, buckets: usize) -> Result<(u64, u64), Box<dyn Error>> {
+    let mut keys = DiskHashSet::new(Path::new(&format!("{}.dedupe", output)), buckets)?;
+    for_each_row(files, |_, row| Ok(keys.insert(context_key(row, near))?))?;
+    let mut repeated = keys.repeated()?;
+
+    let tmp = format!("{}.tmp", output);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let (mut row_number, mut kept) = (0, 0);
+    for_each_row(files, |line, _| {
+        if !repeated.contains(row_number)? {
+            writeln!(writer, "{}", line)?;
+            kept += 1;
+        }
+        row_number += 1;
+        Ok(())
+    })?;
+    writer.flush()?;
+    fs::rename(tmp, output)?;
+    Ok((kept, row_number - kept))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_dedupe_results() {
+        let tmp_dir = TempDir::new("dedupe").unwrap();
+        let (a, b) = (tmp_dir.path().join("a.csv"), tmp_dir.path().join("b.csv"));
+        let line = |cid: u32, context: &str, paper: usize| format!("\"x\",{},\"{}\",{},exact,name,1.000\n", cid, context, paper);
+        let mut rows = String::new();
+        for paper in 0..300 {
+            rows += &line(2244 + (paper % 7) as u32, &format!("context {}", paper % 50), paper);
+        }
+        fs::write(&a, rows).unwrap();
+        fs::write(&b, line(2244, "Context, 7!", 1) + &line(2244, "context 0", 2) + &line(962, "context 0", 3)).unwrap();
+        let files = [a, b].map(|path| path.to_str().unwrap().to_string());
+        let output = tmp_dir.path().join("out.csv");
+
+        // contexts repeat every 50 papers and CIDs every 7, so pairs repeat every 350
+        let (kept, dropped) = dedupe_results(&files, output.to_str().unwrap(), false, 4).unwrap();
+        assert_eq!((kept, dropped), (302, 1));
+        let written = fs::read_to_string(&output).unwrap();
+        assert!(written.starts_with(&line(2244, "context 0", 0)));
+        assert!(written.ends_with(&line(962, "context 0", 3)));
+        assert!(!tmp_dir.path().join("out.csv.dedupe").exists());
+
+        // with near duplicates every context is "context"
+        assert_eq!(dedupe_results(&files, output.to_str().unwrap(), true, 3).unwrap(), (8, 295));
+        assert_eq!(dedupe_results(&files, output.to_str().unwrap(), true, 1).unwrap(), (8, 295));
+    }
+}
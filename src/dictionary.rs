@@ -0,0 +1,1220 @@
+//! Loading, filtering, compiling and summarizing synonym dictionaries.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter};
+use std::error::Error;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashSet, HashMap};
+use std::io::prelude::*;
+use std::borrow::Cow;
+use std::sync::Arc;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::keymap::{KeyMap, SharedBytes};
+use crate::mesh::{read_mesh_ascii, read_mesh_xml, MeshRecord};
+use crate::mmap::Mmap;
+use crate::text::{case_key, normalize, to_nfkc, tokenize, CaseMode, StemmerWrapper, MIN_WORD_LENGTH};
+
+/// What a dictionary maps its keys to: a PubChem CID, or the id of another database such as
+/// "CHEBI:15377" or "DB00945". Outputs write the number or the id as it is, and JSON a number
+/// or a string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Id {
+    Cid(u32),
+    Other(Box<str>),
+}
+
+impl Id {
+    /// The PubChem CID, if this is one
+    pub fn cid(&self) -> Option<u32> {
+        match self {
+            Id::Cid(cid) => Some(*cid),
+            Id::Other(_) => None,
+        }
+    }
+}
+
+impl From<u32> for Id {
+    fn from(cid: u32) -> Id {
+        Id::Cid(cid)
+    }
+}
+
+impl std::str::FromStr for Id {
+    type Err = String;
+
+    /// A CID when s is a number, else another database's id
+    fn from_str(s: &str) -> Result<Id, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("empty id".to_string());
+        }
+        Ok(match s.parse::<u32>() {
+            Ok(cid) if s.bytes().all(|byte| byte.is_ascii_digit()) => Id::Cid(cid),
+            _ => Id::Other(s.into()),
+        })
+    }
+}
+
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Id::Cid(cid) => write!(f, "{}", cid),
+            Id::Other(id) => f.write_str(id),
+        }
+    }
+}
+
+// Binary formats (compiled dictionaries) keep the variant; JSON has a number or a string
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ReadableId<'a> {
+    Cid(u32),
+    Other(Cow<'a, str>),
+}
+
+#[derive(Serialize, Deserialize)]
+enum BinaryId<'a> {
+    Cid(u32),
+    Other(Cow<'a, str>),
+}
+
+impl Serialize for Id {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match (self, serializer.is_human_readable()) {
+            (Id::Cid(cid), true) => ReadableId::Cid(*cid).serialize(serializer),
+            (Id::Other(id), true) => ReadableId::Other(Cow::Borrowed(id)).serialize(serializer),
+            (Id::Cid(cid), false) => BinaryId::Cid(*cid).serialize(serializer),
+            (Id::Other(id), false) => BinaryId::Other(Cow::Borrowed(id)).serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Id, D::Error> {
+        if deserializer.is_human_readable() {
+            match ReadableId::deserialize(deserializer)? {
+                ReadableId::Cid(cid) => Ok(Id::Cid(cid)),
+                ReadableId::Other(id) => id.parse().map_err(serde::de::Error::custom),
+            }
+        } else {
+            match BinaryId::deserialize(deserializer)? {
+                BinaryId::Cid(cid) => Ok(Id::Cid(cid)),
+                BinaryId::Other(id) => Ok(Id::Other(id.into())),
+            }
+        }
+    }
+}
+
+/// Dictionary keys listed with more than one id, and those ids
+pub type Conflicts = HashMap<String, Vec<Id>>;
+
+/// (key -> id map, conflicts, number of entries filtered out, malformed rows left out)
+pub type ParsedDictionary = (HashMap<String, Id>, Conflicts, usize, Vec<Issue>);
+
+/// (key -> id map, conflicts) as stored in a compiled dictionary
+pub type CompiledDictionary = (HashMap<String, Id>, Conflicts);
+
+/// (keys looked up in place in the bytes of a compiled dictionary, conflicts)
+pub type MappedDictionary = (KeyMap, Conflicts);
+
+/// Which dictionary keeps a key when merged dictionaries disagree
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Precedence {
+    First,
+    Last,
+}
+
+impl std::str::FromStr for Precedence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Precedence, String> {
+        match s {
+            "first" => Ok(Precedence::First),
+            "last" => Ok(Precedence::Last),
+            _ => Err(format!("unknown precedence: {}", s)),
+        }
+    }
+}
+
+/// Which cid a key keeps when a dictionary lists it with several
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resolution {
+    First,
+    Last,
+    LowestCid,
+    // remove the key altogether
+    DropAmbiguous,
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Resolution, String> {
+        match s {
+            "first" => Ok(Resolution::First),
+            "last" => Ok(Resolution::Last),
+            "lowest-cid" => Ok(Resolution::LowestCid),
+            "drop-ambiguous" => Ok(Resolution::DropAmbiguous),
+            _ => Err(format!("unknown conflict resolution: {}", s)),
+        }
+    }
+}
+
+/// Positions of the id and name columns of a tab-separated dictionary, given in order as `id`,
+/// `name` or `-` for a column left out: `id,name` for PubChem's `CID<TAB>synonym` lines, or e.g.
+/// `name,-,id`
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct DictColumns {
+    pub id: usize,
+    pub name: usize,
+    /// Number of fields of every row
+    pub count: usize,
+}
+
+impl Default for DictColumns {
+    fn default() -> DictColumns {
+        DictColumns { id: 0, name: 1, count: 2 }
+    }
+}
+
+impl std::str::FromStr for DictColumns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DictColumns, String> {
+        let columns: Vec<&str> = s.split(',').map(str::trim).collect();
+        let position = |role: &str| match columns.iter().filter(|column| **column == role).count() {
+            1 => Ok(columns.iter().position(|column| *column == role).unwrap()),
+            _ => Err(format!("dictionary columns need one {} column: {}", role, s)),
+        };
+        if let Some(unknown) = columns.iter().find(|column| !["id", "name", "-"].contains(column)) {
+            return Err(format!("unknown dictionary column: {} (expected id, name or -)", unknown));
+        }
+        Ok(DictColumns { id: position("id")?, name: position("name")?, count: columns.len() })
+    }
+}
+
+/// Settings for parse_csv
+pub struct ParseOptions {
+    pub nfkc: bool,
+    pub case_mode: CaseMode,
+    /// Lowercased synonyms to drop
+    pub banned_synonyms: HashSet<String>,
+    pub banned_cids: HashSet<Id>,
+    /// When set, only synonyms of these cids are kept
+    pub only_cids: Option<HashSet<Id>>,
+    pub resolution: Resolution,
+    /// Keys shorter than this, in bytes, are dropped
+    pub min_length: usize,
+    /// Lowercased short names kept regardless of min_length (e.g. urea, thc)
+    pub short_names: HashSet<String>,
+    /// CIDs of the DrugBank ids, CAS numbers and other ids of dictionaries not keyed by CIDs
+    pub ids: HashMap<String, Id>,
+    /// Columns of tab-separated dictionaries
+    pub columns: DictColumns,
+    /// Whether tab-separated dictionaries start with a header row, which is skipped
+    pub has_header: bool,
+}
+
+impl ParseOptions {
+    /// Stable summary of the settings, recorded in compiled dictionaries
+    pub fn describe(&self) -> String {
+        let hash_cids = |cids: &HashSet<Id>| hash_strings(cids.iter().map(|cid| cid.to_string()));
+        let description = format!(
+            "nfkc={} case={:?} resolution={:?} min-length={} short-names={:016x} ban-synonyms={:016x} ban-cids={:016x} only-cids={} columns=id:{},name:{},count:{} header={}",
+            self.nfkc,
+            self.case_mode,
+            self.resolution,
+            self.min_length,
+            hash_strings(&self.short_names),
+            hash_strings(&self.banned_synonyms),
+            hash_cids(&self.banned_cids),
+            self.only_cids.as_ref().map_or("none".to_string(), |only_cids| format!("{:016x}", hash_cids(only_cids))),
+            self.columns.id,
+            self.columns.name,
+            self.columns.count,
+            self.has_header,
+        );
+        // left out when unset, so dictionaries compiled before ids existed still load
+        if self.ids.is_empty() {
+            description
+        } else {
+            format!("{} ids={:016x}", description, hash_strings(self.ids.iter().map(|(id, cid)| format!("{}={}", id, cid))))
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            nfkc: true,
+            case_mode: CaseMode::Title,
+            banned_synonyms: HashSet::new(),
+            banned_cids: HashSet::new(),
+            only_cids: None,
+            resolution: Resolution::Last,
+            min_length: MIN_WORD_LENGTH,
+            short_names: HashSet::new(),
+            ids: HashMap::new(),
+            columns: DictColumns::default(),
+            has_header: false,
+        }
+    }
+}
+
+/// Read a file with one entry per line, skipping blank lines
+pub fn read_list(file_path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(fs::read_to_string(file_path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Number of lines in a file, used to size maps and progress bars
+pub fn estimate_lines (file_path: &str) -> Result<usize, Box<dyn Error>> {
+    let file = File::open(file_path)?;
+    let reader = BufReader::new(file);
+    let line_count = reader.lines().count();
+    Ok(line_count)
+}
+
+/// Layout of a dictionary file, told apart by its first line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DictFormat {
+    /// PubChem `CID<TAB>synonym` lines, or the tab-separated columns given by ParseOptions
+    PubChem,
+    /// DrugBank's vocabulary CSV, with `DrugBank ID`, `Common name`, `CAS` and `Synonyms` columns
+    DrugBank,
+    /// A `name,id` header, then names with a CID or another id
+    NameId,
+    /// MeSH records in the ASCII format, e.g. supplementary concept records of chemicals
+    MeshAscii,
+    /// MeSH records in the XML format
+    MeshXml,
+}
+
+impl DictFormat {
+    pub fn detect(first_line: &str) -> DictFormat {
+        let first_line = first_line.trim_start_matches('\u{feff}').trim();
+        if first_line == "*NEWRECORD" {
+            return DictFormat::MeshAscii;
+        } else if first_line.starts_with("<?xml") || first_line.starts_with("<SupplementalRecordSet") || first_line.starts_with("<DescriptorRecordSet") {
+            return DictFormat::MeshXml;
+        }
+        let columns: Vec<String> = split_csv_line(first_line).iter().map(|column| column.trim().to_lowercase()).collect();
+        if columns.first().is_some_and(|column| column.trim_start_matches('\u{feff}') == "drugbank id") {
+            DictFormat::DrugBank
+        } else if columns == ["name", "id"] {
+            DictFormat::NameId
+        } else {
+            DictFormat::PubChem
+        }
+    }
+}
+
+// Fields of a comma separated line, unquoting "quoted, fields" with "" for a quote
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                fields.last_mut().unwrap().push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// id and synonym of a dictionary row, or the issue and detail of a row without them
+type TabRow = Result<(Id, String), (&'static str, String)>;
+
+// Rows of a tab-separated dictionary, PubChem's `CID<TAB>synonym` by default, with their line
+// numbers: the id and synonym, or the issue ("non-utf8", "malformed" or "no-id") and detail of a
+// row without them. Quoted fields may hold tabs, and a byte order mark and \r\n line ends are
+// dropped, as in Excel exports.
+fn tab_rows<'a>(content: &'a [u8], options: &ParseOptions) -> impl Iterator<Item = (usize, TabRow)> + 'a {
+    let columns = options.columns;
+    let reader = csv::ReaderBuilder::new().delimiter(b'\t').has_headers(options.has_header).flexible(true).from_reader(content);
+    // line numbers counted from byte offsets, as the reader's own skip blank lines and \r
+    let (mut counted, mut line) = (0, 1);
+    let mut line_at = move |position: Option<&csv::Position>| {
+        let start = position.map_or(content.len(), |position| position.byte() as usize);
+        // records start after the blank lines before them
+        let start = start + content[start..].iter().take_while(|&&byte| byte == b'\r' || byte == b'\n').count();
+        line += content[counted..start].iter().filter(|&&byte| byte == b'\n').count();
+        counted = start;
+        line
+    };
+    reader.into_byte_records().filter_map(move |record| {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => return Some((line_at(e.position()), Err(("malformed", e.to_string())))),
+        };
+        let line = line_at(record.position());
+        let fields = match record.iter().map(std::str::from_utf8).collect::<Result<Vec<&str>, _>>() {
+            Ok(fields) => fields,
+            Err(e) => return Some((line, Err(("non-utf8", e.to_string())))),
+        };
+        let row = match &fields[..] {
+            [field] if field.trim().is_empty() => return None,
+            _ if fields.len() != columns.count || fields[columns.name].trim().is_empty() => Err(("malformed", fields.join("\t"))),
+            _ => match fields[columns.id].trim_start_matches('\u{feff}').parse::<Id>() {
+                Ok(id) => Ok((id, fields[columns.name].trim().to_string())),
+                Err(_) => Err(("no-id", fields.join("\t"))),
+            },
+        };
+        Some((line, row))
+    })
+}
+
+/// Read CSV file and return
+/// - a HashMap with key-value pairs
+/// - the keys seen with more than one id
+/// - the number of entries filtered out
+/// - the rows that could not be read
+///
+/// Besides PubChem synonyms, DrugBank vocabularies, name,id files and MeSH records are read (see
+/// DictFormat), their ids (MeSH UIs or registry numbers for MeSH) turned into CIDs with
+/// options.ids, or else kept as they are (DrugBank ids, MeSH UIs).
+pub fn parse_csv(file_path: &str, banned: &HashSet<String>, options: &ParseOptions) -> Result<ParsedDictionary, Box<dyn Error>> {
+    let estimate = estimate_lines(file_path)?;
+    let mut map = HashMap::with_capacity(estimate);
+    let mut conflicts: Conflicts = HashMap::new();
+    let stemmer = StemmerWrapper::new();
+
+    let bytes = fs::read(file_path)?;
+    let mut skipped = 0;
+    let mut malformed = Vec::new();
+
+    let pb = ProgressBar::new(estimate as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("building synonym map [{elapsed_precise}] {bar} {pos}/{len} ({eta})")?
+            .progress_chars("█░"),
+    );
+
+    let mut add = |key: &str, cid: Option<Id>| {
+        let key = normalize(if options.nfkc { to_nfkc(key) } else { Cow::Borrowed(key) }.trim());
+        match cid {
+            Some(cid) if (key.len() >= options.min_length || options.short_names.contains(&key.to_lowercase()))
+                && !banned.contains(stemmer.standardize(&key).as_str())
+                && !options.banned_synonyms.contains(&key.to_lowercase())
+                && !options.banned_cids.contains(&cid)
+                && options.only_cids.as_ref().is_none_or(|only_cids| only_cids.contains(&cid)) =>
+            {
+                let key = case_key(&key, options.case_mode);
+                if let Some(previous) = map.insert(key.clone(), cid.clone()).filter(|previous| *previous != cid) {
+                    let cids = conflicts.entry(key).or_insert_with(|| vec![previous]);
+                    if !cids.contains(&cid) {
+                        cids.push(cid);
+                    }
+                }
+            }
+            _ => skipped += 1,
+        }
+    };
+    // the CID of an id, or else the id itself
+    let id_cid = |id: &str| options.ids.get(id.trim()).cloned().or_else(|| id.parse::<Id>().ok());
+    let first_line = bytes.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    // given columns or a header mean a tab-separated dictionary
+    let format = if options.columns != DictColumns::default() || options.has_header {
+        DictFormat::PubChem
+    } else {
+        DictFormat::detect(&String::from_utf8_lossy(first_line))
+    };
+    // PubChem rows are read as CSV, the other formats as text
+    let content = if format == DictFormat::PubChem { "" } else { std::str::from_utf8(&bytes)? };
+    let mut lines = content.lines();
+    match format {
+        DictFormat::PubChem => {
+            for (line, row) in tab_rows(&bytes, options) {
+                match row {
+                    Ok((cid, name)) => add(&name, Some(cid)),
+                    Err((issue, detail)) => malformed.push(Issue { line, issue, detail }),
+                }
+                pb.inc(1);
+            }
+        }
+        DictFormat::DrugBank => {
+            let header: Vec<String> = split_csv_line(lines.next().unwrap()).iter().map(|column| column.trim().to_lowercase()).collect();
+            let column = |name: &str| header.iter().position(|column| column.trim_start_matches('\u{feff}') == name);
+            let (id, name, cas, synonyms) = (column("drugbank id"), column("common name"), column("cas"), column("synonyms"));
+            for line in lines {
+                let fields = split_csv_line(line);
+                let field = |column: Option<usize>| column.and_then(|column| fields.get(column)).map_or("", |field| field.trim());
+                // by DrugBank id, or else CAS number, or else the DrugBank id itself
+                let cid = [field(id), field(cas)]
+                    .into_iter()
+                    .filter(|id| !id.is_empty())
+                    .find_map(|id| options.ids.get(id).cloned())
+                    .or_else(|| field(id).parse::<Id>().ok());
+                let names = std::iter::once(field(name)).chain(field(synonyms).split('|'));
+                for name in names.map(str::trim).filter(|name| !name.is_empty()) {
+                    add(name, cid.clone());
+                }
+                pb.inc(1);
+            }
+        }
+        DictFormat::NameId => {
+            for line in lines.skip(1) {
+                let fields = split_csv_line(line);
+                if let [name, id] = &fields[..] {
+                    add(name, id_cid(id));
+                }
+                pb.inc(1);
+            }
+        }
+        DictFormat::MeshAscii | DictFormat::MeshXml => {
+            let add_record = |record: MeshRecord| {
+                let cid = std::iter::once(&record.ui)
+                    .chain(&record.registry_numbers)
+                    .find_map(|id| options.ids.get(id).cloned())
+                    .or_else(|| record.ui.parse::<Id>().ok());
+                for term in &record.terms {
+                    add(term, cid.clone());
+                }
+                pb.inc(1);
+            };
+            if format == DictFormat::MeshAscii {
+                read_mesh_ascii(content, add_record);
+            } else {
+                read_mesh_xml(content, add_record)?;
+            }
+        }
+    }
+    pb.finish();
+
+    for (key, cids) in &conflicts {
+        match options.resolution {
+            Resolution::First => {
+                map.insert(key.clone(), cids[0].clone());
+            }
+            Resolution::Last => {}
+            Resolution::LowestCid => {
+                map.insert(key.clone(), cids.iter().min().unwrap().clone());
+            }
+            Resolution::DropAmbiguous => {
+                map.remove(key);
+            }
+        }
+    }
+
+    Ok((map, conflicts, skipped, malformed))
+}
+
+/// A filtered synonym dictionary
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Dictionary {
+    /// Key -> PubChem CID
+    pub map: HashMap<String, Id>,
+    /// Keys listed with more than one CID, and those CIDs
+    pub conflicts: Conflicts,
+    /// Number of entries dropped by the filters
+    pub skipped: usize,
+    /// Rows that could not be read
+    pub malformed: Vec<Issue>,
+}
+
+impl Dictionary {
+    /// Parse a `CID<TAB>synonym` file, dropping banned words and the entries filtered by options
+    pub fn from_csv(file_path: &str, banned: &HashSet<String>, options: &ParseOptions) -> Result<Dictionary, Box<dyn Error>> {
+        let (map, conflicts, skipped, malformed) = parse_csv(file_path, banned, options)?;
+        Ok(Dictionary { map, conflicts, skipped, malformed })
+    }
+}
+
+/// A problem found by validate_csv on a line of a dictionary
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub line: usize,
+    pub issue: &'static str,
+    pub detail: String,
+}
+
+/// Check every line of a dictionary instead of skipping what parse_csv can't use
+pub fn validate_csv(file_path: &str, banned: &HashSet<String>, options: &ParseOptions) -> Result<Vec<Issue>, Box<dyn Error>> {
+    let content = fs::read(file_path)?;
+    let stemmer = StemmerWrapper::new();
+    let mut issues = Vec::new();
+    // key -> (line, id) where it was first seen
+    let mut seen: HashMap<String, (usize, Id)> = HashMap::new();
+    for (line_number, row) in tab_rows(&content, options) {
+        let mut report = |issue, detail: String| issues.push(Issue { line: line_number, issue, detail });
+        let (cid, key) = match row {
+            Ok(row) => row,
+            Err((issue, detail)) => {
+                report(issue, detail);
+                continue;
+            }
+        };
+        let key = normalize(if options.nfkc { to_nfkc(&key) } else { Cow::Borrowed(key.as_str()) }.trim());
+        if key.len() < options.min_length && !options.short_names.contains(&key.to_lowercase()) {
+            report("short-key", key.clone());
+        }
+        if banned.contains(stemmer.standardize(&key).as_str()) {
+            report("banned", key.clone());
+        }
+        match seen.get(&case_key(&key, options.case_mode)) {
+            Some((first_line, first_cid)) => report(
+                "duplicate-key",
+                format!("{} (id {}) first seen on line {} with id {}", key, cid, first_line, first_cid),
+            ),
+            None => {
+                seen.insert(case_key(&key, options.case_mode), (line_number, cid));
+            }
+        }
+    }
+    Ok(issues)
+}
+
+/// Write each conflicting key with its comma separated cids
+pub fn write_conflicts(file_path: &str, conflicts: &Conflicts) -> Result<(), Box<dyn Error>> {
+    let mut keys: Vec<&String> = conflicts.keys().collect();
+    keys.sort();
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    for key in keys {
+        let cids: Vec<String> = conflicts[key].iter().map(|cid| cid.to_string()).collect();
+        writeln!(writer, "{}\t{}", key, cids.join(","))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// First bytes of a dictionary written by compile-dict
+const DICT_MAGIC: &[u8; 8] = b"CHEMDICT";
+
+/// Format version of compiled dictionaries
+pub const DICT_VERSION: u32 = 3;
+
+/// How a compiled dictionary was filtered; it is only loaded under the same settings
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DictHeader {
+    pub version: u32,
+    pub banned_hash: u64,
+    pub options: String,
+}
+
+impl DictHeader {
+    /// Header for a dictionary filtered with banned and options
+    pub fn new(banned: &HashSet<String>, options: &ParseOptions) -> DictHeader {
+        DictHeader { version: DICT_VERSION, banned_hash: hash_strings(banned), options: options.describe() }
+    }
+
+    /// Case mode the keys were compiled with, which must be requested's when given: keys cased
+    /// one way are never found in text cased another
+    pub fn case_mode(&self, requested: Option<&str>) -> Result<CaseMode, String> {
+        let compiled = self.options.split(' ').find_map(|option| option.strip_prefix("case=")).ok_or("compiled dictionary records no case mode")?;
+        let compiled: CaseMode = compiled.to_lowercase().parse()?;
+        match requested.map(str::parse::<CaseMode>).transpose()? {
+            Some(requested) if requested != compiled => {
+                Err(format!("case mode {:?} differs from the dictionary's, compiled with {:?}", requested, compiled).to_lowercase())
+            }
+            _ => Ok(compiled),
+        }
+    }
+}
+
+/// FNV-1a hash of a set of strings, independent of iteration order
+pub fn hash_strings<S: AsRef<str>>(items: impl IntoIterator<Item = S>) -> u64 {
+    let mut items: Vec<S> = items.into_iter().collect();
+    items.sort_unstable_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    let mut hash = 0xcbf29ce484222325_u64;
+    for item in &items {
+        for byte in item.as_ref().bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Write a dictionary in the binary format read by read_compiled_dict: the header and conflicts,
+/// then the keys in sorted order, frozen so map_compiled_dict can look them up in place. The file
+/// is written beside file_path and renamed over it, leaving processes mapping the old one unharmed.
+pub fn write_compiled_dict(file_path: &str, header: &DictHeader, map: &HashMap<String, Id>, conflicts: &Conflicts) -> Result<(), Box<dyn Error>> {
+    let mut entries: Vec<(&String, &Id)> = map.iter().collect();
+    entries.sort_unstable();
+    let keys: KeyMap = entries.into_iter().map(|(key, id)| (key.clone(), id.clone())).collect();
+    let tmp_path = format!("{}.tmp", file_path);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writer.write_all(DICT_MAGIC)?;
+    bincode::serialize_into(&mut writer, header)?;
+    bincode::serialize_into(&mut writer, conflicts)?;
+    keys.write_frozen(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, file_path)?;
+    Ok(())
+}
+
+/// Whether a file starts like a dictionary written by write_compiled_dict
+pub fn is_compiled_dict(file_path: &str) -> bool {
+    let mut magic = [0; DICT_MAGIC.len()];
+    File::open(file_path).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == *DICT_MAGIC
+}
+
+// header of a compiled dictionary, leaving reader at the start of the map
+fn read_dict_header(reader: &mut impl Read, name: &str) -> Result<DictHeader, Box<dyn Error>> {
+    let mut magic = [0; DICT_MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != *DICT_MAGIC {
+        return Err(format!("{} is not a compiled dictionary", name).into());
+    }
+    Ok(bincode::deserialize_from(reader)?)
+}
+
+// header, conflicts and keys of the compiled dictionary in bytes, the keys looked up in place
+fn split_compiled_dict(bytes: SharedBytes, name: &str) -> Result<(DictHeader, MappedDictionary), Box<dyn Error>> {
+    let mut rest = (*bytes).as_ref();
+    let header = read_dict_header(&mut rest, name)?;
+    if header.version != DICT_VERSION {
+        return Err(format!("{} is a version {} compiled dictionary, not {}; recompile it with compile-dict", name, header.version, DICT_VERSION).into());
+    }
+    let conflicts: Conflicts = bincode::deserialize_from(&mut rest)?;
+    let offset = (*bytes).as_ref().len() - rest.len();
+    let keys = KeyMap::from_bytes(bytes, offset).map_err(|e| format!("{}: {}", name, e))?;
+    Ok((header, (keys, conflicts)))
+}
+
+// refuse a compiled dictionary built with a different banned list or settings
+fn check_dict_header(file_path: &str, header: &DictHeader, expected: &DictHeader) -> Result<(), Box<dyn Error>> {
+    if header != expected {
+        return Err(format!(
+            "{} was compiled with different settings (version {}, banned list {:016x}, {}); recompile it with compile-dict",
+            file_path, header.version, header.banned_hash, header.options
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Decode a compiled dictionary held in memory, such as one fetched by a browser
+pub fn decode_compiled_dict(bytes: &[u8]) -> Result<(DictHeader, MappedDictionary), Box<dyn Error>> {
+    split_compiled_dict(Arc::new(bytes.to_vec()), "input")
+}
+
+/// Map a compiled dictionary whatever settings it was built with, returning its header
+pub fn load_compiled_dict(file_path: &str) -> Result<(DictHeader, MappedDictionary), Box<dyn Error>> {
+    split_compiled_dict(Arc::new(Mmap::open(file_path)?), file_path)
+}
+
+/// Map a compiled dictionary, refusing one built with a different banned list or settings. Its
+/// keys are looked up in place, so processes mapping the same file share it in the page cache.
+pub fn map_compiled_dict(file_path: &str, expected: &DictHeader) -> Result<MappedDictionary, Box<dyn Error>> {
+    let (header, dictionary) = load_compiled_dict(file_path)?;
+    check_dict_header(file_path, &header, expected)?;
+    Ok(dictionary)
+}
+
+/// Load a compiled dictionary into memory to merge it with others, refusing one built with a
+/// different banned list or settings
+pub fn read_compiled_dict(file_path: &str, expected: &DictHeader) -> Result<CompiledDictionary, Box<dyn Error>> {
+    let (header, (keys, conflicts)) = split_compiled_dict(Arc::new(fs::read(file_path)?), file_path)?;
+    check_dict_header(file_path, &header, expected)?;
+    Ok((keys.iter().map(|(key, id)| (key.to_string(), id.into_owned())).collect(), conflicts))
+}
+
+/// Merge a parsed dictionary into `map`, recording keys that map to different cids in
+/// `conflicts`. Returns the number of keys that collided with a different cid.
+pub fn merge_dictionary(map: &mut HashMap<String, Id>, conflicts: &mut Conflicts, other: HashMap<String, Id>, other_conflicts: Conflicts, precedence: Precedence) -> usize {
+    let mut record = |key: &str, cids: &[Id]| {
+        let merged = conflicts.entry(key.to_string()).or_default();
+        for cid in cids {
+            if !merged.contains(cid) {
+                merged.push(cid.clone());
+            }
+        }
+    };
+    for (key, cids) in other_conflicts {
+        record(&key, &cids);
+    }
+    let mut collisions = 0;
+    for (key, cid) in other {
+        match map.get(&key) {
+            Some(existing) if *existing != cid => {
+                collisions += 1;
+                record(&key, &[existing.clone(), cid.clone()]);
+                if precedence == Precedence::Last {
+                    map.insert(key, cid);
+                }
+            }
+            Some(_) => {}
+            None => {
+                map.insert(key, cid);
+            }
+        }
+    }
+    collisions
+}
+
+/// Number of tokens in the longest dictionary key, which bounds the n-grams worth scanning
+pub fn max_key_tokens(map: &KeyMap) -> usize {
+    map.keys().map(|key| tokenize(key).len()).max().unwrap_or(1)
+}
+
+// Upper bounds (exclusive) of the key length buckets reported by dict-stats
+const LENGTH_BUCKETS: &[usize] = &[5, 10, 20, 50, 100, usize::MAX];
+
+/// Summary of a filtered dictionary, printed by dict-stats
+#[derive(Debug, PartialEq)]
+pub struct DictStats {
+    pub kept: usize,
+    /// CSV entries dropped by the filters (compiled dictionaries don't record them)
+    pub skipped: usize,
+    pub unique_cids: usize,
+    pub multi_word: usize,
+    pub conflicts: usize,
+    /// (bucket upper bound, number of keys) by length in characters
+    pub lengths: Vec<(usize, usize)>,
+    pub longest_ngram: usize,
+    pub longest_key: String,
+}
+
+impl DictStats {
+    /// Statistics of a dictionary, given how many entries the filters dropped
+    pub fn new(map: &HashMap<String, Id>, conflicts: &Conflicts, skipped: usize) -> DictStats {
+        let mut lengths: Vec<(usize, usize)> = LENGTH_BUCKETS.iter().map(|&bound| (bound, 0)).collect();
+        let mut multi_word = 0;
+        let (mut longest_ngram, mut longest_key) = (0, String::new());
+        for key in map.keys() {
+            let length = key.chars().count();
+            lengths.iter_mut().find(|(bound, _)| length < *bound).unwrap().1 += 1;
+            let ngram = tokenize(key).len();
+            if ngram > 1 {
+                multi_word += 1;
+            }
+            if ngram > longest_ngram || (ngram == longest_ngram && *key < longest_key) {
+                (longest_ngram, longest_key) = (ngram, key.clone());
+            }
+        }
+        DictStats {
+            kept: map.len(),
+            skipped,
+            unique_cids: map.values().collect::<HashSet<_>>().len(),
+            multi_word,
+            conflicts: conflicts.len(),
+            lengths,
+            longest_ngram,
+            longest_key,
+        }
+    }
+}
+
+impl std::fmt::Display for DictStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "kept\t{}", self.kept)?;
+        writeln!(f, "skipped\t{}", self.skipped)?;
+        writeln!(f, "unique cids\t{}", self.unique_cids)?;
+        writeln!(f, "multi-word keys\t{}", self.multi_word)?;
+        writeln!(f, "keys with several cids\t{}", self.conflicts)?;
+        writeln!(f, "longest n-gram\t{}\t{}", self.longest_ngram, self.longest_key)?;
+        let mut lower = 0;
+        for (bound, count) in &self.lengths {
+            if *bound == usize::MAX {
+                writeln!(f, "length {}+\t{}", lower, count)?;
+            } else {
+                writeln!(f, "length {}-{}\t{}", lower, bound - 1, count)?;
+            }
+            lower = *bound;
+        }
+        Ok(())
+    }
+}
+
+/// Plural of the last word of a key
+pub fn pluralize(key: &str) -> String {
+    let last = key.chars().last().unwrap_or(' ');
+    let before = key.chars().rev().nth(1).unwrap_or(' ');
+    if last == 'y' && !"aeiou".contains(before) {
+        format!("{}ies", &key[..key.len() - 1])
+    } else if matches!(last, 's' | 'x' | 'z') || key.ends_with("ch") || key.ends_with("sh") {
+        format!("{}es", key)
+    } else {
+        format!("{}s", key)
+    }
+}
+
+/// Plural and -ic acid/-ate variants of dictionary keys that are not keys themselves
+pub fn expand_variants(map: &KeyMap) -> HashMap<String, Id> {
+    let mut variants = HashMap::new();
+    for (key, cid) in map {
+        let mut forms = vec![pluralize(key)];
+        if let Some(stem) = key.strip_suffix("ic acid") {
+            forms.push(format!("{}ate", stem));
+            forms.push(format!("{}ates", stem));
+        } else if let Some(stem) = key.strip_suffix("ous acid") {
+            forms.push(format!("{}ite", stem));
+            forms.push(format!("{}ites", stem));
+        } else if let Some(stem) = key.strip_suffix("ate") {
+            forms.push(format!("{}ic acid", stem));
+        }
+        for form in forms {
+            if !map.contains_key(&form) {
+                variants.entry(form).or_insert_with(|| cid.clone().into_owned());
+            }
+        }
+    }
+    variants
+}
+
+/// Read a file of `CID<TAB>CAS` lines
+pub fn parse_cas_map(file_path: &str) -> Result<HashMap<String, Id>, Box<dyn Error>> {
+    let mut map = HashMap::new();
+    for line in fs::read_to_string(file_path)?.lines() {
+        if let Some((cid, cas)) = line.split_once('\t') {
+            map.insert(cas.trim().to_string(), cid.parse::<Id>()?);
+        }
+    }
+    Ok(map)
+}
+
+/// Lowercase words appearing in at least min_df of the documents, most frequent first,
+/// with their document frequency
+pub fn build_stoplist(documents: &[String], min_df: f64) -> Vec<(String, f64)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for document in documents {
+        let words: HashSet<String> = tokenize(document)
+            .into_iter()
+            .filter(|(_, word)| word.chars().count() >= 3 && word.chars().all(char::is_alphabetic))
+            .map(|(_, word)| word.to_lowercase())
+            .collect();
+        for word in words {
+            *counts.entry(word).or_default() += 1;
+        }
+    }
+    let mut stoplist: Vec<(String, f64)> = counts
+        .into_iter()
+        .map(|(word, count)| (word, count as f64 / documents.len() as f64))
+        .filter(|(_, df)| *df >= min_df)
+        .collect();
+    stoplist.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    stoplist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempdir::TempDir;
+    use crate::matcher::{search_keys_in_text, Matcher, SearchOptions};
+
+    #[test]
+    fn test_parse_csv() {
+        let content = "43\texample\n16\tworld";
+        let mut banned = HashSet::new();
+        banned.insert("exampl".to_string());
+        let (dir, filename) = (std::env::temp_dir(), "test.csv");
+        let file_path = dir.join(filename);
+        fs::write(&file_path, content).unwrap();
+
+        let (map, _, _, _) = parse_csv(file_path.to_str().unwrap(), &banned, &ParseOptions::default()).unwrap();
+
+        let mut expected_map = HashMap::new();
+        //expected_map.insert("example".to_string(), "test".to_string());
+        expected_map.insert("World".to_string(), Id::Cid(16));
+
+        assert_eq!(map, expected_map);
+    }
+
+    #[test]
+    fn test_id() {
+        assert_eq!(" 2244 ".parse(), Ok(Id::Cid(2244)));
+        assert_eq!("CHEBI:15377".parse(), Ok(Id::Other("CHEBI:15377".into())));
+        // too large or signed numbers are not CIDs
+        assert_eq!("99999999999".parse::<Id>().unwrap().cid(), None);
+        assert_eq!("+7".parse::<Id>().unwrap().to_string(), "+7");
+        assert!("".parse::<Id>().is_err());
+        let ids = vec![Id::Cid(2244), Id::Other("DB00945".into())];
+        assert_eq!(serde_json::to_string(&ids).unwrap(), r#"[2244,"DB00945"]"#);
+        assert_eq!(serde_json::from_str::<Vec<Id>>(r#"[2244,"DB00945","962"]"#).unwrap(), vec![ids[0].clone(), ids[1].clone(), Id::Cid(962)]);
+    }
+
+    #[test]
+    fn test_parse_csv_excel_export() {
+        let tmp_dir = TempDir::new("excel").unwrap();
+        let file_path = tmp_dir.path().join("dict.csv");
+        let content = "\u{feff}2244\tAspirin\r\n962\t\"Water\tice\"\r\n\r\nno tabs here\r\n\tCaffeine\r\n702\tEthanol\r\n";
+        fs::write(&file_path, content).unwrap();
+        let (map, _, _, malformed) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &ParseOptions::default()).unwrap();
+        let mut keys: Vec<(&str, &Id)> = map.iter().map(|(key, cid)| (key.as_str(), cid)).collect();
+        keys.sort();
+        assert_eq!(keys, vec![("Aspirin", &Id::Cid(2244)), ("Ethanol", &Id::Cid(702)), ("Water\tice", &Id::Cid(962))]);
+        let malformed: Vec<(usize, &str, &str)> = malformed.iter().map(|issue| (issue.line, issue.issue, issue.detail.as_str())).collect();
+        assert_eq!(malformed, vec![(4, "malformed", "no tabs here"), (5, "no-id", "\tCaffeine")]);
+    }
+
+    #[test]
+    fn test_dict_columns() {
+        assert_eq!("id,name".parse::<DictColumns>(), Ok(DictColumns::default()));
+        assert_eq!("name, -, id".parse::<DictColumns>(), Ok(DictColumns { id: 2, name: 0, count: 3 }));
+        assert!("name,name,id".parse::<DictColumns>().is_err());
+        assert!("name,cid".parse::<DictColumns>().is_err());
+
+        let tmp_dir = TempDir::new("columns").unwrap();
+        let file_path = tmp_dir.path().join("dict.tsv");
+        fs::write(&file_path, "Synonym\tSource\tCID\nAspirin\tMeSH\t2244\nWater\t962\nEthanol\tChEBI\t702\n").unwrap();
+        let options = ParseOptions { columns: "name,-,id".parse().unwrap(), has_header: true, ..Default::default() };
+        let (map, _, _, malformed) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+        let mut keys: Vec<(&str, &Id)> = map.iter().map(|(key, cid)| (key.as_str(), cid)).collect();
+        keys.sort();
+        assert_eq!(keys, vec![("Aspirin", &Id::Cid(2244)), ("Ethanol", &Id::Cid(702))]);
+        assert_eq!(malformed, vec![Issue { line: 3, issue: "malformed", detail: "Water\t962".to_string() }]);
+    }
+
+    #[test]
+    fn test_short_names() {
+        let content = "1176\tUrea\n16129778\tTHC\n3036\tDDT\n2244\tAspirin";
+        let tmp_dir = TempDir::new("short_names").unwrap();
+        let file_path = tmp_dir.path().join("dict.tsv");
+        fs::write(&file_path, content).unwrap();
+
+        let options = ParseOptions {
+            short_names: ["urea".to_string(), "thc".to_string()].into_iter().collect(),
+            ..Default::default()
+        };
+        let (map, _, skipped, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+        let mut keys: Vec<&str> = map.keys().map(|key| key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["Aspirin", "THC", "Urea"]);
+        assert_eq!(skipped, 1);
+
+        let options = ParseOptions { min_length: 3, ..Default::default() };
+        let (map, _, _, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+        assert_eq!(map.len(), 4);
+
+        let options = SearchOptions { min_length: 3, ..Default::default() };
+        let results = search_keys_in_text(&KeyMap::from(map), "Urea and DDT were found.", &options);
+        let keys: Vec<String> = results.into_iter().map(|m| m.key).collect();
+        assert_eq!(keys, vec!["Urea", "DDT"]);
+    }
+
+    #[test]
+    fn test_parse_csv_bans() {
+        let content = "1\tSame\n2\tAspirin\n3\tAcetylsalicylic acid\n4\tCaffeine";
+        let tmp_dir = TempDir::new("bans").unwrap();
+        let file_path = tmp_dir.path().join("dict.tsv");
+        fs::write(&file_path, content).unwrap();
+
+        let options = ParseOptions {
+            banned_synonyms: ["same".to_string()].into_iter().collect(),
+            banned_cids: [Id::Cid(4)].into_iter().collect(),
+            ..Default::default()
+        };
+        let (map, _, _, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+
+        let mut keys = map.keys().cloned().collect::<Vec<String>>();
+        keys.sort();
+        assert_eq!(keys, vec!["Acetylsalicylic acid", "Aspirin"]);
+
+        let options = ParseOptions { only_cids: Some([Id::Cid(2), Id::Cid(4)].into_iter().collect()), ..Default::default() };
+        let (map, _, _, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+
+        let mut keys = map.keys().cloned().collect::<Vec<String>>();
+        keys.sort();
+        assert_eq!(keys, vec!["Aspirin", "Caffeine"]);
+    }
+
+    #[test]
+    fn test_parse_csv_conflicts() {
+        let content = "7\tAspirin\n3\tAspirin\n9\tAspirin\n4\tCaffeine";
+        let tmp_dir = TempDir::new("conflicts").unwrap();
+        let file_path = tmp_dir.path().join("dict.tsv");
+        fs::write(&file_path, content).unwrap();
+
+        for (resolution, expected) in [
+            (Resolution::First, Some(7)),
+            (Resolution::Last, Some(9)),
+            (Resolution::LowestCid, Some(3)),
+            (Resolution::DropAmbiguous, None),
+        ] {
+            let options = ParseOptions { resolution, ..Default::default() };
+            let (map, conflicts, _, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+            assert_eq!(map.get("Aspirin"), expected.map(Id::Cid).as_ref());
+            assert_eq!(map.get("Caffeine"), Some(&Id::Cid(4)));
+            assert_eq!(conflicts.get("Aspirin"), Some(&vec![Id::Cid(7), Id::Cid(3), Id::Cid(9)]));
+        }
+
+        let conflicts_path = tmp_dir.path().join("conflicts.tsv");
+        let (_, conflicts, _, _) = parse_csv(file_path.to_str().unwrap(), &HashSet::new(), &ParseOptions::default()).unwrap();
+        write_conflicts(conflicts_path.to_str().unwrap(), &conflicts).unwrap();
+        assert_eq!(read_to_string(&conflicts_path).unwrap(), "Aspirin\t7,3,9\n");
+    }
+
+    #[test]
+    fn test_parse_vocabularies() {
+        let tmp_dir = TempDir::new("vocabularies").unwrap();
+        let drugbank = tmp_dir.path().join("drugbank vocabulary.csv");
+        fs::write(
+            &drugbank,
+            "DrugBank ID,Accession Numbers,Common name,CAS,UNII,Synonyms,Standard InChI Key\n\
+             DB00316,APRD00252,Acetaminophen,103-90-2,362O9ITL9D,\"Paracetamol | Tylenol | N-(4-hydroxyphenyl)acetamide\",RZVAJINKPMORJF-UHFFFAOYSA-N\n\
+             DB00945,\"APRD00264, EXPT00475\",Acetylsalicylic acid,50-78-2,R16CO5Y76E,Aspirin,BSYNRYMUTXBXSQ-UHFFFAOYSA-N\n\
+             DB09999,,Unmapped drug,,,,\n",
+        )
+        .unwrap();
+        let ids: HashMap<String, Id> = [("DB00316".to_string(), Id::Cid(1983)), ("50-78-2".to_string(), Id::Cid(2244))].into_iter().collect();
+        let options = ParseOptions { ids, ..Default::default() };
+        let (map, _, skipped, _) = parse_csv(drugbank.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+        assert_eq!(map.get("Tylenol"), Some(&Id::Cid(1983)));
+        assert_eq!(map.get("N-(4-hydroxyphenyl)acetamide"), Some(&Id::Cid(1983)));
+        assert_eq!((map.get("Aspirin"), map.get("Acetylsalicylic acid")), (Some(&Id::Cid(2244)), Some(&Id::Cid(2244))));
+        // drugs without a CID keep their DrugBank id
+        assert_eq!(map.get("Unmapped drug"), Some(&"DB09999".parse().unwrap()));
+        assert_eq!((map.len(), skipped), (7, 0));
+
+        let name_id = tmp_dir.path().join("brands.csv");
+        fs::write(&name_id, "name,id\nTylenol,DB00316\n\"Bayer, Aspirin\",2244\nPanadol,DB99999\nNothing,\n").unwrap();
+        let (map, _, skipped, _) = parse_csv(name_id.to_str().unwrap(), &HashSet::new(), &options).unwrap();
+        assert_eq!((map.get("Tylenol"), map.get("Bayer, Aspirin")), (Some(&Id::Cid(1983)), Some(&Id::Cid(2244))));
+        assert_eq!(map.get("Panadol"), Some(&Id::Other("DB99999".into())));
+        assert_eq!(skipped, 1);
+        assert_ne!(options.describe(), ParseOptions::default().describe());
+        assert!(!ParseOptions::default().describe().contains(" ids="));
+    }
+
+    #[test]
+    fn test_parse_mesh() {
+        let tmp_dir = TempDir::new("mesh").unwrap();
+        let path = tmp_dir.path().join("c2024.bin");
+        fs::write(&path, "*NEWRECORD\nNM = bevonium\nSY = bevonium methyl sulfate|EN|NRW\nRN = 5205-82-5\nUI = C000002\n\n*NEWRECORD\nNM = ferrous lactate\nRN = 0\nUI = C000008\n\n*NEWRECORD\nNM = unmapped extract\nUI = C000009\n").unwrap();
+        let ids: HashMap<String, Id> = [("5205-82-5".to_string(), Id::Cid(71136)), ("C000008".to_string(), Id::Cid(24861))].into_iter().collect();
+        let (map, _, skipped, _) = parse_csv(path.to_str().unwrap(), &HashSet::new(), &ParseOptions { ids, ..Default::default() }).unwrap();
+        assert_eq!((map.get("Bevonium methyl sulfate"), map.get("Ferrous lactate")), (Some(&Id::Cid(71136)), Some(&Id::Cid(24861))));
+        assert_eq!(map.get("Unmapped extract"), Some(&Id::Other("C000009".into())));
+        assert_eq!((map.len(), skipped), (4, 0));
+        assert_eq!(DictFormat::detect("<?xml version=\"1.0\"?>"), DictFormat::MeshXml);
+    }
+
+    #[test]
+    fn test_compiled_dict() {
+        let tmp_dir = TempDir::new("compiled_dict").unwrap();
+        let dict_path = tmp_dir.path().join("dict.bin");
+        let dict_path = dict_path.to_str().unwrap();
+        let banned: HashSet<String> = ["water".to_string()].into_iter().collect();
+        let options = ParseOptions::default();
+        let map: HashMap<String, Id> = [("Aspirin".to_string(), Id::Cid(2244)), ("Water".to_string(), Id::Other("CHEBI:15377".into()))].into_iter().collect();
+        let conflicts: Conflicts = [("Aspirin".to_string(), vec![Id::Cid(2244), Id::Other("DB00945".into())])].into_iter().collect();
+
+        write_compiled_dict(dict_path, &DictHeader::new(&banned, &options), &map, &conflicts).unwrap();
+        assert!(is_compiled_dict(dict_path));
+        let (loaded_map, loaded_conflicts) = read_compiled_dict(dict_path, &DictHeader::new(&banned, &options)).unwrap();
+        assert_eq!(loaded_map, map);
+        assert_eq!(loaded_conflicts, conflicts);
+
+        let other_options = ParseOptions { case_mode: CaseMode::Fold, ..Default::default() };
+        let other_header = DictHeader::new(&banned, &other_options);
+        assert_eq!((other_header.case_mode(None), other_header.case_mode(Some("fold"))), (Ok(CaseMode::Fold), Ok(CaseMode::Fold)));
+        assert_eq!(other_header.case_mode(Some("title")).unwrap_err(), "case mode title differs from the dictionary's, compiled with fold");
+        assert!(read_compiled_dict(dict_path, &DictHeader::new(&banned, &other_options)).is_err());
+        assert!(read_compiled_dict(dict_path, &DictHeader::new(&HashSet::new(), &options)).is_err());
+        // and so is one compiled from tab-separated dictionaries laid out differently
+        let with_header = ParseOptions { has_header: true, ..Default::default() };
+        assert!(read_compiled_dict(dict_path, &DictHeader::new(&banned, &with_header)).is_err());
+        let name_id = ParseOptions { columns: "name,id".parse().unwrap(), ..Default::default() };
+        assert!(map_compiled_dict(dict_path, &DictHeader::new(&banned, &name_id)).is_err());
+        let (header, (mapped, _)) = load_compiled_dict(dict_path).unwrap();
+        assert_eq!(header, DictHeader::new(&banned, &options));
+        assert_eq!((mapped.keys().collect::<Vec<&str>>(), mapped.heap_size()), (vec!["Aspirin", "Water"], 0));
+        assert!(map.iter().all(|(key, id)| mapped.get(key).as_deref() == Some(id)));
+        let (mapped, mapped_conflicts) = map_compiled_dict(dict_path, &DictHeader::new(&banned, &options)).unwrap();
+        assert_eq!((mapped.get("Water").as_deref(), &mapped_conflicts), (map.get("Water"), &conflicts));
+        // processes sharing the map build no filter of their own
+        let matcher = Matcher::new(mapped, SearchOptions::default());
+        assert!(matcher.options().first_words.is_none());
+        assert_eq!(matcher.search("Water and aspirin").iter().map(|m| m.key.as_str()).collect::<Vec<&str>>(), vec!["Water", "Aspirin"]);
+        assert!(map_compiled_dict(dict_path, &DictHeader::new(&HashSet::new(), &options)).is_err());
+        let (_, (decoded, _)) = decode_compiled_dict(&fs::read(dict_path).unwrap()).unwrap();
+        assert_eq!(decoded.get("Aspirin").as_deref(), Some(&Id::Cid(2244)));
+        assert!(decode_compiled_dict(b"2244\tAspirin\n").is_err());
+        let bytes = fs::read(dict_path).unwrap();
+        assert!(decode_compiled_dict(&bytes[..bytes.len() - 1]).is_err());
+        // dictionaries compiled in an older format ask to be recompiled
+        let mut old = DICT_MAGIC.to_vec();
+        bincode::serialize_into(&mut old, &DictHeader { version: 2, ..DictHeader::new(&banned, &options) }).unwrap();
+        bincode::serialize_into(&mut old, &(&map, &conflicts)).unwrap();
+        assert!(decode_compiled_dict(&old).unwrap_err().to_string().contains("recompile it"));
+
+        let csv_path = tmp_dir.path().join("dict.csv");
+        fs::write(&csv_path, "2244\tAspirin\n").unwrap();
+        assert!(!is_compiled_dict(csv_path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_validate_csv() {
+        let tmp_dir = TempDir::new("validate_csv").unwrap();
+        let csv_path = tmp_dir.path().join("dict.csv");
+        let mut content = b"2244\tAspirin\nno tabs here\n \tCaffeine\n5\tUrea\n7\tWater\n\n9\taspirin\n".to_vec();
+        content.extend_from_slice(b"1\tBad\xff name\n");
+        fs::write(&csv_path, content).unwrap();
+        let banned: HashSet<String> = [StemmerWrapper::new().standardize("Water")].into_iter().collect();
+        let issues: Vec<(usize, &str)> = validate_csv(csv_path.to_str().unwrap(), &banned, &ParseOptions::default())
+            .unwrap()
+            .into_iter()
+            .map(|issue| (issue.line, issue.issue))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![(2, "malformed"), (3, "no-id"), (4, "short-key"), (5, "banned"), (7, "duplicate-key"), (8, "non-utf8")]
+        );
+    }
+
+    #[test]
+    fn test_dict_stats() {
+        let map: HashMap<String, Id> = [("Aspirin", 2244), ("Acetylsalicylic acid", 2244), ("Sodium chloride solution", 5234), ("Urea", 1176)]
+            .into_iter()
+            .map(|(key, cid)| (key.to_string(), Id::Cid(cid)))
+            .collect();
+        let conflicts: Conflicts = [("Aspirin".to_string(), vec![Id::Cid(2244), Id::Cid(7)])].into_iter().collect();
+        let stats = DictStats::new(&map, &conflicts, 3);
+        assert_eq!(stats.kept, 4);
+        assert_eq!(stats.skipped, 3);
+        assert_eq!(stats.unique_cids, 3);
+        assert_eq!(stats.multi_word, 2);
+        assert_eq!(stats.conflicts, 1);
+        assert_eq!(stats.lengths, vec![(5, 1), (10, 1), (20, 0), (50, 2), (100, 0), (usize::MAX, 0)]);
+        assert_eq!((stats.longest_ngram, stats.longest_key.as_str()), (3, "Sodium chloride solution"));
+        assert!(stats.to_string().contains("length 20-49\t2\n"));
+    }
+
+    #[test]
+    fn test_build_stoplist() {
+        let documents: Vec<String> = [
+            "Figure 1 shows the dataset.",
+            "The dataset in Figure 2 contains aspirin.",
+            "See figure 3 for caffeine.",
+            "The caffeine results.",
+        ]
+        .iter()
+        .map(|document| document.to_string())
+        .collect();
+        let stoplist = build_stoplist(&documents, 0.5);
+        assert_eq!(
+            stoplist,
+            vec![("figure".to_string(), 0.75), ("the".to_string(), 0.75), ("caffeine".to_string(), 0.5), ("dataset".to_string(), 0.5)]
+        );
+    }
+
+    #[test]
+    fn test_merge_dictionary() {
+        let dictionary = |entries: &[(&str, u32)]| entries.iter().map(|(key, cid)| (key.to_string(), Id::Cid(*cid))).collect::<HashMap<String, Id>>();
+
+        for (precedence, expected) in [(Precedence::First, 1), (Precedence::Last, 3)] {
+            let mut map = HashMap::new();
+            let mut conflicts = Conflicts::new();
+            assert_eq!(merge_dictionary(&mut map, &mut conflicts, dictionary(&[("Aspirin", 1), ("Caffeine", 2)]), Conflicts::new(), precedence), 0);
+            assert_eq!(merge_dictionary(&mut map, &mut conflicts, dictionary(&[("Aspirin", 3), ("Caffeine", 2), ("Tylenol", 4)]), Conflicts::new(), precedence), 1);
+
+            assert_eq!(map, dictionary(&[("Aspirin", expected), ("Caffeine", 2), ("Tylenol", 4)]));
+            assert_eq!(conflicts.get("Aspirin"), Some(&vec![Id::Cid(1), Id::Cid(3)]));
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Scoring matches against gold-standard chemical annotations, e.g. converted from CHEMDNER or
+//! BC5CDR, at mention and document level.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use crate::dictionary::Id;
+use crate::matcher::Match;
+
+/// A chemical mention, by character range in its document
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Mention {
+    pub start: usize,
+    pub end: usize,
+    /// Dictionary id, e.g. a PubChem CID, when annotated
+    pub cid: Option<Id>,
+}
+
+/// Gold mentions by document id
+pub type GoldMentions = HashMap<String, Vec<Mention>>;
+
+/// Texts with their document ids
+pub type Documents = Vec<(String, String)>;
+
+/// Read gold mentions from `doc_id<TAB>start<TAB>end<TAB>cid` lines, with character offsets and
+/// an empty or `-` CID when unknown; further columns, e.g. the mention text, are ignored
+pub fn read_gold_tsv(path: &str) -> Result<GoldMentions, Box<dyn Error>> {
+    let mut gold = GoldMentions::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            return Err(format!("{}:{}: expected doc_id, start, end and cid", path, i + 1).into());
+        }
+        let offset = |field: &str| field.parse::<usize>().map_err(|e| format!("{}:{}: bad offset: {}", path, i + 1, e));
+        let cid = match fields[3] {
+            "" | "-" => None,
+            cid => Some(cid.parse::<Id>().map_err(|e| format!("{}:{}: bad cid: {}", path, i + 1, e))?),
+        };
+        gold.entry(fields[0].to_string()).or_default().push(Mention { start: offset(fields[1])?, end: offset(fields[2])?, cid });
+    }
+    Ok(gold)
+}
+
+/// Read documents from JSON lines with `id` and `text` fields
+pub fn read_texts_jsonl(path: &str) -> Result<Documents, Box<dyn Error>> {
+    let mut texts = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let document: serde_json::Value = serde_json::from_str(line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        let id = match &document["id"] {
+            serde_json::Value::String(id) => id.clone(),
+            serde_json::Value::Number(id) => id.to_string(),
+            _ => return Err(format!("{}:{}: missing id", path, i + 1).into()),
+        };
+        let text = document["text"].as_str().ok_or(format!("{}:{}: missing text", path, i + 1))?;
+        texts.push((id, text.to_string()));
+    }
+    Ok(texts)
+}
+
+/// Mentions of matches in text, with their byte ranges turned into character ranges
+pub fn predicted_mentions(text: &str, matches: &[Match]) -> Vec<Mention> {
+    let chars = |byte: usize| text[..byte].chars().count();
+    matches.iter().map(|found| Mention { start: chars(found.start), end: chars(found.end), cid: found.cid.clone() }).collect()
+}
+
+/// True positives, false positives and false negatives
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub tp: u64,
+    pub fp: u64,
+    pub fn_: u64,
+}
+
+impl Counts {
+    fn add<T: Ord>(&mut self, gold: &BTreeSet<T>, predicted: &BTreeSet<T>) {
+        let tp = gold.intersection(predicted).count() as u64;
+        self.tp += tp;
+        self.fp += predicted.len() as u64 - tp;
+        self.fn_ += gold.len() as u64 - tp;
+    }
+
+    fn ratio(numerator: u64, denominator: u64) -> f64 {
+        if denominator == 0 {
+            0.0
+        } else {
+            numerator as f64 / denominator as f64
+        }
+    }
+
+    pub fn precision(&self) -> f64 {
+        Counts::ratio(self.tp, self.tp + self.fp)
+    }
+
+    pub fn recall(&self) -> f64 {
+        Counts::ratio(self.tp, self.tp + self.fn_)
+    }
+
+    pub fn f1(&self) -> f64 {
+        Counts::ratio(2 * self.tp, 2 * self.tp + self.fp + self.fn_)
+    }
+}
+
+/// Scores over documents, printed by the eval subcommand
+#[derive(Debug, Default, PartialEq)]
+pub struct Evaluation {
+    pub documents: u64,
+    /// Mentions with the same span
+    pub mentions: Counts,
+    /// Mentions with the same span and CID, among gold mentions with a CID
+    pub linked_mentions: Counts,
+    /// CIDs of each document, regardless of where they are mentioned
+    pub document_cids: Counts,
+}
+
+impl Evaluation {
+    /// Score the mentions predicted in a document against its gold mentions
+    pub fn add(&mut self, gold: &[Mention], predicted: &[Mention]) {
+        self.documents += 1;
+        let spans = |mentions: &[Mention]| mentions.iter().map(|mention| (mention.start, mention.end)).collect::<BTreeSet<_>>();
+        self.mentions.add(&spans(gold), &spans(predicted));
+        let linked: Vec<Mention> = gold.iter().filter(|mention| mention.cid.is_some()).cloned().collect();
+        let linked_spans = spans(&linked);
+        // only predictions over annotated spans can be judged on their CID
+        let predicted_linked = predicted.iter().filter(|mention| linked_spans.contains(&(mention.start, mention.end))).cloned().collect();
+        self.linked_mentions.add(&linked.into_iter().collect(), &predicted_linked);
+        let cids = |mentions: &[Mention]| mentions.iter().filter_map(|mention| mention.cid.clone()).collect::<BTreeSet<Id>>();
+        self.document_cids.add(&cids(gold), &cids(predicted));
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "documents\t{}", self.documents)?;
+        writeln!(f, "level\tprecision\trecall\tf1\ttp\tfp\tfn")?;
+        for (level, counts) in [("mention", &self.mentions), ("mention+cid", &self.linked_mentions), ("document", &self.document_cids)] {
+            writeln!(
+                f,
+                "{}\t{:.4}\t{:.4}\t{:.4}\t{}\t{}\t{}",
+                level,
+                counts.precision(),
+                counts.recall(),
+                counts.f1(),
+                counts.tp,
+                counts.fp,
+                counts.fn_
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{Matcher, SearchOptions};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_evaluation() {
+        let tmp_dir = TempDir::new("eval").unwrap();
+        let gold_path = tmp_dir.path().join("gold.tsv");
+        fs::write(&gold_path, "1\t4\t11\t2244\taspirin\n1\t15\t20\t-\n2\t0\t8\t2519\n").unwrap();
+        let texts_path = tmp_dir.path().join("texts.jsonl");
+        fs::write(&texts_path, "{\"id\": 1, \"text\": \"Épi aspirin in water\"}\n{\"id\": \"2\", \"text\": \"Caffeine\"}\n").unwrap();
+        let gold = read_gold_tsv(gold_path.to_str().unwrap()).unwrap();
+        assert_eq!(gold["1"][1], Mention { start: 15, end: 20, cid: None });
+
+        let map: HashMap<String, Id> = [("Aspirin", 2244), ("Water", 962)].iter().map(|(key, cid)| (key.to_string(), Id::Cid(*cid))).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let mut evaluation = Evaluation::default();
+        for (id, text) in read_texts_jsonl(texts_path.to_str().unwrap()).unwrap() {
+            evaluation.add(&gold[&id], &predicted_mentions(&text, &matcher.search(&text)));
+        }
+        // both spans of document 1 are found, caffeine is missed
+        assert_eq!(evaluation.mentions, Counts { tp: 2, fp: 0, fn_: 1 });
+        assert_eq!(evaluation.linked_mentions, Counts { tp: 1, fp: 0, fn_: 1 });
+        assert_eq!(evaluation.document_cids, Counts { tp: 1, fp: 1, fn_: 1 });
+        assert!((evaluation.mentions.f1() - 0.8).abs() < 1e-9);
+        assert!(evaluation.to_string().contains("document\t0.5000\t0.5000\t0.5000\t1\t1\t1\n"));
+
+        fs::write(&gold_path, "1\t4\n").unwrap();
+        assert!(read_gold_tsv(gold_path.to_str().unwrap()).unwrap_err().to_string().contains("gold.tsv:1"));
+    }
+}
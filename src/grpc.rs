@@ -0,0 +1,141 @@
+//! gRPC service streaming documents through a matcher loaded once, from proto/chem_matcher.proto.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use crate::dictionary::Id;
+use crate::matcher::Matcher;
+use crate::metrics::WorkerMetrics;
+
+/// Messages and service generated from proto/chem_matcher.proto
+pub mod proto {
+    tonic::include_proto!("chem_matcher");
+}
+
+use proto::chem_matcher_server::{ChemMatcher, ChemMatcherServer};
+use proto::{Document, DocumentMatches};
+
+// responses buffered before the service stops reading documents
+const IN_FLIGHT: usize = 64;
+
+/// Answers MatchDocuments with a shared matcher, counting documents in worker
+pub struct MatchService {
+    matcher: Arc<Matcher>,
+    worker: Arc<WorkerMetrics>,
+}
+
+impl MatchService {
+    pub fn new(matcher: Arc<Matcher>, worker: Arc<WorkerMetrics>) -> MatchService {
+        MatchService { matcher, worker }
+    }
+}
+
+fn document_matches(matcher: &Matcher, document: Document, worker: &WorkerMetrics) -> DocumentMatches {
+    let (found, paragraphs) = matcher.search_counted(&document.text);
+    worker.record(paragraphs, found.len());
+    let matches = found
+        .into_iter()
+        .map(|found| proto::Match {
+            key: found.key,
+            cid: found.cid.as_ref().and_then(Id::cid),
+            id: found.cid.as_ref().map(Id::to_string),
+            context: found.context,
+            match_type: found.match_type.to_string(),
+            id_type: found.id_type.to_string(),
+            score: found.score,
+            start: found.start as u64,
+            end: found.end as u64,
+        })
+        .collect();
+    DocumentMatches { id: document.id, matches }
+}
+
+#[tonic::async_trait]
+impl ChemMatcher for MatchService {
+    type MatchDocumentsStream = ReceiverStream<Result<DocumentMatches, Status>>;
+
+    async fn match_documents(&self, request: Request<Streaming<Document>>) -> Result<Response<Self::MatchDocumentsStream>, Status> {
+        let mut documents = request.into_inner();
+        let (tx, rx) = mpsc::channel(IN_FLIGHT);
+        let (matcher, worker) = (Arc::clone(&self.matcher), Arc::clone(&self.worker));
+        tokio::spawn(async move {
+            loop {
+                let response = match documents.message().await {
+                    Ok(Some(document)) => {
+                        let (matcher, worker) = (Arc::clone(&matcher), Arc::clone(&worker));
+                        // matching is CPU bound, keep it off the runtime threads
+                        tokio::task::spawn_blocking(move || document_matches(&matcher, document, &worker))
+                            .await
+                            .map_err(|e| Status::internal(e.to_string()))
+                    }
+                    Ok(None) => break,
+                    Err(status) => Err(status),
+                };
+                let failed = response.is_err();
+                // waits while IN_FLIGHT responses are unread; stops once the client hangs up
+                if tx.send(response).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Answer MatchDocuments on address until the process stops
+pub async fn serve_grpc(matcher: Arc<Matcher>, address: SocketAddr, worker: Arc<WorkerMetrics>) -> Result<(), Box<dyn Error>> {
+    tonic::transport::Server::builder()
+        .add_service(ChemMatcherServer::new(MatchService::new(matcher, worker)))
+        .serve(address)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use crate::matcher::SearchOptions;
+    use crate::metrics::Metrics;
+    use proto::chem_matcher_client::ChemMatcherClient;
+
+    #[tokio::test]
+    async fn test_match_documents() {
+        let map: HashMap<String, Id> = [("Aspirin".to_string(), Id::Cid(2244))].into_iter().collect();
+        let matcher = Arc::new(Matcher::new(map, SearchOptions::default()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let metrics = Metrics::new();
+        let worker = metrics.worker("grpc");
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(ChemMatcherServer::new(MatchService::new(matcher, Arc::clone(&worker))))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let mut client = ChemMatcherClient::connect(format!("http://{}", address)).await.unwrap();
+        let documents = ["Take aspirin", "nothing here", "Aspirin again"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| Document { id: i.to_string(), text: text.to_string() })
+            .collect::<Vec<Document>>();
+        let mut responses = client.match_documents(tokio_stream::iter(documents)).await.unwrap().into_inner();
+        let mut received = Vec::new();
+        while let Some(response) = responses.message().await.unwrap() {
+            received.push((response.id, response.matches.iter().map(|m| (m.cid, m.start, m.end)).collect::<Vec<_>>()));
+        }
+        assert_eq!(
+            received,
+            vec![
+                ("0".to_string(), vec![(Some(2244), 5, 12)]),
+                ("1".to_string(), vec![]),
+                ("2".to_string(), vec![(Some(2244), 0, 7)]),
+            ]
+        );
+        assert_eq!(worker.counts()[0], 3);
+    }
+}
@@ -0,0 +1,186 @@
+//! Text of HTML inputs, e.g. scraped supplier catalogs and blog posts: tags, scripts and styles
+//! are stripped, and navigation, headers, footers and link lists optionally left out so only
+//! the main content is matched.
+
+use std::sync::OnceLock;
+use regex::Regex;
+use crate::text::decode_entities;
+
+// Elements skipped with their contents, which are not text
+const RAW_ELEMENTS: [&str; 6] = ["script", "style", "noscript", "template", "svg", "math"];
+
+// Elements without an end tag
+const VOID_ELEMENTS: [&str; 12] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "wbr"];
+
+// Elements starting and ending a paragraph of text
+const BLOCK_ELEMENTS: [&str; 31] = [
+    "address", "article", "aside", "blockquote", "caption", "dd", "div", "dl", "dt", "figcaption", "figure", "footer", "form", "h1", "h2",
+    "h3", "h4", "h5", "h6", "header", "hr", "li", "main", "nav", "ol", "p", "pre", "section", "table", "td", "title",
+];
+
+// Elements that are boilerplate rather than main content
+const BOILERPLATE_ELEMENTS: [&str; 6] = ["nav", "header", "footer", "aside", "form", "menu"];
+
+// Class or id of boilerplate elements
+fn boilerplate_re() -> &'static Regex {
+    static BOILERPLATE: OnceLock<Regex> = OnceLock::new();
+    BOILERPLATE.get_or_init(|| {
+        Regex::new(r#"(?i)\b(?:class|id)\s*=\s*["']?[^"'>]*\b(?:nav|navbar|menu|sidebar|footer|header|banner|breadcrumbs?|cookies?|comments?|share|social|related|advert|ads|popup|subscribe|newsletter)\b"#).unwrap()
+    })
+}
+
+struct Element {
+    name: String,
+    boilerplate: bool,
+    main: bool,
+}
+
+// A paragraph of text with how much of it is link text and what it is inside of
+#[derive(Default)]
+struct Block {
+    text: String,
+    link_len: usize,
+    boilerplate: bool,
+    main: bool,
+}
+
+impl Block {
+    // Readability-style guess of whether this is main content: outside boilerplate, mostly not
+    // links, and inside the main or article element or long enough to be prose
+    fn is_content(&self) -> bool {
+        !self.boilerplate && self.link_len * 2 < self.text.len() && (self.main || self.text.split_whitespace().count() >= 8)
+    }
+}
+
+#[derive(Default)]
+struct Extractor {
+    stack: Vec<Element>,
+    block: Block,
+    blocks: Vec<Block>,
+}
+
+impl Extractor {
+    fn flush(&mut self) {
+        let text = self.block.text.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if !text.is_empty() {
+            self.blocks.push(Block { text, ..std::mem::take(&mut self.block) });
+        }
+        self.block = Block::default();
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.block.text.trim().is_empty() {
+            self.block.boilerplate = self.stack.iter().any(|element| element.boilerplate);
+            self.block.main = self.stack.iter().any(|element| element.main);
+        }
+        let text = decode_entities(text);
+        if self.stack.iter().any(|element| element.name == "a") {
+            self.block.link_len += text.trim().len();
+        }
+        self.block.text.push_str(&text);
+    }
+
+    fn start(&mut self, name: &str, tag: &str) {
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.flush();
+        } else if name == "br" {
+            self.block.text.push('\n');
+        }
+        if !VOID_ELEMENTS.contains(&name) && !tag.ends_with('/') {
+            let boilerplate = BOILERPLATE_ELEMENTS.contains(&name) || boilerplate_re().is_match(tag);
+            let main = name == "main" || name == "article";
+            self.stack.push(Element { name: name.to_string(), boilerplate, main });
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.flush();
+        }
+        // end tags close the elements left open inside them, e.g. <li> without </li>
+        if let Some(open) = self.stack.iter().rposition(|element| element.name == name) {
+            self.stack.truncate(open);
+        }
+    }
+}
+
+/// Text of an HTML page, one paragraph per block element (p, li, headings, table cells, ...)
+/// separated by blank lines, with scripts, styles and comments left out and entities decoded.
+/// With main_content, only paragraphs that look like main content are kept: not inside
+/// navigation, headers, footers, sidebars or elements classed as such, not mostly link text, and
+/// in the main or article element or at least eight words long.
+pub fn html_text(html: &str, main_content: bool) -> String {
+    // same byte offsets as html, for case-insensitive searches
+    let lower = html.to_ascii_lowercase();
+    let mut extractor = Extractor::default();
+    let mut i = 0;
+    while i < html.len() {
+        if lower[i..].starts_with("<!--") {
+            i = lower[i..].find("-->").map_or(html.len(), |end| i + end + 3);
+            continue;
+        }
+        // a tag starts with a name, an end tag's slash or a doctype's !, unlike "1 < 2"
+        let is_tag = html.as_bytes()[i] == b'<' && html.as_bytes().get(i + 1).is_some_and(|&c| c.is_ascii_alphabetic() || b"/!?".contains(&c));
+        let Some(tag_end) = html[i..].find('>').map(|end| i + end).filter(|_| is_tag) else {
+            // text up to the next tag, past a '<' that starts none
+            let from = if html.as_bytes()[i] == b'<' { i + 1 } else { i };
+            let end = html[from..].find('<').map_or(html.len(), |end| from + end);
+            extractor.push_text(&html[i..end]);
+            i = end;
+            continue;
+        };
+        let tag = &lower[i + 1..tag_end];
+        let closing = tag.starts_with('/');
+        let name: String = tag.trim_start_matches('/').chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        i = tag_end + 1;
+        if name.is_empty() {
+            // doctype, processing instruction or stray markup
+            continue;
+        }
+        if closing {
+            extractor.end(&name);
+        } else if RAW_ELEMENTS.contains(&name.as_str()) {
+            let end_tag = format!("</{}", name);
+            i = lower[i..].find(&end_tag).and_then(|end| lower[i + end..].find('>').map(|close| i + end + close + 1)).unwrap_or(html.len());
+        } else {
+            extractor.start(&name, tag.trim_end());
+        }
+    }
+    extractor.flush();
+    let paragraphs: Vec<&str> = extractor.blocks.iter().filter(|block| !main_content || block.is_content()).map(|block| block.text.as_str()).collect();
+    paragraphs.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_text() {
+        let html = r#"<!DOCTYPE html>
+<html><head><title>Aspirin &amp; Co</title><style>p { color: red; }</style>
+<script>var water = "<p>not text</p>";</script></head>
+<body>
+<nav><ul><li><a href="/">Home</a><li><a href="/products">Products</a></ul></nav>
+<div class="sidebar-related">Buy our best ethanol and acetone today at low prices now</div>
+<article>
+<h1>Acetylsalicylic acid</h1>
+<!-- a comment about benzene -->
+<p>Aspirin, or <b>acetylsalicylic acid</b>, dissolves in<br>water at 3&nbsp;g/L.</p>
+<p><a href="/a">Aspirin</a> <a href="/b">Caffeine</a></p>
+</article>
+<p>A long paragraph outside the article about toluene that still reads as prose.</p>
+<footer>Copyright 2024 Chemicals Inc. All rights reserved, including the right to sell methanol.</footer>
+</body></html>"#;
+        let text = html_text(html, false);
+        assert!(text.starts_with("Aspirin & Co\n\nHome\n\nProducts\n\nBuy our best ethanol"));
+        assert!(text.contains("\n\nAcetylsalicylic acid\n\nAspirin, or acetylsalicylic acid, dissolves in water at 3 g/L.\n\nAspirin Caffeine\n\n"));
+        assert!(!text.contains("not text") && !text.contains("color") && !text.contains("benzene"));
+        assert_eq!(
+            html_text(html, true),
+            "Acetylsalicylic acid\n\nAspirin, or acetylsalicylic acid, dissolves in water at 3 g/L.\n\nA long paragraph outside the article about toluene that still reads as prose."
+        );
+        assert_eq!(html_text("1 < 2 and 3 > 1, <unclosed", false), "1 < 2 and 3 > 1, <unclosed");
+        assert_eq!(html_text("<p>2 <i>β</i>-<sub>x</sub></p>é", false), "2 β-x\n\né");
+    }
+}
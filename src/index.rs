@@ -0,0 +1,122 @@
+//! Inverted index from each CID to the documents mentioning it, for finding the papers about a
+//! compound without scanning the contexts of every match, and TF-IDF scores of the mentions.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use serde_json::json;
+use crate::dictionary::Id;
+use crate::matcher::Match;
+
+/// Matches of each CID per document id, e.g. a corpusid
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CidIndex {
+    pub documents: HashMap<Id, BTreeMap<String, u64>>,
+}
+
+impl CidIndex {
+    /// Count the matches with a CID in the document called id
+    pub fn add_document(&mut self, id: &str, matches: &[Match]) {
+        for cid in matches.iter().filter_map(|found| found.cid.as_ref()) {
+            *self.documents.entry(cid.clone()).or_default().entry(id.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: CidIndex) {
+        for (cid, documents) in other.documents {
+            let entry = self.documents.entry(cid).or_default();
+            for (id, count) in documents {
+                *entry.entry(id).or_default() += count;
+            }
+        }
+    }
+
+    /// Write one JSON line per CID, in ascending order, through a temporary file, e.g.
+    /// `{"cid":2244,"corpusids":["12","40"]}`, or with counts
+    /// `{"cid":2244,"corpusids":{"12":3,"40":1}}`
+    pub fn write_jsonl(&self, file_path: &str, counts: bool) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", file_path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        let mut cids: Vec<&Id> = self.documents.keys().collect();
+        cids.sort();
+        for cid in cids {
+            let documents = &self.documents[cid];
+            let line = if counts {
+                json!({ "cid": cid, "corpusids": documents })
+            } else {
+                json!({ "cid": cid, "corpusids": documents.keys().collect::<Vec<&String>>() })
+            };
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, file_path)?;
+        Ok(())
+    }
+
+    /// Write one `corpusid,cid:score;cid:score` row per document, highest scores first, through a
+    /// temporary file. A score is the mentions of the CID in the document times
+    /// ln(documents / documents mentioning the CID), so a CID found everywhere scores 0.
+    pub fn write_tfidf(&self, file_path: &str, documents: u64) -> Result<(), Box<dyn Error>> {
+        let mut papers: BTreeMap<&str, Vec<(&Id, f64)>> = BTreeMap::new();
+        for (cid, mentions) in &self.documents {
+            let idf = (documents as f64 / mentions.len() as f64).ln();
+            for (id, count) in mentions {
+                papers.entry(id).or_default().push((cid, *count as f64 * idf));
+            }
+        }
+        let tmp = format!("{}.tmp", file_path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for (id, mut scores) in papers {
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+            let scores: Vec<String> = scores.iter().map(|(cid, score)| format!("{}:{:.4}", cid, score)).collect();
+            writeln!(writer, "{},{}", id, scores.join(";"))?;
+        }
+        writer.flush()?;
+        fs::rename(tmp, file_path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{Matcher, SearchOptions};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_cid_index() {
+        let map: HashMap<String, Id> = [("Aspirin", 2244), ("Water", 962)].iter().map(|(key, cid)| (key.to_string(), Id::Cid(*cid))).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let mut index = CidIndex::default();
+        index.add_document("40", &matcher.search("Aspirin in water. Aspirin again."));
+        let mut other = CidIndex::default();
+        other.add_document("12", &matcher.search("Aspirin only."));
+        other.add_document("40", &matcher.search("More water."));
+        index.merge(other);
+        assert_eq!(index.documents[&Id::Cid(2244)], [("12".to_string(), 1), ("40".to_string(), 1)].into_iter().collect());
+        assert_eq!(index.documents[&Id::Cid(962)]["40"], 2);
+
+        let tmp_dir = TempDir::new("index").unwrap();
+        let path = tmp_dir.path().join("index.jsonl");
+        index.write_jsonl(path.to_str().unwrap(), false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"cid\":962,\"corpusids\":[\"40\"]}\n{\"cid\":2244,\"corpusids\":[\"12\",\"40\"]}\n");
+        index.write_jsonl(path.to_str().unwrap(), true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap().lines().next(),
+            Some("{\"cid\":962,\"corpusids\":{\"40\":2}}")
+        );
+    }
+
+    #[test]
+    fn test_tfidf() {
+        let mut index = CidIndex::default();
+        index.documents.insert(Id::Cid(2244), [("1".to_string(), 3), ("2".to_string(), 1)].into_iter().collect());
+        index.documents.insert(Id::Cid(962), [("1".to_string(), 1)].into_iter().collect());
+        let tmp_dir = TempDir::new("tfidf").unwrap();
+        let path = tmp_dir.path().join("tfidf.csv");
+        index.write_tfidf(path.to_str().unwrap(), 4).unwrap();
+        // ln(4 / 2) = 0.6931, ln(4 / 1) = 1.3863
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1,2244:2.0794;962:1.3863\n2,2244:0.6931\n");
+    }
+}
@@ -0,0 +1,370 @@
+//! Downloading banned word lists and reading corpus documents.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::HashSet;
+use flate2::read::GzDecoder;
+use std::time::Duration;
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_8};
+use tracing::warn;
+use crate::dictionary::hash_strings;
+use crate::text::StemmerWrapper;
+
+/// Default list of common English words dropped from the dictionary
+pub const BANNED: &str = "https://raw.githubusercontent.com/first20hours/google-10000-english/master/20k.txt";
+
+// Wait before the first retry of a failed download; doubled for each further attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Settings for downloads
+pub struct FetchOptions {
+    /// Hours a cached download is reused (0 disables the cache)
+    pub ttl_hours: u64,
+    pub max_attempts: u32,
+    /// Proxy for every request; HTTP_PROXY/HTTPS_PROXY are used when unset
+    pub proxy: Option<String>,
+    /// PEM file of extra root certificates
+    pub ca_bundle: Option<String>,
+}
+
+impl FetchOptions {
+    /// HTTP client using the proxy and CA bundle
+    pub fn client(&self) -> Result<reqwest::Client, Box<dyn Error>> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            const END: &str = "-----END CERTIFICATE-----";
+            let bundle = fs::read_to_string(ca_bundle)?;
+            for pem in bundle.split_inclusive(END).filter(|pem| pem.contains(END)) {
+                builder = builder.add_root_certificate(reqwest::Certificate::from_pem(pem.trim_start().as_bytes())?);
+            }
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl Default for FetchOptions {
+    fn default() -> FetchOptions {
+        FetchOptions { ttl_hours: 0, max_attempts: 1, proxy: None, ca_bundle: None }
+    }
+}
+
+/// Where downloads are cached: $XDG_CACHE_HOME/chem-matcher, or ~/.cache/chem-matcher
+pub fn cache_dir() -> Option<std::path::PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join("chem-matcher"))
+}
+
+/// Contents of a cache file, unless it is older than ttl
+pub fn read_cache(path: &Path, ttl: Duration) -> Option<String> {
+    let age = fs::metadata(path).ok()?.modified().ok()?.elapsed().unwrap_or_default();
+    if age > ttl {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Run attempt until it succeeds, at most max_attempts times, waiting base_delay doubled at
+/// each retry plus up to half as much jitter
+pub async fn retry<T, E, F, Fut>(max_attempts: u32, base_delay: Duration, mut attempt: F) -> Result<T, Box<dyn Error>>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    let mut delay = base_delay;
+    let mut n = 0;
+    loop {
+        n += 1;
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if n >= max_attempts => return Err(format!("failed after {} attempts: {}", n, e).into()),
+            Err(e) => {
+                let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+                let jitter = delay.mul_f64(nanos as f64 / u32::MAX as f64 / 2.0);
+                warn!(attempt = n, error = %e, retry_in = (delay + jitter).as_secs_f64(), "download failed, retrying");
+                tokio::time::sleep(delay + jitter).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+/// Download a word list, retrying failures and reusing a cached copy younger than the TTL.
+/// A stale copy is still used when every attempt fails.
+pub async fn fetch_words_from_url(url: &str, fetch: &FetchOptions) -> Result<HashSet<String>, Box<dyn Error>> {
+    let cache_path = cache_dir()
+        .filter(|_| fetch.ttl_hours > 0)
+        .map(|dir| dir.join(format!("{:016x}.txt", hash_strings([url]))));
+    if let Some(text) = cache_path.as_ref().and_then(|path| read_cache(path, Duration::from_secs(fetch.ttl_hours * 3600))) {
+        return banned_words(&text);
+    }
+    let client = fetch.client()?;
+    let download = retry(fetch.max_attempts, RETRY_DELAY, || async {
+        client.get(url).send().await?.error_for_status()?.text().await
+    })
+    .await;
+    let text = match download {
+        Ok(text) => text,
+        Err(e) => match cache_path.as_ref().and_then(|path| read_cache(path, Duration::MAX)) {
+            Some(text) => {
+                warn!(url, error = %e, "could not fetch, using the cached copy");
+                text
+            }
+            None => return Err(format!("could not fetch {}: {}", url, e).into()),
+        },
+    };
+    if let Some(path) = &cache_path {
+        // the cache only saves a download, so failing to write it is not an error
+        if let Err(e) = fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(path, &text)) {
+            warn!(url, error = %e, "could not cache");
+        }
+    }
+    banned_words(&text)
+}
+
+/// Standardized words of a banned list, skipping # comments
+pub fn banned_words(text: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+    let pb = ProgressBar::new(20000_u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("fetching common words [{elapsed_precise}] {bar} {pos}/{len} ({eta})")?
+            .progress_chars("█░"),
+    );
+    let stemmer = StemmerWrapper::new();
+    let words: HashSet<String> = text
+        .split_whitespace()
+        .filter(|word| !word.starts_with('#'))
+        .map(|word| {
+            pb.inc(1);
+            stemmer.standardize(word)
+        })
+        .collect();
+    pb.finish();
+    Ok(words)
+}
+
+/// Reader counting the bytes read through it, e.g. the compressed bytes consumed by a GzDecoder
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R) -> CountingReader<R> {
+        CountingReader { inner, count: Arc::new(AtomicU64::new(0)) }
+    }
+
+    /// Counter of the bytes read so far, readable while the reader is in use
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.count)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Text of an input file's bytes in encoding, or, when it is None, in UTF-8 if they are valid
+/// UTF-8 and otherwise in the encoding guessed from them (e.g. windows-1252 for Latin-1 legacy
+/// corpora). A byte order mark overrides both. Returns the text, the encoding used and how many
+/// malformed sequences were replaced with U+FFFD.
+pub fn decode_input(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, &'static Encoding, u64) {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, _)) => bom_encoding,
+        None => encoding.unwrap_or_else(|| {
+            if std::str::from_utf8(bytes).is_ok() {
+                return UTF_8;
+            }
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            detector.guess(None, true)
+        }),
+    };
+    // the decoder removes the byte order mark
+    let mut decoder = encoding.new_decoder();
+    let capacity = |decoder: &encoding_rs::Decoder, len: usize| decoder.max_utf8_buffer_length_without_replacement(len).unwrap_or(len * 3);
+    let mut text = String::with_capacity(capacity(&decoder, bytes.len()));
+    let mut rest = bytes;
+    let mut replaced = 0;
+    loop {
+        let (result, read) = decoder.decode_to_string_without_replacement(rest, &mut text, true);
+        rest = &rest[read..];
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::OutputFull => text.reserve(capacity(&decoder, rest.len()).max(4)),
+            DecoderResult::Malformed(..) => {
+                replaced += 1;
+                text.push('\u{fffd}');
+            }
+        }
+    }
+    (text, encoding, replaced)
+}
+
+/// Texts of up to limit documents in a file: the whole file for text, or the property of each
+/// record for gzipped JSON lines
+pub fn sample_documents(file_path: &Path, property: &str, limit: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    if file_path.extension().is_some_and(|ext| ext == "gz") {
+        let mut documents = Vec::new();
+        for line in BufReader::new(GzDecoder::new(File::open(file_path)?)).lines() {
+            if documents.len() == limit {
+                break;
+            }
+            let line = line?;
+            if let Some(text) = serde_json::from_str::<serde_json::Value>(&line).ok().and_then(|json| json["content"][property].as_str().map(str::to_string)) {
+                documents.push(text);
+            }
+        }
+        Ok(documents)
+    } else {
+        Ok(vec![decode_input(&fs::read(file_path)?, None).0].into_iter().take(limit).collect())
+    }
+}
+
+/// Union of the banned words downloaded from urls and read from files
+pub async fn load_banned_lists(urls: &[String], files: &[String], fetch: &FetchOptions) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut banned = HashSet::new();
+    for url in urls {
+        banned.extend(fetch_words_from_url(url, fetch).await?);
+    }
+    for file in files {
+        banned.extend(banned_words(&fs::read_to_string(file)?)?);
+    }
+    Ok(banned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    #[ignore = "needs network access"]
+    async fn test_fetch_banned() {
+        let stemmer = StemmerWrapper::new();
+        let banned = fetch_words_from_url(BANNED, &FetchOptions::default()).await.unwrap();
+        assert!(banned.contains(stemmer.standardize("pathways").as_str()));
+        assert!(!banned.contains(stemmer.standardize("Acetaminophen").as_str()));
+    }
+
+    #[test]
+    fn test_standardize() {
+        let stemmer = StemmerWrapper::new();
+        let banned = banned_words("the\npathway\n#acetaminophen\nwater").unwrap();
+        assert!(banned.contains(stemmer.standardize("pathways").as_str()));
+        assert!(banned.contains(stemmer.standardize("Water").as_str()));
+        assert!(!banned.contains(stemmer.standardize("Acetaminophen").as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_load_banned_lists() {
+        let tmp_dir = TempDir::new("banned").unwrap();
+        let english = tmp_dir.path().join("english.txt");
+        let biology = tmp_dir.path().join("biology.txt");
+        fs::write(&english, "the\npathway\n").unwrap();
+        fs::write(&biology, "#biology\nproteins\ncells\n").unwrap();
+        let files = [english.to_str().unwrap().to_string(), biology.to_str().unwrap().to_string()];
+        let banned = load_banned_lists(&[], &files, &FetchOptions::default()).await.unwrap();
+        let stemmer = StemmerWrapper::new();
+        for word in ["pathways", "protein", "Cell"] {
+            assert!(banned.contains(stemmer.standardize(word).as_str()), "{}", word);
+        }
+        assert!(!banned.contains(stemmer.standardize("biology").as_str()));
+    }
+
+    #[test]
+    fn test_sample_documents() {
+        let documents = ["Figure 1 shows the dataset.", "The dataset in Figure 2 contains aspirin.", "See figure 3 for caffeine."];
+        let tmp_dir = TempDir::new("stoplist").unwrap();
+        let text_path = tmp_dir.path().join("corpus.json.gz");
+        let mut writer = GzEncoder::new(File::create(&text_path).unwrap(), Compression::fast());
+        for document in &documents {
+            writeln!(writer, "{}", serde_json::json!({"corpusid": 1, "content": {"text": document}})).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(sample_documents(&text_path, "text", 2).unwrap(), documents[..2].to_vec());
+    }
+
+    #[test]
+    fn test_counting_reader() {
+        let tmp_dir = TempDir::new("counting").unwrap();
+        let path = tmp_dir.path().join("corpus.json.gz");
+        let mut writer = GzEncoder::new(File::create(&path).unwrap(), Compression::fast());
+        writeln!(writer, "{}", "aspirin ".repeat(1000)).unwrap();
+        writer.finish().unwrap();
+
+        let reader = CountingReader::new(File::open(&path).unwrap());
+        let counter = reader.counter();
+        let mut text = String::new();
+        GzDecoder::new(reader).read_to_string(&mut text).unwrap();
+        assert_eq!(text.len(), 8001);
+        assert_eq!(counter.load(Ordering::Relaxed), fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn test_decode_input() {
+        let latin1 = b"La caf\xe9ine et l'ac\xe9tone sont solubles dans l'\xe9thanol \xe0 20 \xb0C.";
+        let (text, encoding, replaced) = decode_input(latin1, None);
+        assert_eq!((text.as_str(), encoding.name(), replaced), ("La caféine et l'acétone sont solubles dans l'éthanol à 20 °C.", "windows-1252", 0));
+        assert_eq!(decode_input("Caféine".as_bytes(), None), ("Caféine".to_string(), UTF_8, 0));
+        let (text, _, replaced) = decode_input(b"Aspirin \xff\xfe and water\xe9", Some(UTF_8));
+        assert_eq!((text.as_str(), replaced), ("Aspirin \u{fffd}\u{fffd} and water\u{fffd}", 3));
+        // a byte order mark wins over the given encoding
+        let (text, encoding, _) = decode_input(b"\xff\xfeA\x00s\x00p\x00", Some(UTF_8));
+        assert_eq!((text.as_str(), encoding.name()), ("Asp", "UTF-16LE"));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let mut calls = 0;
+        let result = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            let n = calls;
+            async move { if n < 3 { Err(format!("failure {}", n)) } else { Ok(n) } }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let result: Result<(), _> = retry(2, Duration::from_millis(1), || async { Err("unreachable host") }).await;
+        assert_eq!(result.unwrap_err().to_string(), "failed after 2 attempts: unreachable host");
+    }
+
+    #[test]
+    fn test_fetch_client() {
+        assert!(FetchOptions::default().client().is_ok());
+        let proxied = FetchOptions { proxy: Some("http://proxy.example:3128".to_string()), ..Default::default() };
+        assert!(proxied.client().is_ok());
+        let missing_bundle = FetchOptions { ca_bundle: Some("/nonexistent/ca.pem".to_string()), ..Default::default() };
+        assert!(missing_bundle.client().is_err());
+    }
+
+    #[test]
+    fn test_read_cache() {
+        let tmp_dir = TempDir::new("cache").unwrap();
+        let path = tmp_dir.path().join("words.txt");
+        assert_eq!(read_cache(&path, Duration::MAX), None);
+        fs::write(&path, "pathway\n").unwrap();
+        assert_eq!(read_cache(&path, Duration::from_secs(3600)), Some("pathway\n".to_string()));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(read_cache(&path, Duration::ZERO), None);
+    }
+}
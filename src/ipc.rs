@@ -0,0 +1,114 @@
+//! Documents of Arrow IPC inputs (the pyarrow and Spark stream format, or Feather/Arrow files),
+//! read one record batch at a time with the arrow feature.
+
+use std::error::Error;
+
+#[cfg(feature = "arrow")]
+use arrow_array::{cast::AsArray, types, Array, RecordBatchReader};
+
+// A cell of a string or integer column as text, None when null or of another type
+#[cfg(feature = "arrow")]
+fn cell_text(array: &dyn Array, row: usize) -> Option<String> {
+    if array.is_null(row) {
+        return None;
+    }
+    array
+        .as_string_opt::<i32>()
+        .map(|strings| strings.value(row).to_string())
+        .or_else(|| array.as_string_opt::<i64>().map(|strings| strings.value(row).to_string()))
+        .or_else(|| array.as_string_view_opt().map(|strings| strings.value(row).to_string()))
+        .or_else(|| array.as_primitive_opt::<types::Int64Type>().map(|ints| ints.value(row).to_string()))
+        .or_else(|| array.as_primitive_opt::<types::Int32Type>().map(|ints| ints.value(row).to_string()))
+        .or_else(|| array.as_primitive_opt::<types::UInt64Type>().map(|ints| ints.value(row).to_string()))
+        .or_else(|| array.as_primitive_opt::<types::UInt32Type>().map(|ints| ints.value(row).to_string()))
+}
+
+/// Call f with the id and text of each row of the Arrow IPC stream or file at path, from its
+/// text_column (strings) and id_column (strings or integers); rows are numbered from 1 without
+/// one or with a null or empty id. Null texts are passed as empty. f returns false to stop.
+#[cfg(feature = "arrow")]
+pub fn read_arrow(path: &str, text_column: &str, id_column: Option<&str>, mut f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+    use arrow_ipc::reader::{FileReader, StreamReader};
+
+    // files start with a magic number, streams with their schema message
+    let mut magic = [0; 6];
+    let is_file = File::open(path)?.read_exact(&mut magic).is_ok() && &magic == b"ARROW1";
+    let batches: Box<dyn RecordBatchReader> = if is_file {
+        Box::new(FileReader::try_new(File::open(path)?, None)?)
+    } else {
+        Box::new(StreamReader::try_new(BufReader::new(File::open(path)?), None)?)
+    };
+    let mut row_number = 0u64;
+    for batch in batches {
+        let batch = batch?;
+        let column = |name: &str| batch.column_by_name(name).ok_or_else(|| format!("no {} column", name));
+        let texts = column(text_column)?;
+        let ids = id_column.map(column).transpose()?;
+        for row in 0..batch.num_rows() {
+            row_number += 1;
+            let text = cell_text(texts.as_ref(), row).unwrap_or_default();
+            let id = match ids {
+                // rows with an empty id are numbered like rows without an id column
+                Some(ids) => cell_text(ids.as_ref(), row).filter(|id| !id.is_empty()).unwrap_or_else(|| row_number.to_string()),
+                None => row_number.to_string(),
+            };
+            if !f(&id, &text) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rows of an Arrow IPC input, needing the arrow feature
+#[cfg(not(feature = "arrow"))]
+pub fn read_arrow(path: &str, _text_column: &str, _id_column: Option<&str>, _f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    Err(format!("{}: reading Arrow inputs needs chem-matcher built with --features arrow", path).into())
+}
+
+#[cfg(all(test, feature = "arrow"))]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+    use arrow_ipc::writer::{FileWriter, StreamWriter};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_read_arrow() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("doc_id", Arc::new(Int64Array::from(vec![7, 8, 9])) as ArrayRef),
+            ("body", Arc::new(StringArray::from(vec![Some("Aspirin"), None, Some("Water")])) as ArrayRef),
+        ])
+        .unwrap();
+        let tmp_dir = TempDir::new("ipc").unwrap();
+        let stream = tmp_dir.path().join("docs.arrows");
+        let mut writer = StreamWriter::try_new(File::create(&stream).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.write(&batch.slice(2, 1)).unwrap();
+        writer.finish().unwrap();
+        let mut rows = Vec::new();
+        read_arrow(stream.to_str().unwrap(), "body", Some("doc_id"), |id, text| {
+            rows.push(format!("{}:{}", id, text));
+            true
+        })
+        .unwrap();
+        assert_eq!(rows, vec!["7:Aspirin", "8:", "9:Water", "9:Water"]);
+
+        let file = tmp_dir.path().join("docs.arrow");
+        let mut writer = FileWriter::try_new(File::create(&file).unwrap(), &batch.schema()).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        let mut rows = Vec::new();
+        read_arrow(file.to_str().unwrap(), "body", None, |id, text| {
+            rows.push(format!("{}:{}", id, text));
+            rows.len() < 2
+        })
+        .unwrap();
+        assert_eq!(rows, vec!["1:Aspirin", "2:"]);
+        assert_eq!(read_arrow(file.to_str().unwrap(), "text", None, |_, _| true).unwrap_err().to_string(), "no text column");
+    }
+}
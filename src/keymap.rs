@@ -0,0 +1,489 @@
+//! Dictionary keys interned into one string arena, known by u32 handles, with their ids in a
+//! parallel vec: about half the memory of a `HashMap<String, Id>`, which pays a heap allocation
+//! and a 24-byte String per key, for dictionaries of hundreds of millions of synonyms. A frozen
+//! map, as written into compiled dictionaries, is looked up in place in the bytes holding it, such
+//! as a memory map shared by every process on a machine.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Write};
+use std::sync::Arc;
+use hashbrown::hash_table::Entry;
+use hashbrown::HashTable;
+use crate::dictionary::Id;
+
+/// Bytes shared by the maps looked up in them, e.g. a Mmap or a Vec<u8>
+pub type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+// Flag of the frozen ids that are offsets into the other ids rather than CIDs
+const OTHER_ID: u64 = 1 << 63;
+
+// Empty slot of a frozen table
+const EMPTY_SLOT: u32 = u32::MAX;
+
+// Counts before the sections of a frozen map: keys, arena bytes, other id bytes and table slots
+const FROZEN_COUNTS: usize = 4;
+
+// FNV-1a hash of a key, the same in every process unlike RandomState
+fn stable_hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// Little-endian u64 at offset of bytes
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+// A frozen map in bytes: sections of u64 key ends, u64 ids (a CID, or OTHER_ID and the offset of
+// a u32 length and the id), u32 table slots found by stable_hash with linear probing, then the
+// arena of keys and the other ids
+#[derive(Clone)]
+struct Frozen {
+    bytes: SharedBytes,
+    len: usize,
+    ends: usize,
+    ids: usize,
+    table: usize,
+    slots: usize,
+    arena: usize,
+    others: usize,
+    end: usize,
+}
+
+impl Frozen {
+    // Frozen map starting at offset of bytes and running to their end. Every offset, handle and
+    // string is checked here, so lookups can index and decode without checking again.
+    fn new(bytes: SharedBytes, offset: usize) -> Result<Frozen, String> {
+        let data = (*bytes).as_ref();
+        if data.len() < offset.saturating_add(FROZEN_COUNTS * 8) {
+            return Err("truncated dictionary keys".to_string());
+        }
+        let count = |i: usize| usize::try_from(u64_at(data, offset + i * 8)).map_err(|_| "dictionary keys too large for this platform".to_string());
+        let (len, arena_len, others_len, slots) = (count(0)?, count(1)?, count(2)?, count(3)?);
+        let corrupt = |what: &str| format!("corrupt dictionary keys ({}; {} keys in {} bytes)", what, len, data.len() - offset);
+        let sections = (|| {
+            let ends = offset + FROZEN_COUNTS * 8;
+            let ids = ends.checked_add(len.checked_mul(8)?)?;
+            let table = ids.checked_add(len.checked_mul(8)?)?;
+            let arena = table.checked_add(slots.checked_mul(4)?)?;
+            let others = arena.checked_add(arena_len)?;
+            Some((ends, ids, table, arena, others, others.checked_add(others_len)?))
+        })();
+        let Some((ends, ids, table, arena, others, end)) = sections.filter(|sections| sections.5 == data.len()) else {
+            return Err(corrupt("section lengths"));
+        };
+        if !slots.is_power_of_two() || slots <= len || len >= EMPTY_SLOT as usize {
+            return Err(corrupt("table size"));
+        }
+
+        let keys = std::str::from_utf8(&data[arena..others]).map_err(|_| corrupt("keys are not UTF-8"))?;
+        let mut start = 0;
+        for handle in 0..len {
+            match usize::try_from(u64_at(data, ends + handle * 8)) {
+                Ok(key_end) if key_end >= start && key_end <= arena_len && keys.is_char_boundary(key_end) => start = key_end,
+                _ => return Err(corrupt("key ends")),
+            }
+            let id = u64_at(data, ids + handle * 8);
+            if id & OTHER_ID == 0 {
+                if id > u32::MAX as u64 {
+                    return Err(corrupt("CID out of range"));
+                }
+                continue;
+            }
+            let other = usize::try_from(id & !OTHER_ID).ok().and_then(|at| {
+                let len_end = at.checked_add(4).filter(|&len_end| len_end <= others_len)?;
+                let len = u32::from_le_bytes(data[others + at..others + len_end].try_into().unwrap()) as usize;
+                let id_end = len_end.checked_add(len).filter(|&id_end| id_end <= others_len)?;
+                std::str::from_utf8(&data[others + len_end..others + id_end]).ok()
+            });
+            if other.is_none() {
+                return Err(corrupt("other id"));
+            }
+        }
+        if start != arena_len {
+            return Err(corrupt("arena length"));
+        }
+        let mut empty_slot = false;
+        for slot in 0..slots {
+            let handle = u32::from_le_bytes(data[table + slot * 4..table + slot * 4 + 4].try_into().unwrap());
+            if handle == EMPTY_SLOT {
+                empty_slot = true;
+            } else if handle as usize >= len {
+                return Err(corrupt("table handle"));
+            }
+        }
+        if !empty_slot {
+            return Err(corrupt("full table"));
+        }
+        Ok(Frozen { bytes, len, ends, ids, table, slots, arena, others, end })
+    }
+
+    fn data(&self) -> &[u8] {
+        (*self.bytes).as_ref()
+    }
+
+    // end of a key in the arena
+    fn end_of(&self, handle: u32) -> usize {
+        u64_at(self.data(), self.ends + handle as usize * 8) as usize
+    }
+
+    fn key_bytes(&self, handle: u32) -> &[u8] {
+        assert!((handle as usize) < self.len, "no dictionary key has handle {}", handle);
+        let start = if handle == 0 { 0 } else { self.end_of(handle - 1) };
+        &self.data()[self.arena + start..self.arena + self.end_of(handle)]
+    }
+
+    fn key(&self, handle: u32) -> &str {
+        // SAFETY: new checked that the arena is UTF-8 and that every key ends on a char boundary
+        unsafe { std::str::from_utf8_unchecked(self.key_bytes(handle)) }
+    }
+
+    fn id(&self, handle: u32) -> Id {
+        assert!((handle as usize) < self.len, "no dictionary key has handle {}", handle);
+        let id = u64_at(self.data(), self.ids + handle as usize * 8);
+        if id & OTHER_ID == 0 {
+            return Id::Cid(id as u32);
+        }
+        let start = self.others + (id & !OTHER_ID) as usize;
+        let len = u32::from_le_bytes(self.data()[start..start + 4].try_into().unwrap()) as usize;
+        // SAFETY: new checked that every other id is in bounds and UTF-8
+        Id::Other(unsafe { std::str::from_utf8_unchecked(&self.data()[start + 4..start + 4 + len]) }.into())
+    }
+
+    fn handle(&self, key: &str) -> Option<u32> {
+        let mask = self.slots - 1;
+        let mut slot = stable_hash(key) as usize & mask;
+        loop {
+            let offset = self.table + slot * 4;
+            let handle = u32::from_le_bytes(self.data()[offset..offset + 4].try_into().unwrap());
+            if handle == EMPTY_SLOT {
+                return None;
+            }
+            if self.key_bytes(handle) == key.as_bytes() {
+                return Some(handle);
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+}
+
+// Key of handle, from the arena and the end offsets of the keys in it
+fn key_at<'a>(arena: &'a str, ends: &[usize], handle: u32) -> &'a str {
+    let handle = handle as usize;
+    let start = if handle == 0 { 0 } else { ends[handle - 1] };
+    &arena[start..ends[handle]]
+}
+
+/// Map of dictionary keys to ids. Handles number the keys in the order they were first inserted.
+#[derive(Clone, Default)]
+pub struct KeyMap {
+    arena: String,
+    // end of each key in arena, by handle
+    ends: Vec<usize>,
+    ids: Vec<Id>,
+    // handles, found by the hash of their key
+    table: HashTable<u32>,
+    hasher: RandomState,
+    // keys looked up in place instead, until the first insert copies them into the fields above
+    frozen: Option<Frozen>,
+}
+
+impl KeyMap {
+    pub fn new() -> KeyMap {
+        KeyMap::default()
+    }
+
+    /// Empty map with room for keys of bytes in total
+    pub fn with_capacity(keys: usize, bytes: usize) -> KeyMap {
+        KeyMap {
+            arena: String::with_capacity(bytes),
+            ends: Vec::with_capacity(keys),
+            ids: Vec::with_capacity(keys),
+            table: HashTable::with_capacity(keys),
+            hasher: RandomState::new(),
+            frozen: None,
+        }
+    }
+
+    /// Frozen map written by write_frozen at offset of bytes, looked up in place
+    pub fn from_bytes(bytes: SharedBytes, offset: usize) -> Result<KeyMap, String> {
+        Ok(KeyMap { frozen: Some(Frozen::new(bytes, offset)?), ..KeyMap::default() })
+    }
+
+    /// Write the keys and ids, with a table to find them, in the layout read by from_bytes
+    pub fn write_frozen(&self, writer: &mut impl Write) -> io::Result<()> {
+        let slots = (self.len() * 2).max(1).next_power_of_two();
+        let mut table = vec![EMPTY_SLOT; slots];
+        let mut ids = Vec::with_capacity(self.len());
+        let mut others = Vec::new();
+        let mut arena_len = 0;
+        for (handle, (key, id)) in self.iter().enumerate() {
+            let mut slot = stable_hash(key) as usize & (slots - 1);
+            while table[slot] != EMPTY_SLOT {
+                slot = (slot + 1) & (slots - 1);
+            }
+            table[slot] = handle as u32;
+            ids.push(match &*id {
+                Id::Cid(cid) => *cid as u64,
+                Id::Other(other) => {
+                    let offset = others.len() as u64;
+                    others.extend_from_slice(&(other.len() as u32).to_le_bytes());
+                    others.extend_from_slice(other.as_bytes());
+                    OTHER_ID | offset
+                }
+            });
+            arena_len += key.len();
+        }
+        for count in [self.len(), arena_len, others.len(), slots] {
+            writer.write_all(&(count as u64).to_le_bytes())?;
+        }
+        let mut end = 0;
+        for key in self.keys() {
+            end += key.len();
+            writer.write_all(&(end as u64).to_le_bytes())?;
+        }
+        for id in ids {
+            writer.write_all(&id.to_le_bytes())?;
+        }
+        for handle in table {
+            writer.write_all(&handle.to_le_bytes())?;
+        }
+        for key in self.keys() {
+            writer.write_all(key.as_bytes())?;
+        }
+        writer.write_all(&others)
+    }
+
+    pub fn len(&self) -> usize {
+        self.frozen.as_ref().map_or(self.ends.len(), |frozen| frozen.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Handle of key, if it is one
+    pub fn handle(&self, key: &str) -> Option<u32> {
+        if let Some(frozen) = &self.frozen {
+            return frozen.handle(key);
+        }
+        self.table.find(self.hasher.hash_one(key), |&handle| key_at(&self.arena, &self.ends, handle) == key).copied()
+    }
+
+    /// Key of a handle of this map
+    pub fn key(&self, handle: u32) -> &str {
+        match &self.frozen {
+            Some(frozen) => frozen.key(handle),
+            None => key_at(&self.arena, &self.ends, handle),
+        }
+    }
+
+    /// Id of a handle of this map, decoded from a frozen map
+    pub fn id(&self, handle: u32) -> Cow<'_, Id> {
+        match &self.frozen {
+            Some(frozen) => Cow::Owned(frozen.id(handle)),
+            None => Cow::Borrowed(&self.ids[handle as usize]),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Cow<'_, Id>> {
+        self.handle(key).map(|handle| self.id(handle))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.handle(key).is_some()
+    }
+
+    /// Map key to id, returning the id it had; a frozen map is copied into memory first. Panics
+    /// past u32::MAX keys.
+    pub fn insert(&mut self, key: &str, id: Id) -> Option<Id> {
+        if self.frozen.is_some() {
+            let thawed: KeyMap = self.iter().map(|(key, id)| (key.to_string(), id.into_owned())).collect();
+            *self = thawed;
+        }
+        let KeyMap { arena, ends, ids, table, hasher, .. } = self;
+        let hash = hasher.hash_one(key);
+        match table.entry(hash, |&handle| key_at(arena, ends, handle) == key, |&handle| hasher.hash_one(key_at(arena, ends, handle))) {
+            Entry::Occupied(entry) => Some(std::mem::replace(&mut ids[*entry.get() as usize], id)),
+            Entry::Vacant(entry) => {
+                let handle = u32::try_from(ends.len()).expect("more dictionary keys than u32 handles");
+                arena.push_str(key);
+                ends.push(arena.len());
+                ids.push(id);
+                entry.insert(handle);
+                None
+            }
+        }
+    }
+
+    /// Keys and their ids, by handle
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { map: self, handle: 0 }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        (0..self.len() as u32).map(|handle| self.key(handle))
+    }
+
+    /// Release the room reserved beyond the keys held
+    pub fn shrink_to_fit(&mut self) {
+        let KeyMap { arena, ends, ids, table, hasher, .. } = self;
+        table.shrink_to_fit(|&handle| hasher.hash_one(key_at(arena, ends, handle)));
+        arena.shrink_to_fit();
+        ends.shrink_to_fit();
+        ids.shrink_to_fit();
+    }
+
+    /// Bytes allocated for the keys, ids and index
+    pub fn heap_size(&self) -> usize {
+        let others: usize = self.ids.iter().map(|id| if let Id::Other(id) = id { id.len() } else { 0 }).sum();
+        self.arena.capacity()
+            + self.ends.capacity() * std::mem::size_of::<usize>()
+            + self.ids.capacity() * std::mem::size_of::<Id>()
+            + others
+            // a u32 and a control byte per bucket
+            + self.table.capacity() * (std::mem::size_of::<u32>() + 1)
+    }
+
+    /// Bytes of a frozen map looked up in place, 0 for one in memory
+    pub fn frozen_size(&self) -> usize {
+        self.frozen.as_ref().map_or(0, |frozen| frozen.end - frozen.ends + FROZEN_COUNTS * 8)
+    }
+}
+
+/// Keys of a KeyMap and their ids, by handle
+pub struct Iter<'a> {
+    map: &'a KeyMap,
+    handle: u32,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, Cow<'a, Id>);
+
+    fn next(&mut self) -> Option<(&'a str, Cow<'a, Id>)> {
+        if self.handle as usize == self.map.len() {
+            return None;
+        }
+        self.handle += 1;
+        Some((self.map.key(self.handle - 1), self.map.id(self.handle - 1)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.map.len() - self.handle as usize;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'a> IntoIterator for &'a KeyMap {
+    type Item = (&'a str, Cow<'a, Id>);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl From<HashMap<String, Id>> for KeyMap {
+    /// Intern the keys of map, dropping its strings as they are copied
+    fn from(map: HashMap<String, Id>) -> KeyMap {
+        let bytes = map.keys().map(String::len).sum();
+        let mut keys = KeyMap::with_capacity(map.len(), bytes);
+        for (key, id) in map {
+            keys.insert(&key, id);
+        }
+        keys
+    }
+}
+
+impl FromIterator<(String, Id)> for KeyMap {
+    fn from_iter<I: IntoIterator<Item = (String, Id)>>(entries: I) -> KeyMap {
+        let mut keys = KeyMap::new();
+        for (key, id) in entries {
+            keys.insert(&key, id);
+        }
+        keys.shrink_to_fit();
+        keys
+    }
+}
+
+impl std::fmt::Debug for KeyMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_key_map() {
+        let mut keys = KeyMap::new();
+        assert_eq!(keys.insert("Aspirin", Id::Cid(2244)), None);
+        assert_eq!(keys.insert("Water", Id::Other("CHEBI:15377".into())), None);
+        assert_eq!(keys.insert("Aspirin", Id::Cid(1)), Some(Id::Cid(2244)));
+        assert_eq!(keys.len(), 2);
+        assert_eq!((keys.get("Aspirin").as_deref(), keys.get("Water").and_then(|id| id.cid())), (Some(&Id::Cid(1)), None));
+        assert!(!keys.contains_key("Aspiri") && !keys.contains_key("AspirinWater"));
+        let water = keys.handle("Water").unwrap();
+        assert_eq!((water, keys.key(water)), (1, "Water"));
+        assert_eq!(keys.keys().collect::<Vec<&str>>(), vec!["Aspirin", "Water"]);
+
+        let map: HashMap<String, Id> = (0..1000).map(|cid| (format!("Compound {}", cid), Id::Cid(cid))).collect();
+        let mut keys = KeyMap::from(map.clone());
+        keys.shrink_to_fit();
+        assert_eq!(keys.len(), map.len());
+        assert!(map.iter().all(|(key, id)| keys.get(key).as_deref() == Some(id)));
+        assert_eq!(keys.iter().map(|(key, id)| (key.to_string(), id.into_owned())).collect::<HashMap<String, Id>>(), map);
+        // smaller than the table of the HashMap alone, before its strings
+        assert!(keys.heap_size() < map.capacity() * std::mem::size_of::<(String, Id)>());
+    }
+
+    #[test]
+    fn test_frozen_key_map() {
+        let mut keys: KeyMap = (0..1000).map(|cid| (format!("Compound {}", cid), Id::Cid(cid))).collect();
+        keys.insert("Water", Id::Other("CHEBI:15377".into()));
+        keys.insert("", Id::Other(String::new().into()));
+        let mut bytes = b"header".to_vec();
+        keys.write_frozen(&mut bytes).unwrap();
+        let frozen = KeyMap::from_bytes(Arc::new(bytes.clone()), 6).unwrap();
+        assert_eq!((frozen.len(), frozen.heap_size(), frozen.frozen_size()), (keys.len(), 0, bytes.len() - 6));
+        assert!(keys.iter().all(|(key, id)| frozen.get(key) == Some(id)));
+        assert_eq!(frozen.keys().collect::<Vec<&str>>(), keys.keys().collect::<Vec<&str>>());
+        assert_eq!((frozen.handle("Water"), frozen.get("Compound 1000")), (Some(1000), None));
+
+        let mut thawed = frozen.clone();
+        assert_eq!(thawed.insert("Water", Id::Cid(962)), Some(Id::Other("CHEBI:15377".into())));
+        assert_eq!((thawed.frozen_size(), thawed.get("Compound 7").as_deref()), (0, Some(&Id::Cid(7))));
+        assert_eq!(frozen.get("Water").as_deref(), Some(&Id::Other("CHEBI:15377".into())));
+
+        assert!(KeyMap::from_bytes(Arc::new(bytes[..bytes.len() - 1].to_vec()), 6).is_err());
+        assert!(KeyMap::from_bytes(Arc::new(b"header".to_vec()), 6).is_err());
+        let corrupt = |at: usize, patch: &[u8]| {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + patch.len()].copy_from_slice(patch);
+            KeyMap::from_bytes(Arc::new(corrupt), 6).err().unwrap()
+        };
+        let (ids, table) = (6 + 32 + keys.len() * 8, 6 + 32 + keys.len() * 16);
+        let arena = table + keys.len().next_power_of_two() * 2 * 4;
+        assert!(corrupt(6, &u64::MAX.to_le_bytes()).contains("section lengths"));
+        assert!(corrupt(6 + 32, &u64::MAX.to_le_bytes()).contains("key ends"));
+        assert!(corrupt(ids + 1000 * 8, &(OTHER_ID | 1 << 40).to_le_bytes()).contains("other id"));
+        assert!(corrupt(arena, &[0xff]).contains("not UTF-8"));
+        let empty_slot = (table..arena).step_by(4).find(|&at| bytes[at..at + 4] == EMPTY_SLOT.to_le_bytes()).unwrap();
+        assert!(corrupt(empty_slot, &5000_u32.to_le_bytes()).contains("table handle"));
+
+        let tmp_dir = TempDir::new("keymap").unwrap();
+        let path = tmp_dir.path().join("keys.bin");
+        std::fs::write(&path, &bytes).unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_len(bytes.len() as u64 / 2).unwrap();
+        assert!(KeyMap::from_bytes(Arc::new(std::fs::read(&path).unwrap()), 6).unwrap_err().contains("section lengths"));
+        let mut empty = Vec::new();
+        KeyMap::new().write_frozen(&mut empty).unwrap();
+        assert_eq!(KeyMap::from_bytes(Arc::new(empty), 0).unwrap().get("Water"), None);
+    }
+}
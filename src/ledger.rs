@@ -0,0 +1,143 @@
+//! Ledger of processed input files, so repeated runs skip inputs that already finished.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Size and content hash identifying a version of an input file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileId {
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl FileId {
+    /// Read path to identify it (FNV-1a of its bytes)
+    pub fn of(path: &Path) -> io::Result<FileId> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut buffer = [0; 64 * 1024];
+        let (mut size, mut hash) = (0_u64, 0xcbf29ce484222325_u64);
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            for byte in &buffer[..read] {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+            size += read as u64;
+        }
+        Ok(FileId { size, hash })
+    }
+}
+
+/// Inputs recorded in a ledger file, one `path<TAB>size<TAB>hash<TAB>status` line per event,
+/// optionally followed by `<TAB>bytes` of merged output once the input's rows were written;
+/// the last line for a path wins
+pub struct Ledger {
+    path: PathBuf,
+    entries: HashMap<String, (FileId, String)>,
+    output_len: Option<u64>,
+}
+
+impl Ledger {
+    /// Load the ledger at path; a missing file is an empty ledger
+    pub fn open(path: &Path) -> Result<Ledger, Box<dyn Error>> {
+        let (mut entries, mut output_len) = (HashMap::new(), None);
+        if path.exists() {
+            for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let (input, size, hash, status) = match fields[..] {
+                    [input, size, hash, status] => (input, size, hash, status),
+                    [input, size, hash, status, len] => {
+                        output_len = Some(len.parse()?);
+                        (input, size, hash, status)
+                    }
+                    _ => return Err(format!("{}:{}: malformed ledger line", path.display(), i + 1).into()),
+                };
+                let id = FileId { size: size.parse()?, hash: u64::from_str_radix(hash, 16)? };
+                entries.insert(input.to_string(), (id, status.to_string()));
+            }
+        }
+        Ok(Ledger { path: path.to_path_buf(), entries, output_len })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether input finished in an earlier run with the same content
+    pub fn is_complete(&self, input: &str, id: &FileId) -> bool {
+        self.entries.get(input).is_some_and(|(recorded, status)| recorded == id && status == "complete")
+    }
+
+    /// Bytes of merged output recorded last, past which an interrupted run left rows of an input
+    /// it had not finished
+    pub fn output_len(&self) -> Option<u64> {
+        self.output_len
+    }
+
+    /// Append a status such as "complete" for input to the ledger file
+    pub fn record(&mut self, input: &str, id: &FileId, status: &str) -> Result<(), Box<dyn Error>> {
+        self.append(input, id, status, None)
+    }
+
+    /// Record a status for input along with the bytes of merged output written so far
+    pub fn record_at(&mut self, input: &str, id: &FileId, status: &str, output_len: u64) -> Result<(), Box<dyn Error>> {
+        self.append(input, id, status, Some(output_len))
+    }
+
+    fn append(&mut self, input: &str, id: &FileId, status: &str, output_len: Option<u64>) -> Result<(), Box<dyn Error>> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        match output_len {
+            Some(len) => writeln!(file, "{}\t{}\t{:016x}\t{}\t{}", input, id.size, id.hash, status, len)?,
+            None => writeln!(file, "{}\t{}\t{:016x}\t{}", input, id.size, id.hash, status)?,
+        }
+        file.sync_data()?;
+        self.entries.insert(input.to_string(), (*id, status.to_string()));
+        self.output_len = output_len.or(self.output_len);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_ledger() {
+        let tmp_dir = TempDir::new("ledger").unwrap();
+        let input = tmp_dir.path().join("a.txt");
+        fs::write(&input, "Aspirin").unwrap();
+        let id = FileId::of(&input).unwrap();
+        assert_eq!(id.size, 7);
+
+        let ledger_path = tmp_dir.path().join("out.ledger");
+        let mut ledger = Ledger::open(&ledger_path).unwrap();
+        assert!(ledger.is_empty());
+        ledger.record("a.txt", &id, "complete").unwrap();
+        assert!(ledger.is_complete("a.txt", &id));
+
+        let ledger = Ledger::open(&ledger_path).unwrap();
+        assert!(ledger.is_complete("a.txt", &id));
+        assert!(!ledger.is_complete("b.txt", &id));
+        fs::write(&input, "Caffeine").unwrap();
+        assert!(!ledger.is_complete("a.txt", &FileId::of(&input).unwrap()));
+        assert_eq!(ledger.output_len(), None);
+
+        let mut ledger = Ledger::open(&ledger_path).unwrap();
+        ledger.record_at("b.txt", &id, "complete", 120).unwrap();
+        ledger.record("c.txt", &id, "complete").unwrap();
+        assert_eq!(ledger.output_len(), Some(120));
+        let ledger = Ledger::open(&ledger_path).unwrap();
+        assert!(ledger.is_complete("b.txt", &id) && ledger.is_complete("c.txt", &id));
+        assert_eq!(ledger.output_len(), Some(120));
+
+        fs::write(&ledger_path, "a.txt\t7\n").unwrap();
+        assert!(Ledger::open(&ledger_path).is_err());
+    }
+}
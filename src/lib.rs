@@ -0,0 +1,62 @@
+//! Find chemicals in text: synonyms from a PubChem `CID<TAB>synonym` dictionary, plus CAS
+//! numbers, InChIs and molecular formulas.
+//!
+//! ```no_run
+//! use std::collections::HashSet;
+//! use chem_matcher::{Dictionary, Matcher, ParseOptions, SearchOptions};
+//!
+//! let dictionary = Dictionary::from_csv("CID-Synonym-filtered", &HashSet::new(), &ParseOptions::default())?;
+//! let matcher = Matcher::new(dictionary.map, SearchOptions::default());
+//! for found in matcher.search("Aspirin is also called acetylsalicylic acid.") {
+//!     println!("{} {:?} {}", found.key, found.cid, found.context);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod bloom;
+pub mod brat;
+pub mod capi;
+pub mod conll;
+pub mod convert;
+pub mod cooccurrence;
+pub mod cord19;
+#[cfg(feature = "parquet")]
+pub mod dataset;
+pub mod dedupe;
+pub mod dictionary;
+pub mod eval;
+pub mod html;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod index;
+#[cfg(feature = "cli")]
+pub mod io;
+pub mod ipc;
+pub mod keymap;
+pub mod ledger;
+pub mod matcher;
+pub mod mesh;
+pub mod mmap;
+#[cfg(feature = "cli")]
+pub mod metrics;
+pub mod opsin;
+pub mod pdf;
+#[cfg(feature = "cli")]
+pub mod pool;
+#[cfg(feature = "cli")]
+pub mod pubchem;
+pub mod report;
+#[cfg(feature = "cli")]
+pub mod server;
+pub mod table;
+pub mod tex;
+pub mod text;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod xml;
+pub mod xref;
+
+pub use dictionary::{Dictionary, Id, ParseOptions};
+pub use keymap::KeyMap;
+pub use matcher::{IdType, Match, MatchType, Matcher, MatcherBuilder, SearchOptions};
+pub use text::CaseMode;
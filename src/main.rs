@@ -1,377 +1,2045 @@
 use std::sync::Arc;
-use std::fs::{self, File, read_to_string};
-use std::io::{BufRead, BufReader, BufWriter};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal};
 use std::error::Error;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::net::SocketAddr;
 use structopt::StructOpt;
-use indicatif::{ProgressBar, ProgressStyle};
-use reqwest;
-use std::collections::{HashSet, HashMap};
-use rust_stemmers::{Algorithm, Stemmer};
-use tokio;
-use flume;
+use encoding_rs::Encoding;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet, HashMap};
+use std::collections::hash_map::Entry;
 use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use serde_json::Value;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::io::prelude::*;
-use regex;
-use tempdir::TempDir;
 use std::process;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use tracing::{debug, error, info, info_span, trace, warn, Level};
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriter};
+use chem_matcher::dictionary::{
+    build_stoplist, is_compiled_dict, map_compiled_dict, merge_dictionary, parse_cas_map, parse_csv, read_compiled_dict, read_list,
+    validate_csv, write_compiled_dict, write_conflicts, Conflicts, DictColumns, DictHeader, DictStats, Id, MappedDictionary, ParseOptions,
+    ParsedDictionary, Precedence, Resolution,
+};
+use chem_matcher::brat::{read_brat_dir, write_brat};
+use chem_matcher::conll::write_conll;
+use chem_matcher::convert::{convert_results, ResultFormat};
+use chem_matcher::cooccurrence::{Cooccurrences, GraphFormat};
+use chem_matcher::cord19::cord19_document;
+use chem_matcher::dedupe::dedupe_results;
+use chem_matcher::eval::{predicted_mentions, read_gold_tsv, read_texts_jsonl, Evaluation};
+use chem_matcher::html::html_text;
+use chem_matcher::index::CidIndex;
+use chem_matcher::ipc::read_arrow;
+use chem_matcher::ledger::{FileId, Ledger};
+use chem_matcher::io::{cache_dir, decode_input, load_banned_lists, sample_documents, CountingReader, FetchOptions, BANNED};
+use chem_matcher::matcher::{find_unknown_names, Match, Matcher, MatcherBuilder, SearchOptions, MASK, SALT_SUFFIXES};
+use chem_matcher::metrics::{Metrics, WorkerMetrics};
+use chem_matcher::opsin::{opsin_structures, OPSIN};
+use chem_matcher::pdf::read_pdf;
+use chem_matcher::pool;
+use chem_matcher::pubchem::{enrich_results, PubChem, PUG_REST};
+use chem_matcher::server::{serve, serve_metrics};
+use chem_matcher::report::{merge_results, ner_json, sample_contexts, sample_results, Aggregate, OutputFormat, write_candidates, InputStats, PartitionBy, PartitionWriter, ResultStats, RotatingWriter, RunSummary};
+use chem_matcher::table::read_table;
+use chem_matcher::tex::strip_latex;
+use chem_matcher::text::{normalize, CaseMode, StemmerWrapper};
+use chem_matcher::xml::{read_jats, read_pubmed, read_tei, read_uspto, root_element};
+use chem_matcher::xref::Xrefs;
 
-const WORD_SPLITS: &[char] = &[' ', '\t', '\n', '\r', ',', '.', ';', ':', '!', '?', '(', ')', '[', ']', '{', '}', '<', '>', '"', '\''];
-const MIN_WORD_LENGTH: usize = 5;
-const BANNED: &str = "https://raw.githubusercontent.com/first20hours/google-10000-english/master/20k.txt";
-const MASK: &str = "<|MOLECULE|>";
-
-type SearchResults = Vec<(String, String, u32)>;
+#[derive(StructOpt, Serialize, Debug)]
+enum Command {
+    /// Parse and filter the --csv dictionaries once into a binary dictionary that --csv can load
+    CompileDict {
+        /// Where to write the compiled dictionary
+        output: String,
+    },
+    /// Check the --csv dictionaries for problems, writing one JSON issue per line
+    ValidateDict {
+        /// Where to write the report (stdout when omitted)
+        report: Option<String>,
+    },
+    /// Print statistics of the --csv dictionaries after filtering
+    DictStats,
+    /// Print matches per CID, synonym and paper, and context lengths, of result files
+    Stats {
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Number of CIDs, synonyms and papers listed
+        #[structopt(long = "top", default_value = "20")]
+        top: usize,
+        /// Instead of the statistics, list the --top CIDs with their synonyms and up to this many
+        /// sampled contexts each
+        #[structopt(long = "examples")]
+        examples: Option<usize>,
+    },
+    /// Write result files as a Hugging Face datasets directory of train, validation and test parquet
+    /// splits with a dataset_infos.json (needs the parquet feature)
+    ExportDataset {
+        /// Directory to write the dataset to
+        output: String,
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Shares of the papers in the train, validation and test splits
+        #[structopt(long = "splits", default_value = "0.8,0.1,0.1")]
+        splits: String,
+        /// Seed of the assignment of papers to splits
+        #[structopt(long = "seed", default_value = "0")]
+        seed: u64,
+    },
+    /// Convert result files between csv, jsonl and parquet (needs the parquet feature), or into the
+    /// ner-json and conll training formats, without searching again
+    Convert {
+        /// Where to write the converted results
+        output: String,
+        /// Result files, e.g. written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Format of the result files, otherwise taken from their extensions (.jsonl, .parquet, else csv)
+        #[structopt(long = "from", possible_values = &["csv", "jsonl", "parquet"])]
+        from: Option<ResultFormat>,
+        /// Format written, otherwise taken from the extension of output
+        #[structopt(long = "to", possible_values = &["csv", "jsonl", "parquet", "ner-json", "conll"])]
+        to: Option<ResultFormat>,
+    },
+    /// Search the texts of gold-standard annotations with the --csv dictionaries and print
+    /// precision, recall and F1 at mention and document level
+    Eval {
+        /// Gold mentions as doc_id<TAB>start<TAB>end<TAB>cid lines, with character offsets and an
+        /// empty or - CID when unknown, or a BRAT directory of <id>.txt and <id>.ann files
+        gold: String,
+        /// Annotated documents as JSON lines with id and text fields (for a gold TSV file)
+        texts: Option<String>,
+        /// Only score BRAT annotations of this entity type, e.g. Chemical
+        #[structopt(long = "entity-type")]
+        entity_type: Option<String>,
+    },
+    /// Merge result files, e.g. of sharded runs, dropping rows with the same corpusid, CID and
+    /// context, and write a summary of the merge to <output>.summary.json
+    Merge {
+        /// Where to write the merged results
+        output: String,
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+    },
+    /// Write result files without rows repeating the CID and context of an earlier row, keeping the
+    /// row hashes on disk so files larger than memory can be deduplicated
+    Dedupe {
+        /// Where to write the deduplicated results
+        output: String,
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Also drop near duplicates: contexts differing only in case, digits, punctuation or spacing
+        #[structopt(long = "near")]
+        near: bool,
+        /// Number of bucket files the row hashes are spread over; memory holds one at a time
+        #[structopt(long = "buckets", default_value = "64")]
+        buckets: usize,
+    },
+    /// Write result files with the canonical SMILES, molecular formula and IUPAC name of each row's
+    /// CID as three more columns, looked up once per CID in PubChem and cached in
+    /// <cache dir>/pubchem, followed by its ChEBI, MeSH and DrugBank ids with --xrefs
+    Enrich {
+        /// Where to write the enriched results
+        output: String,
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Most PubChem requests per second, each for up to 100 CIDs
+        #[structopt(long = "pubchem-rate", default_value = "5")]
+        pubchem_rate: f64,
+        /// Base URL of PUG-REST, e.g. of a mirror
+        #[structopt(long = "pubchem-url", default_value = PUG_REST)]
+        pubchem_url: String,
+        /// Always ask PubChem instead of reading and writing the cache
+        #[structopt(long = "no-cache")]
+        no_cache: bool,
+        /// Tab-separated mapping of CIDs to ChEBI, MeSH and DrugBank ids, with a header naming its
+        /// cid, chebi, mesh and drugbank columns and several ids of a cell separated by |
+        #[structopt(long = "xrefs")]
+        xrefs: Option<String>,
+        /// Leave out the PubChem columns, e.g. to only add --xrefs offline
+        #[structopt(long = "no-pubchem", requires = "xrefs")]
+        no_pubchem: bool,
+    },
+    /// Print rows of result files picked at random for review, reading each file once
+    Sample {
+        /// Result files written by a search
+        #[structopt(required = true)]
+        results: Vec<String>,
+        /// Number of rows picked
+        #[structopt(short = "k", long = "rows", default_value = "100")]
+        rows: usize,
+        /// Pick --rows rows of every CID instead
+        #[structopt(long = "per-cid")]
+        per_cid: bool,
+        /// Seed of the random picks
+        #[structopt(long = "seed", default_value = "0")]
+        seed: u64,
+    },
+    /// Write a banned list of words found in many documents of the --files sample
+    BuildStoplist {
+        /// Where to write the banned list
+        output: String,
+        /// Minimum fraction of documents a word must appear in
+        #[structopt(long = "min-df", default_value = "0.05")]
+        min_df: f64,
+        /// Number of documents to sample
+        #[structopt(long = "sample", default_value = "10000")]
+        sample: usize,
+    },
+    /// Load the --csv dictionaries once and answer POST /match requests over HTTP
+    Serve {
+        /// Address to listen on
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        address: SocketAddr,
+        /// Also serve the gRPC MatchDocuments stream on this address (needs the grpc feature)
+        #[structopt(long = "grpc-address")]
+        grpc_address: Option<SocketAddr>,
+    },
+}
 
-#[derive(StructOpt, Debug)]
+// Options are named after their long flag, which --config files and environment variables set
+// them by
+#[derive(StructOpt, Serialize, Debug)]
 #[structopt(name = "key-search")]
 struct Opt {
-    ///CSV file containing the JSON key-value pairs
-    #[structopt(short = "c", long = "csv")]
-    csv_file: String,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file of options by their long flag, e.g. csv = ["dict.csv"], output = "out.csv" and
+    /// no-banned = true; options given on the command line, including every value of a repeated
+    /// one, take the place of the file's. Environment variables named after the long flag, e.g.
+    /// CHEM_MATCHER_OUTPUT=out.csv, CHEM_MATCHER_NO_BANNED=true or CHEM_MATCHER_CSV='["a.csv",
+    /// "b.csv"]', set options too, in place of the file's and in turn replaced by the command line.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// CSV file containing the JSON key-value pairs, a DrugBank vocabulary CSV, name,id CSV or MeSH
+    /// ASCII or XML records (see --id-map), or a compiled dictionary (repeat to merge several dictionaries)
+    #[structopt(short = "c", long = "csv", name = "csv", number_of_values = 1)]
+    csv_files: Vec<String>,
+
+    /// Which dictionary wins when repeated --csv files map a key to different CIDs: first or last
+    #[structopt(long = "csv-precedence", default_value = "first", possible_values = &["first", "last"])]
+    csv_precedence: Precedence,
+
+    /// Look keys up in place in a memory map of a single compiled --csv dictionary instead of
+    /// loading it, so worker processes on one machine share it in the page cache. Tokens are then
+    /// looked up without the in-memory filter of the keys' first words, which each process would
+    /// otherwise build at about 10 bits per key.
+    #[structopt(long = "mmap-dict")]
+    mmap_dict: bool,
 
-    /// Files (text or gzipped JSON) to search for keys
+    /// Columns of tab-separated --csv dictionaries in order: id, name, or - for a column left out,
+    /// e.g. name,id or id,-,name
+    #[structopt(long = "csv-columns", default_value = "id,name")]
+    csv_columns: DictColumns,
+
+    /// Skip the first row of tab-separated --csv dictionaries, a header of column names
+    #[structopt(long = "csv-has-header")]
+    csv_has_header: bool,
+
+    /// Files to search for keys: text, gzipped JSON lines, tables (*.csv, *.tsv) or Arrow IPC streams
+    /// and files (*.arrow, *.arrows, *.feather; needs the arrow feature) with --text-column, CORD-19
+    /// document parses (*.json), gzipped PubMed baseline XML (*.xml.gz), GROBID TEI XML (*.tei.xml),
+    /// PMC JATS or USPTO patent XML (*.xml, *.nxml), HTML (*.html, *.htm), LaTeX (*.tex) or PDFs
+    /// (needs the pdf feature)
     #[structopt(short = "f", long = "files", parse(from_os_str))]
     files: Vec<std::path::PathBuf>,
 
-    //Output file to write results
-    #[structopt(short = "o", long = "output")]
-    output_file: String,
+    //Output file to write results (required unless running a subcommand)
+    #[structopt(short = "o", long = "output", name = "output")]
+    output_file: Option<String>,
 
     //context_window_prop_name
     #[structopt(short = "p", long = "property", default_value = "text")]
     property: String,
 
+    /// Column of the texts of .csv, .tsv and Arrow inputs, named in their header or schema
+    #[structopt(long = "text-column", default_value = "text")]
+    text_column: String,
+
+    /// Column of the document ids of .csv, .tsv and Arrow inputs; rows are numbered from 1 without it
+    #[structopt(long = "id-column")]
+    id_column: Option<String>,
+
     //when to stop (number of lines)
     #[structopt(short = "s", long = "stop", default_value = "0")]
     stop: usize,
 
+    /// Threads matching the documents of each JSON lines .gz input, besides one decompressing it
+    /// and one parsing its JSON per four of them, so a huge shard keeps several cores busy; output
+    /// rows stay in input order
+    #[structopt(long = "file-threads", default_value = "1")]
+    file_threads: usize,
+
+    /// Merge the matches of each input in --files order rather than as inputs finish, so identical
+    /// runs give identical output; rows of inputs searched ahead wait on disk, not in memory
+    #[structopt(long = "ordered")]
+    ordered: bool,
+
+    /// Write the merged output to <output>.part-0001, <output>.part-0002, ... of at most this many rows,
+    /// listed with their row counts in <output>.manifest
+    #[structopt(long = "rotate-rows")]
+    rotate_rows: Option<u64>,
+
+    /// Like --rotate-rows, starting a new part before one would exceed this many bytes
+    #[structopt(long = "rotate-bytes")]
+    rotate_bytes: Option<u64>,
+
+    /// Make --output a directory of cid-<cid>.csv files, one per CID, with rows without a CID in
+    /// no-cid.csv, so each molecule's rows can be processed without sorting the whole output
+    #[structopt(
+        long = "partition-by",
+        possible_values = &["cid"],
+        conflicts_with_all = &["rotate-rows", "rotate-bytes", "per-file-output", "no-merge", "watch"]
+    )]
+    partition_by: Option<PartitionBy>,
+
+    /// Spread the CIDs of --partition-by over this many bucket-<n>.csv files by their hash instead
+    #[structopt(long = "partition-buckets", requires = "partition-by")]
+    partition_buckets: Option<u64>,
+
+    /// Most partition files kept open at once; the least recently written is closed to open another
+    #[structopt(long = "max-open-files", default_value = "256")]
+    max_open_files: usize,
+
+    /// Log documents, paragraphs and matches per second every this many seconds (0 never); shown with -v,
+    /// and per worker with -vv
+    #[structopt(long = "metrics-interval", default_value = "60")]
+    metrics_interval: u64,
+
+    /// Serve GET /metrics in the Prometheus text format on this address while searching or watching
+    /// (serve also answers it on --address)
+    #[structopt(long = "metrics-address")]
+    metrics_address: Option<SocketAddr>,
+
+    /// Where to write the JSON summary of the run: inputs, records read and skipped, matches,
+    /// unique CIDs, wall time and options (default <output>.summary.json)
+    #[structopt(long = "summary", name = "summary")]
+    summary_file: Option<String>,
+
+    /// Write the matches of each input to <dir>/<input name>.csv (e.g. shard-0001.json.gz to
+    /// shard-0001.csv) instead of merging them into --output; the ledger goes to <dir>/chem-matcher.ledger
+    #[structopt(long = "per-file-output", parse(from_os_str))]
+    per_file_output: Option<PathBuf>,
+
+    /// Keep the matches of each input in its own <output>_<index> file instead of merging them
+    #[structopt(long = "no-merge")]
+    no_merge: bool,
+
+    /// Process inputs again even when <output>.ledger records them as finished. Without it, inputs
+    /// already processed with the same content are skipped and new results are appended to --output;
+    /// --candidates, --cooccurrence, --index and --tfidf count every input, so they require it then.
+    #[structopt(long = "force")]
+    force: bool,
+
+    /// Process only every --shard-count-th file of --files, starting at this index (e.g. a SLURM
+    /// array task id). Output and candidate files get a .shard<index> suffix before their extension.
+    #[structopt(long = "shard-index", requires = "shard-count")]
+    shard_index: Option<usize>,
+
+    /// Number of shards the --files are split into
+    #[structopt(long = "shard-count", requires = "shard-index")]
+    shard_count: Option<usize>,
+
+    /// Keep polling this directory for new .json.gz (or PubMed .xml.gz) shards, appending each one's matches to --output
+    /// once it stops growing. Shards recorded in <output>.ledger are skipped on restart.
+    #[structopt(long = "watch", parse(from_os_str))]
+    watch: Option<PathBuf>,
+
+    /// Seconds between polls of the --watch directory
+    #[structopt(long = "watch-interval", default_value = "10")]
+    watch_interval: u64,
+
+    /// How to resolve a key listed with several CIDs: first, last, lowest-cid or drop-ambiguous
+    #[structopt(long = "conflict-resolution", default_value = "last", possible_values = &["first", "last", "lowest-cid", "drop-ambiguous"])]
+    conflict_resolution: Resolution,
+
+    /// Write keys listed with several CIDs, and those CIDs, to this file
+    #[structopt(long = "conflicts", name = "conflicts")]
+    conflicts_file: Option<String>,
+
+    /// URL of a list of common words dropped from the dictionary (repeat to union several; defaults to a 20k English list)
+    #[structopt(long = "banned-url", name = "banned-url", number_of_values = 1)]
+    banned_urls: Vec<String>,
+
+    /// Local list of words dropped from the dictionary (repeat to union several); without --banned-url nothing is downloaded
+    #[structopt(long = "banned-file", name = "banned-file", number_of_values = 1)]
+    banned_files: Vec<String>,
+
+    /// Hours a downloaded banned list is reused from the cache directory (0 always downloads)
+    #[structopt(long = "banned-ttl", default_value = "168")]
+    banned_ttl: u64,
+
+    /// Attempts at each download before giving up, with exponential backoff between them
+    #[structopt(long = "max-attempts", default_value = "4")]
+    max_attempts: u32,
+
+    /// Proxy URL for downloads (HTTP_PROXY and HTTPS_PROXY are used otherwise)
+    #[structopt(long = "proxy")]
+    proxy: Option<String>,
+
+    /// PEM file of extra CA certificates trusted for downloads
+    #[structopt(long = "ca-bundle")]
+    ca_bundle: Option<String>,
+
+    /// Keep common English words in the dictionary
+    #[structopt(long = "no-banned", conflicts_with_all = &["banned-file", "banned-url"])]
+    no_banned: bool,
+
+    /// File of synonyms (one per line, any case) to drop from the dictionary
+    #[structopt(long = "ban-synonyms")]
+    ban_synonyms: Option<String>,
+
+    /// File of CIDs or other ids (one per line) whose synonyms are dropped from the dictionary
+    #[structopt(long = "ban-cids")]
+    ban_cids: Option<String>,
+
+    /// File of CIDs or other ids (one per line); only their synonyms are kept in the dictionary
+    #[structopt(long = "only-cids")]
+    only_cids: Option<String>,
+
+    /// Minimum length of dictionary keys, in bytes
+    #[structopt(long = "min-length", default_value = "5")]
+    min_length: usize,
+
+    /// File of short names (one per line, any case, e.g. urea, THC) kept regardless of --min-length
+    #[structopt(long = "short-names")]
+    short_names: Option<String>,
+
+    /// Number of tokens to keep on each side of a match (0 keeps the whole paragraph)
+    #[structopt(long = "context-window", default_value = "0")]
+    context_window: usize,
+
+    /// Regex used to split documents into paragraphs
+    #[structopt(long = "paragraph-delimiter", default_value = r"\n\n")]
+    paragraph_delimiter: String,
+
+    /// Text replacing matched names in the context
+    #[structopt(long, default_value = MASK)]
+    mask: String,
+
+    /// Emit every overlapping match instead of keeping only the longest
+    #[structopt(long = "all-overlaps")]
+    all_overlaps: bool,
+
+    /// Skip Unicode NFKC normalization of text and dictionary keys
+    #[structopt(long = "no-nfkc")]
+    no_nfkc: bool,
+
+    /// Case matching: title (first letter only), exact, fold, or smart (fold except acronyms)
+    #[structopt(long = "case-mode", default_value = "title", possible_values = &["title", "exact", "fold", "smart"])]
+    case_mode: CaseMode,
+
+    /// Also match plurals and -ic acid/-ate forms of dictionary keys
+    #[structopt(long = "variants")]
+    variants: bool,
+
+    /// Map salt and hydrate forms (e.g. "morphine sulfate") to the parent compound
+    #[structopt(long = "strip-salts")]
+    strip_salts: bool,
+
+    /// Comma separated suffixes used by --strip-salts instead of the built-in list
+    #[structopt(long = "salt-suffixes", use_delimiter = true)]
+    salt_suffixes: Vec<String>,
+
+    /// Also match keys within edit distance 1 (e.g. "acetominophen")
+    #[structopt(long = "fuzzy")]
+    fuzzy: bool,
+
+    /// Minimum key length, in characters, considered by --fuzzy
+    #[structopt(long = "fuzzy-min-length", default_value = "8")]
+    fuzzy_min_length: usize,
+
+    /// Also match abbreviations defined next to a match, e.g. "tetrahydrofuran (THF)", within a document
+    #[structopt(long = "abbreviations")]
+    abbreviations: bool,
+
+    /// Also detect CAS Registry Numbers (e.g. 50-78-2)
+    #[structopt(long = "cas")]
+    cas: bool,
+
+    /// File of CID<TAB>CAS lines used to attach CIDs to detected CAS numbers, and to the CAS
+    /// numbers of --csv DrugBank vocabularies and MeSH records
+    #[structopt(long = "cas-map")]
+    cas_map: Option<String>,
+
+    /// Tab-separated mapping of CIDs to DrugBank, MeSH and other ids (see enrich --xrefs), giving CIDs
+    /// to --csv DrugBank vocabularies, name,id files and MeSH records not keyed by CIDs; without
+    /// one, their DrugBank ids, ids and MeSH UIs are reported as they are
+    #[structopt(long = "id-map")]
+    id_map: Option<String>,
+
+    /// Also detect InChI strings and InChIKeys
+    #[structopt(long = "inchi")]
+    inchi: bool,
+
+    /// Also detect molecular formulas with at least two elements (e.g. C6H12O6, NaCl)
+    #[structopt(long = "formulas")]
+    formulas: bool,
+
+    /// Comma separated formulas always accepted by --formulas, e.g. H2,O2,CO
+    #[structopt(long = "formula-whitelist", use_delimiter = true)]
+    formula_whitelist: Vec<String>,
+
+    /// Write chemical-looking words missing from the dictionary, with counts, to this file
+    #[structopt(long = "candidates", name = "candidates")]
+    candidates_file: Option<String>,
+
+    /// Only keep --candidates that OPSIN parses as systematic names
+    #[structopt(long = "opsin", requires = "candidates")]
+    opsin: bool,
+
+    /// Command running OPSIN for --opsin, split on whitespace; it gets one name per line and must
+    /// answer each with a SMILES or an empty line
+    #[structopt(long = "opsin-command", default_value = OPSIN)]
+    opsin_command: String,
+
+    /// Also count pairs of CIDs matched in the same paragraph, writing cid<TAB>cid<TAB>paragraphs
+    /// lines, most frequent first, to this file
+    #[structopt(long = "cooccurrence", name = "cooccurrence")]
+    cooccurrence_file: Option<String>,
+
+    /// Format of the --cooccurrence file: edgelist, or graphml with nodes labelled by their shortest
+    /// dictionary name (for networkx or Gephi)
+    #[structopt(long = "cooccurrence-format", default_value = "edgelist", possible_values = &["edgelist", "graphml"])]
+    cooccurrence_format: GraphFormat,
+
+    /// Also write an inverted index to this file: one JSON line per CID listing the corpusids (file
+    /// paths for .txt inputs) mentioning it
+    #[structopt(long = "index", name = "index")]
+    index_file: Option<String>,
+
+    /// Map each corpusid in the --index file to its number of matches instead of listing them
+    #[structopt(long = "index-counts")]
+    index_counts: bool,
+
+    /// Rows written to the output: match for one row per match, or paper for one
+    /// `corpusid,cid:mentions;cid:mentions` row per document with matched CIDs
+    #[structopt(long = "aggregate", default_value = "match", possible_values = &["match", "paper"])]
+    aggregate: Aggregate,
+
+    /// Format of the output: csv rows (see --aggregate); conll for token<TAB>tag lines with
+    /// B-CHEM, I-CHEM and O tags of each paragraph with matches, e.g. to train NER models; or
+    /// ner-json for one {"id", "text", "entities": [[start, end, "CHEMICAL", cid]]} line per
+    /// document with matches, as spaCy and Hugging Face token classification load
+    #[structopt(long = "output-format", default_value = "csv", possible_values = &["csv", "conll", "ner-json"])]
+    output_format: OutputFormat,
+
+    /// Also write each document with matches to this directory as <corpusid>.txt (<file stem> for
+    /// .txt inputs) with its matches as BRAT standoff annotations in <corpusid>.ann
+    #[structopt(long = "brat", name = "brat", parse(from_os_str))]
+    brat_dir: Option<PathBuf>,
+
+    /// Also write one `corpusid,cid:score;cid:score` row per document to this file once the run
+    /// ends, scoring each CID by its mentions times ln(documents searched / documents mentioning it)
+    #[structopt(long = "tfidf", name = "tfidf")]
+    tfidf_file: Option<String>,
+
+    /// File of ambiguous terms (e.g. lead, gold) only matched near chemistry words like "solution" or "mg"
+    #[structopt(long = "ambiguous-terms")]
+    ambiguous_terms: Option<String>,
+
+    /// Tokens on each side of an ambiguous term searched for chemistry words
+    #[structopt(long = "gate-window", default_value = "10")]
+    gate_window: usize,
+
+    /// Keep words split by a hyphen and line break (e.g. "acetami-\nnophen") as they are
+    #[structopt(long = "no-dehyphenate")]
+    no_dehyphenate: bool,
+
+    /// Keep HTML character references (e.g. "&amp;", "&#8217;") in the text instead of decoding them
+    #[structopt(long = "no-decode-entities")]
+    no_decode_entities: bool,
+
+    /// Keep zero-width, soft hyphen and control characters in the text instead of dropping them
+    #[structopt(long = "no-strip-controls")]
+    no_strip_controls: bool,
+
+    /// Search the formulas of mhchem \ce{} commands in .tex inputs, which are otherwise dropped with math
+    #[structopt(long = "tex-mhchem")]
+    tex_mhchem: bool,
+
+    /// Search only the main content of .html inputs, leaving out navigation, headers, footers,
+    /// sidebars and link lists
+    #[structopt(long = "html-main-content")]
+    html_main_content: bool,
+
+    /// Encoding of .txt, .tex, .html and CORD-19 .json inputs, e.g. latin1 or windows-1252, which
+    /// is otherwise UTF-8 or guessed from the bytes of inputs that are not valid UTF-8. Bytes that
+    /// cannot be decoded are replaced and counted in the summary.
+    #[structopt(long = "encoding")]
+    encoding: Option<String>,
+
+    /// Log what the run does besides warnings and errors: -v for dictionary and input summaries,
+    /// -vv also for every unreadable input line, -vvv for every document
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: u8,
+
+    /// Write logs as JSON lines, with fields such as file, line and corpusid, for log collectors
+    #[structopt(long = "log-json")]
+    log_json: bool,
+
+    /// Append logs to this file instead of printing them to stderr
+    #[structopt(long = "log-file")]
+    log_file: Option<String>,
+
 }
 
-fn estimate_lines (file_path: &str) -> Result<usize, Box<dyn Error>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let line_count = reader.lines().count();
-    Ok(line_count)
+// Prefix of the environment variables setting options, e.g. CHEM_MATCHER_NO_BANNED for --no-banned
+const ENV_PREFIX: &str = "CHEM_MATCHER_";
+
+// Value of an option's environment variable: true or false for a flag, a TOML array to repeat
+// the option, otherwise the string as is
+fn env_value(value: String) -> toml::Value {
+    if value == "true" || value == "false" {
+        return toml::Value::Boolean(value == "true");
+    }
+    let array = value.starts_with('[').then(|| toml::from_str::<toml::Table>(&format!("values = {}", value)).ok()).flatten();
+    array.and_then(|mut array| array.remove("values")).unwrap_or(toml::Value::String(value))
 }
 
-struct StemmerWrapper {
-    stemmer: Stemmer,
+// Whether name is the long flag of an option, other than --help and --version
+fn is_option(name: &str) -> bool {
+    if name.is_empty() || name.contains('=') || name == "help" || name == "version" {
+        return false;
+    }
+    let parsed = Opt::clap().get_matches_from_safe(["chem-matcher".to_string(), format!("--{}", name)]);
+    !matches!(parsed, Err(e) if e.kind == structopt::clap::ErrorKind::UnknownArgument)
 }
 
-impl StemmerWrapper{
-    pub fn new() -> StemmerWrapper {
-        StemmerWrapper {
-            stemmer: Stemmer::create(Algorithm::English),
+// Options from the command line args, then from the CHEM_MATCHER_* variables of vars and then
+// from the --config file for those the args leave out
+fn parse_args(args: Vec<OsString>, vars: impl IntoIterator<Item = (String, String)>) -> Result<Opt, Box<dyn Error>> {
+    let opt = Opt::from_iter(&args);
+    // value of each option by its long flag, with where it was set
+    let mut options = BTreeMap::new();
+    for (var, value) in vars {
+        let Some(name) = var.strip_prefix(ENV_PREFIX).map(|name| name.to_lowercase().replace('_', "-")) else {
+            continue;
+        };
+        if is_option(&name) {
+            options.insert(name, (var, env_value(value)));
+        } else {
+            // logging starts only once the options are known
+            eprintln!("warning: ignoring {}, which sets no option (there is no --{})", var, name);
         }
     }
+    let path = opt.config.clone().or_else(|| options.get("config").and_then(|(_, path)| path.as_str()).map(PathBuf::from));
+    if let Some(path) = path {
+        let read = |path: &Path| -> Result<toml::Table, Box<dyn Error>> { Ok(toml::from_str(&fs::read_to_string(path)?)?) };
+        let config = read(&path).map_err(|e| format!("cannot read --config {}: {}", path.display(), e))?;
+        for (name, value) in config {
+            if name == "config" {
+                return Err(format!("--config {} cannot name another config file", path.display()).into());
+            }
+            if !is_option(&name) {
+                return Err(format!("{}: unknown option {}", path.display(), name).into());
+            }
+            options.entry(name).or_insert_with(|| (format!("--config {}", path.display()), value));
+        }
+    }
+    if options.is_empty() {
+        return Ok(opt);
+    }
+    let given = Opt::clap().get_matches_from(&args);
+    let mut option_args = Vec::new();
+    for (name, (source, value)) in options {
+        if given.occurrences_of(&name) > 0 {
+            continue;
+        }
+        // arrays repeat an option, and flags are true or false
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => option_args.push(OsString::from(format!("--{}", name))),
+                toml::Value::Boolean(false) => {}
+                toml::Value::String(value) => option_args.push(OsString::from(format!("--{}={}", name, value))),
+                value @ (toml::Value::Integer(_) | toml::Value::Float(_)) => option_args.push(OsString::from(format!("--{}={}", name, value))),
+                _ => return Err(format!("{} in {} must be a string, number, boolean or array of them", name, source).into()),
+            }
+        }
+    }
+    // before the command line args, which end with the subcommand
+    Ok(Opt::from_iter(args[..1].iter().cloned().chain(option_args).chain(args[1..].iter().cloned())))
+}
 
-    pub fn standardize(&self, word: &str) -> String {
-        self.stemmer.stem(word.trim().to_lowercase().as_str()).to_string()
+// Common words dropped from the dictionary: nothing with --no-banned, the BANNED list when no
+// source is given, otherwise the union of every --banned-url and --banned-file
+async fn load_banned(opt: &Opt) -> Result<HashSet<String>, Box<dyn Error>> {
+    if opt.no_banned {
+        return Ok(HashSet::new());
     }
+    let default_urls = [BANNED.to_string()];
+    let urls = if opt.banned_urls.is_empty() && opt.banned_files.is_empty() { &default_urls[..] } else { &opt.banned_urls[..] };
+    let fetch = FetchOptions {
+        ttl_hours: opt.banned_ttl,
+        max_attempts: opt.max_attempts,
+        proxy: opt.proxy.clone(),
+        ca_bundle: opt.ca_bundle.clone(),
+    };
+    load_banned_lists(urls, &opt.banned_files, &fetch).await
 }
 
+// Dictionary parse settings from the command line
+fn parse_options(opt: &Opt) -> Result<ParseOptions, Box<dyn Error>> {
+    let mut parse_options = ParseOptions {
+        nfkc: !opt.no_nfkc,
+        case_mode: opt.case_mode,
+        resolution: opt.conflict_resolution,
+        min_length: opt.min_length,
+        columns: opt.csv_columns,
+        has_header: opt.csv_has_header,
+        ..Default::default()
+    };
+    if let Some(short_names) = &opt.short_names {
+        parse_options.short_names = read_list(short_names)?.iter().map(|name| normalize(name).to_lowercase()).collect();
+    }
+    if let Some(ban_synonyms) = &opt.ban_synonyms {
+        parse_options.banned_synonyms = read_list(ban_synonyms)?.iter().map(|synonym| normalize(synonym).to_lowercase()).collect();
+    }
+    if let Some(ban_cids) = &opt.ban_cids {
+        parse_options.banned_cids = read_list(ban_cids)?.iter().map(|cid| cid.parse::<Id>()).collect::<Result<_, _>>()?;
+    }
+    if let Some(only_cids) = &opt.only_cids {
+        parse_options.only_cids = Some(read_list(only_cids)?.iter().map(|cid| cid.parse::<Id>()).collect::<Result<_, _>>()?);
+    }
+    if let Some(cas_map) = &opt.cas_map {
+        parse_options.ids.extend(parse_cas_map(cas_map)?);
+    }
+    if let Some(id_map) = &opt.id_map {
+        parse_options.ids.extend(Xrefs::read(id_map)?.by_id());
+    }
+    Ok(parse_options)
+}
 
-fn to_ascii_titlecase(s: &str) -> String {
-    let mut titlecased = s.to_owned();
-    if let Some(r) = titlecased.get_mut(0..1) {
-        r.make_ascii_uppercase();
+// Parse or load every --csv dictionary and merge them, also returning how many CSV entries were filtered
+fn load_dictionaries(opt: &Opt, banned: &HashSet<String>, parse_options: &ParseOptions) -> Result<ParsedDictionary, Box<dyn Error>> {
+    if opt.csv_files.is_empty() {
+        return Err("at least one --csv dictionary is required".into());
     }
-    titlecased
+    let header = DictHeader::new(banned, parse_options);
+    let mut map = HashMap::new();
+    let mut conflicts = Conflicts::new();
+    let mut collisions = 0;
+    let mut skipped = 0;
+    let mut malformed = Vec::new();
+    for csv_file in &opt.csv_files {
+        let (file_map, file_conflicts) = if is_compiled_dict(csv_file) {
+            read_compiled_dict(csv_file, &header)?
+        } else {
+            let (file_map, file_conflicts, file_skipped, file_malformed) = parse_csv(csv_file, banned, parse_options)?;
+            info!(dictionary = %csv_file, skipped = file_skipped, conflicts = file_conflicts.len(), "parsed dictionary");
+            if let Some(first) = file_malformed.first() {
+                warn!(dictionary = %csv_file, rows = file_malformed.len(), line = first.line, issue = first.issue, detail = %first.detail, "left out malformed rows, listed by validate-dict");
+            }
+            skipped += file_skipped;
+            malformed.extend(file_malformed);
+            (file_map, file_conflicts)
+        };
+        collisions += merge_dictionary(&mut map, &mut conflicts, file_map, file_conflicts, opt.csv_precedence);
+    }
+    if opt.csv_files.len() > 1 {
+        info!(collisions, "keys map to different CIDs across dictionaries");
+    }
+    if let Some(conflicts_file) = &opt.conflicts_file {
+        write_conflicts(conflicts_file, &conflicts)?;
+    }
+    Ok((map, conflicts, skipped, malformed))
 }
 
-fn from_ascii_titlecase(s: &str) -> String {
-    let mut titlecased = s.to_owned();
-    if let Some(r) = titlecased.get_mut(0..1) {
-        r.make_ascii_lowercase();
+// The single compiled --csv dictionary, memory mapped for --mmap-dict
+fn map_dictionary(opt: &Opt, banned: &HashSet<String>) -> Result<MappedDictionary, Box<dyn Error>> {
+    let csv_file = match opt.csv_files.as_slice() {
+        [csv_file] if is_compiled_dict(csv_file) => csv_file,
+        _ => return Err("--mmap-dict needs a single --csv dictionary written by compile-dict".into()),
+    };
+    let (map, conflicts) = map_compiled_dict(csv_file, &DictHeader::new(banned, &parse_options(opt)?))?;
+    info!(dictionary = %csv_file, keys = map.len(), bytes = map.frozen_size(), "mapped compiled dictionary");
+    if let Some(conflicts_file) = &opt.conflicts_file {
+        write_conflicts(conflicts_file, &conflicts)?;
     }
-    titlecased
+    Ok((map, conflicts))
 }
 
-async fn fetch_words_from_url(url: &str) -> Result<HashSet<String>, Box<dyn Error>> {
-    let response = reqwest::get(url).await?;
-    let pb = ProgressBar::new(20000 as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("fetching common words [{elapsed_precise}] {bar} {pos}/{len} ({eta})")?
-            .progress_chars("█░"),
-    );
-    let stemmer = StemmerWrapper::new();
-    let words: HashSet<String> = response
-        .text()
-        .await?
-        .split_whitespace()
-        .filter(|word| !word.starts_with('#'))
-        .map(|word| {
-            pb.inc(1);
-            stemmer.standardize(word)
-        })
-        .collect();
-    pb.finish();
-    Ok(words)
+async fn compile_dict(opt: &Opt, output: &str) -> Result<(), Box<dyn Error>> {
+    let banned = load_banned(opt).await?;
+    let parse_options = parse_options(opt)?;
+    let (map, conflicts, ..) = load_dictionaries(opt, &banned, &parse_options)?;
+    write_compiled_dict(output, &DictHeader::new(&banned, &parse_options), &map, &conflicts)?;
+    println!("Wrote {} keys to {}", map.len(), output);
+    Ok(())
 }
 
-// Read CSV file and returns a HashMap with key-value pairs
-fn parse_csv(file_path: &str, banned: &HashSet<String>) -> Result<HashMap<String, u32>, Box<dyn Error>> {
-    let estimate = estimate_lines(file_path)?;
-    let mut map = HashMap::with_capacity(estimate);
-    let stemmer = StemmerWrapper::new();
+async fn print_dict_stats(opt: &Opt) -> Result<(), Box<dyn Error>> {
+    let banned = load_banned(opt).await?;
+    let (map, conflicts, skipped, _) = load_dictionaries(opt, &banned, &parse_options(opt)?)?;
+    print!("{}", DictStats::new(&map, &conflicts, skipped));
+    Ok(())
+}
 
-    let content = fs::read_to_string(file_path)?;
-    let mut skipped = 0;
+fn print_result_stats(results: &[String], top: usize, examples: Option<usize>) -> Result<(), Box<dyn Error>> {
+    let mut stats = ResultStats::new(top);
+    for result in results {
+        stats.add_file(result)?;
+    }
+    match examples {
+        Some(examples) => {
+            // a second pass keeps contexts of the top CIDs only
+            let cids = stats.top_cids().into_iter().map(|(cid, _)| cid).collect();
+            print!("{}", stats.molecule_report(&sample_contexts(results, &cids, examples)?));
+        }
+        None => print!("{}", stats),
+    }
+    Ok(())
+}
+
+fn export_dataset(output: &str, results: &[String], splits: &str, seed: u64) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "parquet")]
+    {
+        use chem_matcher::dataset::{SplitRatios, SPLITS};
+        let ratios: SplitRatios = splits.parse()?;
+        let counts = chem_matcher::dataset::export_dataset(results, Path::new(output), ratios, seed)?;
+        for (split, count) in SPLITS.iter().zip(counts) {
+            println!("{}\t{}", split, count);
+        }
+        Ok(())
+    }
+    #[cfg(not(feature = "parquet"))]
+    {
+        let _ = (output, results, splits, seed);
+        Err("export-dataset needs chem-matcher built with --features parquet".into())
+    }
+}
+
+async fn evaluate(opt: &Opt, gold_path: &str, texts_file: Option<&str>, entity_type: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (mut gold, texts) = if Path::new(gold_path).is_dir() {
+        read_brat_dir(Path::new(gold_path), entity_type)?
+    } else {
+        let texts_file = texts_file.ok_or("eval needs the texts of a gold TSV file")?;
+        (read_gold_tsv(gold_path)?, read_texts_jsonl(texts_file)?)
+    };
+    let banned = load_banned(opt).await?;
+    let matcher = build_matcher(opt, &banned)?;
+    let mut evaluation = Evaluation::default();
+    for (id, text) in texts {
+        // documents without gold mentions only count false positives
+        let mentions = gold.remove(&id).unwrap_or_default();
+        evaluation.add(&mentions, &predicted_mentions(&text, &matcher.search(&text)));
+    }
+    if let Some(id) = gold.keys().next() {
+        return Err(format!("no text for gold document {}", id).into());
+    }
+    print!("{}", evaluation);
+    Ok(())
+}
+
+async fn validate_dict(opt: &Opt, report: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let banned = load_banned(opt).await?;
+    let parse_options = parse_options(opt)?;
+    let mut writer: Box<dyn Write> = match report {
+        Some(report) => Box::new(BufWriter::new(File::create(report)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for csv_file in &opt.csv_files {
+        for issue in validate_csv(csv_file, &banned, &parse_options)? {
+            *counts.entry(issue.issue).or_default() += 1;
+            let mut record = serde_json::to_value(&issue)?;
+            record["file"] = csv_file.as_str().into();
+            writeln!(writer, "{}", record)?;
+        }
+    }
+    writer.flush()?;
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort();
+    for (issue, count) in &counts {
+        eprintln!("{}: {}", issue, count);
+    }
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    if total > 0 {
+        return Err(format!("{} issues found", total).into());
+    }
+    Ok(())
+}
 
-    let pb = ProgressBar::new(estimate as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("building synonym map [{elapsed_precise}] {bar} {pos}/{len} ({eta})")?
-            .progress_chars("█░"),
-    );
+fn write_stoplist(opt: &Opt, output: &str, min_df: f64, sample: usize) -> Result<(), Box<dyn Error>> {
+    let mut documents = Vec::new();
+    for file_path in &opt.files {
+        documents.extend(sample_documents(file_path, &opt.property, sample - documents.len())?);
+    }
+    if documents.is_empty() {
+        return Err("no documents found in --files".into());
+    }
+    let stoplist = build_stoplist(&documents, min_df);
+    let mut writer = BufWriter::new(File::create(output)?);
+    // words starting with # are skipped when the list is read back
+    writeln!(writer, "#built-from-{}-documents #min-df={}", documents.len(), min_df)?;
+    for (word, _) in &stoplist {
+        writeln!(writer, "{}", word)?;
+    }
+    writer.flush()?;
+    println!("Wrote {} words from {} documents to {}", stoplist.len(), documents.len(), output);
+    Ok(())
+}
 
-    for line in content.lines() {
-        let split: Vec<&str> = line.split('\t').collect();
-        if split.len() == 2 {
-            let value = split[0].trim().to_string();
-            let key = split[1].trim().to_string();
-            if key.len() >= MIN_WORD_LENGTH && !banned.contains(stemmer.standardize(&key).as_str()) {
-                map.insert(to_ascii_titlecase(&key), value.parse::<u32>().unwrap());
-            } else {
-                skipped += 1;
+// Matcher over the --csv dictionaries configured by the search options
+fn build_matcher(opt: &Opt, banned: &HashSet<String>) -> Result<Matcher, Box<dyn Error>> {
+    let (map, conflicts) = if opt.mmap_dict {
+        map_dictionary(opt, banned)?
+    } else {
+        let (map, conflicts, ..) = load_dictionaries(opt, banned, &parse_options(opt)?)?;
+        (map.into(), conflicts)
+    };
+    let mut builder = MatcherBuilder::new()
+        .paragraph_delimiter(&opt.paragraph_delimiter)
+        .context_window(opt.context_window)
+        .mask(&opt.mask)
+        .variants(opt.variants)
+        .all_overlaps(opt.all_overlaps)
+        .nfkc(!opt.no_nfkc)
+        .case_mode(opt.case_mode)
+        .abbreviations(opt.abbreviations)
+        .cas(opt.cas)
+        .inchi(opt.inchi)
+        .formulas(opt.formulas)
+        .formula_whitelist(opt.formula_whitelist.iter().map(|formula| formula.trim().to_string()).collect())
+        .dehyphenate(!opt.no_dehyphenate)
+        .decode_entities(!opt.no_decode_entities)
+        .strip_controls(!opt.no_strip_controls)
+        .conflicts(&conflicts)
+        .gate_window(opt.gate_window);
+    if opt.fuzzy {
+        builder = builder.fuzzy(opt.fuzzy_min_length);
+    }
+    if opt.strip_salts {
+        builder = builder.salt_suffixes(if opt.salt_suffixes.is_empty() {
+            SALT_SUFFIXES.iter().map(|suffix| suffix.to_string()).collect()
+        } else {
+            opt.salt_suffixes.iter().map(|suffix| suffix.trim().to_lowercase()).collect()
+        });
+    }
+    if let Some(cas_map) = &opt.cas_map {
+        builder = builder.cas_map(parse_cas_map(cas_map)?);
+    }
+    if let Some(ambiguous_terms) = &opt.ambiguous_terms {
+        builder = builder.ambiguous_terms(read_list(ambiguous_terms)?);
+    }
+    let matcher = builder.build(map)?;
+    if !opt.mmap_dict {
+        info!(keys = matcher.map().len(), bytes = matcher.map().heap_size(), "interned dictionary keys");
+    }
+    Ok(matcher)
+}
+
+async fn serve_matches(opt: &Opt, address: SocketAddr, grpc_address: Option<SocketAddr>) -> Result<(), Box<dyn Error>> {
+    if grpc_address.is_some() && cfg!(not(feature = "grpc")) {
+        return Err("--grpc-address needs chem-matcher built with --features grpc".into());
+    }
+    let banned = load_banned(opt).await?;
+    let matcher = Arc::new(build_matcher(opt, &banned)?);
+    let metrics = Arc::new(Metrics::new());
+    report_metrics(opt, &metrics);
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_address) = grpc_address {
+        let (matcher, worker) = (Arc::clone(&matcher), metrics.worker("grpc"));
+        println!("Serving gRPC on {}", grpc_address);
+        tokio::spawn(async move {
+            if let Err(e) = chem_matcher::grpc::serve_grpc(matcher, grpc_address, worker).await {
+                error!(error = %e, "gRPC server stopped");
+                process::exit(1);
+            }
+        });
+    }
+    println!("Listening on http://{}/match", address);
+    serve(matcher, opt.property.clone(), address, metrics).await
+}
+
+// Log the rates of metrics every --metrics-interval seconds and answer GET /metrics on
+// --metrics-address, both until the process stops
+fn report_metrics(opt: &Opt, metrics: &Arc<Metrics>) {
+    if opt.metrics_interval > 0 {
+        let metrics = Arc::clone(metrics);
+        let mut ticks = tokio::time::interval(std::time::Duration::from_secs(opt.metrics_interval));
+        tokio::spawn(async move {
+            // the first tick completes at once
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                info!("{}", metrics.rate_line());
+                for (name, worker) in metrics.workers() {
+                    let [documents, paragraphs, matches] = worker.rates();
+                    debug!(worker = %name, documents_per_second = documents, paragraphs_per_second = paragraphs, matches_per_second = matches, "worker rates");
+                }
             }
+        });
+    }
+    if let Some(address) = opt.metrics_address {
+        let metrics = Arc::clone(metrics);
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(metrics, address).await {
+                error!(error = %e, "metrics server stopped");
+            }
+        });
+    }
+}
+
+// Bar counting documents across all inputs, with the number of inputs finished as its message
+fn documents_bar(multi: &MultiProgress) -> Result<ProgressBar, Box<dyn Error>> {
+    let bar = multi.add(ProgressBar::new_spinner());
+    bar.set_style(ProgressStyle::with_template("[{elapsed_precise}] {pos} documents ({per_sec}) {msg}")?);
+    Ok(bar)
+}
+
+// Progress of one input being searched: its own bar, removed once it finishes, the bar
+// counting documents across all inputs and the throughput counters of its worker
+struct FileProgress {
+    file: ProgressBar,
+    documents: ProgressBar,
+    worker: Arc<WorkerMetrics>,
+}
+
+impl FileProgress {
+    // Bar for fp below the others. Documents in gzipped and patent files aren't known up front, so
+    // their bar follows the (compressed) bytes read out of the file size; other files are a single
+    // document.
+    fn new(multi: &MultiProgress, documents: &ProgressBar, fp: &str, worker: Arc<WorkerMetrics>) -> Result<FileProgress, Box<dyn Error>> {
+        let name = Path::new(fp).file_name().map_or(fp.to_string(), |name| name.to_string_lossy().to_string());
+        let file = if fp.ends_with(".gz") || (fp.ends_with(".xml") && is_patent_xml(fp)) {
+            let bar = ProgressBar::new(fs::metadata(fp)?.len());
+            bar.set_style(ProgressStyle::with_template("  {prefix} {bar:30} {bytes}/{total_bytes} ({eta})")?.progress_chars("█░"));
+            bar
+        } else {
+            let bar = ProgressBar::new(1);
+            bar.set_style(ProgressStyle::with_template("  {prefix} {bar:30} {pos}/{len} documents")?.progress_chars("█░"));
+            bar
+        };
+        file.set_prefix(name);
+        Ok(FileProgress { file: multi.add(file), documents: documents.clone(), worker })
+    }
+
+    // A document of a text file was searched; files of several documents, e.g. tables, grow their
+    // bar to the documents found
+    fn document(&self) {
+        if self.file.position() >= self.file.length().unwrap_or_default() {
+            self.file.inc_length(1);
         }
-        pb.inc(1);
+        self.file.inc(1);
+        self.documents.inc(1);
+    }
+
+    // A document of a gzipped or patent file was searched, with the (compressed) bytes read so far
+    fn document_at(&self, read: u64) {
+        self.file.set_position(read);
+        self.documents.inc(1);
     }
-    pb.finish();
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        self.file.finish_and_clear();
+    }
+}
+
+// How every input is searched besides writing its matches
+struct FileSearch {
+    property: String,
+    stop: usize,
+    file_threads: usize,
+    // banned words left out of candidate names, when --candidates is given
+    candidates_banned: Option<Arc<HashSet<String>>>,
+    cooccurrence: bool,
+    index: bool,
+    aggregate: Aggregate,
+    output_format: OutputFormat,
+    brat_dir: Option<PathBuf>,
+    tex_mhchem: bool,
+    html_main_content: bool,
+    // None to detect the encoding of each input
+    encoding: Option<&'static Encoding>,
+    text_column: String,
+    id_column: Option<String>,
+}
 
-    println!("Skipped {} words", skipped);
+impl FileSearch {
+    fn new(opt: &Opt, banned: &Arc<HashSet<String>>) -> Result<FileSearch, Box<dyn Error>> {
+        if let Some(dir) = &opt.brat_dir {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(FileSearch {
+            property: opt.property.clone(),
+            stop: opt.stop,
+            file_threads: opt.file_threads,
+            candidates_banned: opt.candidates_file.as_ref().map(|_| Arc::clone(banned)),
+            cooccurrence: opt.cooccurrence_file.is_some(),
+            // TF-IDF scores come from the index once every document is counted
+            index: opt.index_file.is_some() || opt.tfidf_file.is_some(),
+            aggregate: opt.aggregate,
+            output_format: opt.output_format,
+            brat_dir: opt.brat_dir.clone(),
+            tex_mhchem: opt.tex_mhchem,
+            html_main_content: opt.html_main_content,
+            encoding: match &opt.encoding {
+                Some(label) => Some(Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding: {}", label))?),
+                None => None,
+            },
+            text_column: opt.text_column.clone(),
+            id_column: opt.id_column.clone(),
+        })
+    }
+}
 
-    Ok(map)
+// What searching one input found besides its matches
+#[derive(Default)]
+struct FileResults {
+    candidates: HashMap<String, usize>,
+    stats: InputStats,
+    cooccurrences: Cooccurrences,
+    index: CidIndex,
+    // documents searched
+    documents: u64,
 }
 
+impl FileResults {
+    // Fold the results of another input into these, keeping stats apart for the summary
+    fn merge(&mut self, other: FileResults) -> InputStats {
+        for (name, count) in other.candidates {
+            *self.candidates.entry(name).or_default() += count;
+        }
+        self.cooccurrences.merge(other.cooccurrences);
+        self.index.merge(other.index);
+        self.documents += other.documents;
+        other.stats
+    }
 
-fn search_keys_in_text<'a>(map: &'a HashMap<String, u32>, text: &'a str) -> SearchResults {
-    let mut search_results = Vec::new();
-    let re = regex::Regex::new(r"\n\n").unwrap();
-    re.split(text).map(|paragraph| {
-        let mut count: usize = 0;
-        let mut last_word = String::new();
-        let mut last_count: usize = 0;
-        let mut last_key = String::new();
-        let mut seen = HashSet::new(); // we only want to observer a key once
-        paragraph.split(WORD_SPLITS).map(|word| {
-            count += word.len() + 1;
-            let title_word = to_ascii_titlecase(word);
-            let mut value: Option<&u32> = None;
-            last_key.clear();
-            last_key.push_str(&last_word);
-            last_key.push(' ');
-            last_key.push_str(word);
-            if word.len() >= MIN_WORD_LENGTH && map.contains_key(&last_key) && !seen.contains(&last_key) {
-                value = map.get(&last_key);
-            } else if last_word.len() >= MIN_WORD_LENGTH && map.contains_key(&last_word) && !seen.contains(&last_word) {
-                value = map.get(&last_word);
-                last_key.clear();
-                last_key.push_str(&last_word);
+    // Write --candidates, --cooccurrence, --index and --tfidf, naming graph nodes from the dictionary of matcher
+    fn write(&self, opt: &Opt, matcher: &Matcher) -> Result<(), Box<dyn Error>> {
+        if let Some(candidates_file) = &opt.candidates_file {
+            let mut candidates = self.candidates.clone();
+            if opt.opsin {
+                let names: Vec<String> = candidates.keys().cloned().collect();
+                let structures = opsin_structures(&opt.opsin_command, &names)?;
+                for (name, structure) in names.iter().zip(structures) {
+                    if structure.is_none() {
+                        candidates.remove(name);
+                    }
+                }
+                info!(kept = candidates.len(), candidates = names.len(), "checked candidates with OPSIN");
             }
-            
-            if value.is_some() {
-                // need to copy paragraph so I can mask out the word
-                let mut paragraph = paragraph.to_string().replace(&last_key, MASK);
-                paragraph = paragraph.replace(from_ascii_titlecase(&last_key).as_str(), MASK);
-                seen.insert(last_key.to_string());
-                search_results.push((paragraph, last_key.to_string(), *value.unwrap()));
+            write_candidates(candidates_file, candidates)?;
+        }
+        if let Some(cooccurrence_file) = &opt.cooccurrence_file {
+            let names = match opt.cooccurrence_format {
+                GraphFormat::GraphMl => node_names(matcher, &self.cooccurrences.nodes()),
+                GraphFormat::EdgeList => HashMap::new(),
+            };
+            self.cooccurrences.write_graph(cooccurrence_file, opt.cooccurrence_format, &names)?;
+        }
+        if let Some(index_file) = &opt.index_file {
+            self.index.write_jsonl(index_file, opt.index_counts)?;
+        }
+        if let Some(tfidf_file) = &opt.tfidf_file {
+            self.index.write_tfidf(tfidf_file, self.documents)?;
+        }
+        Ok(())
+    }
+}
+
+// Shortest dictionary key of each of cids, the first alphabetically among equals
+fn node_names(matcher: &Matcher, cids: &BTreeSet<Id>) -> HashMap<Id, String> {
+    let mut names: HashMap<Id, String> = HashMap::new();
+    for (key, cid) in matcher.map() {
+        if cids.contains(&*cid) {
+            let name = names.entry(cid.into_owned()).or_insert_with(|| key.to_string());
+            if (key.len(), key) < (name.len(), name.as_str()) {
+                *name = key.to_string();
+            }
+        }
+    }
+    names
+}
+
+// Whether fp is a USPTO full-text file, whose documents are patent grants or applications
+fn is_patent_xml(fp: &str) -> bool {
+    let root = File::open(fp).map_err(Box::from).and_then(|file| root_element(BufReader::new(file)));
+    matches!(root.ok().flatten().as_deref(), Some("us-patent-grant" | "us-patent-application"))
+}
+
+// Lines of a JSON lines input passed between the stages of its pipeline at a time
+const LINE_BATCH: usize = 64;
+
+// What became of a line of a JSON lines input
+enum LineOutcome {
+    Empty,
+    Invalid(serde_json::Error),
+    MissingProperty,
+    // the whole record, which has text but no corpusid
+    NoCorpusId(serde_json::Value),
+    // matches are filled in by the matching stage
+    Document { corpus_id: u64, text: String, matches: Vec<Match>, paragraphs: usize },
+}
+
+// Parse a line of a JSON lines input, on a parsing thread
+fn parse_line(line: &str, property: &str) -> LineOutcome {
+    if line.is_empty() {
+        return LineOutcome::Empty;
+    }
+    let json_data = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(json_data) => json_data,
+        Err(e) => return LineOutcome::Invalid(e),
+    };
+    let Some(text) = json_data["content"][property].as_str() else {
+        return LineOutcome::MissingProperty;
+    };
+    let Some(corpus_id) = json_data["corpusid"].as_u64() else {
+        return LineOutcome::NoCorpusId(json_data);
+    };
+    LineOutcome::Document { corpus_id, text: text.to_string(), matches: Vec::new(), paragraphs: 0 }
+}
+
+// Bytes of rows a worker of process_files buffers before sending them to the collector
+const CHUNK_BYTES: usize = 256 * 1024;
+
+// Chunks waiting for the collector before workers block on sending more. Rows held in memory
+// stay under (COLLECTED_CHUNKS + workers) * CHUNK_BYTES, plus the rows of one document per chunk
+// and the write buffer of the spool of each input still being searched while another is written;
+// spools of finished inputs are closed until their turn.
+const COLLECTED_CHUNKS: usize = 16;
+
+// Messages from the workers of process_files to the collector, in order for each input
+enum Collected {
+    // rows of whole documents of the input at a position
+    Rows(usize, Vec<u8>),
+    // the input at a position searched: its part file, results, path and ledger id
+    Finished(usize, Box<(String, FileResults, String, FileId)>),
+}
+
+// Where search_file writes the rows of an input
+enum RowWriter {
+    // a part file, written under a temporary name and renamed to its path once complete
+    Part(BufWriter<File>, String),
+    // chunks sent to the collector as the input at a position is searched
+    Chunks(usize, Vec<u8>, flume::Sender<Collected>),
+}
+
+impl RowWriter {
+    fn part(path: &str) -> RowWriter {
+        // an interrupted part is never taken for a finished one
+        RowWriter::Part(BufWriter::new(File::create(format!("{}.tmp", path)).unwrap()), path.to_string())
+    }
+
+    // Send the rows buffered once they fill a chunk; called between documents, so a chunk only
+    // holds whole documents
+    fn end_document(&mut self) {
+        if let RowWriter::Chunks(position, rows, sender) = self {
+            if rows.len() >= CHUNK_BYTES {
+                sender.send(Collected::Rows(*position, std::mem::take(rows))).unwrap();
             }
-    
-            last_word = title_word.to_string();
-            last_count = count;
-        }).count();
+        }
+    }
 
-        // add the last word
-        if last_word.len() >= MIN_WORD_LENGTH && map.contains_key(&last_word) && !seen.contains(&last_word) {
-            let value = map.get(&last_word);
-            if value.is_some() {
-                // need to copy paragraph so I can mask out the word
-                let mut paragraph = paragraph.to_string().replace(&last_word, MASK);
-                paragraph = paragraph.replace(from_ascii_titlecase(&last_word).as_str(), MASK);
-                seen.insert(last_word.to_string());
-                search_results.push((paragraph.replace(&last_word, MASK), last_word.to_string(), *value.unwrap()));
+    fn finish(self) {
+        match self {
+            RowWriter::Part(mut writer, path) => {
+                writer.flush().unwrap();
+                fs::rename(format!("{}.tmp", path), path).unwrap();
             }
+            RowWriter::Chunks(position, rows, sender) if !rows.is_empty() => sender.send(Collected::Rows(position, rows)).unwrap(),
+            RowWriter::Chunks(..) => {}
         }
+    }
+}
 
-    }).count();
+impl Write for RowWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RowWriter::Part(writer, _) => writer.write(buf),
+            RowWriter::Chunks(_, rows, _) => rows.write(buf),
+        }
+    }
 
-    search_results
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RowWriter::Part(writer, _) => writer.flush(),
+            RowWriter::Chunks(..) => Ok(()),
+        }
+    }
 }
 
+// Write the matches of a document in the --output-format
+fn write_document(search: &FileSearch, matcher: &Matcher, writer: &mut RowWriter, paper_id: &str, text: &str, matches: Vec<Match>) {
+    match search.output_format {
+        OutputFormat::Csv => search.aggregate.write(matches, writer, paper_id),
+        OutputFormat::Conll => write_conll(writer, matcher.options(), text, &matches).unwrap(),
+        OutputFormat::NerJson if !matches.is_empty() => writeln!(writer, "{}", ner_json(paper_id, text, &matches)).unwrap(),
+        OutputFormat::NerJson => {}
+    }
+    writer.end_document();
+}
 
-// Generate the report in a readable format
-fn generate_report(search_results: SearchResults, writer: &mut BufWriter<File>, paper_id: &str) {
-    for (context, word, cid) in search_results {
-        // show the context window around the word
-        let msg = format!("\"{}\",{},\"{}\",{}\n", word, cid, context.replace("\"", "\\\"").replace("\n", "\\n"), paper_id);
-        writer.write_all(msg.as_bytes()).unwrap();
+// Text of a whole-file input, adding the characters that could not be decoded to replaced
+fn read_input(fp: &str, encoding: Option<&'static Encoding>, replaced: &mut u64) -> String {
+    let (text, encoding, replacements) = decode_input(&fs::read(fp).unwrap(), encoding);
+    if replacements > 0 {
+        warn!(encoding = encoding.name(), replacements, "replaced undecodable bytes");
+    } else {
+        debug!(encoding = encoding.name(), "decoded input");
     }
+    *replaced += replacements;
+    text
 }
 
-async fn process_files(opt: Opt) -> Result<(), Box<dyn Error>> {
-    let banned = Arc::new(fetch_words_from_url(BANNED).await.unwrap());
-    let map = Arc::new(parse_csv(&opt.csv_file, &banned)?);
-    let (tx, rx) = flume::unbounded();
+// Search one input file, writing its rows to writer
+fn search_file(fp: &str, mut writer: RowWriter, matcher: &Matcher, search: &FileSearch, progress: &FileProgress) -> FileResults {
+    let _span = info_span!("search_file", file = fp).entered();
+    let (property, stop) = (search.property.as_str(), search.stop);
+    let stemmer = StemmerWrapper::new();
+    let mut results = FileResults::default();
+    let stats = &mut results.stats;
+    let (candidates, cooccurrences, index) = (&mut results.candidates, &mut results.cooccurrences, &mut results.index);
+    let mut count_document = |id: &str, text: &str, matches: &[Match]| {
+        if let Some(banned) = &search.candidates_banned {
+            for name in find_unknown_names(matcher.map(), text, matcher.options(), banned, &stemmer) {
+                *candidates.entry(name).or_default() += 1;
+            }
+        }
+        if search.cooccurrence {
+            cooccurrences.add_document(&matcher.options().paragraph_re, text, matches);
+        }
+        if search.index {
+            index.add_document(id, matches);
+        }
+        if let (Some(dir), false) = (&search.brat_dir, matches.is_empty()) {
+            // text inputs, and documents without an id, are named after their file
+            let stem = Path::new(fp).file_stem().unwrap().to_string_lossy();
+            let name = if id == fp || id.is_empty() { &stem } else { id };
+            write_brat(dir, name, text, matches).unwrap();
+        }
+    };
+    let ext = Path::new(fp).extension().unwrap();
+    let text: String;
+    // characters of whole-file inputs replaced while decoding them
+    let mut replaced = 0;
+    // Search a document of an input other than text or JSON lines, unless it has no text
+    let mut search_document = |id: &str, text: &str| -> bool {
+        stats.records_read += 1;
+        if text.is_empty() {
+            stats.skip("empty");
+            return false;
+        }
+        let (search_result, paragraphs) = matcher.search_counted(text);
+        trace!(id, matches = search_result.len(), "searched document");
+        stats.add_matches(&search_result);
+        progress.worker.record(paragraphs, search_result.len());
+        count_document(id, text, &search_result);
+        write_document(search, matcher, &mut writer, id, text, search_result);
+        true
+    };
+    let documents = match ext.to_str().unwrap() {
+        "txt" => {
+            text = read_input(fp, search.encoding, &mut replaced);
+            let (search_result, paragraphs) = matcher.search_counted(&text);
+            stats.records_read = 1;
+            stats.add_matches(&search_result);
+            progress.worker.record(paragraphs, search_result.len());
+            count_document(fp, &text, &search_result);
+            write_document(search, matcher, &mut writer, "", &text, search_result);
+            progress.document();
+            1
+        },
+        "gz" if fp.ends_with(".xml.gz") => {
+            let compressed = CountingReader::new(File::open(fp).unwrap());
+            let read = compressed.counter();
+            let mut count = 0;
+            let parsed = read_pubmed(BufReader::new(GzDecoder::new(compressed)), |pmid, text| {
+                if search_document(pmid, text) {
+                    progress.document_at(read.load(Ordering::Relaxed));
+                    count += 1;
+                }
+                !(stop > 0 && count == stop)
+            });
+            if let Err(e) = parsed {
+                // the articles before the error are kept
+                error!(error = %e, "unreadable PubMed XML");
+                stats.skip("invalid-xml");
+            }
+            count
+        },
+        extension @ ("csv" | "tsv") => {
+            let delimiter = if extension == "tsv" { b'\t' } else { b',' };
+            let mut count = 0;
+            let parsed = read_table(File::open(fp).unwrap(), delimiter, &search.text_column, search.id_column.as_deref(), |id, text| {
+                if search_document(id, text) {
+                    progress.document();
+                    count += 1;
+                }
+                !(stop > 0 && count == stop)
+            });
+            if let Err(e) = parsed {
+                error!(error = %e, "unreadable table");
+                stats.skip("invalid-table");
+            }
+            count
+        },
+        "arrow" | "arrows" | "feather" => {
+            let mut count = 0;
+            let parsed = read_arrow(fp, &search.text_column, search.id_column.as_deref(), |id, text| {
+                if search_document(id, text) {
+                    progress.document();
+                    count += 1;
+                }
+                !(stop > 0 && count == stop)
+            });
+            if let Err(e) = parsed {
+                error!(error = %e, "unreadable Arrow input");
+                stats.skip("invalid-arrow");
+            }
+            count
+        },
+        "json" => {
+            let json = read_input(fp, search.encoding, &mut replaced);
+            let parsed = serde_json::from_str(&json).map_err(Box::from).and_then(|json| cord19_document(&json));
+            match parsed {
+                Ok((paper_id, text)) => {
+                    let searched = search_document(&paper_id, &text) as usize;
+                    progress.document();
+                    searched
+                }
+                Err(e) => {
+                    error!(error = %e, "unreadable CORD-19 document");
+                    stats.skip("invalid-json");
+                    0
+                }
+            }
+        },
+        "html" | "htm" => {
+            let text = html_text(&read_input(fp, search.encoding, &mut replaced), search.html_main_content);
+            let stem = Path::new(fp).file_stem().unwrap().to_string_lossy();
+            let searched = search_document(&stem, &text) as usize;
+            progress.document();
+            searched
+        },
+        "tex" => {
+            let text = strip_latex(&read_input(fp, search.encoding, &mut replaced), search.tex_mhchem);
+            let stem = Path::new(fp).file_stem().unwrap().to_string_lossy();
+            let searched = search_document(&stem, &text) as usize;
+            progress.document();
+            searched
+        },
+        "pdf" => match read_pdf(fp) {
+            Ok(text) => {
+                // named after their file like TEI documents, LaTeX sources and HTML pages
+                let stem = Path::new(fp).file_stem().unwrap().to_string_lossy();
+                let searched = search_document(&stem, &text) as usize;
+                progress.document();
+                searched
+            }
+            Err(e) => {
+                error!(error = %e, "unreadable PDF");
+                stats.skip("invalid-pdf");
+                0
+            }
+        },
+        "xml" if fp.ends_with(".tei.xml") => {
+            match read_tei(BufReader::new(File::open(fp).unwrap())) {
+                Ok((id, text)) => {
+                    // GROBID leaves the id out, so documents are named after their file
+                    let name = Path::new(fp).file_name().unwrap().to_string_lossy();
+                    let id = if id.is_empty() { name.trim_end_matches(".tei.xml") } else { &id };
+                    let searched = search_document(id, &text) as usize;
+                    progress.document();
+                    searched
+                }
+                Err(e) => {
+                    error!(error = %e, "unreadable TEI XML");
+                    stats.skip("invalid-xml");
+                    0
+                }
+            }
+        },
+        "xml" if is_patent_xml(fp) => {
+            let file = CountingReader::new(File::open(fp).unwrap());
+            let read = file.counter();
+            let mut count = 0;
+            let parsed = read_uspto(BufReader::new(file), |number, text| {
+                if search_document(number, text) {
+                    progress.document_at(read.load(Ordering::Relaxed));
+                    count += 1;
+                }
+                !(stop > 0 && count == stop)
+            });
+            if let Err(e) = parsed {
+                error!(error = %e, "unreadable USPTO XML");
+                stats.skip("invalid-xml");
+            }
+            count
+        },
+        "xml" | "nxml" => {
+            // articles without a PMCID are named after their file
+            let stem = Path::new(fp).file_stem().unwrap().to_string_lossy().to_string();
+            let mut count = 0;
+            let parsed = read_jats(BufReader::new(File::open(fp).unwrap()), |pmcid, text| {
+                if search_document(if pmcid.is_empty() { &stem } else { pmcid }, text) {
+                    progress.document();
+                    count += 1;
+                }
+                !(stop > 0 && count == stop)
+            });
+            if let Err(e) = parsed {
+                error!(error = %e, "unreadable JATS XML");
+                stats.skip("invalid-xml");
+            }
+            count
+        },
+        "gz" => {
+            let compressed = CountingReader::new(File::open(fp).unwrap());
+            let read = compressed.counter();
+            let lines = BufReader::new(GzDecoder::new(compressed)).lines().enumerate().map(|(line_index, line)| (line_index + 1, line.unwrap()));
+            let mut count = 0;
+            let handle = |(line_number, outcome): (usize, LineOutcome)| {
+                stats.records_read += 1;
+                match outcome {
+                    LineOutcome::Empty => stats.skip("empty"),
+                    LineOutcome::Invalid(e) => {
+                        debug!(line = line_number, error = %e, "unreadable line");
+                        stats.skip("invalid-json");
+                    }
+                    LineOutcome::MissingProperty => stats.skip("missing-property"),
+                    LineOutcome::NoCorpusId(json_data) => {
+                        error!(line = line_number, document = %json_data, "corpusid not found");
+                        process::exit(1);
+                    }
+                    LineOutcome::Document { corpus_id, text, matches, paragraphs } => {
+                        trace!(line = line_number, corpusid = corpus_id, matches = matches.len(), "searched document");
+                        stats.add_matches(&matches);
+                        progress.worker.record(paragraphs, matches.len());
+                        count_document(&corpus_id.to_string(), &text, &matches);
+                        write_document(search, matcher, &mut writer, &corpus_id.to_string(), &text, matches);
+                        progress.document_at(read.load(Ordering::Relaxed));
+                        count += 1;
+                    }
+                }
+                !(stop > 0 && count == stop)
+            };
+            // decompressing, parsing and matching overlap on threads of their own, and the
+            // outcomes come back here in order
+            std::thread::scope(|scope| {
+                let lines = pool::source(scope, LINE_BATCH, lines);
+                let parsed = pool::stage(scope, search.file_threads.div_ceil(4), lines, |(line_number, line): (usize, String)| (line_number, parse_line(&line, property)));
+                let searched = pool::stage(scope, search.file_threads, parsed, |(line_number, mut outcome): (usize, LineOutcome)| {
+                    if let LineOutcome::Document { text, matches, paragraphs, .. } = &mut outcome {
+                        (*matches, *paragraphs) = matcher.search_counted(text);
+                    }
+                    (line_number, outcome)
+                });
+                pool::for_each_ordered(searched, handle);
+            });
+            count
+        },
+        _ => { panic!("Unsupported file type") }
+    };
+    writer.finish();
+    info!(documents, "searched file");
+    results.documents = documents as u64;
+    results.stats.replaced_characters = replaced;
+    results
+}
 
+// path with .shard<index> inserted before its extension
+fn shard_path(path: &str, index: usize) -> String {
+    let file_name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    match path[file_name_start..].find('.').filter(|&dot| dot > 0) {
+        Some(dot) => format!("{}.shard{}{}", &path[..file_name_start + dot], index, &path[file_name_start + dot..]),
+        None => format!("{}.shard{}", path, index),
+    }
+}
+
+// Every count-th file starting at index
+fn select_shard(files: &[PathBuf], index: usize, count: usize) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if index >= count {
+        return Err(format!("--shard-index {} must be below --shard-count {}", index, count).into());
+    }
+    Ok(files.iter().skip(index).step_by(count).cloned().collect())
+}
+
+async fn process_files(mut opt: Opt, multi: &MultiProgress) -> Result<(), Box<dyn Error>> {
+    let started = std::time::Instant::now();
+    let mut summary = RunSummary::new(serde_json::to_value(&opt)?);
+    if let (Some(index), Some(count)) = (opt.shard_index, opt.shard_count) {
+        opt.files = select_shard(&opt.files, index, count)?;
+        opt.output_file = opt.output_file.map(|output_file| shard_path(&output_file, index));
+        opt.candidates_file = opt.candidates_file.map(|candidates_file| shard_path(&candidates_file, index));
+        opt.summary_file = opt.summary_file.map(|summary_file| shard_path(&summary_file, index));
+        opt.cooccurrence_file = opt.cooccurrence_file.map(|cooccurrence_file| shard_path(&cooccurrence_file, index));
+        opt.index_file = opt.index_file.map(|index_file| shard_path(&index_file, index));
+        opt.tfidf_file = opt.tfidf_file.map(|tfidf_file| shard_path(&tfidf_file, index));
+    }
+    if opt.partition_by.is_some() && (opt.aggregate != Aggregate::Match || opt.output_format != OutputFormat::Csv) {
+        return Err("--partition-by needs csv rows of each match".into());
+    }
+    let keep_parts = opt.no_merge || opt.per_file_output.is_some();
+    let output_file = match &opt.per_file_output {
+        // the directory of per-file outputs also holds the ledger
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let base = dir.join("chem-matcher").display().to_string();
+            opt.shard_index.map_or(base.clone(), |index| shard_path(&base, index))
+        }
+        None => opt.output_file.clone().ok_or("--output is required to search files")?,
+    };
+    if let Some(dir) = &opt.per_file_output {
+        let mut names = HashSet::new();
+        for file_path in &opt.files {
+            if !names.insert(per_file_path(dir, file_path)) {
+                return Err(format!("several inputs would write {}", per_file_path(dir, file_path)).into());
+            }
+        }
+    }
+    let mut ledger = open_ledger(&opt, &output_file)?;
+    // results of inputs finished earlier are already in the output
+    let resume = !ledger.is_empty();
+    let mut inputs = Vec::new();
     for (index, file_path) in opt.files.iter().enumerate() {
-        let property = opt.property.clone();
         let fp = file_path.to_str().unwrap().to_string();
-        let map: Arc<HashMap<String, u32>> = Arc::clone(&map);
+        let id = FileId::of(file_path)?;
+        if ledger.is_complete(&fp, &id) {
+            info!(file = %fp, "already processed, skipping");
+            summary.inputs_skipped.push(fp);
+        } else {
+            inputs.push((index, fp, id));
+        }
+    }
+    // a single part becomes the output as is
+    let rename_single = inputs.len() == 1 && !resume && !keep_parts && !rotates(&opt) && opt.partition_by.is_none();
+    let banned = Arc::new(load_banned(&opt).await?);
+    let matcher = Arc::new(build_matcher(&opt, &banned)?);
+    let search = Arc::new(FileSearch::new(&opt, &banned)?);
+    let (tx, rx) = flume::bounded(COLLECTED_CHUNKS);
+    let metrics = Arc::new(Metrics::new());
+    report_metrics(&opt, &metrics);
+    let documents = documents_bar(multi)?;
+    let total = inputs.len();
+    documents.set_message(format!("0/{} files", total));
+
+    // the merged output, which workers stream their rows to; kept parts are written by workers
+    let mut merged = if keep_parts || rename_single {
+        None
+    } else if rotates(&opt) {
+        Some(Merged::Rotating(RotatingWriter::new(&output_file, opt.rotate_rows, opt.rotate_bytes, resume)?))
+    } else if let Some(PartitionBy::Cid) = opt.partition_by {
+        Some(Merged::Partitions(PartitionWriter::new(Path::new(&output_file), opt.partition_buckets, opt.max_open_files, resume)?))
+    } else if resume {
+        let output = fs::OpenOptions::new().create(true).append(true).open(&output_file)?;
+        // rows an interrupted run wrote of an input it did not finish
+        if let Some(len) = ledger.output_len().filter(|len| output.metadata().is_ok_and(|metadata| metadata.len() > *len)) {
+            warn!(output = %output_file, len, "truncating rows of an unfinished input");
+            output.set_len(len)?;
+        }
+        Some(Merged::File(BufWriter::new(output)))
+    } else {
+        // a new output is merged under a temporary name and renamed once complete
+        Some(Merged::File(BufWriter::new(File::create(format!("{}.tmp", output_file))?)))
+    };
+    let merging_tmp = matches!(merged, Some(Merged::File(_))) && !resume;
+    // part file of each input, which holds its rows when they are not streamed
+    let mut parts = Vec::new();
+    for (position, (index, fp, id)) in inputs.into_iter().enumerate() {
         let tx = tx.clone();
-        let output_file = opt.output_file.clone();
+        let (matcher, search) = (Arc::clone(&matcher), Arc::clone(&search));
+        let (multi, documents, metrics) = (multi.clone(), documents.clone(), Arc::clone(&metrics));
+        let ofp = match &opt.per_file_output {
+            Some(dir) => per_file_path(dir, Path::new(&fp)),
+            None => format!("{}_{}", output_file, &index.to_string()),
+        };
+        parts.push(ofp.clone());
+        let writer = if merged.is_some() { RowWriter::Chunks(position, Vec::new(), tx.clone()) } else { RowWriter::part(&ofp) };
         tokio::spawn(async move {
-            let ext = Path::new(&fp).extension().unwrap();
-            let mut text: String;
-            let ofp = format!("{}_{}", output_file, &index.to_string());
-            let output_path = Path::new(&ofp);
-            let mut writer = BufWriter::new(File::create(output_path).unwrap());
-            match ext.to_str().unwrap() {
-                "txt" => {
-                    text = fs::read_to_string(&fp).unwrap();
-                    let search_result = search_keys_in_text(&*map, &text);
-                    generate_report(search_result, &mut writer, "");
-                },
-                "gz" => {
-                    // TODO: WHY IS IT ALL LOADING INTO RAM??
-                    let gz = BufReader::new(GzDecoder::new(File::open(&fp).unwrap()));
-                    let mut count = 0;
-                    for line in gz.lines() {
-                        if opt.stop > 0 && count == opt.stop {
-                            break;
-                        }
-                        // skip empty lines
-                        if line.as_ref().unwrap().is_empty() {
-                            continue;
-                        }
-                        match serde_json::from_str::<serde_json::Value>(&line.unwrap()) {
-                            Ok(json_data) => {
-                                //print out json_data attributes
-                                match json_data["content"][&property].as_str() {
-                                    Some(t) => { text = t.to_string(); },
-                                    None => { continue; }
-                                }
-                                let corpus_id  = match json_data["corpusid"].as_u64() {
-                                    Some(t) => { t },
-                                    None => {
-                                        println!("{}", json_data.to_string());
-                                        println!("Error: corpusid not found"); 
-                                        process::exit(1);
-                                        //continue; 
-                                    }
-                                };
-                                let search_result = search_keys_in_text(&*map, &text);
-                                generate_report(search_result, &mut writer, &corpus_id.to_string());
-                                count += 1;
-                            },
-                            Err(e) => {
-                                println!("Error: {}", e);
-                                continue;
-                            }
-                        }
-                    }
-                },
-                _ => { panic!("Unsupported file type") }
-            }
-            writer.flush().unwrap();
-            tx.send(ofp).unwrap();
+            // the bar and task slot are taken once the input is being searched
+            let slot = metrics.task_slot();
+            let progress = FileProgress::new(&multi, &documents, &fp, Arc::clone(&slot.worker)).unwrap();
+            let results = search_file(&fp, writer, &matcher, &search, &progress);
+            tx.send(Collected::Finished(position, Box::new((ofp, results, fp, id)))).unwrap();
         });
     }
 
     drop(tx);
 
-    // concat all files
-    let mut writer = BufWriter::new(File::create(&opt.output_file).unwrap());
-    for file_path in rx.iter() {
-        let content = fs::read_to_string(&file_path).unwrap();
-        writer.write_all(content.as_bytes()).unwrap();
-        fs::remove_file(file_path).unwrap();
+    // Rows go straight to the output from one input at a time, so each input's rows stay together
+    // and an interrupted run leaves at most one input partly written, which resuming truncates
+    // back to the output length recorded in the ledger with the last finished input. Rows of the others are
+    // spooled to their part file until their turn, rather than held in memory, and the bounded
+    // channel blocks workers while the collector catches up.
+    let mut current = None;
+    // part files holding the rows of inputs other than the current one, open while the input is
+    // searched and closed once it finishes
+    let mut spools: HashMap<usize, Option<BufWriter<File>>> = HashMap::new();
+    // inputs to record in the ledger once their results are in the output
+    let mut unrecorded = Vec::new();
+    let mut results = FileResults::default();
+    // finished inputs not written yet, by position in the inputs
+    let mut finished = BTreeMap::new();
+    let mut next = 0;
+    let mut done = 0;
+    for collected in rx.iter() {
+        match collected {
+            Collected::Rows(position, rows) => {
+                // with --ordered, only the next input in --files order is written
+                let current = *current.get_or_insert(if opt.ordered { next } else { position });
+                match merged.as_mut() {
+                    Some(merged) if position == current => merged.write_rows(&rows[..])?,
+                    _ => match spools.entry(position) {
+                        Entry::Occupied(mut spool) => spool.get_mut().as_mut().unwrap().write_all(&rows)?,
+                        Entry::Vacant(spool) => spool.insert(Some(BufWriter::new(File::create(&parts[position])?))).as_mut().unwrap().write_all(&rows)?,
+                    },
+                }
+            }
+            Collected::Finished(position, part) => {
+                done += 1;
+                documents.set_message(format!("{}/{} files", done, total));
+                finished.insert(position, part);
+                // its rows are all sent, so its spool is complete
+                if let Some(spool) = spools.get_mut(&position).and_then(Option::take) {
+                    drop(spool.into_inner()?);
+                }
+            }
+        }
+        loop {
+            if current.is_none() {
+                // inputs already finished first, then one being spooled
+                current = if opt.ordered { Some(next) } else { finished.keys().chain(spools.keys()).next().copied() };
+                if let (Some(spool), Some(merged)) = (current.and_then(|current| spools.remove(&current)), merged.as_mut()) {
+                    let part = &parts[current.unwrap()];
+                    if let Some(spool) = spool {
+                        drop(spool.into_inner()?);
+                    }
+                    merged.write_rows(BufReader::new(File::open(part)?))?;
+                    fs::remove_file(part)?;
+                }
+            }
+            let Some(input) = current.and_then(|current| finished.remove(&current)) else {
+                break;
+            };
+            let (part, file_results, input, id) = *input;
+            current = None;
+            next += 1;
+            let mut output_len = None;
+            if let Some(merged) = merged.as_mut() {
+                merged.flush()?;
+                output_len = merged.len()?;
+            } else if rename_single {
+                fs::rename(part, &output_file)?;
+                output_len = Some(fs::metadata(&output_file)?.len());
+            }
+            summary.add_input(&input, results.merge(file_results));
+            unrecorded.push((input, id, output_len));
+            if !merging_tmp {
+                for (input, id, output_len) in unrecorded.drain(..) {
+                    record(&mut ledger, &input, &id, output_len)?;
+                }
+            }
+        }
+    }
+    documents.finish();
+    info!("{}", metrics.rate_line());
+    if merging_tmp {
+        fs::rename(format!("{}.tmp", output_file), &output_file)?;
+        for (input, id, output_len) in unrecorded {
+            record(&mut ledger, &input, &id, output_len)?;
+        }
     }
+    results.write(&opt, &matcher)?;
+    summary.wall_time_seconds = started.elapsed().as_secs_f64();
+    summary.write(&summary_path(&opt, &output_file))?;
     Ok(())
 }
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let opt = Opt::from_args();
-    process_files(opt).await?;
-    Ok(())
+// The single output the rows of every input are merged into
+enum Merged {
+    File(BufWriter<File>),
+    Rotating(RotatingWriter),
+    Partitions(PartitionWriter),
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Merged {
+    fn write_rows(&mut self, mut rows: impl BufRead) -> Result<(), Box<dyn Error>> {
+        match self {
+            Merged::File(writer) => {
+                io::copy(&mut rows, writer)?;
+            }
+            Merged::Rotating(rotating) => rotating.copy_lines(rows)?,
+            Merged::Partitions(partitions) => partitions.copy_lines(rows)?,
+        }
+        Ok(())
+    }
 
-    #[tokio::test]
-    async fn test_standardize() {
-        let stemmer = StemmerWrapper::new();
-        let banned = fetch_words_from_url(BANNED).await.unwrap();
-        assert!(banned.contains(stemmer.standardize("pathways").as_str()));
-        assert!(!banned.contains(stemmer.standardize("Acetaminophen").as_str()));
+    // Bytes written to a single output file, once flushed
+    fn len(&self) -> io::Result<Option<u64>> {
+        match self {
+            Merged::File(writer) => Ok(Some(writer.get_ref().metadata()?.len())),
+            Merged::Rotating(_) | Merged::Partitions(_) => Ok(None),
+        }
     }
 
-    #[test]
-    fn test_parse_csv() {
-        let content = "43\texample\n16\tworld";
-        let mut banned = HashSet::new();
-        banned.insert("exampl".to_string());
-        let (dir, filename) = (std::env::temp_dir(), "test.csv");
-        let file_path = dir.join(filename);
-        fs::write(&file_path, content).unwrap();
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Merged::File(writer) => writer.flush(),
+            Merged::Rotating(rotating) => rotating.flush(),
+            Merged::Partitions(partitions) => partitions.flush(),
+        }
+    }
+}
 
-        let map = parse_csv(file_path.to_str().unwrap(), &banned).unwrap();
+// --summary, or <output>.summary.json
+fn summary_path(opt: &Opt, output_file: &str) -> String {
+    opt.summary_file.clone().unwrap_or_else(|| format!("{}.summary.json", output_file))
+}
 
-        let mut expected_map = HashMap::new();
-        //expected_map.insert("example".to_string(), "test".to_string());
-        expected_map.insert("World".to_string(), 16);
+// Result file in dir named after an input, e.g. dir/shard-0001.csv for shard-0001.json.gz
+fn per_file_path(dir: &Path, input: &Path) -> String {
+    let name = input.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    let stem = [".json", ".jsonl", ".txt", ".tei.xml", ".xml", ".nxml", ".pdf", ".tex", ".csv", ".tsv", ".arrow", ".arrows", ".feather", ".html", ".htm"].iter().find_map(|extension| name.strip_suffix(extension)).unwrap_or(name);
+    dir.join(format!("{}.csv", stem)).display().to_string()
+}
 
-        assert_eq!(map, expected_map);
+// Whether merged output goes to numbered parts instead of a single file
+fn rotates(opt: &Opt) -> bool {
+    opt.rotate_rows.is_some() || opt.rotate_bytes.is_some()
+}
+
+// Record input as complete, with the length of the merged output once its rows are in it
+fn record(ledger: &mut Ledger, input: &str, id: &FileId, output_len: Option<u64>) -> Result<(), Box<dyn Error>> {
+    match output_len {
+        Some(len) => ledger.record_at(input, id, "complete", len),
+        None => ledger.record(input, id, "complete"),
     }
+}
 
-    #[test]
-    fn test_search_keys_in_text() {
-        let mut map = HashMap::new();
-        map.insert("Apple".to_string(), 1);
-        map.insert("Orange".to_string(), 2);
-        map.insert("Carrot".to_string(), 3);
+// Whether path is a file --watch searches
+fn is_shard(path: &Path) -> bool {
+    path.to_str().is_some_and(|name| name.ends_with(".json.gz") || name.ends_with(".xml.gz")) && path.is_file()
+}
+
+// Ledger of inputs already written to output_file; --force starts a new one. Files counting every
+// input are refused once it lists any, as the inputs it skips would be missing from their counts.
+fn open_ledger(opt: &Opt, output_file: &str) -> Result<Ledger, Box<dyn Error>> {
+    let ledger_path = PathBuf::from(format!("{}.ledger", output_file));
+    if opt.force && ledger_path.exists() {
+        fs::remove_file(&ledger_path)?;
+    }
+    let ledger = Ledger::open(&ledger_path)?;
+    let counted = [
+        ("--candidates", &opt.candidates_file),
+        ("--cooccurrence", &opt.cooccurrence_file),
+        ("--index", &opt.index_file),
+        ("--tfidf", &opt.tfidf_file),
+    ];
+    let counted: Vec<&str> = counted.iter().filter(|(_, file)| file.is_some()).map(|(flag, _)| *flag).collect();
+    if !ledger.is_empty() && !counted.is_empty() {
+        return Err(format!(
+            "{} lists inputs finished by an earlier run, which {} would leave out; rerun with --force to search them all again",
+            ledger_path.display(),
+            counted.join(", ")
+        )
+        .into());
+    }
+    Ok(ledger)
+}
+
+// Append the whole of part to output in one write, so readers never see a partial shard
+fn append_file(part: &str, output: &str) -> Result<(), Box<dyn Error>> {
+    let content = fs::read(part)?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(output)?;
+    file.write_all(&content)?;
+    file.sync_data()?;
+    fs::remove_file(part)?;
+    Ok(())
+}
 
-        let text = "I have an apple and an orange, but I do not have a carrot.";
-        let search_results = search_keys_in_text(&map, &text);
+async fn watch_dir(opt: &Opt, dir: &Path, multi: &MultiProgress) -> Result<(), Box<dyn Error>> {
+    let output_file = opt.output_file.clone().ok_or("--output is required to search files")?;
+    let banned = Arc::new(load_banned(opt).await?);
+    let matcher = Arc::new(build_matcher(opt, &banned)?);
+    let mut ledger = open_ledger(opt, &output_file)?;
+    let mut rotating = if rotates(opt) { Some(RotatingWriter::new(&output_file, opt.rotate_rows, opt.rotate_bytes, true)?) } else { None };
+    // shards finished by an earlier run, checked once rather than rehashed on every poll
+    let mut done: HashSet<PathBuf> = HashSet::new();
+    for path in fs::read_dir(dir)?.filter_map(|entry| entry.ok().map(|entry| entry.path())).filter(|path| is_shard(path)) {
+        // a shard that cannot be read now is searched, and its error reported, by the poll below
+        if FileId::of(&path).is_ok_and(|id| ledger.is_complete(&path.display().to_string(), &id)) {
+            done.insert(path);
+        }
+    }
+    let search = Arc::new(FileSearch::new(opt, &banned)?);
+    let mut results = FileResults::default();
+    // counts since the watch started, rewritten after every shard
+    let started = std::time::Instant::now();
+    let mut summary = RunSummary::new(serde_json::to_value(opt)?);
+    // size of each pending shard at the previous poll
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let documents = documents_bar(multi)?;
+    let metrics = Arc::new(Metrics::new());
+    report_metrics(opt, &metrics);
+    info!(dir = %dir.display(), "watching for new shards");
+    loop {
+        let mut shards: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_shard(path) && !done.contains(path))
+            .collect();
+        shards.sort();
+        for shard in shards {
+            // a shard still being copied in grows between polls
+            let size = fs::metadata(&shard)?.len();
+            if sizes.insert(shard.clone(), size) != Some(size) {
+                continue;
+            }
+            let (fp, part) = (shard.to_str().unwrap().to_string(), format!("{}.part", output_file));
+            let (shard_matcher, search) = (Arc::clone(&matcher), Arc::clone(&search));
+            let part_path = part.clone();
+            let progress = FileProgress::new(multi, &documents, &fp, metrics.worker("watch"))?;
+            let shard_results = tokio::task::spawn_blocking(move || search_file(&fp, RowWriter::part(&part_path), &shard_matcher, &search, &progress)).await?;
+            match rotating.as_mut() {
+                Some(rotating) => {
+                    rotating.copy_lines(BufReader::new(File::open(&part)?))?;
+                    rotating.flush()?;
+                    fs::remove_file(&part)?;
+                }
+                None => append_file(&part, &output_file)?,
+            }
+            ledger.record(&shard.display().to_string(), &FileId::of(&shard)?, "complete")?;
+            summary.add_input(&shard.display().to_string(), results.merge(shard_results));
+            results.write(opt, &matcher)?;
+            summary.wall_time_seconds = started.elapsed().as_secs_f64();
+            summary.write(&summary_path(opt, &output_file))?;
+            info!(shard = %shard.display(), "processed shard");
+            sizes.remove(&shard);
+            done.insert(shard);
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(opt.watch_interval)).await;
+    }
+}
 
-        let expected_results = vec![
-            ("I have an <|MOLECULE|> and an orange, but I do not have a carrot.".to_string(), "Apple".to_string(), 1),
-            ("I have an apple and an <|MOLECULE|>, but I do not have a carrot.".to_string(), "Orange".to_string(), 2),
-            ("I have an apple and an orange, but I do not have a <|MOLECULE|>.".to_string(), "Carrot".to_string(), 3),
-        ];
+// Stderr for log lines, written while the progress bars are cleared so they don't garble them
+#[derive(Clone)]
+struct ProgressStderr(MultiProgress);
 
-        assert_eq!(search_results, expected_results);
+impl Write for ProgressStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stderr().write(buf))
     }
 
-    #[test]
-    fn test_search_keys_in_text_cases() {
-        let mut map = HashMap::new();
-        map.insert("Apple juice".to_string(), 1);
-        map.insert("ORANGE".to_string(), 2);
-        map.insert("Carrot".to_string(), 3);
-        map.insert("juice".to_string(), 4);
-        map.insert("Apple".to_string(), 5);
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
 
-        let text = "I have an apple juice and an ORANGE, but I do not have a CARROT. Apple";
-        let search_results = search_keys_in_text(&map, &text);
+impl<'a> MakeWriter<'a> for ProgressStderr {
+    type Writer = ProgressStderr;
 
-        let expected_results = vec![
-            ("I have an <|MOLECULE|> and an ORANGE, but I do not have a CARROT. Apple".to_string(), "Apple juice".to_string(), 1),
-            ("I have an apple juice and an <|MOLECULE|>, but I do not have a CARROT. Apple".to_string(), "ORANGE".to_string(), 2),
-            ("I have an <|MOLECULE|> juice and an ORANGE, but I do not have a CARROT. <|MOLECULE|>".to_string(), "Apple".to_string(), 5),
-        ];
+    fn make_writer(&'a self) -> ProgressStderr {
+        self.clone()
+    }
+}
+
+// Most detailed level logged for a --verbose count
+fn log_level(verbose: u8) -> Level {
+    match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
+}
 
-        assert_eq!(search_results, expected_results);
+// Send log events to --log-file, or to stderr around the progress bars of multi
+fn init_logging(opt: &Opt, multi: &MultiProgress) -> Result<(), Box<dyn Error>> {
+    let writer = match &opt.log_file {
+        Some(log_file) => BoxMakeWriter::new(Mutex::new(fs::OpenOptions::new().create(true).append(true).open(log_file)?)),
+        None => BoxMakeWriter::new(ProgressStderr(multi.clone())),
+    };
+    let logs = tracing_subscriber::fmt().with_max_level(log_level(opt.verbose)).with_target(false).with_writer(writer);
+    if opt.log_json {
+        logs.json().init();
+    } else {
+        logs.with_ansi(opt.log_file.is_none() && io::stderr().is_terminal()).init();
     }
+    Ok(())
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // variables that are not unicode set no option
+    let vars = std::env::vars_os().filter_map(|(var, value)| Some((var.into_string().ok()?, value.into_string().ok()?)));
+    let opt = parse_args(std::env::args_os().collect(), vars)?;
+    let multi = MultiProgress::new();
+    init_logging(&opt, &multi)?;
+    match &opt.command {
+        Some(Command::CompileDict { output }) => compile_dict(&opt, output).await?,
+        Some(Command::ValidateDict { report }) => validate_dict(&opt, report.as_deref()).await?,
+        Some(Command::DictStats) => print_dict_stats(&opt).await?,
+        Some(Command::Stats { results, top, examples }) => print_result_stats(results, *top, *examples)?,
+        Some(Command::ExportDataset { output, results, splits, seed }) => export_dataset(output, results, splits, *seed)?,
+        Some(Command::Convert { output, results, from, to }) => {
+            let options = SearchOptions::new(&opt.paragraph_delimiter, 0)?;
+            let to = to.unwrap_or_else(|| ResultFormat::of_path(output));
+            let rows = convert_results(results, *from, output, to, &options, &opt.mask)?;
+            println!("Converted {} rows to {}", rows, output);
+        }
+        Some(Command::Eval { gold, texts, entity_type }) => evaluate(&opt, gold, texts.as_deref(), entity_type.as_deref()).await?,
+        Some(Command::Merge { output, results }) => {
+            let started = std::time::Instant::now();
+            let mut summary = RunSummary::new(serde_json::to_value(&opt)?);
+            merge_results(results, output, &mut summary)?;
+            summary.wall_time_seconds = started.elapsed().as_secs_f64();
+            summary.write(&summary_path(&opt, output))?;
+            println!("{} rows of {} files, {} duplicates dropped", summary.matches, results.len(), summary.records_skipped.get("duplicate-row").unwrap_or(&0));
+        }
+        Some(Command::Dedupe { output, results, near, buckets }) => {
+            let (kept, dropped) = dedupe_results(results, output, *near, *buckets)?;
+            println!("{} rows kept, {} duplicates dropped", kept, dropped);
+        }
+        Some(Command::Enrich { output, results, pubchem_rate, pubchem_url, no_cache, xrefs, no_pubchem }) => {
+            let fetch = FetchOptions { ttl_hours: 0, max_attempts: opt.max_attempts, proxy: opt.proxy.clone(), ca_bundle: opt.ca_bundle.clone() };
+            let cache = cache_dir().filter(|_| !no_cache).map(|dir| dir.join("pubchem"));
+            let mut pubchem = if *no_pubchem { None } else { Some(PubChem::new(pubchem_url, cache, *pubchem_rate, &fetch)?) };
+            let xrefs = xrefs.as_deref().map(Xrefs::read).transpose()?;
+            let (rows, cids) = enrich_results(results, output, pubchem.as_mut(), xrefs.as_ref()).await?;
+            println!("{} rows enriched with the properties of {} CIDs", rows, cids);
+        }
+        Some(Command::Sample { results, rows, per_cid, seed }) => {
+            for line in sample_results(results, *rows, *per_cid, *seed)? {
+                println!("{}", line);
+            }
+        }
+        Some(Command::BuildStoplist { output, min_df, sample }) => write_stoplist(&opt, output, *min_df, *sample)?,
+        Some(Command::Serve { address, grpc_address }) => serve_matches(&opt, *address, *grpc_address).await?,
+        None => match &opt.watch {
+            Some(dir) => watch_dir(&opt, dir, &multi).await?,
+            None => process_files(opt, &multi).await?,
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use std::path::PathBuf;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tempdir::TempDir;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_gz_json_file() {
@@ -382,6 +2050,8 @@ mod tests {
 
         let tmp_dir = TempDir::new("rs_temp_dir").unwrap();
         let csv_filename = tmp_dir.path().join("test.csv");
+        let banned_filename = tmp_dir.path().join("banned.txt");
+        fs::write(&banned_filename, "example\nthis\n").unwrap();
         let text_filename = tmp_dir.path().join("text.json.gz");
 
         let text_filename_str = text_filename.to_str().unwrap();
@@ -395,17 +2065,194 @@ mod tests {
         }
 
         let opt = Opt {
-            csv_file: csv_filename.to_str().unwrap().to_string(),
+            csv_files: vec![csv_filename.to_str().unwrap().to_string()],
+            csv_precedence: Precedence::First,
+            mmap_dict: false,
+            csv_columns: DictColumns::default(),
+            csv_has_header: false,
+            conflict_resolution: Resolution::Last,
+            conflicts_file: None,
             files: vec![PathBuf::from(text_filename_str)],
-            output_file: "output.txt".to_string(),
+            command: None,
+            output_file: Some("output.txt".to_string()),
             property: "text".to_string(),
             stop: 0,
+            file_threads: 1,
+            banned_urls: vec![],
+            banned_files: vec![banned_filename.to_str().unwrap().to_string()],
+            banned_ttl: 0,
+            max_attempts: 1,
+            proxy: None,
+            ca_bundle: None,
+            no_banned: false,
+            ban_synonyms: None,
+            ban_cids: None,
+            only_cids: None,
+            min_length: 5,
+            short_names: None,
+            context_window: 0,
+            paragraph_delimiter: r"\n\n".to_string(),
+            mask: MASK.to_string(),
+            all_overlaps: false,
+            no_nfkc: false,
+            no_dehyphenate: false,
+            no_decode_entities: false,
+            no_strip_controls: false,
+            tex_mhchem: false,
+            html_main_content: false,
+            encoding: None,
+            text_column: "text".to_string(),
+            id_column: None,
+            config: None,
+            verbose: 0,
+            log_json: false,
+            log_file: None,
+            watch: None,
+            force: false,
+            no_merge: false,
+            per_file_output: None,
+            summary_file: None,
+            metrics_interval: 0,
+            metrics_address: None,
+            ordered: false,
+            rotate_rows: None,
+            rotate_bytes: None,
+            partition_by: None,
+            partition_buckets: None,
+            max_open_files: 256,
+            shard_index: None,
+            shard_count: None,
+            watch_interval: 10,
+            variants: false,
+            strip_salts: false,
+            salt_suffixes: vec![],
+            case_mode: CaseMode::Title,
+            fuzzy: false,
+            fuzzy_min_length: 8,
+            abbreviations: false,
+            cas: false,
+            cas_map: None,
+            id_map: None,
+            inchi: false,
+            formulas: false,
+            formula_whitelist: vec![],
+            candidates_file: None,
+            opsin: false,
+            opsin_command: OPSIN.to_string(),
+            cooccurrence_file: None,
+            cooccurrence_format: GraphFormat::EdgeList,
+            index_file: None,
+            index_counts: false,
+            aggregate: Aggregate::Match,
+            output_format: OutputFormat::Csv,
+            tfidf_file: None,
+            brat_dir: None,
+            ambiguous_terms: None,
+            gate_window: 10,
         };
-        let result = process_files(opt).await;
+        let result = process_files(opt, &MultiProgress::new()).await;
         assert!(result.is_ok());
         assert!(read_to_string("output.txt").is_ok());
-        assert_eq!(read_to_string("output.txt").unwrap(), "\"Phenol peroxidase\",43,\"this is a <|MOLECULE|> of \\\"json\\\"\",533\n");
+        assert_eq!(read_to_string("output.txt").unwrap(), "\"Phenol peroxidase\",43,\"this is a <|MOLECULE|> of \\\"json\\\"\",533,exact,name,0.800\n");
         //clean-up
+        let summary: serde_json::Value = serde_json::from_str(&read_to_string("output.txt.summary.json").unwrap()).unwrap();
+        assert_eq!((summary["records_read"].as_u64(), summary["matches"].as_u64(), summary["unique_cids"].as_u64()), (Some(2), Some(1), Some(1)));
+        assert_eq!(summary["config"]["case_mode"], "title");
         fs::remove_file("output.txt").unwrap();
+        fs::remove_file("output.txt.ledger").unwrap();
+        fs::remove_file("output.txt.summary.json").unwrap();
     }
-}
\ No newline at end of file
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_resume_counts() {
+        let tmp_dir = TempDir::new("resume_counts").unwrap();
+        let path = |name: &str| tmp_dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("dict.csv"), "2244\tAspirin\n").unwrap();
+        fs::write(path("a.txt"), "Aspirin was given.").unwrap();
+        fs::write(path("b.txt"), "More Aspirin.").unwrap();
+        let opt = |files: &[&str], force: bool| {
+            let mut args = vec!["chem-matcher".to_string(), "--no-banned".to_string(), "-c".to_string(), path("dict.csv")];
+            args.extend(["-o".to_string(), path("out.csv"), "--index".to_string(), path("index.jsonl"), "-f".to_string()]);
+            args.extend(files.iter().map(|file| path(file)));
+            if force {
+                args.push("--force".to_string());
+            }
+            parse_args(args.into_iter().map(OsString::from).collect(), Vec::new()).unwrap()
+        };
+        let index = || read_to_string(path("index.jsonl")).unwrap();
+
+        process_files(opt(&["a.txt"], false), &MultiProgress::new()).await.unwrap();
+        assert_eq!(index(), format!("{{\"cid\":2244,\"corpusids\":[{:?}]}}\n", path("a.txt")));
+        // resuming would write an index of b.txt alone
+        let error = process_files(opt(&["a.txt", "b.txt"], false), &MultiProgress::new()).await.unwrap_err();
+        assert!(error.to_string().contains("--index would leave out; rerun with --force"));
+        assert!(index().contains("a.txt") && !index().contains("b.txt"));
+        process_files(opt(&["a.txt", "b.txt"], true), &MultiProgress::new()).await.unwrap();
+        assert_eq!(index(), format!("{{\"cid\":2244,\"corpusids\":[{:?},{:?}]}}\n", path("a.txt"), path("b.txt")));
+        assert_eq!(read_to_string(path("out.csv")).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_select_shard() {
+        let files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("{}.json.gz", i))).collect();
+        assert_eq!(select_shard(&files, 1, 2).unwrap(), vec![PathBuf::from("1.json.gz"), PathBuf::from("3.json.gz")]);
+        assert_eq!(select_shard(&files, 0, 1).unwrap(), files);
+        assert!(select_shard(&files, 2, 2).is_err());
+
+        assert_eq!(shard_path("out/results.csv", 3), "out/results.shard3.csv");
+        assert_eq!(shard_path("out.d/results", 0), "out.d/results.shard0");
+        assert_eq!(shard_path(".hidden", 1), ".hidden.shard1");
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let tmp_dir = TempDir::new("config").unwrap();
+        let config = tmp_dir.path().join("run.toml");
+        fs::write(&config, "csv = [\"a.csv\", \"b.csv\"]\noutput = \"out.csv\"\nstop = 5\nno-banned = true\nordered = false\nmask = \"-\"\n").unwrap();
+        let args = |args: &[&str]| -> Vec<OsString> { ["chem-matcher", "--config", config.to_str().unwrap()].iter().chain(args).map(OsString::from).collect() };
+        let no_vars = Vec::new;
+        let opt = parse_args(args(&["--stop", "7", "-f", "in.txt"]), no_vars()).unwrap();
+        assert_eq!(opt.csv_files, vec!["a.csv", "b.csv"]);
+        assert_eq!((opt.output_file.as_deref(), opt.stop, opt.no_banned, opt.ordered, opt.mask.as_str()), (Some("out.csv"), 7, true, false, "-"));
+        assert_eq!(opt.files, vec![PathBuf::from("in.txt")]);
+        // a repeated option on the command line replaces all of the file's values
+        assert_eq!(parse_args(args(&["-c", "c.csv"]), no_vars()).unwrap().csv_files, vec!["c.csv"]);
+        assert!(parse_args(args(&["dict-stats"]), no_vars()).unwrap().command.is_some());
+
+        // environment variables come between the command line and the file
+        let vars = |vars: &[(&str, &str)]| -> Vec<(String, String)> { vars.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect() };
+        let set = vars(&[("CHEM_MATCHER_HOME", "/x"), ("CHEM_MATCHER_STOP", "9"), ("CHEM_MATCHER_CSV", "[\"x.csv\", \"y.csv\"]"), ("CHEM_MATCHER_LOG_JSON", "true"), ("CHEM_MATCHER_MASK", "[unclosed"), ("HOME", "/root")]);
+        let opt = parse_args(args(&["-s", "7"]), set.clone()).unwrap();
+        assert_eq!((opt.stop, opt.csv_files, opt.log_json, opt.mask, opt.output_file), (7, vec!["x.csv".to_string(), "y.csv".to_string()], true, "[unclosed".to_string(), Some("out.csv".to_string())));
+        assert_eq!(parse_args(args(&[]), set).unwrap().stop, 9);
+        let opt = parse_args(vec![OsString::from("chem-matcher")], vars(&[("CHEM_MATCHER_CONFIG", config.to_str().unwrap())])).unwrap();
+        assert_eq!((opt.config, opt.stop), (Some(config.clone()), 5));
+        let error = parse_args(vec![OsString::from("chem-matcher")], vars(&[("CHEM_MATCHER_CSV", "[{ path = \"a.csv\" }]")])).unwrap_err();
+        assert_eq!(error.to_string(), "csv in CHEM_MATCHER_CSV must be a string, number, boolean or array of them");
+
+        fs::write(&config, "csv = \"a.csv\"\nno-baned = true\n").unwrap();
+        assert_eq!(parse_args(args(&[]), no_vars()).unwrap_err().to_string(), format!("{}: unknown option no-baned", config.display()));
+        fs::write(&config, "csv = { path = \"a.csv\" }\n").unwrap();
+        assert!(parse_args(args(&[]), no_vars()).unwrap_err().to_string().contains("must be a string"));
+        fs::write(&config, "csv = \"a.csv").unwrap();
+        assert!(parse_args(args(&[]), no_vars()).unwrap_err().to_string().starts_with("cannot read --config"));
+    }
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(log_level(0), Level::WARN);
+        assert_eq!(log_level(2), Level::DEBUG);
+        assert_eq!(log_level(5), Level::TRACE);
+    }
+
+    #[test]
+    fn test_per_file_path() {
+        let dir = Path::new("results");
+        assert_eq!(per_file_path(dir, Path::new("in/shard-0001.json.gz")), "results/shard-0001.csv");
+        assert_eq!(per_file_path(dir, Path::new("pubmed24n0001.xml.gz")), "results/pubmed24n0001.csv");
+        assert_eq!(per_file_path(dir, Path::new("PMC1234567.nxml")), "results/PMC1234567.csv");
+        assert_eq!(per_file_path(dir, Path::new("paper.tei.xml")), "results/paper.csv");
+        assert_eq!(per_file_path(dir, Path::new("paper.txt")), "results/paper.csv");
+        assert_eq!(per_file_path(dir, Path::new("in/data.v2.gz")), "results/data.v2.csv");
+    }
+}
@@ -0,0 +1,1487 @@
+//! Finding dictionary keys and chemical identifiers in text.
+
+use std::error::Error;
+use std::collections::{HashSet, HashMap};
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use crate::dictionary::{expand_variants, Id};
+use aho_corasick::AhoCorasick;
+use crate::bloom::BloomFilter;
+use crate::keymap::KeyMap;
+use crate::text::{
+    case_key, case_word, clean_text_mapped, closing_bracket, dehyphenate_mapped, from_ascii_titlecase, normalize, to_ascii_titlecase, to_nfkc,
+    to_nfkc_mapped, tokenize_with, CaseMode, OffsetMap, StemmerWrapper, MIN_WORD_LENGTH, WORD_SPLITS,
+};
+
+/// Default text replacing the matched name in contexts
+pub const MASK: &str = "<|MOLECULE|>";
+
+// Element symbols accepted in molecular formulas
+const ELEMENTS: &[&str] = &[
+    "H", "He", "Li", "Be", "B", "C", "N", "O", "F", "Ne", "Na", "Mg", "Al", "Si", "P", "S", "Cl", "Ar", "K", "Ca",
+    "Sc", "Ti", "V", "Cr", "Mn", "Fe", "Co", "Ni", "Cu", "Zn", "Ga", "Ge", "As", "Se", "Br", "Kr", "Rb", "Sr", "Y",
+    "Zr", "Nb", "Mo", "Tc", "Ru", "Rh", "Pd", "Ag", "Cd", "In", "Sn", "Sb", "Te", "I", "Xe", "Cs", "Ba", "La", "Ce",
+    "Pr", "Nd", "Pm", "Sm", "Eu", "Gd", "Tb", "Dy", "Ho", "Er", "Tm", "Yb", "Lu", "Hf", "Ta", "W", "Re", "Os", "Ir",
+    "Pt", "Au", "Hg", "Tl", "Pb", "Bi", "Po", "At", "Rn", "Fr", "Ra", "Ac", "Th", "Pa", "U", "Np", "Pu", "Am", "Cm",
+    "Bk", "Cf", "Es", "Fm", "Md", "No", "Lr", "Rf", "Db", "Sg", "Bh", "Hs", "Mt", "Ds", "Rg", "Cn", "Nh", "Fl", "Mc",
+    "Lv", "Ts", "Og",
+];
+
+// Endings typical of chemical names, used to flag unknown candidates
+const CHEMICAL_SUFFIXES: &[&str] = &[
+    "ol", "ane", "ene", "yne", "ide", "ium", "ate", "ite", "one", "amine", "amide", "azole", "idine", "ose", "oic",
+];
+
+// Words suggesting a chemistry context around a match
+const CONTEXT_CUES: &[&str] = &[
+    "solution", "solvent", "mg", "g", "kg", "ml", "l", "mm", "μm", "mum", "mol", "mmol", "concentration", "dissolved",
+    "synthesized", "synthesised", "synthesis", "reacted", "reaction", "compound", "compounds", "yield", "added",
+    "purified", "dose", "doses", "treated", "molar", "aqueous", "buffer", "reagent", "catalyst", "titrated",
+];
+
+// Tokens on each side of a match searched for context cues
+const CUE_WINDOW: usize = 10;
+
+// Most key prefixes searched for before looking up a paragraph's tokens, few enough for Teddy
+const MAX_PREFIXES: usize = 64;
+// Bytes of those prefixes, short enough to cover small dictionaries, long enough to be rare
+const MIN_PREFIX_LEN: usize = 3;
+const MAX_PREFIX_LEN: usize = 8;
+
+/// Counter-ions and hydrates stripped by --strip-salts to find the parent compound
+pub const SALT_SUFFIXES: &[&str] = &[
+    "sulfate", "sulphate", "hydrochloride", "dihydrochloride", "hydrobromide", "hydrate", "monohydrate",
+    "dihydrate", "trihydrate", "hemihydrate", "sesquihydrate", "pentahydrate", "hexahydrate", "heptahydrate",
+    "anhydrous", "mesylate", "besylate", "tosylate", "maleate", "fumarate", "tartrate", "bitartrate",
+    "citrate", "succinate", "phosphate", "sodium", "potassium", "calcium", "sodium salt", "potassium salt",
+];
+
+/// Matches found in a text, in order
+pub type SearchResults = Vec<Match>;
+
+/// A key found in the text, with its masked context
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// Text around the match, with the match replaced by the mask
+    pub context: String,
+    /// Dictionary key or identifier found
+    pub key: String,
+    /// PubChem CID or other id, when known
+    pub cid: Option<Id>,
+    pub match_type: MatchType,
+    pub id_type: IdType,
+    /// Confidence between 0 and 1
+    pub score: f32,
+    /// Byte range of the match in the searched text
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What kind of identifier a match is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdType {
+    // a synonym from the dictionary
+    Name,
+    // a CAS Registry Number such as 50-78-2
+    Cas,
+    // an InChI string such as InChI=1S/CH4/h1H4
+    Inchi,
+    // a 27 character InChIKey such as VNWKTOKETHGBQD-UHFFFAOYSA-N
+    InchiKey,
+    // a molecular formula such as C6H12O6
+    Formula,
+}
+
+impl std::fmt::Display for IdType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IdType::Name => write!(f, "name"),
+            IdType::Cas => write!(f, "cas"),
+            IdType::Inchi => write!(f, "inchi"),
+            IdType::InchiKey => write!(f, "inchikey"),
+            IdType::Formula => write!(f, "formula"),
+        }
+    }
+}
+
+/// How a key was found in the text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchType {
+    Exact,
+    // a plural or -ic/-ate variant of a dictionary key
+    Inflected,
+    // a salt or hydrate form of a dictionary key, e.g. "morphine sulfate"
+    Salt,
+    // within the given edit distance of a dictionary key
+    Fuzzy(usize),
+    // an abbreviation defined next to a dictionary key, as in "tetrahydrofuran (THF)"
+    Abbreviation,
+}
+
+impl std::fmt::Display for MatchType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MatchType::Exact => write!(f, "exact"),
+            MatchType::Inflected => write!(f, "inflected"),
+            MatchType::Salt => write!(f, "salt"),
+            MatchType::Fuzzy(distance) => write!(f, "fuzzy:{}", distance),
+            MatchType::Abbreviation => write!(f, "abbreviation"),
+        }
+    }
+}
+
+// A dictionary hit within a paragraph
+struct Candidate {
+    first: usize, // token indices
+    last: usize,
+    start: usize, // byte range in the paragraph
+    end: usize,
+    key: String,
+    cid: Option<Id>,
+    match_type: MatchType,
+    id_type: IdType,
+}
+
+/// Settings for search_keys_in_text, compiled once and shared across workers
+pub struct SearchOptions {
+    /// Splits documents into paragraphs
+    pub paragraph_re: regex::Regex,
+    /// Tokens kept on each side of a match (0 keeps the whole paragraph)
+    pub context_window: usize,
+    /// Longest key, in tokens
+    pub max_ngram: usize,
+    /// Keep every overlapping match instead of the longest
+    pub all_overlaps: bool,
+    pub nfkc: bool,
+    /// Join words split by a hyphen and line break
+    pub dehyphenate: bool,
+    /// Decode HTML character references such as &amp; and &#8217;
+    pub decode_entities: bool,
+    /// Drop zero-width and control characters, see clean_text_mapped
+    pub strip_controls: bool,
+    /// Inflected forms (see expand_variants) -> CID
+    pub variants: HashMap<String, Id>,
+    /// Lowercase suffixes stripped to find a parent compound
+    pub salt_suffixes: Vec<String>,
+    pub case_mode: CaseMode,
+    pub fuzzy: Option<FuzzyIndex>,
+    /// Match abbreviations defined next to a match
+    pub abbreviations: bool,
+    /// Detect CAS Registry Numbers
+    pub cas: bool,
+    /// CAS number -> CID
+    pub cas_map: HashMap<String, Id>,
+    /// Detect InChI strings and InChIKeys
+    pub inchi: bool,
+    /// Detect molecular formulas
+    pub formulas: bool,
+    /// Formulas always accepted
+    pub formula_whitelist: HashSet<String>,
+    /// Number of CIDs of keys listed with several, lowering their score
+    pub ambiguity: HashMap<String, usize>,
+    /// Keys only matched near chemistry words
+    pub ambiguous_terms: HashSet<String>,
+    /// Tokens on each side of an ambiguous term searched for chemistry words
+    pub gate_window: usize,
+    /// Shortest key worth looking up, in bytes
+    pub min_length: usize,
+    /// Characters separating words, besides whitespace
+    pub word_splits: Vec<char>,
+    /// Replaces the matched name in contexts
+    pub mask: String,
+    /// First words of the keys and variants, so tokens starting none are skipped; None (as with
+    /// fuzzy matching, whose keys may start with any word, or a frozen map, whose processes would
+    /// each build their own) looks up every token
+    pub first_words: Option<BloomFilter>,
+    /// Prefixes, ASCII case-insensitive, starting every ASCII key and variant, so ASCII paragraphs
+    /// without any skip the lookups; None when the keys need more than MAX_PREFIXES
+    pub prefixes: Option<AhoCorasick>,
+}
+
+impl SearchOptions {
+    /// Default settings with paragraphs split on paragraph_delimiter (a regex) and contexts of
+    /// context_window tokens on each side (0 keeps the whole paragraph)
+    pub fn new(paragraph_delimiter: &str, context_window: usize) -> Result<SearchOptions, Box<dyn Error>> {
+        Ok(SearchOptions {
+            paragraph_re: regex::Regex::new(paragraph_delimiter)?,
+            context_window,
+            max_ngram: 2,
+            all_overlaps: false,
+            nfkc: true,
+            dehyphenate: true,
+            decode_entities: true,
+            strip_controls: true,
+            variants: HashMap::new(),
+            salt_suffixes: Vec::new(),
+            case_mode: CaseMode::Title,
+            fuzzy: None,
+            abbreviations: false,
+            cas: false,
+            cas_map: HashMap::new(),
+            inchi: false,
+            formulas: false,
+            formula_whitelist: HashSet::new(),
+            ambiguity: HashMap::new(),
+            ambiguous_terms: HashSet::new(),
+            gate_window: CUE_WINDOW,
+            min_length: MIN_WORD_LENGTH,
+            word_splits: WORD_SPLITS.to_vec(),
+            mask: MASK.to_string(),
+            first_words: None,
+            prefixes: None,
+        })
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions::new(r"\n\n", 0).unwrap()
+    }
+}
+
+/// A dictionary and the settings used to search text for it
+pub struct Matcher {
+    map: KeyMap,
+    options: SearchOptions,
+}
+
+impl Matcher {
+    /// Matcher for the keys of map, interned into a KeyMap; the n-gram and key lengths searched and
+    /// the filter of first words are derived from the keys, variants, salt suffixes and fuzzy index
+    pub fn new(map: impl Into<KeyMap>, mut options: SearchOptions) -> Matcher {
+        let map = map.into();
+        let max_tokens = |keys: &mut dyn Iterator<Item = &str>| keys.map(|key| tokenize_with(key, &options.word_splits).len()).max();
+        options.max_ngram = max_tokens(&mut map.keys()).max(max_tokens(&mut options.variants.keys().map(String::as_str))).unwrap_or(1);
+        options.max_ngram += max_tokens(&mut options.salt_suffixes.iter().map(String::as_str)).unwrap_or(0);
+        // shortest word anything can be found for: a key, a variant of one, or a typo of a fuzzy key
+        options.min_length = map.keys().chain(options.variants.keys().map(String::as_str)).map(|key| key.len())
+            .chain(options.fuzzy.as_ref().map(|fuzzy| fuzzy.min_length.saturating_sub(1)))
+            .min()
+            .unwrap_or(MIN_WORD_LENGTH);
+        // a filter per process would outweigh the frozen map they share through the page cache
+        options.first_words = (options.fuzzy.is_none() && map.frozen_size() == 0).then(|| {
+            let mut first_words = BloomFilter::new(map.len() + options.variants.len());
+            for key in map.keys().chain(options.variants.keys().map(String::as_str)) {
+                first_words.insert(first_word(key));
+            }
+            first_words
+        });
+        options.prefixes = options.fuzzy.is_none().then(|| key_prefixes(&map, &options.variants)).flatten();
+        Matcher { map, options }
+    }
+
+    /// Chemicals found in text, in order of appearance
+    pub fn search(&self, text: &str) -> Vec<Match> {
+        search_keys_in_text(&self.map, text, &self.options)
+    }
+
+    /// Chemicals found in text, as search finds them, with the number of paragraphs searched
+    pub fn search_counted(&self, text: &str) -> (Vec<Match>, usize) {
+        search_paragraphs(&self.map, text, &self.options)
+    }
+
+    /// Dictionary searched, key -> id
+    pub fn map(&self) -> &KeyMap {
+        &self.map
+    }
+
+    /// Settings used by search
+    pub fn options(&self) -> &SearchOptions {
+        &self.options
+    }
+}
+
+/// Configures and builds a Matcher: tokenization, normalization, masking and the optional
+/// detectors. Keys of the map given to build must be cased for the chosen case mode, as
+/// Dictionary::from_csv does with the same mode.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use chem_matcher::{Id, MatcherBuilder};
+///
+/// let map: HashMap<String, Id> = [("aspirin".to_string(), Id::Cid(2244))].into_iter().collect();
+/// let matcher = MatcherBuilder::new().min_len(4).case_fold(true).mask("<mol>").build(map)?;
+/// assert_eq!(matcher.search("ASPIRIN was given")[0].context, "<mol> was given");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct MatcherBuilder {
+    options: SearchOptions,
+    paragraph_delimiter: String,
+    min_len: Option<usize>,
+    variants: bool,
+    fuzzy_min_length: Option<usize>,
+    ambiguous_terms: Vec<String>,
+}
+
+impl Default for MatcherBuilder {
+    fn default() -> MatcherBuilder {
+        MatcherBuilder::new()
+    }
+}
+
+impl MatcherBuilder {
+    pub fn new() -> MatcherBuilder {
+        MatcherBuilder {
+            options: SearchOptions::default(),
+            paragraph_delimiter: r"\n\n".to_string(),
+            min_len: None,
+            variants: false,
+            fuzzy_min_length: None,
+            ambiguous_terms: Vec::new(),
+        }
+    }
+
+    /// Shortest key looked up, in bytes (defaults to the shortest key of the map)
+    pub fn min_len(mut self, min_len: usize) -> Self {
+        self.min_len = Some(min_len);
+        self
+    }
+
+    pub fn case_mode(mut self, case_mode: CaseMode) -> Self {
+        self.options.case_mode = case_mode;
+        self
+    }
+
+    /// Shorthand for case_mode(CaseMode::Fold), or the default CaseMode::Title when false
+    pub fn case_fold(self, fold: bool) -> Self {
+        self.case_mode(if fold { CaseMode::Fold } else { CaseMode::Title })
+    }
+
+    /// Text replacing matched names in contexts (default MASK)
+    pub fn mask(mut self, mask: &str) -> Self {
+        self.options.mask = mask.to_string();
+        self
+    }
+
+    /// Characters separating words besides whitespace (default WORD_SPLITS)
+    pub fn word_splits(mut self, word_splits: &[char]) -> Self {
+        self.options.word_splits = word_splits.to_vec();
+        self
+    }
+
+    /// Apply Unicode NFKC normalization to the text (default true)
+    pub fn nfkc(mut self, nfkc: bool) -> Self {
+        self.options.nfkc = nfkc;
+        self
+    }
+
+    /// Join words split by a hyphen and line break (default true)
+    pub fn dehyphenate(mut self, dehyphenate: bool) -> Self {
+        self.options.dehyphenate = dehyphenate;
+        self
+    }
+
+    /// Decode HTML character references in the text (default true)
+    pub fn decode_entities(mut self, decode_entities: bool) -> Self {
+        self.options.decode_entities = decode_entities;
+        self
+    }
+
+    /// Drop zero-width and control characters from the text (default true)
+    pub fn strip_controls(mut self, strip_controls: bool) -> Self {
+        self.options.strip_controls = strip_controls;
+        self
+    }
+
+    /// Regex splitting documents into paragraphs (default two newlines)
+    pub fn paragraph_delimiter(mut self, paragraph_delimiter: &str) -> Self {
+        self.paragraph_delimiter = paragraph_delimiter.to_string();
+        self
+    }
+
+    /// Tokens kept on each side of a match (default 0, the whole paragraph)
+    pub fn context_window(mut self, context_window: usize) -> Self {
+        self.options.context_window = context_window;
+        self
+    }
+
+    pub fn all_overlaps(mut self, all_overlaps: bool) -> Self {
+        self.options.all_overlaps = all_overlaps;
+        self
+    }
+
+    /// Also match plurals and -ic acid/-ate forms of the keys
+    pub fn variants(mut self, variants: bool) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    /// Lowercase salt and hydrate suffixes stripped to find the parent compound
+    pub fn salt_suffixes(mut self, salt_suffixes: Vec<String>) -> Self {
+        self.options.salt_suffixes = salt_suffixes;
+        self
+    }
+
+    /// Also match keys of at least min_length characters within edit distance 1
+    pub fn fuzzy(mut self, min_length: usize) -> Self {
+        self.fuzzy_min_length = Some(min_length);
+        self
+    }
+
+    pub fn abbreviations(mut self, abbreviations: bool) -> Self {
+        self.options.abbreviations = abbreviations;
+        self
+    }
+
+    pub fn cas(mut self, cas: bool) -> Self {
+        self.options.cas = cas;
+        self
+    }
+
+    /// CAS number -> CID, attached to detected CAS numbers
+    pub fn cas_map(mut self, cas_map: HashMap<String, Id>) -> Self {
+        self.options.cas_map = cas_map;
+        self
+    }
+
+    pub fn inchi(mut self, inchi: bool) -> Self {
+        self.options.inchi = inchi;
+        self
+    }
+
+    pub fn formulas(mut self, formulas: bool) -> Self {
+        self.options.formulas = formulas;
+        self
+    }
+
+    pub fn formula_whitelist(mut self, formula_whitelist: HashSet<String>) -> Self {
+        self.options.formula_whitelist = formula_whitelist;
+        self
+    }
+
+    /// Lower the score of keys the dictionary lists under several CIDs
+    pub fn conflicts(mut self, conflicts: &HashMap<String, Vec<Id>>) -> Self {
+        self.options.ambiguity = conflicts.iter().map(|(key, cids)| (key.clone(), cids.len())).collect();
+        self
+    }
+
+    /// Terms (any case) only matched near chemistry words
+    pub fn ambiguous_terms(mut self, ambiguous_terms: Vec<String>) -> Self {
+        self.ambiguous_terms = ambiguous_terms;
+        self
+    }
+
+    /// Tokens on each side of an ambiguous term searched for chemistry words
+    pub fn gate_window(mut self, gate_window: usize) -> Self {
+        self.options.gate_window = gate_window;
+        self
+    }
+
+    /// Matcher for the keys of map, indexing them for variants and fuzzy matching when enabled
+    pub fn build(self, map: impl Into<KeyMap>) -> Result<Matcher, Box<dyn Error>> {
+        let map = map.into();
+        let mut options = self.options;
+        options.paragraph_re = regex::Regex::new(&self.paragraph_delimiter)?;
+        if self.variants {
+            options.variants = expand_variants(&map);
+        }
+        if let Some(min_length) = self.fuzzy_min_length {
+            options.fuzzy = Some(FuzzyIndex::new(&map, min_length));
+        }
+        options.ambiguous_terms = self.ambiguous_terms.iter().map(|term| case_key(&normalize(term), options.case_mode)).collect();
+        let mut matcher = Matcher::new(map, options);
+        if let Some(min_len) = self.min_len {
+            matcher.options.min_length = min_len;
+        }
+        Ok(matcher)
+    }
+}
+
+/// Symspell-style index for edit distance 1: every key and every single-character deletion of
+/// it point back to the key, so a lookup only needs the deletions of the query
+pub struct FuzzyIndex {
+    keys: KeyMap,
+    // handles of the keys in keys
+    deletes: HashMap<String, Vec<u32>>,
+    min_length: usize,
+}
+
+fn deletions(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    (0..chars.len())
+        .map(|i| chars[..i].iter().chain(&chars[i + 1..]).collect())
+        .collect()
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1).min(row[j] + 1).min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+impl FuzzyIndex {
+    /// Index the keys of map with at least min_length characters
+    pub fn new(map: &KeyMap, min_length: usize) -> FuzzyIndex {
+        let mut index = FuzzyIndex { keys: KeyMap::new(), deletes: HashMap::new(), min_length };
+        for (key, cid) in map.iter().filter(|(key, _)| key.chars().count() >= min_length) {
+            let handle = index.keys.len() as u32;
+            index.keys.insert(key, cid.into_owned());
+            index.deletes.entry(key.to_string()).or_default().push(handle);
+            for deletion in deletions(key) {
+                index.deletes.entry(deletion).or_default().push(handle);
+            }
+        }
+        index.keys.shrink_to_fit();
+        index
+    }
+
+    /// The closest key within edit distance 1 of `word`, with its id and distance
+    pub fn lookup(&self, word: &str) -> Option<(&str, Cow<'_, Id>, usize)> {
+        if word.chars().count() < self.min_length {
+            return None;
+        }
+        let mut queries = deletions(word);
+        queries.push(word.to_string());
+        queries
+            .iter()
+            .filter_map(|query| self.deletes.get(query))
+            .flatten()
+            .map(|&handle| (self.keys.key(handle), self.keys.id(handle), levenshtein(word, self.keys.key(handle))))
+            .filter(|(_, _, distance)| *distance <= 1)
+            .min_by(|a, b| a.2.cmp(&b.2).then(a.0.cmp(b.0)))
+    }
+}
+
+// Find the byte range covering `window` tokens on each side of `start..end`
+fn context_bounds(tokens: &[(usize, &str)], start: usize, end: usize, window: usize) -> (usize, usize) {
+    let left = tokens
+        .iter()
+        .rev()
+        .filter(|(offset, _)| *offset < start)
+        .take(window)
+        .last()
+        .map_or(start, |(offset, _)| *offset);
+    let right = tokens
+        .iter()
+        .filter(|(offset, _)| *offset >= end)
+        .take(window)
+        .last()
+        .map_or(end, |(offset, word)| offset + word.len());
+    (left, right)
+}
+
+// Mask the text a key was matched from along with the key itself, which differ once
+// normalization or salt stripping applies
+fn mask_key(text: &str, key: &str, surface: &str, mask: &str) -> String {
+    let masked = if surface == key { Cow::Borrowed(text) } else { Cow::Owned(text.replace(surface, mask)) };
+    masked.replace(key, mask).replace(from_ascii_titlecase(key).as_str(), mask)
+}
+
+// Paragraphs of text separated by re, with their byte offsets
+fn split_paragraphs<'a>(re: &regex::Regex, text: &'a str) -> Vec<(usize, &'a str)> {
+    let mut paragraphs = Vec::new();
+    let mut last = 0;
+    for delimiter in re.find_iter(text) {
+        paragraphs.push((last, &text[last..delimiter.start()]));
+        last = delimiter.end();
+    }
+    paragraphs.push((last, &text[last..]));
+    paragraphs
+}
+
+// Build the masked context for a match at `start..end`. A window of 0 keeps the whole paragraph.
+fn build_context(paragraph: &str, tokens: &[(usize, &str)], key: &str, start: usize, end: usize, window: usize, mask: &str) -> String {
+    let surface = &paragraph[start..end];
+    if window == 0 {
+        return mask_key(paragraph, key, surface, mask);
+    }
+    let (left, right) = context_bounds(tokens, start, end, window);
+    format!("{}{}{}", mask_key(&paragraph[left..start], key, surface, mask), mask, mask_key(&paragraph[end..right], key, surface, mask))
+}
+
+// Key up to its first space: every n-gram equal to a key, or to a key plus a salt suffix, starts
+// with the same first word as that key
+fn first_word(key: &str) -> &str {
+    key.split(' ').next().unwrap_or(key)
+}
+
+// Lowercase prefixes of len bytes of the first words of the ASCII keys, or None past MAX_PREFIXES
+fn prefixes_of_len(map: &KeyMap, variants: &HashMap<String, Id>, len: usize) -> Option<Vec<String>> {
+    let mut prefixes = HashSet::new();
+    for key in map.keys().chain(variants.keys().map(String::as_str)).filter(|key| key.is_ascii()) {
+        let word = first_word(key);
+        prefixes.insert(word[..word.len().min(len)].to_ascii_lowercase());
+        // counted before dropping redundant prefixes, which rarely drops many
+        if prefixes.len() > MAX_PREFIXES * 4 {
+            return None;
+        }
+    }
+    let mut prefixes: Vec<String> = prefixes.into_iter().collect();
+    prefixes.sort_unstable();
+    // a prefix starting with another adds nothing
+    prefixes.dedup_by(|prefix, kept| prefix.starts_with(kept.as_str()));
+    (prefixes.len() <= MAX_PREFIXES).then_some(prefixes)
+}
+
+// Prefixes starting every ASCII key and variant, found by a SIMD (Teddy or memchr) search: the
+// longest, of MIN_PREFIX_LEN to MAX_PREFIX_LEN bytes, that keep them within MAX_PREFIXES. Large
+// dictionaries need too many, and are left to the first word filter. Paragraphs with non-ASCII
+// text are not filtered, since normalize turns e.g. "α" into "alpha".
+fn key_prefixes(map: &KeyMap, variants: &HashMap<String, Id>) -> Option<AhoCorasick> {
+    let mut prefixes = prefixes_of_len(map, variants, MIN_PREFIX_LEN)?;
+    for len in MIN_PREFIX_LEN + 1..=MAX_PREFIX_LEN {
+        match prefixes_of_len(map, variants, len) {
+            Some(longer) => prefixes = longer,
+            None => break,
+        }
+    }
+    AhoCorasick::builder().ascii_case_insensitive(true).build(&prefixes).ok()
+}
+
+// The key without a trailing salt or hydrate suffix, if it has one
+fn strip_salt<'a>(key: &'a str, suffixes: &[String]) -> Option<&'a str> {
+    suffixes.iter().find_map(|suffix| {
+        let base = key.len().checked_sub(suffix.len() + 1)?;
+        let (head, tail) = (key.get(..base)?, key.get(base..)?);
+        (tail.starts_with(' ') && tail[1..].eq_ignore_ascii_case(suffix)).then_some(head)
+    })
+}
+
+// Short forms like "THF", "DMSO" or "5-FU": no spaces, mostly alphanumeric, with a capital letter
+fn is_abbreviation(word: &str) -> bool {
+    (2..=10).contains(&word.chars().count())
+        && word.chars().all(|c| c.is_alphanumeric() || c == '-')
+        && word.chars().any(|c| c.is_uppercase())
+}
+
+// An abbreviation in parentheses directly after a match ending at `end`, with the offset past it
+fn abbreviation_definition(paragraph: &str, end: usize) -> Option<(&str, usize)> {
+    let rest = &paragraph[end..];
+    let inner = rest.strip_prefix(' ').unwrap_or(rest).strip_prefix('(')?;
+    let abbreviation = &inner[..inner.find(')')?];
+    let defined_at = paragraph.len() - inner.len() + abbreviation.len() + 1;
+    is_abbreviation(abbreviation).then_some((abbreviation, defined_at))
+}
+
+fn has_context_cue(tokens: &[(usize, &str)], first: usize, last: usize, window: usize) -> bool {
+    let window = &tokens[first.saturating_sub(window)..(last + 1 + window).min(tokens.len())];
+    window.iter().any(|(_, word)| CONTEXT_CUES.contains(&normalize(&word.to_lowercase()).as_str()))
+}
+
+// Confidence in [0, 1] that a candidate is a real mention: long, unambiguous synonyms matched
+// exactly near chemistry vocabulary score highest
+fn score_candidate(candidate: &Candidate, paragraph: &str, tokens: &[(usize, &str)], options: &SearchOptions) -> f32 {
+    let type_factor = match (candidate.id_type, candidate.match_type) {
+        (IdType::Inchi | IdType::InchiKey | IdType::Cas, _) => return 1.0,
+        (IdType::Formula, _) => 0.8,
+        (_, MatchType::Exact) if paragraph[candidate.start..candidate.end] == candidate.key => 1.0,
+        (_, MatchType::Exact) => 0.9, // needed case folding or normalization
+        (_, MatchType::Salt) => 0.85,
+        (_, MatchType::Inflected) => 0.8,
+        (_, MatchType::Abbreviation) => 0.7,
+        (_, MatchType::Fuzzy(_)) => 0.6,
+    };
+    let ambiguity = options.ambiguity.get(&candidate.key).copied().unwrap_or(1) as f32;
+    let length_factor = 0.5 + 0.5 * (candidate.key.chars().count().min(12) as f32 / 12.0);
+    let context_factor = if has_context_cue(tokens, candidate.first, candidate.last, CUE_WINDOW) { 1.0 } else { 0.8 };
+    type_factor * length_factor * context_factor / ambiguity
+}
+
+// Keep the longest candidates and drop any candidate overlapping an already kept one
+fn resolve_overlaps(mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| {
+        (b.last - b.first).cmp(&(a.last - a.first))
+            .then(b.key.len().cmp(&a.key.len()))
+            .then(a.first.cmp(&b.first))
+    });
+    let mut kept: Vec<Candidate> = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        if kept.iter().all(|k| candidate.last < k.first || candidate.first > k.last) {
+            kept.push(candidate);
+        }
+    }
+    kept
+}
+
+/// CAS Registry Numbers have 2-7 digits, 2 digits and a check digit, e.g. 7732-18-5
+pub fn is_cas(word: &str) -> bool {
+    let parts: Vec<&str> = word.split('-').collect();
+    if parts.len() != 3
+        || !(2..=7).contains(&parts[0].len())
+        || parts[1].len() != 2
+        || parts[2].len() != 1
+        || !parts.iter().all(|part| part.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return false;
+    }
+    let digits = parts[0].bytes().chain(parts[1].bytes()).map(|b| (b - b'0') as usize);
+    let checksum: usize = digits.rev().enumerate().map(|(i, d)| (i + 1) * d).sum();
+    checksum % 10 == (parts[2].as_bytes()[0] - b'0') as usize
+}
+
+// Parse element symbols, counts and bracketed groups from `chars[*i..]` until an unmatched
+// closing bracket, collecting the elements seen. Returns false on anything else.
+fn parse_formula_groups(chars: &[char], i: &mut usize, elements: &mut HashSet<String>) -> bool {
+    let start = *i;
+    while *i < chars.len() {
+        let c = chars[*i];
+        if let Some(close) = closing_bracket(c) {
+            *i += 1;
+            if !parse_formula_groups(chars, i, elements) || chars.get(*i) != Some(&close) {
+                return false;
+            }
+            *i += 1;
+        } else if c.is_ascii_uppercase() {
+            let two = chars.get(*i + 1).filter(|next| next.is_ascii_lowercase()).map(|next| format!("{}{}", c, next));
+            match two.filter(|symbol| ELEMENTS.contains(&symbol.as_str())) {
+                Some(symbol) => {
+                    elements.insert(symbol);
+                    *i += 2;
+                }
+                None if ELEMENTS.contains(&c.to_string().as_str()) => {
+                    elements.insert(c.to_string());
+                    *i += 1;
+                }
+                None => return false,
+            }
+        } else if c.is_ascii_digit() && *i > start {
+            *i += 1;
+        } else {
+            break;
+        }
+    }
+    *i > start
+}
+
+/// Molecular formulas such as "C6H12O6", "Ca(OH)2" or "CuSO4·5H2O". To avoid section numbers
+/// ("H2") and acronyms ("HIV"), a formula needs two distinct elements and a digit or a
+/// two-letter element, unless whitelisted.
+pub fn is_formula(word: &str, whitelist: &HashSet<String>) -> bool {
+    if whitelist.contains(word) {
+        return true;
+    }
+    let mut elements = HashSet::new();
+    for part in word.split('·') {
+        let chars: Vec<char> = part.trim_start_matches(|c: char| c.is_ascii_digit()).chars().collect();
+        let mut i = 0;
+        if !parse_formula_groups(&chars, &mut i, &mut elements) || i != chars.len() {
+            return false;
+        }
+    }
+    elements.len() >= 2 && word.chars().any(|c| c.is_ascii_digit() || c.is_ascii_lowercase())
+}
+
+/// InChIKeys are 14 letters, 8 letters plus a standard flag and version, and a protonation letter
+pub fn is_inchikey(word: &str) -> bool {
+    let parts: Vec<&str> = word.split('-').collect();
+    parts.len() == 3
+        && parts[0].len() == 14
+        && parts[1].len() == 10
+        && parts[2].len() == 1
+        && parts.iter().all(|part| part.bytes().all(|b| b.is_ascii_uppercase()))
+        && matches!(parts[1].as_bytes()[8], b'S' | b'N')
+        && parts[1].as_bytes()[9] == b'A'
+}
+
+/// Byte ranges of InChI strings, which run to the next whitespace minus trailing punctuation
+pub fn find_inchis(paragraph: &str) -> Vec<(usize, usize)> {
+    static INCHI: OnceLock<regex::Regex> = OnceLock::new();
+    let re = INCHI.get_or_init(|| regex::Regex::new(r"InChI=1S?/[A-Za-z0-9.]+(/[^\s/]+)*").unwrap());
+    re.find_iter(paragraph)
+        .map(|found| {
+            let mut inchi = found.as_str().trim_end_matches(['.', ',', ';', ':', '"', '\'']);
+            while inchi.ends_with(')') && inchi.matches('(').count() < inchi.matches(')').count() {
+                inchi = inchi[..inchi.len() - 1].trim_end_matches(['.', ',', ';', ':', '"', '\'']);
+            }
+            (found.start(), found.start() + inchi.len())
+        })
+        .collect()
+}
+
+/// Dictionary keys and identifiers found in text, in order; see Matcher::search
+pub fn search_keys_in_text(map: &KeyMap, text: &str, options: &SearchOptions) -> SearchResults {
+    search_paragraphs(map, text, options).0
+}
+
+// Matches of search_keys_in_text and the number of paragraphs text was split into
+fn search_paragraphs(map: &KeyMap, text: &str, options: &SearchOptions) -> (SearchResults, usize) {
+    let mut search_results = Vec::new();
+    // abbreviation -> (cid, paragraph and byte offset where it was defined)
+    let mut abbreviations: HashMap<String, (Option<Id>, usize, usize)> = HashMap::new();
+    let (text, cleaned) = clean_text_mapped(text, options.decode_entities, options.strip_controls);
+    let (text, joined) = if options.dehyphenate { dehyphenate_mapped(&text) } else { (Cow::Borrowed(text.as_ref()), OffsetMap::default()) };
+    let paragraphs = split_paragraphs(&options.paragraph_re, &text);
+    let paragraph_count = paragraphs.len();
+    paragraphs.into_iter().enumerate().for_each(|(index, (paragraph_start, paragraph))| {
+        let (paragraph, normalized) = if options.nfkc { to_nfkc_mapped(paragraph) } else { (Cow::Borrowed(paragraph), OffsetMap::default()) };
+        let paragraph = paragraph.as_ref();
+        // most paragraphs contain no prefix of any key
+        let has_prefix = !paragraph.is_ascii() || options.prefixes.as_ref().is_none_or(|prefixes| prefixes.is_match(paragraph));
+        let tokens = tokenize_with(paragraph, &options.word_splits);
+        let words: Vec<String> = tokens.iter().map(|(_, word)| case_word(&normalize(word), options.case_mode)).collect();
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for (i, &(start, word)) in tokens.iter().enumerate() {
+            let mut key = if options.case_mode == CaseMode::Title { to_ascii_titlecase(&words[i]) } else { words[i].clone() };
+            let mut keys = Vec::new();
+            // most tokens start no key, which spares building and looking up their n-grams
+            if has_prefix && options.first_words.as_ref().is_none_or(|first_words| first_words.contains(first_word(&key))) {
+                keys.push((key.clone(), start + word.len()));
+                for (j, window) in tokens[i..].windows(2).take(options.max_ngram.saturating_sub(1)).enumerate() {
+                    let ((last_start, last_word), (next_start, next_word)) = (window[0], window[1]);
+                    // words only form a key when separated by a single delimiter
+                    if last_start + last_word.len() + 1 != next_start {
+                        break;
+                    }
+                    key.push(' ');
+                    key.push_str(&words[i + j + 1]);
+                    keys.push((key.clone(), next_start + next_word.len()));
+                }
+            }
+            for (n, (key, end)) in keys.into_iter().enumerate().rev() {
+                if key.len() < options.min_length {
+                    continue;
+                }
+                let found = map.get(&key).map(|cid| (key.clone(), cid, MatchType::Exact))
+                    .or_else(|| options.variants.get(&key).map(|cid| (key.clone(), Cow::Borrowed(cid), MatchType::Inflected)))
+                    .or_else(|| {
+                        let base = strip_salt(&key, &options.salt_suffixes)?;
+                        map.get(base).map(|cid| (base.to_string(), cid, MatchType::Salt))
+                    })
+                    .or_else(|| {
+                        let (key, cid, distance) = options.fuzzy.as_ref()?.lookup(&key)?;
+                        Some((key.to_string(), cid, MatchType::Fuzzy(distance)))
+                    });
+                if let Some((key, cid, match_type)) = found {
+                    let (cid, id_type) = (Some(cid.into_owned()), IdType::Name);
+                    candidates.push(Candidate { first: i, last: i + n, start, end, key, cid, match_type, id_type });
+                }
+            }
+            if options.cas && is_cas(word) {
+                let (key, match_type, id_type) = (word.to_string(), MatchType::Exact, IdType::Cas);
+                let cid = options.cas_map.get(word).cloned();
+                candidates.push(Candidate { first: i, last: i, start, end: start + word.len(), key, cid, match_type, id_type });
+            }
+            if options.formulas && is_formula(word, &options.formula_whitelist) {
+                let (key, match_type, id_type) = (word.to_string(), MatchType::Exact, IdType::Formula);
+                candidates.push(Candidate { first: i, last: i, start, end: start + word.len(), key, cid: None, match_type, id_type });
+            }
+            if options.inchi && is_inchikey(word) {
+                let (key, match_type, id_type) = (word.to_string(), MatchType::Exact, IdType::InchiKey);
+                candidates.push(Candidate { first: i, last: i, start, end: start + word.len(), key, cid: None, match_type, id_type });
+            }
+        }
+        if options.inchi {
+            for (start, end) in find_inchis(paragraph) {
+                let first = tokens.iter().position(|(offset, word)| offset + word.len() > start).unwrap_or(0);
+                let last = tokens.iter().rposition(|(offset, _)| *offset < end).unwrap_or(first);
+                let (key, match_type, id_type) = (paragraph[start..end].to_string(), MatchType::Exact, IdType::Inchi);
+                candidates.push(Candidate { first, last, start, end, key, cid: None, match_type, id_type });
+            }
+        }
+
+        if !options.all_overlaps {
+            candidates = resolve_overlaps(candidates);
+        }
+
+        // ambiguous terms such as "lead" only count with chemistry words nearby
+        if !options.ambiguous_terms.is_empty() {
+            candidates.retain(|candidate| {
+                !options.ambiguous_terms.contains(&candidate.key)
+                    || has_context_cue(&tokens, candidate.first, candidate.last, options.gate_window)
+            });
+        }
+
+        if options.abbreviations {
+            for candidate in candidates.iter().filter(|candidate| candidate.id_type == IdType::Name) {
+                if let Some((abbreviation, defined_at)) = abbreviation_definition(paragraph, candidate.end) {
+                    abbreviations.entry(abbreviation.to_string()).or_insert_with(|| (candidate.cid.clone(), index, defined_at));
+                }
+            }
+            for (i, &(start, word)) in tokens.iter().enumerate() {
+                let defined = abbreviations.get(word).filter(|(_, defined_in, defined_at)| *defined_in < index || *defined_at < start);
+                let overlaps = candidates.iter().any(|candidate| candidate.first <= i && i <= candidate.last);
+                if let (Some((cid, _, _)), false) = (defined, overlaps) {
+                    let (key, match_type, id_type, cid) = (word.to_string(), MatchType::Abbreviation, IdType::Name, cid.clone());
+                    candidates.push(Candidate { first: i, last: i, start, end: start + word.len(), key, cid, match_type, id_type });
+                }
+            }
+        }
+        candidates.sort_by_key(|candidate| candidate.first);
+
+        let mut seen = HashSet::new(); // we only want to observer a key once
+        for candidate in candidates {
+            if seen.contains(&candidate.key) {
+                continue;
+            }
+            let score = score_candidate(&candidate, paragraph, &tokens, options);
+            let Candidate { start, end, key, cid, match_type, id_type, .. } = candidate;
+            let context = build_context(paragraph, &tokens, &key, start, end, options.context_window, &options.mask);
+            let (start, end) = normalized.source_range(start, end);
+            let (start, end) = joined.source_range(paragraph_start + start, paragraph_start + end);
+            let (start, end) = cleaned.source_range(start, end);
+            search_results.push(Match { context, key: key.clone(), cid, match_type, id_type, score, start, end });
+            seen.insert(key);
+        }
+    });
+
+    (search_results, paragraph_count)
+}
+
+/// Words like "2-methylpentane" or "oxolane": at least min_letters letters, a chemical suffix or
+/// a locant prefix, letters otherwise, and not a common English word
+pub fn is_chemical_like(word: &str, min_letters: usize, banned: &HashSet<String>, stemmer: &StemmerWrapper) -> bool {
+    let lower = word.to_lowercase();
+    let letters = lower.chars().filter(|c| c.is_alphabetic()).count();
+    let locant_prefix = lower.split_once('-').is_some_and(|(head, _)| {
+        head.starts_with(|c: char| c.is_ascii_digit()) && head.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '\'')
+    });
+    let chemical_suffix = CHEMICAL_SUFFIXES.iter().any(|suffix| lower.ends_with(suffix));
+    letters >= min_letters
+        && lower.chars().all(|c| c.is_alphanumeric() || "-,()[]'".contains(c))
+        && (chemical_suffix || locant_prefix)
+        && !banned.contains(stemmer.standardize(&lower).as_str())
+}
+
+/// Chemical-looking words in the text that are not dictionary keys, to help grow the dictionary
+pub fn find_unknown_names(map: &KeyMap, text: &str, options: &SearchOptions, banned: &HashSet<String>, stemmer: &StemmerWrapper) -> Vec<String> {
+    let (text, _) = clean_text_mapped(text, options.decode_entities, options.strip_controls);
+    let text = if options.nfkc { to_nfkc(&text) } else { Cow::Borrowed(text.as_ref()) };
+    tokenize_with(&text, &options.word_splits)
+        .into_iter()
+        .map(|(_, word)| normalize(word))
+        .filter(|word| {
+            let key = case_key(word, options.case_mode);
+            !map.contains_key(&key) && !options.variants.contains_key(&key)
+        })
+        .filter(|word| is_chemical_like(word, options.min_length.max(MIN_WORD_LENGTH), banned, stemmer))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::max_key_tokens;
+
+    // (context, key, cid, match type) of dictionary matches
+    fn rows(search_results: SearchResults) -> Vec<(String, String, u32, MatchType)> {
+        search_results
+            .into_iter()
+            .map(|m| (m.context, m.key, m.cid.as_ref().and_then(Id::cid).unwrap(), m.match_type))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_keys_in_text() {
+        let mut map = KeyMap::new();
+        map.insert("Apple", Id::Cid(1));
+        map.insert("Orange", Id::Cid(2));
+        map.insert("Carrot", Id::Cid(3));
+
+        let text = "I have an apple and an orange, but I do not have a carrot.";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+
+        let expected_results = vec![
+            ("I have an <|MOLECULE|> and an orange, but I do not have a carrot.".to_string(), "Apple".to_string(), 1, MatchType::Exact),
+            ("I have an apple and an <|MOLECULE|>, but I do not have a carrot.".to_string(), "Orange".to_string(), 2, MatchType::Exact),
+            ("I have an apple and an orange, but I do not have a <|MOLECULE|>.".to_string(), "Carrot".to_string(), 3, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_cases() {
+        let mut map = KeyMap::new();
+        map.insert("Apple juice", Id::Cid(1));
+        map.insert("ORANGE", Id::Cid(2));
+        map.insert("Carrot", Id::Cid(3));
+        map.insert("juice", Id::Cid(4));
+        map.insert("Apple", Id::Cid(5));
+
+        let text = "I have an apple juice and an ORANGE, but I do not have a CARROT. Apple";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+
+        let expected_results = vec![
+            ("I have an <|MOLECULE|> and an ORANGE, but I do not have a CARROT. Apple".to_string(), "Apple juice".to_string(), 1, MatchType::Exact),
+            ("I have an apple juice and an <|MOLECULE|>, but I do not have a CARROT. Apple".to_string(), "ORANGE".to_string(), 2, MatchType::Exact),
+            ("I have an <|MOLECULE|> juice and an ORANGE, but I do not have a CARROT. <|MOLECULE|>".to_string(), "Apple".to_string(), 5, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_context_window() {
+        let mut map = KeyMap::new();
+        map.insert("Apple juice", Id::Cid(1));
+        map.insert("Carrot", Id::Cid(3));
+
+        let text = "I have an apple juice and an orange, but I do not have a carrot.";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::new(r"\n\n", 2).unwrap());
+
+        let expected_results = vec![
+            ("have an <|MOLECULE|> and an".to_string(), "Apple juice".to_string(), 1, MatchType::Exact),
+            ("have a <|MOLECULE|>".to_string(), "Carrot".to_string(), 3, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_paragraph_delimiter() {
+        let mut map = KeyMap::new();
+        map.insert("Apple", Id::Cid(1));
+
+        let text = "An apple a day.\u{c}Another apple.";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::new(r"\f", 0).unwrap());
+
+        let expected_results = vec![
+            ("An <|MOLECULE|> a day.".to_string(), "Apple".to_string(), 1, MatchType::Exact),
+            ("Another <|MOLECULE|>.".to_string(), "Apple".to_string(), 1, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+        assert!(SearchOptions::new("(", 0).is_err());
+    }
+
+    #[test]
+    fn test_search_keys_in_text_iupac() {
+        let mut map = KeyMap::new();
+        map.insert("2,4-dinitrophenol", Id::Cid(1));
+        map.insert("(±)-ibuprofen", Id::Cid(2));
+
+        let text = "Both 2,4-dinitrophenol and (±)-ibuprofen were tested.";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+
+        let expected_results = vec![
+            ("Both <|MOLECULE|> and (±)-ibuprofen were tested.".to_string(), "2,4-dinitrophenol".to_string(), 1, MatchType::Exact),
+            ("Both 2,4-dinitrophenol and <|MOLECULE|> were tested.".to_string(), "(±)-ibuprofen".to_string(), 2, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_ngrams() {
+        let mut map = KeyMap::new();
+        map.insert("Sodium dodecyl sulfate", Id::Cid(1));
+        map.insert("Sodium chloride", Id::Cid(2));
+        map.insert("Sodium", Id::Cid(3));
+
+        let options = SearchOptions { max_ngram: max_key_tokens(&map), ..Default::default() };
+        assert_eq!(options.max_ngram, 3);
+
+        let text = "We added sodium dodecyl sulfate to sodium chloride, then sodium. dodecyl sulfate";
+        let search_results = search_keys_in_text(&map, text, &options);
+        let keys = search_results.iter().map(|m| (m.key.as_str(), m.cid.as_ref().and_then(Id::cid).unwrap())).collect::<Vec<(&str, u32)>>();
+
+        assert_eq!(keys, vec![("Sodium dodecyl sulfate", 1), ("Sodium chloride", 2), ("Sodium", 3)]);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_overlaps() {
+        let mut map = KeyMap::new();
+        map.insert("Apple juice", Id::Cid(1));
+        map.insert("Juice concentrate", Id::Cid(2));
+        map.insert("Apple", Id::Cid(3));
+        map.insert("Concentrate", Id::Cid(4));
+
+        let text = "I have apple juice concentrate";
+        let keys = |options: &SearchOptions| search_keys_in_text(&map, text, options)
+            .into_iter()
+            .map(|m| (m.key, m.cid.as_ref().and_then(Id::cid).unwrap()))
+            .collect::<Vec<(String, u32)>>();
+
+        // "Juice concentrate" is the longest key, so the overlapping "Apple juice" is dropped
+        assert_eq!(keys(&SearchOptions::default()), vec![("Apple".to_string(), 3), ("Juice concentrate".to_string(), 2)]);
+
+        let options = SearchOptions { all_overlaps: true, ..Default::default() };
+        assert_eq!(keys(&options), vec![
+            ("Apple juice".to_string(), 1),
+            ("Apple".to_string(), 3),
+            ("Juice concentrate".to_string(), 2),
+            ("Concentrate".to_string(), 4),
+        ]);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_greek() {
+        let mut map = KeyMap::new();
+        map.insert(&to_ascii_titlecase(&normalize("alpha-pinene")), Id::Cid(1));
+        map.insert(&to_ascii_titlecase(&normalize("β-carotene")), Id::Cid(2));
+
+        let text = "Both α-pinene and beta-carotene were tested.";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+
+        let expected_results = vec![
+            ("Both <|MOLECULE|> and beta-carotene were tested.".to_string(), "Alpha-pinene".to_string(), 1, MatchType::Exact),
+            ("Both α-pinene and <|MOLECULE|> were tested.".to_string(), "Beta-carotene".to_string(), 2, MatchType::Exact),
+        ];
+
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_nfkc() {
+        let mut map = KeyMap::new();
+        map.insert("Sulfanilamide", Id::Cid(1));
+        map.insert("Fluorine", Id::Cid(2));
+
+        let text = "Ｓｕｌｆａｎｉｌａｍｉｄｅ and ﬂuorine";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+
+        let expected_results = vec![
+            ("<|MOLECULE|> and fluorine".to_string(), "Sulfanilamide".to_string(), 1, MatchType::Exact),
+            ("Sulfanilamide and <|MOLECULE|>".to_string(), "Fluorine".to_string(), 2, MatchType::Exact),
+        ];
+        assert_eq!(rows(search_results), expected_results);
+
+        // ligatures are expanded without NFKC too, but full-width letters are not
+        let options = SearchOptions { nfkc: false, ..Default::default() };
+        let search_results = search_keys_in_text(&map, text, &options);
+        assert_eq!(search_results.len(), 1);
+        assert_eq!(&text[search_results[0].start..search_results[0].end], "ﬂuorine");
+    }
+
+    #[test]
+    fn test_search_keys_in_text_cleanup() {
+        let mut map = KeyMap::new();
+        map.insert("Aspirin", Id::Cid(1));
+        map.insert("Water", Id::Cid(2));
+
+        let text = "asp\u{200b}irin &amp; water\u{7}";
+        let search_results = search_keys_in_text(&map, text, &SearchOptions::default());
+        assert_eq!(search_results[0].context, "<|MOLECULE|> & water");
+        assert_eq!(&text[search_results[0].start..search_results[0].end], "asp\u{200b}irin");
+        assert_eq!(&text[search_results[1].start..search_results[1].end], "water");
+
+        let options = SearchOptions { decode_entities: false, strip_controls: false, ..Default::default() };
+        assert!(search_keys_in_text(&map, text, &options).is_empty());
+    }
+
+    #[test]
+    fn test_search_keys_in_text_variants() {
+        let mut map = KeyMap::new();
+        map.insert("Acetic acid", Id::Cid(1));
+        map.insert("Phenol", Id::Cid(2));
+        map.insert("Nitrate", Id::Cid(3));
+        map.insert("Phenols", Id::Cid(4));
+
+        let options = SearchOptions { variants: expand_variants(&map), ..Default::default() };
+        assert_eq!(options.variants.get("Acetates"), Some(&Id::Cid(1)));
+        assert_eq!(options.variants.get("Nitric acid"), Some(&Id::Cid(3)));
+        assert!(!options.variants.contains_key("Phenols"));
+
+        let text = "Acetates and nitrates, phenols and nitrate";
+        let keys = search_keys_in_text(&map, text, &options)
+            .into_iter()
+            .map(|m| (m.key, m.cid.as_ref().and_then(Id::cid).unwrap(), m.match_type))
+            .collect::<Vec<(String, u32, MatchType)>>();
+
+        assert_eq!(keys, vec![
+            ("Acetates".to_string(), 1, MatchType::Inflected),
+            ("Nitrates".to_string(), 3, MatchType::Inflected),
+            ("Phenols".to_string(), 4, MatchType::Exact),
+            ("Nitrate".to_string(), 3, MatchType::Exact),
+        ]);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_salts() {
+        let mut map = KeyMap::new();
+        map.insert("Morphine", Id::Cid(1));
+        map.insert("Caffeine", Id::Cid(2));
+        map.insert("Quinine sulfate", Id::Cid(3));
+
+        let suffixes = SALT_SUFFIXES.iter().map(|suffix| suffix.to_string()).collect::<Vec<String>>();
+        assert_eq!(strip_salt("Naproxen sodium salt", &suffixes), Some("Naproxen"));
+        assert_eq!(strip_salt("Morphine", &suffixes), None);
+
+        let options = SearchOptions { salt_suffixes: suffixes, max_ngram: 3, ..Default::default() };
+        let text = "Given morphine sulfate, caffeine monohydrate and quinine sulfate.";
+        let search_results = search_keys_in_text(&map, text, &options);
+
+        let expected_results = vec![
+            ("Given <|MOLECULE|>, caffeine monohydrate and quinine sulfate.".to_string(), "Morphine".to_string(), 1, MatchType::Salt),
+            ("Given morphine sulfate, <|MOLECULE|> and quinine sulfate.".to_string(), "Caffeine".to_string(), 2, MatchType::Salt),
+            ("Given morphine sulfate, caffeine monohydrate and <|MOLECULE|>.".to_string(), "Quinine sulfate".to_string(), 3, MatchType::Exact),
+        ];
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_case_modes() {
+        let keys = |mode: CaseMode| {
+            let mut map = KeyMap::new();
+            for (key, cid) in [("ibuprofen", 1), ("PEDOT", 2), ("Phosphate buffer", 3)] {
+                map.insert(&case_key(key, mode), Id::Cid(cid));
+            }
+            let options = SearchOptions { case_mode: mode, ..Default::default() };
+            let text = "IBUPROFEN on pedot with phosphate BUFFER, not PEDOT";
+            search_keys_in_text(&map, text, &options)
+                .into_iter()
+                .map(|m| (m.key, m.cid.as_ref().and_then(Id::cid).unwrap()))
+                .collect::<Vec<(String, u32)>>()
+        };
+
+        assert_eq!(case_key("pH buffer", CaseMode::Smart), "pH buffer");
+        assert_eq!(case_key("Sodium DDT", CaseMode::Smart), "sodium DDT");
+        assert_eq!(keys(CaseMode::Exact), vec![("PEDOT".to_string(), 2)]);
+        assert_eq!(keys(CaseMode::Fold), vec![
+            ("ibuprofen".to_string(), 1),
+            ("pedot".to_string(), 2),
+            ("phosphate buffer".to_string(), 3),
+        ]);
+        // the acronym only matches when written in capitals
+        assert_eq!(keys(CaseMode::Smart), vec![
+            ("ibuprofen".to_string(), 1),
+            ("phosphate buffer".to_string(), 3),
+            ("PEDOT".to_string(), 2),
+        ]);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_fuzzy() {
+        let mut map = KeyMap::new();
+        map.insert("Acetaminophen", Id::Cid(1));
+        map.insert("Ethanol", Id::Cid(2));
+
+        assert_eq!(levenshtein("acetominophen", "acetaminophen"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+
+        let options = SearchOptions { fuzzy: Some(FuzzyIndex::new(&map, 8)), ..Default::default() };
+        let text = "We gave acetominophen in ethenol and acetaminophn";
+        let search_results = search_keys_in_text(&map, text, &options);
+
+        // "ethenol" is below the length threshold and the key is only reported once
+        let expected_results = vec![
+            ("We gave <|MOLECULE|> in ethenol and acetaminophn".to_string(), "Acetaminophen".to_string(), 1, MatchType::Fuzzy(1)),
+        ];
+        assert_eq!(rows(search_results), expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_abbreviations() {
+        let mut map = KeyMap::new();
+        map.insert("Tetrahydrofuran", Id::Cid(1));
+
+        let options = SearchOptions { abbreviations: true, ..Default::default() };
+        let text = "THF is common. We used tetrahydrofuran (THF) as solvent, then THF again.\n\nMore THF here.";
+        let search_results = search_keys_in_text(&map, text, &options);
+
+        let expected_results = vec![
+            ("THF is common. We used <|MOLECULE|> (THF) as solvent, then THF again.".to_string(), "Tetrahydrofuran".to_string(), 1, MatchType::Exact),
+            ("<|MOLECULE|> is common. We used tetrahydrofuran (<|MOLECULE|>) as solvent, then <|MOLECULE|> again.".to_string(), "THF".to_string(), 1, MatchType::Abbreviation),
+            ("More <|MOLECULE|> here.".to_string(), "THF".to_string(), 1, MatchType::Abbreviation),
+        ];
+        assert_eq!(rows(search_results), expected_results);
+        assert!(search_keys_in_text(&map, "More THF here.", &options).is_empty());
+    }
+
+    #[test]
+    fn test_search_keys_in_text_cas() {
+        assert!(is_cas("50-78-2"));
+        assert!(is_cas("7732-18-5"));
+        assert!(!is_cas("50-78-3"));
+        assert!(!is_cas("1-78-2"));
+        assert!(!is_cas("2019-10-1"));
+
+        let map = KeyMap::new();
+        let mut cas_map = HashMap::new();
+        cas_map.insert("50-78-2".to_string(), Id::Cid(2244));
+        let options = SearchOptions { cas: true, cas_map, ..Default::default() };
+        let text = "Aspirin (50-78-2) in water (7732-18-5), see 50-78-3.";
+        let search_results = search_keys_in_text(&map, text, &options);
+
+        let expected_results = vec![
+            Match {
+                context: "Aspirin (<|MOLECULE|>) in water (7732-18-5), see 50-78-3.".to_string(),
+                key: "50-78-2".to_string(),
+                cid: Some(Id::Cid(2244)),
+                match_type: MatchType::Exact,
+                id_type: IdType::Cas,
+                score: 1.0,
+                start: 9,
+                end: 16,
+            },
+            Match {
+                context: "Aspirin (50-78-2) in water (<|MOLECULE|>), see 50-78-3.".to_string(),
+                key: "7732-18-5".to_string(),
+                cid: None,
+                match_type: MatchType::Exact,
+                id_type: IdType::Cas,
+                score: 1.0,
+                start: 28,
+                end: 37,
+            },
+        ];
+        assert_eq!(search_results, expected_results);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_offsets() {
+        let mut map = KeyMap::new();
+        map.insert("Acetone", Id::Cid(180));
+        map.insert("Fluorene", Id::Cid(6853));
+        let text = "Fluorene first.\n\nWe used acet-\none and ｆｌｕｏｒｅｎｅ, then ﬂuorene.";
+        let spans: Vec<&str> = search_keys_in_text(&map, text, &SearchOptions::default())
+            .iter()
+            .map(|m| &text[m.start..m.end])
+            .collect();
+        // keys are reported once per paragraph
+        assert_eq!(spans, vec!["Fluorene", "acet-\none", "ｆｌｕｏｒｅｎｅ"]);
+
+        let text = "A ﬂuorene ring";
+        let found = search_keys_in_text(&map, text, &SearchOptions::default());
+        assert_eq!(&text[found[0].start..found[0].end], "ﬂuorene");
+    }
+
+    #[test]
+    fn test_search_keys_in_text_inchi() {
+        assert!(is_inchikey("BSYNRYMUTXBXSQ-UHFFFAOYSA-N"));
+        assert!(!is_inchikey("BSYNRYMUTXBXSQ-UHFFFAOYXA-N"));
+        assert!(!is_inchikey("BSYNRYMUTXBXS-UHFFFAOYSA-N"));
+
+        let inchi = "InChI=1S/C9H8O4/c1-6(10)13-8-5-3-2-4-7(8)9(11)12/h2-5H,1H3,(H,11,12)";
+        let text = format!("Aspirin ({}) has key BSYNRYMUTXBXSQ-UHFFFAOYSA-N.", inchi);
+        let options = SearchOptions { inchi: true, ..Default::default() };
+        let search_results = search_keys_in_text(&KeyMap::new(), &text, &options);
+
+        let found = search_results.iter().map(|m| (m.key.as_str(), m.id_type)).collect::<Vec<(&str, IdType)>>();
+        assert_eq!(found, vec![(inchi, IdType::Inchi), ("BSYNRYMUTXBXSQ-UHFFFAOYSA-N", IdType::InchiKey)]);
+        assert_eq!(search_results[0].context, "Aspirin (<|MOLECULE|>) has key BSYNRYMUTXBXSQ-UHFFFAOYSA-N.");
+    }
+
+    #[test]
+    fn test_search_keys_in_text_formulas() {
+        let whitelist: HashSet<String> = ["H2".to_string()].into_iter().collect();
+        for formula in ["C6H12O6", "CH3COOH", "NaCl", "Ca(OH)2", "CuSO4·5H2O", "[Cu(NH3)4]SO4", "H2"] {
+            assert!(is_formula(formula, &whitelist), "{}", formula);
+        }
+        for word in ["O2", "HIV", "CNS", "Cooling", "C6H12O6)", "2C", "Ca(OH", "Xy2"] {
+            assert!(!is_formula(word, &whitelist), "{}", word);
+        }
+
+        let options = SearchOptions { formulas: true, ..Default::default() };
+        let text = "Section H2 describes C6H12O6 and NaCl in HIV studies.";
+        let found = search_keys_in_text(&KeyMap::new(), text, &options)
+            .into_iter()
+            .map(|m| (m.key, m.id_type))
+            .collect::<Vec<(String, IdType)>>();
+        assert_eq!(found, vec![("C6H12O6".to_string(), IdType::Formula), ("NaCl".to_string(), IdType::Formula)]);
+    }
+
+    #[test]
+    fn test_find_unknown_names() {
+        let mut map = KeyMap::new();
+        map.insert("Ethanol", Id::Cid(1));
+        let banned: HashSet<String> = ["control", "membran"].iter().map(|word| word.to_string()).collect();
+        let stemmer = StemmerWrapper::new();
+
+        let text = "Ethanol, oxolane and 2-methylpentane crossed the membrane in control, as did oxolane.";
+        let names = find_unknown_names(&map, text, &SearchOptions::default(), &banned, &stemmer);
+
+        assert_eq!(names, vec!["oxolane", "2-methylpentane", "oxolane"]);
+    }
+
+    #[test]
+    fn test_search_keys_in_text_scores() {
+        let mut map = KeyMap::new();
+        map.insert("Acetaminophen", Id::Cid(1));
+        map.insert("Ethanol", Id::Cid(3));
+
+        let mut ambiguity = HashMap::new();
+        ambiguity.insert("Ethanol".to_string(), 2);
+        let options = SearchOptions { ambiguity, ..Default::default() };
+        let scores = |text: &str| search_keys_in_text(&map, text, &options)
+            .into_iter()
+            .map(|m| (m.key, m.score))
+            .collect::<Vec<(String, f32)>>();
+
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        assert!(close(scores("Acetaminophen was dissolved in solution")[0].1, 1.0));
+        // case folded and no chemistry context
+        assert!(close(scores("We took acetaminophen")[0].1, 0.9 * 0.8));
+        // listed with two cids
+        assert!(close(scores("Ethanol was the solvent")[0].1, (0.5 + 0.5 * 7.0 / 12.0) / 2.0));
+    }
+
+    #[test]
+    fn test_search_keys_in_text_ambiguous_terms() {
+        let mut map = KeyMap::new();
+        map.insert("Silver", Id::Cid(1));
+        map.insert("Phenol", Id::Cid(2));
+
+        let ambiguous_terms: HashSet<String> = ["Silver".to_string()].into_iter().collect();
+        let options = SearchOptions { ambiguous_terms, gate_window: 3, ..Default::default() };
+        let keys = |text: &str| search_keys_in_text(&map, text, &options)
+            .into_iter()
+            .map(|m| m.key)
+            .collect::<Vec<String>>();
+
+        assert_eq!(keys("She won silver and phenol"), vec!["Phenol"]);
+        assert_eq!(keys("Silver nitrate solution"), vec!["Silver"]);
+        assert_eq!(keys("Silver lining of a cloud, in solution"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_matcher_builder() {
+        let mut map = KeyMap::new();
+        map.insert("zinc", Id::Cid(1));
+        map.insert("benzene", Id::Cid(2));
+
+        let matcher = MatcherBuilder::new().case_fold(true).mask("<mol>").build(map.clone()).unwrap();
+        assert_eq!(matcher.options().min_length, 4);
+        let found = matcher.search("Zinc and BENZENE mixed");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].context, "<mol> and BENZENE mixed");
+
+        let matcher = MatcherBuilder::new().case_fold(true).min_len(5).build(map.clone()).unwrap();
+        assert_eq!(matcher.search("Zinc and BENZENE mixed").iter().map(|m| m.key.as_str()).collect::<Vec<&str>>(), vec!["benzene"]);
+
+        // words shorter than every key are still searched for variants and typos
+        let acid: KeyMap = [("acetic acid".to_string(), Id::Cid(176))].into_iter().collect();
+        let matcher = MatcherBuilder::new().case_fold(true).variants(true).build(acid).unwrap();
+        assert_eq!(matcher.search("acetate buffer").iter().map(|m| m.key.as_str()).collect::<Vec<&str>>(), vec!["acetate"]);
+        let paracetamol: KeyMap = [("Acetaminophen".to_string(), Id::Cid(1983))].into_iter().collect();
+        let matcher = MatcherBuilder::new().fuzzy(8).build(paracetamol).unwrap();
+        assert_eq!(matcher.search("acetminophen was given").iter().map(|m| m.key.as_str()).collect::<Vec<&str>>(), vec!["Acetaminophen"]);
+
+        // split on '/' only, so ',' stays part of the word
+        let matcher = MatcherBuilder::new().case_fold(true).word_splits(&['/']).build(map).unwrap();
+        assert_eq!(matcher.search("zinc/benzene").len(), 2);
+        assert!(matcher.search("zinc,benzene").is_empty());
+    }
+
+    #[test]
+    fn test_search_keys_in_text_prefilters() {
+        let mut map = KeyMap::new();
+        map.insert("Acetic acid", Id::Cid(1));
+        map.insert("Morphine", Id::Cid(2));
+        map.insert("Sodium chloride solution", Id::Cid(3));
+        let text = "Acetates, morphine sulfate and sodium chloride solution, but no acid.\n\nNothing here.";
+
+        let mut matcher = MatcherBuilder::new().variants(true).salt_suffixes(vec!["sulfate".to_string()]).build(map.clone()).unwrap();
+        let first_words = matcher.options().first_words.as_ref().unwrap();
+        assert!(first_words.contains("Acetic") && first_words.contains("Acetates") && first_words.contains("Sodium"));
+        // acetate (covering acetates), acetic, morphine and sodium
+        let prefixes = matcher.options().prefixes.as_ref().unwrap();
+        assert_eq!(prefixes.patterns_len(), 4);
+        assert!(prefixes.is_match("ACETATE") && prefixes.is_match("morphine") && !prefixes.is_match("Nothing here."));
+        let found = matcher.search(text);
+        assert_eq!(found.iter().map(|m| m.key.as_str()).collect::<Vec<&str>>(), vec!["Acetates", "Morphine", "Sodium chloride solution"]);
+        matcher.options.first_words = None;
+        matcher.options.prefixes = None;
+        assert_eq!(rows(matcher.search(text)), rows(found));
+
+        let matcher = MatcherBuilder::new().fuzzy(5).build(map).unwrap();
+        assert!(matcher.options().first_words.is_none() && matcher.options().prefixes.is_none());
+        assert_eq!(matcher.search("Morphyne")[0].match_type, MatchType::Fuzzy(1));
+
+        let map: KeyMap = (0..1000).map(|i| (format!("Compound{}", i), Id::Cid(i))).chain([("Zn".to_string(), Id::Cid(23994))]).collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        assert_eq!(matcher.options().prefixes.as_ref().unwrap().patterns_len(), 2);
+        assert_eq!(matcher.search("Compound42 and zn")[0].key, "Compound42");
+        let map: KeyMap = (0..1000).map(|i| (format!("{:03}", i), Id::Cid(i))).collect();
+        assert!(Matcher::new(map, SearchOptions::default()).options().prefixes.is_none());
+    }
+}
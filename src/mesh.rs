@@ -0,0 +1,144 @@
+//! Reading MeSH records, e.g. the supplementary concept records (SCR) of chemicals, from the
+//! ASCII (`c2024.bin`) or XML (`supp2024.xml`) files NLM distributes.
+
+use std::error::Error;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+/// Terms of a MeSH record with its unique id and registry numbers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MeshRecord {
+    /// MeSH UI, e.g. C000123 for a supplementary concept record
+    pub ui: String,
+    /// The record's name followed by its synonyms
+    pub terms: Vec<String>,
+    /// CAS numbers, UNIIs or EC numbers of the record, without "0" (none)
+    pub registry_numbers: Vec<String>,
+}
+
+impl MeshRecord {
+    fn add_registry_number(&mut self, number: &str) {
+        // related numbers carry a description, e.g. "7207-89-0 (bevonium methyl sulfate)"
+        let number = number.split_whitespace().next().unwrap_or_default();
+        if !number.is_empty() && number != "0" && !self.registry_numbers.iter().any(|known| known == number) {
+            self.registry_numbers.push(number.to_string());
+        }
+    }
+}
+
+/// Call f with each record of the ASCII format: `*NEWRECORD` lines, each followed by `FIELD = value`
+/// lines, of which NM and MH (name), SY and ENTRY (synonyms, the term before the first `|`), RN and
+/// RR (registry numbers) and UI are read
+pub fn read_mesh_ascii(content: &str, mut f: impl FnMut(MeshRecord)) {
+    let mut record: Option<MeshRecord> = None;
+    for line in content.lines() {
+        if line.trim() == "*NEWRECORD" {
+            if let Some(record) = record.replace(MeshRecord::default()) {
+                f(record);
+            }
+            continue;
+        }
+        let (Some(record), Some((field, value))) = (record.as_mut(), line.split_once(" = ")) else {
+            continue;
+        };
+        let value = value.trim();
+        match field.trim() {
+            "NM" | "MH" => record.terms.insert(0, value.to_string()),
+            "SY" | "ENTRY" | "PRINT ENTRY" => record.terms.push(value.split('|').next().unwrap_or_default().to_string()),
+            "RN" | "RR" => record.add_registry_number(value),
+            "UI" => record.ui = value.to_string(),
+            _ => {}
+        }
+    }
+    if let Some(record) = record {
+        f(record);
+    }
+}
+
+/// Call f with each SupplementalRecord or DescriptorRecord of the XML format, reading its UI,
+/// the String of each of its Terms and its RegistryNumbers
+pub fn read_mesh_xml(content: &str, mut f: impl FnMut(MeshRecord)) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_str(content);
+    // names of the open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut record: Option<MeshRecord> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(element) => {
+                let name = element.name().as_ref().to_vec();
+                if name == b"SupplementalRecord" || name == b"DescriptorRecord" {
+                    record = Some(MeshRecord::default());
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                if let Some(name) = path.pop() {
+                    if name == b"SupplementalRecord" || name == b"DescriptorRecord" {
+                        f(record.take().unwrap_or_default());
+                    }
+                }
+            }
+            Event::Text(text) => {
+                if let (Some(record), [.., parent, name]) = (record.as_mut(), &path[..]) {
+                    let text = text.unescape()?;
+                    match (parent.as_slice(), name.as_slice()) {
+                        (b"SupplementalRecord" | b"DescriptorRecord", b"SupplementalRecordUI" | b"DescriptorUI") => record.ui = text.trim().to_string(),
+                        (b"Term", b"String") => record.terms.push(text.trim().to_string()),
+                        (_, b"RegistryNumber" | b"RelatedRegistryNumber") => record.add_registry_number(&text),
+                        _ => {}
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mesh() {
+        let ascii = "*NEWRECORD\nRECTYPE = C\nNM = bevonium\nSY = bevonium methyl sulfate|EN|NRW|NLM (1986)|860101|abbcdef\nRN = 5205-82-5\nRR = 7207-89-0 (bevonium methyl sulfate)\nUI = C000002\n\n*NEWRECORD\nRECTYPE = C\nNM = mystery extract\nRN = 0\nUI = C000003\n";
+        let mut records = Vec::new();
+        read_mesh_ascii(ascii, |record| records.push(record));
+        assert_eq!(
+            records[0],
+            MeshRecord {
+                ui: "C000002".to_string(),
+                terms: vec!["bevonium".to_string(), "bevonium methyl sulfate".to_string()],
+                registry_numbers: vec!["5205-82-5".to_string(), "7207-89-0".to_string()],
+            }
+        );
+        assert!(records[1].registry_numbers.is_empty());
+
+        let xml = r#"<?xml version="1.0"?>
+<!DOCTYPE SupplementalRecordSet SYSTEM "https://www.nlm.nih.gov/databases/dtd/nlmsupplementalrecordset_20240101.dtd">
+<SupplementalRecordSet LanguageCode="eng">
+<SupplementalRecord SCRClass="1">
+ <SupplementalRecordUI>C000002</SupplementalRecordUI>
+ <SupplementalRecordName><String>bevonium</String></SupplementalRecordName>
+ <HeadingMappedToList><HeadingMappedTo><DescriptorReferredTo><DescriptorUI>*D001553</DescriptorUI><DescriptorName><String>Benzilates</String></DescriptorName></DescriptorReferredTo></HeadingMappedTo></HeadingMappedToList>
+ <ConceptList><Concept PreferredConceptYN="Y">
+  <ConceptUI>M0000002</ConceptUI><ConceptName><String>bevonium</String></ConceptName>
+  <RegistryNumber>5205-82-5</RegistryNumber>
+  <RelatedRegistryNumberList><RelatedRegistryNumber>7207-89-0 (bevonium methyl sulfate)</RelatedRegistryNumber></RelatedRegistryNumberList>
+  <TermList>
+   <Term ConceptPreferredTermYN="Y"><TermUI>T000002</TermUI><String>bevonium</String></Term>
+   <Term ConceptPreferredTermYN="N"><TermUI>T000003</TermUI><String>bevonium methyl sulfate &amp; salts</String></Term>
+  </TermList>
+ </Concept></ConceptList>
+</SupplementalRecord>
+</SupplementalRecordSet>"#;
+        let mut records = Vec::new();
+        read_mesh_xml(xml, |record| records.push(record)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ui, "C000002");
+        assert_eq!(records[0].terms, vec!["bevonium".to_string(), "bevonium methyl sulfate & salts".to_string()]);
+        assert_eq!(records[0].registry_numbers, vec!["5205-82-5".to_string(), "7207-89-0".to_string()]);
+        assert!(read_mesh_xml("<SupplementalRecordSet><SupplementalRecord></Other>", |_| {}).is_err());
+    }
+}
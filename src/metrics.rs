@@ -0,0 +1,160 @@
+//! Throughput counters shared by the workers of a run, reported as rate lines or in the
+//! Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Work done by one worker, e.g. a task slot searching inputs or the HTTP handlers
+pub struct WorkerMetrics {
+    started: Instant,
+    documents: AtomicU64,
+    paragraphs: AtomicU64,
+    matches: AtomicU64,
+}
+
+impl WorkerMetrics {
+    fn new() -> WorkerMetrics {
+        WorkerMetrics { started: Instant::now(), documents: AtomicU64::new(0), paragraphs: AtomicU64::new(0), matches: AtomicU64::new(0) }
+    }
+
+    /// Count a document searched, with the paragraphs and matches Matcher::search_counted found
+    pub fn record(&self, paragraphs: usize, matches: usize) {
+        self.documents.fetch_add(1, Ordering::Relaxed);
+        self.paragraphs.fetch_add(paragraphs as u64, Ordering::Relaxed);
+        self.matches.fetch_add(matches as u64, Ordering::Relaxed);
+    }
+
+    /// Documents, paragraphs and matches counted so far
+    pub fn counts(&self) -> [u64; 3] {
+        [&self.documents, &self.paragraphs, &self.matches].map(|count| count.load(Ordering::Relaxed))
+    }
+
+    /// Documents, paragraphs and matches per second since the worker started
+    pub fn rates(&self) -> [f64; 3] {
+        let seconds = self.started.elapsed().as_secs_f64().max(1e-9);
+        self.counts().map(|count| count as f64 / seconds)
+    }
+}
+
+/// Workers of a run by name
+pub struct Metrics {
+    started: Instant,
+    workers: Mutex<BTreeMap<String, Arc<WorkerMetrics>>>,
+    // whether each task slot is held by a running task
+    slots: Mutex<Vec<bool>>,
+}
+
+/// A task slot held until dropped, with the counters of its worker
+pub struct TaskSlot {
+    metrics: Arc<Metrics>,
+    slot: usize,
+    pub worker: Arc<WorkerMetrics>,
+}
+
+impl Drop for TaskSlot {
+    fn drop(&mut self) {
+        self.metrics.slots.lock().unwrap()[self.slot] = false;
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics { started: Instant::now(), workers: Mutex::new(BTreeMap::new()), slots: Mutex::new(Vec::new()) }
+    }
+
+    /// The lowest task slot no running task holds, counted by the worker "task-N", so a run of
+    /// many inputs has as many workers as it searches at once rather than one per input
+    pub fn task_slot(self: &Arc<Metrics>) -> TaskSlot {
+        let slot = {
+            let mut slots = self.slots.lock().unwrap();
+            let slot = slots.iter().position(|held| !held).unwrap_or(slots.len());
+            if slot == slots.len() {
+                slots.push(true);
+            }
+            slots[slot] = true;
+            slot
+        };
+        TaskSlot { metrics: Arc::clone(self), slot, worker: self.worker(&format!("task-{}", slot)) }
+    }
+
+    /// Counters of the worker called name, created on first use
+    pub fn worker(&self, name: &str) -> Arc<WorkerMetrics> {
+        let mut workers = self.workers.lock().unwrap();
+        Arc::clone(workers.entry(name.to_string()).or_insert_with(|| Arc::new(WorkerMetrics::new())))
+    }
+
+    /// Every worker with its name
+    pub fn workers(&self) -> Vec<(String, Arc<WorkerMetrics>)> {
+        let workers = self.workers.lock().unwrap();
+        workers.iter().map(|(name, worker)| (name.clone(), Arc::clone(worker))).collect()
+    }
+
+    /// Documents, paragraphs and matches of every worker
+    pub fn totals(&self) -> [u64; 3] {
+        let workers = self.workers.lock().unwrap();
+        workers.values().fold([0; 3], |totals, worker| {
+            let counts = worker.counts();
+            [totals[0] + counts[0], totals[1] + counts[1], totals[2] + counts[2]]
+        })
+    }
+
+    /// One line of overall rates since the run started, e.g.
+    /// "120.0 documents/s, 480.0 paragraphs/s, 15.0 matches/s over 2 workers"
+    pub fn rate_line(&self) -> String {
+        let seconds = self.started.elapsed().as_secs_f64().max(1e-9);
+        let [documents, paragraphs, matches] = self.totals().map(|count| count as f64 / seconds);
+        let workers = self.workers.lock().unwrap().len();
+        format!("{:.1} documents/s, {:.1} paragraphs/s, {:.1} matches/s over {} workers", documents, paragraphs, matches, workers)
+    }
+
+    /// Counters of every worker in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let workers = self.workers.lock().unwrap();
+        let mut text = String::new();
+        for (i, (metric, help)) in [("documents", "Documents searched"), ("paragraphs", "Paragraphs searched"), ("matches", "Matches found")]
+            .iter()
+            .enumerate()
+        {
+            writeln!(text, "# HELP chem_matcher_{}_total {}", metric, help).unwrap();
+            writeln!(text, "# TYPE chem_matcher_{}_total counter", metric).unwrap();
+            for (name, worker) in workers.iter() {
+                let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+                writeln!(text, "chem_matcher_{}_total{{worker=\"{}\"}} {}", metric, name, worker.counts()[i]).unwrap();
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Arc::new(Metrics::new());
+        let (first, second) = (metrics.task_slot(), metrics.task_slot());
+        first.worker.record(2, 1);
+        second.worker.record(1, 2);
+        drop(first);
+        metrics.task_slot().worker.record(1, 0);
+        metrics.worker("http").record(1, 0);
+        assert_eq!(metrics.totals(), [4, 5, 3]);
+        assert_eq!(metrics.worker("task-0").counts(), [2, 3, 1]);
+        assert!(metrics.rate_line().ends_with("over 3 workers"));
+
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE chem_matcher_documents_total counter\n"));
+        assert!(text.contains("chem_matcher_paragraphs_total{worker=\"task-0\"} 3\n"));
+        assert!(text.contains("chem_matcher_matches_total{worker=\"http\"} 0\n"));
+    }
+}
@@ -0,0 +1,86 @@
+//! Read-only memory maps of compiled dictionaries, so worker processes on one machine share a
+//! single copy of the dictionary in the OS page cache instead of each loading their own.
+
+use std::io;
+
+/// The bytes of a file, mapped read-only on Unix and read into memory elsewhere. The file must
+/// not be truncated or rewritten in place while mapped; write_compiled_dict replaces it instead.
+pub struct Mmap {
+    #[cfg(unix)]
+    ptr: *const u8,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// the mapping is read-only and unmapped only on drop
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map the file at path
+    #[cfg(unix)]
+    pub fn open(path: &str) -> io::Result<Mmap> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = std::fs::File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "file too large to map"))?;
+        if len == 0 {
+            // mmap refuses empty mappings
+            return Ok(Mmap { ptr: std::ptr::NonNull::dangling().as_ptr(), len });
+        }
+        let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr: ptr as *const u8, len })
+    }
+
+    /// Read the file at path, which can't be mapped on this platform
+    #[cfg(not(unix))]
+    pub fn open(path: &str) -> io::Result<Mmap> {
+        Ok(Mmap { bytes: std::fs::read(path)? })
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    #[cfg(unix)]
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_mmap() {
+        let tmp_dir = TempDir::new("mmap").unwrap();
+        let path = tmp_dir.path().join("bytes");
+        fs::write(&path, b"Aspirin\t2244").unwrap();
+        assert_eq!(Mmap::open(path.to_str().unwrap()).unwrap().as_ref(), b"Aspirin\t2244");
+        fs::write(&path, b"").unwrap();
+        assert!(Mmap::open(path.to_str().unwrap()).unwrap().as_ref().is_empty());
+        assert!(Mmap::open(tmp_dir.path().join("missing").to_str().unwrap()).is_err());
+    }
+}
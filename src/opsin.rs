@@ -0,0 +1,61 @@
+//! Checking candidate names with an external OPSIN process, which turns systematic (IUPAC) names
+//! into structures and so tells real chemical names from chemical-looking words.
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Command running OPSIN on names read one per line, writing a SMILES or an empty line for each
+pub const OPSIN: &str = "java -jar opsin-cli.jar";
+
+/// SMILES of each of names that command, split on whitespace (e.g. OPSIN), parses, None for
+/// the others. Names are sent on the standard input of one process, one per line.
+pub fn opsin_structures(command: &str, names: &[String]) -> Result<Vec<Option<String>>, Box<dyn Error>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut args = command.split_whitespace();
+    let program = args.next().ok_or("empty OPSIN command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", command, e))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = names.join("\n") + "\n";
+    // written from a thread so a full output pipe cannot block both processes
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut structures = Vec::with_capacity(names.len());
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let line = line?;
+        structures.push(Some(line.trim().to_string()).filter(|smiles| !smiles.is_empty()));
+    }
+    writer.join().map_err(|_| "writing to OPSIN failed")??;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", command, status).into());
+    }
+    if structures.len() != names.len() {
+        return Err(format!("{} answered {} of {} names", command, structures.len(), names.len()).into());
+    }
+    Ok(structures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opsin_structures() {
+        let names = ["2-acetyloxybenzoic acid", "benzol", "ethanoic acid"].map(str::to_string);
+        // stands in for OPSIN, failing on names ending in "ol"
+        let structures = opsin_structures("sed s/.*ol$//", &names).unwrap();
+        assert_eq!(structures, vec![Some(names[0].clone()), None, Some(names[2].clone())]);
+        assert!(opsin_structures("head -n 1", &names).is_err());
+        assert!(opsin_structures("/nonexistent/opsin", &[]).unwrap().is_empty());
+        assert!(opsin_structures("/nonexistent/opsin", &names).unwrap_err().to_string().starts_with("cannot run"));
+    }
+}
@@ -0,0 +1,53 @@
+//! Text of PDF inputs, extracted with pdf-extract (the pdf feature) and cleaned of the
+//! ligatures, hyphenation and line breaks of typesetting before matching.
+
+use std::error::Error;
+use crate::text::{dehyphenate, to_nfkc};
+
+/// Text extracted from a PDF cleaned up for matching: ligatures and other compatibility forms
+/// folded (NFKC), soft hyphens dropped, words broken across lines rejoined, the remaining line
+/// breaks of a paragraph turned into spaces and pages and blank lines into paragraph breaks
+pub fn clean_pdf_text(text: &str) -> String {
+    let text = to_nfkc(text).replace('\u{ad}', "").replace('\u{c}', "\n\n");
+    let text = dehyphenate(&text);
+    let mut cleaned = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if !cleaned.is_empty() {
+            cleaned.push_str(if blank { "\n\n" } else { " " });
+        }
+        cleaned.push_str(line);
+        blank = false;
+    }
+    cleaned
+}
+
+/// Cleaned text of the PDF at path
+#[cfg(feature = "pdf")]
+pub fn read_pdf(path: &str) -> Result<String, Box<dyn Error>> {
+    // pdf-extract panics on some malformed or unsupported files, which only lose that input
+    let text = std::panic::catch_unwind(|| pdf_extract::extract_text(path)).map_err(|_| format!("{}: cannot extract text", path))??;
+    Ok(clean_pdf_text(&text))
+}
+
+/// Cleaned text of the PDF at path, needing the pdf feature
+#[cfg(not(feature = "pdf"))]
+pub fn read_pdf(path: &str) -> Result<String, Box<dyn Error>> {
+    Err(format!("{}: reading PDFs needs chem-matcher built with --features pdf", path).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_pdf_text() {
+        let text = "  Aspirin was dis-\nsolved in 2-\nchloro\u{fb01}ne and eth\u{ad}anol.\n\n\nIt was\nstirred.\u{c}Page two.\n";
+        assert_eq!(clean_pdf_text(text), "Aspirin was dissolved in 2-chlorofine and ethanol.\n\nIt was stirred.\n\nPage two.");
+        assert_eq!(clean_pdf_text("\n \n"), "");
+    }
+}
@@ -0,0 +1,116 @@
+//! Pipelines of threads connected by bounded channels, for searching one huge input on several
+//! cores: a source thread reads numbered batches of records, each stage maps them on workers of
+//! its own, and the calling thread takes the results back in input order. Reading, parsing and
+//! matching overlap, and each record's output rows are still written together and in place.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread::Scope;
+
+/// A batch of records and its position in the input
+pub type Batch<T> = (u64, Vec<T>);
+
+// Batches waiting for each worker of a stage, enough to keep it busy without reading far ahead
+const QUEUED_PER_WORKER: usize = 2;
+
+/// Read records on a thread of scope, passed on in batches of batch_size. Reading stops early
+/// once the batches are no longer received.
+pub fn source<'scope, T, I>(scope: &'scope Scope<'scope, '_>, batch_size: usize, records: I) -> flume::Receiver<Batch<T>>
+where
+    T: Send + 'scope,
+    I: Iterator<Item = T> + Send + 'scope,
+{
+    let (sender, receiver) = flume::bounded(QUEUED_PER_WORKER);
+    scope.spawn(move || {
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut sequence = 0;
+        for record in records {
+            batch.push(record);
+            if batch.len() == batch_size.max(1) {
+                if sender.send((sequence, std::mem::replace(&mut batch, Vec::with_capacity(batch_size)))).is_err() {
+                    return;
+                }
+                sequence += 1;
+            }
+        }
+        if !batch.is_empty() {
+            let _ = sender.send((sequence, batch));
+        }
+    });
+    receiver
+}
+
+/// Map the records of batches with f on threads workers of scope (at least one)
+pub fn stage<'scope, T, R, F>(scope: &'scope Scope<'scope, '_>, threads: usize, batches: flume::Receiver<Batch<T>>, f: F) -> flume::Receiver<Batch<R>>
+where
+    T: Send + 'scope,
+    R: Send + 'scope,
+    F: Fn(T) -> R + Send + Sync + 'scope,
+{
+    let threads = threads.max(1);
+    let (sender, receiver) = flume::bounded(threads * QUEUED_PER_WORKER);
+    let f = Arc::new(f);
+    for _ in 0..threads {
+        let (f, batches, sender) = (Arc::clone(&f), batches.clone(), sender.clone());
+        scope.spawn(move || {
+            for (sequence, batch) in batches.iter() {
+                if sender.send((sequence, batch.into_iter().map(|record| f(record)).collect())).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    receiver
+}
+
+/// Hand the records of batches to done in input order, until done returns false
+pub fn for_each_ordered<R>(batches: flume::Receiver<Batch<R>>, mut done: impl FnMut(R) -> bool) {
+    // batches received ahead of the next one
+    let mut finished = BTreeMap::new();
+    let mut next = 0;
+    for (sequence, batch) in batches.iter() {
+        finished.insert(sequence, batch);
+        while let Some(batch) = finished.remove(&next) {
+            next += 1;
+            for record in batch {
+                if !done(record) {
+                    // dropping the receiver stops the stages upstream
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        for threads in [1, 4] {
+            let mut squares = Vec::new();
+            std::thread::scope(|scope| {
+                let numbers = source(scope, 7, 0..1000_u64);
+                let doubled = stage(scope, 1, numbers, |n| n * 2);
+                let squared = stage(scope, threads, doubled, |n| n * n / 4);
+                for_each_ordered(squared, |square| {
+                    squares.push(square);
+                    true
+                });
+            });
+            assert_eq!(squares, (0..1000).map(|n| n * n).collect::<Vec<u64>>());
+
+            let mut kept = Vec::new();
+            std::thread::scope(|scope| {
+                let numbers = source(scope, 7, 0..);
+                let incremented = stage(scope, threads, numbers, |n: u64| n + 1);
+                for_each_ordered(incremented, |n| {
+                    kept.push(n);
+                    kept.len() < 10
+                });
+            });
+            assert_eq!(kept, (1..=10).collect::<Vec<u64>>());
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Enriching result files with properties of the matched compounds from PubChem PUG-REST,
+//! cached on disk and fetched no faster than a set rate.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use crate::dictionary::Id;
+use crate::io::{retry, FetchOptions};
+use crate::report::parse_result_line;
+use crate::xref::Xrefs;
+
+/// Base URL of PUG-REST
+pub const PUG_REST: &str = "https://pubchem.ncbi.nlm.nih.gov/rest/pug";
+
+// CIDs asked for in one request
+const BATCH_CIDS: usize = 100;
+
+// Wait before the first retry of a failed request; doubled for each further attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Properties of a compound added to its rows; empty when PubChem has none
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Properties {
+    pub smiles: String,
+    pub formula: String,
+    pub iupac_name: String,
+}
+
+impl Properties {
+    // Properties of an entry of a PropertyTable; PubChem reports canonical SMILES as
+    // ConnectivitySMILES in newer responses
+    fn from_json(entry: &serde_json::Value) -> Properties {
+        let field = |names: &[&str]| names.iter().find_map(|name| entry[name].as_str()).unwrap_or_default().to_string();
+        Properties {
+            smiles: field(&["CanonicalSMILES", "ConnectivitySMILES", "SMILES"]),
+            formula: field(&["MolecularFormula"]),
+            iupac_name: field(&["IUPACName"]),
+        }
+    }
+}
+
+/// Client of PUG-REST keeping the properties of each CID in `<cache>/<cid>.json`
+pub struct PubChem {
+    client: reqwest::Client,
+    base_url: String,
+    cache: Option<PathBuf>,
+    max_attempts: u32,
+    // shortest time between the starts of two requests
+    interval: Duration,
+    last_request: Option<Instant>,
+}
+
+impl PubChem {
+    /// Client of base_url making at most rate requests per second (PubChem allows 5), caching in
+    /// cache when given
+    pub fn new(base_url: &str, cache: Option<PathBuf>, rate: f64, fetch: &FetchOptions) -> Result<PubChem, Box<dyn Error>> {
+        if rate <= 0.0 {
+            return Err(format!("request rate must be positive: {}", rate).into());
+        }
+        if let Some(dir) = &cache {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(PubChem {
+            client: fetch.client()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            cache,
+            max_attempts: fetch.max_attempts.max(1),
+            interval: Duration::from_secs_f64(1.0 / rate),
+            last_request: None,
+        })
+    }
+
+    // Properties of cids PubChem knows, in one request
+    async fn fetch(&mut self, cids: &[u32]) -> Result<HashMap<u32, Properties>, Box<dyn Error>> {
+        let list: Vec<String> = cids.iter().map(u32::to_string).collect();
+        let url = format!("{}/compound/cid/{}/property/CanonicalSMILES,MolecularFormula,IUPACName/JSON", self.base_url, list.join(","));
+        let (client, interval, last_request) = (&self.client, self.interval, &mut self.last_request);
+        let body = retry(self.max_attempts, RETRY_DELAY, || {
+            let wait = last_request.map(|last| interval.saturating_sub(last.elapsed())).unwrap_or_default();
+            *last_request = Some(Instant::now() + wait);
+            let url = &url;
+            async move {
+                tokio::time::sleep(wait).await;
+                let response = client.get(url).send().await?;
+                // asked only for unknown CIDs
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(serde_json::Value::Null);
+                }
+                response.error_for_status()?.json::<serde_json::Value>().await
+            }
+        })
+        .await?;
+        let entries = body["PropertyTable"]["Properties"].as_array().cloned().unwrap_or_default();
+        debug!(cids = cids.len(), found = entries.len(), "fetched PubChem properties");
+        Ok(entries.iter().filter_map(|entry| Some((entry["CID"].as_u64()? as u32, Properties::from_json(entry)))).collect())
+    }
+
+    /// Properties of each of cids, from the cache or fetched in batches; CIDs PubChem does not
+    /// know get empty properties, which are cached too
+    pub async fn properties(&mut self, cids: &BTreeSet<u32>) -> Result<HashMap<u32, Properties>, Box<dyn Error>> {
+        let mut properties = HashMap::new();
+        let mut missing = Vec::new();
+        for &cid in cids {
+            let cached = self.cache.as_ref().and_then(|dir| fs::read_to_string(dir.join(format!("{}.json", cid))).ok());
+            match cached.and_then(|json| serde_json::from_str(&json).ok()) {
+                Some(cached) => {
+                    properties.insert(cid, cached);
+                }
+                None => missing.push(cid),
+            }
+        }
+        for batch in missing.chunks(BATCH_CIDS) {
+            let mut fetched = self.fetch(batch).await?;
+            for cid in batch {
+                let found = fetched.remove(cid).unwrap_or_default();
+                if let Some(dir) = &self.cache {
+                    // the cache only saves a request, so failing to write it is not an error
+                    if let Err(e) = fs::write(dir.join(format!("{}.json", cid)), serde_json::to_string(&found)?) {
+                        warn!(cid, error = %e, "could not cache");
+                    }
+                }
+                properties.insert(*cid, found);
+            }
+        }
+        Ok(properties)
+    }
+}
+
+// Quote a column the way generate_report does
+fn quoted(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// Write the rows of result files to output with more columns about their CID, empty for rows
+/// without one or with another kind of id: with pubchem its canonical SMILES, molecular formula
+/// and IUPAC name, then with xrefs its ChEBI, MeSH and DrugBank ids. Returns the rows written and
+/// the distinct CIDs.
+pub async fn enrich_results(files: &[String], output: &str, pubchem: Option<&mut PubChem>, xrefs: Option<&Xrefs>) -> Result<(u64, usize), Box<dyn Error>> {
+    let mut cids = BTreeSet::new();
+    for file_path in files {
+        for (i, line) in BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if !line.is_empty() {
+                let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+                cids.extend(row.cid.as_ref().and_then(Id::cid));
+            }
+        }
+    }
+    let properties = match pubchem {
+        Some(pubchem) => Some(pubchem.properties(&cids).await?),
+        None => None,
+    };
+
+    let tmp = format!("{}.tmp", output);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let mut rows = 0;
+    for file_path in files {
+        for (i, line) in BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let cid = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?.cid.as_ref().and_then(Id::cid);
+            let mut columns = Vec::new();
+            if let Some(properties) = &properties {
+                let found = cid.and_then(|cid| properties.get(&cid)).cloned().unwrap_or_default();
+                columns.extend([found.smiles, found.formula, found.iupac_name]);
+            }
+            if let Some(xrefs) = xrefs {
+                columns.extend(xrefs.columns(cid));
+            }
+            let columns: Vec<String> = columns.iter().map(|column| quoted(column)).collect();
+            writeln!(writer, "{},{}", line, columns.join(","))?;
+            rows += 1;
+        }
+    }
+    writer.flush()?;
+    fs::rename(tmp, output)?;
+    Ok((rows, cids.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use axum::extract::{Path, State};
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use tempdir::TempDir;
+
+    // PUG-REST property table of the known ones of a comma-separated list of CIDs
+    async fn property_table(State(requests): State<Arc<AtomicUsize>>, Path((cids, _)): Path<(String, String)>) -> Json<serde_json::Value> {
+        requests.fetch_add(1, Ordering::SeqCst);
+        let known = |cid: &str| match cid {
+            "2244" => Some(serde_json::json!({"CID": 2244, "ConnectivitySMILES": "CC(=O)OC1=CC=CC=C1C(=O)O", "MolecularFormula": "C9H8O4", "IUPACName": "2-acetyloxybenzoic acid"})),
+            "962" => Some(serde_json::json!({"CID": 962, "CanonicalSMILES": "O", "MolecularFormula": "H2O", "IUPACName": "oxidane"})),
+            _ => None,
+        };
+        let properties: Vec<serde_json::Value> = cids.split(',').filter_map(known).collect();
+        Json(serde_json::json!({"PropertyTable": {"Properties": properties}}))
+    }
+
+    #[tokio::test]
+    async fn test_enrich_results() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route("/compound/cid/:cids/property/:properties/JSON", get(property_table)).with_state(Arc::clone(&requests));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+        let tmp_dir = TempDir::new("pubchem").unwrap();
+        let results = tmp_dir.path().join("out.csv");
+        fs::write(&results, "\"Aspirin\",2244,\"take <|MOLECULE|>\",7,exact,name,1.000\n\"Water\",962,\"in <|MOLECULE|>\",7,exact,name,1.000\n\"x\",1,\"c\",8,exact,name,1.000\n\"50-00-0\",,\"c\",8,exact,cas,1.000\n").unwrap();
+        let files = [results.to_str().unwrap().to_string()];
+        let output = tmp_dir.path().join("enriched.csv");
+        let cache = tmp_dir.path().join("cache");
+        let mut pubchem = PubChem::new(&format!("http://{}/", address), Some(cache.clone()), 50.0, &FetchOptions::default()).unwrap();
+        assert_eq!(enrich_results(&files, output.to_str().unwrap(), Some(&mut pubchem), None).await.unwrap(), (4, 3));
+        let lines: Vec<String> = fs::read_to_string(&output).unwrap().lines().map(str::to_string).collect();
+        assert_eq!(lines[0], "\"Aspirin\",2244,\"take <|MOLECULE|>\",7,exact,name,1.000,\"CC(=O)OC1=CC=CC=C1C(=O)O\",\"C9H8O4\",\"2-acetyloxybenzoic acid\"");
+        assert!(lines[1].ends_with(",\"O\",\"H2O\",\"oxidane\""));
+        assert!(lines[2].ends_with(",1.000,\"\",\"\",\"\"") && lines[3].ends_with(",1.000,\"\",\"\",\"\""));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // every CID, known or not, now comes from the cache
+        let mut pubchem = PubChem::new(&format!("http://{}", address), Some(cache), 50.0, &FetchOptions::default()).unwrap();
+        let properties = pubchem.properties(&[962, 1].into_iter().collect()).await.unwrap();
+        assert_eq!(properties[&962].formula, "H2O");
+        assert_eq!(properties[&1], Properties::default());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert!(PubChem::new(PUG_REST, None, 0.0, &FetchOptions::default()).is_err());
+
+        // cross-references alone need no requests
+        let mapping = tmp_dir.path().join("xrefs.tsv");
+        fs::write(&mapping, "cid\tchebi\tmesh\n2244\tCHEBI:15365\tD001241\n").unwrap();
+        let xrefs = Xrefs::read(mapping.to_str().unwrap()).unwrap();
+        enrich_results(&files, output.to_str().unwrap(), None, Some(&xrefs)).await.unwrap();
+        let lines: Vec<String> = fs::read_to_string(&output).unwrap().lines().map(str::to_string).collect();
+        assert!(lines[0].ends_with(",1.000,\"CHEBI:15365\",\"D001241\",\"\""));
+        assert!(lines[1].ends_with(",1.000,\"\",\"\",\"\""));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,999 @@
+//! Writing matches and candidate names, and summarizing result files.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter};
+use std::error::Error;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::OnceLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::dictionary::{hash_strings, Id};
+use crate::eval::{predicted_mentions, Mention};
+use crate::matcher::{Match, SearchResults};
+
+/// Write candidate names and their counts, most frequent first
+pub fn write_candidates(file_path: &str, counts: HashMap<String, usize>) -> Result<(), Box<dyn Error>> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let tmp = format!("{}.tmp", file_path);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for (name, count) in counts {
+        writeln!(writer, "{}\t{}", name, count)?;
+    }
+    writer.flush()?;
+    fs::rename(tmp, file_path)?;
+    Ok(())
+}
+
+/// A match as a JSON object
+pub fn match_json(found: &Match) -> serde_json::Value {
+    serde_json::json!({
+        "key": found.key,
+        "cid": found.cid,
+        "context": found.context,
+        "match_type": found.match_type.to_string(),
+        "id_type": found.id_type.to_string(),
+        "score": found.score,
+        "start": found.start,
+        "end": found.end,
+    })
+}
+
+/// Label of the entities in ner_json records
+pub const NER_LABEL: &str = "CHEMICAL";
+
+/// A document and its matches as a `{"id": ..., "text": ..., "entities": [[start, end, "CHEMICAL", cid], ...]}`
+/// record, with character offsets as spaCy and Hugging Face token classification expect
+pub fn ner_json(paper_id: &str, text: &str, matches: &[Match]) -> serde_json::Value {
+    ner_record(paper_id, text, &predicted_mentions(text, matches))
+}
+
+/// ner_json for chemical mentions of text
+pub fn ner_record(paper_id: &str, text: &str, mentions: &[Mention]) -> serde_json::Value {
+    let entities: Vec<serde_json::Value> =
+        mentions.iter().map(|mention| serde_json::json!([mention.start, mention.end, NER_LABEL, mention.cid])).collect();
+    serde_json::json!({ "id": paper_id, "text": text, "entities": entities })
+}
+
+/// Counts from searching one input
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InputStats {
+    /// Lines of a JSON input, or 1 for a text file
+    pub records_read: u64,
+    /// Records not searched, by reason
+    pub records_skipped: BTreeMap<String, u64>,
+    pub matches: u64,
+    pub cids: HashSet<Id>,
+    /// Characters that could not be decoded and were replaced with U+FFFD
+    pub replaced_characters: u64,
+}
+
+impl InputStats {
+    /// Count a record left out for reason, e.g. "invalid-json"
+    pub fn skip(&mut self, reason: &str) {
+        *self.records_skipped.entry(reason.to_string()).or_default() += 1;
+    }
+
+    /// Count the matches of a searched record
+    pub fn add_matches(&mut self, results: &SearchResults) {
+        self.matches += results.len() as u64;
+        self.cids.extend(results.iter().filter_map(|found| found.cid.clone()));
+    }
+}
+
+/// Machine-readable summary of a run
+#[derive(Serialize, Debug, Default)]
+pub struct RunSummary {
+    pub inputs_processed: Vec<String>,
+    /// Inputs the ledger records as finished by an earlier run
+    pub inputs_skipped: Vec<String>,
+    pub records_read: u64,
+    pub records_skipped: BTreeMap<String, u64>,
+    pub matches: u64,
+    pub unique_cids: usize,
+    /// Characters replaced while decoding each input that had any
+    pub replaced_characters: BTreeMap<String, u64>,
+    pub wall_time_seconds: f64,
+    /// Arguments the program was started with
+    pub command_line: Vec<String>,
+    /// Every option, defaults included
+    pub config: serde_json::Value,
+    #[serde(skip)]
+    cids: HashSet<Id>,
+}
+
+impl RunSummary {
+    pub fn new(config: serde_json::Value) -> RunSummary {
+        RunSummary { command_line: std::env::args().collect(), config, ..Default::default() }
+    }
+
+    /// Add the counts of a processed input
+    pub fn add_input(&mut self, input: &str, stats: InputStats) {
+        self.inputs_processed.push(input.to_string());
+        self.records_read += stats.records_read;
+        for (reason, count) in stats.records_skipped {
+            *self.records_skipped.entry(reason).or_default() += count;
+        }
+        self.matches += stats.matches;
+        if stats.replaced_characters > 0 {
+            *self.replaced_characters.entry(input.to_string()).or_default() += stats.replaced_characters;
+        }
+        self.cids.extend(stats.cids);
+        self.unique_cids = self.cids.len();
+    }
+
+    /// Write the summary as pretty JSON, through a temporary file
+    pub fn write(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let tmp = format!("{}.tmp", file_path);
+        fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        fs::rename(tmp, file_path)?;
+        Ok(())
+    }
+}
+
+/// Hash identifying a result row by its paper, CID and context
+pub fn row_key(row: &ResultRow) -> u64 {
+    let cid = row.cid.as_ref().map(Id::to_string).unwrap_or_default();
+    hash_strings([format!("{}\t{}\t{}", row.paper_id, cid, row.context)])
+}
+
+/// Concatenate result files into output through a temporary file, leaving out rows with the same
+/// paper, CID and context as one already written. Each file is added to summary with the rows it
+/// contributed; records read and skipped come from the summary written next to it by its run
+/// (`<file>.summary.json`) when there is one, and dropped rows are skipped as "duplicate-row".
+pub fn merge_results(files: &[String], output: &str, summary: &mut RunSummary) -> Result<(), Box<dyn Error>> {
+    let tmp = format!("{}.tmp", output);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    let mut seen: HashSet<u64> = HashSet::new();
+    for file_path in files {
+        let mut stats = InputStats::default();
+        let run_summary = format!("{}.summary.json", file_path);
+        if Path::new(&run_summary).exists() {
+            let run: serde_json::Value = serde_json::from_str(&fs::read_to_string(&run_summary)?)?;
+            stats.records_read = run["records_read"].as_u64().unwrap_or_default();
+            for (reason, count) in run["records_skipped"].as_object().into_iter().flatten() {
+                stats.records_skipped.insert(reason.clone(), count.as_u64().unwrap_or_default());
+            }
+        }
+        for (i, line) in io::BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+            if seen.insert(row_key(&row)) {
+                writeln!(writer, "{}", line)?;
+                stats.matches += 1;
+                stats.cids.extend(row.cid);
+            } else {
+                stats.skip("duplicate-row");
+            }
+        }
+        summary.add_input(file_path, stats);
+    }
+    writer.flush()?;
+    fs::rename(tmp, output)?;
+    Ok(())
+}
+
+/// Generate the report in a readable format
+pub fn generate_report(search_results: SearchResults, writer: &mut impl Write, paper_id: &str) {
+    for Match { context, key, cid, match_type, id_type, score, .. } in search_results {
+        // show the context window around the word
+        let msg = result_line(&key, cid.as_ref(), &context, paper_id, &match_type, &id_type, score);
+        writer.write_all(msg.as_bytes()).unwrap();
+    }
+}
+
+// A line of a result file, with its newline
+fn result_line(key: &str, cid: Option<&Id>, context: &str, paper_id: &str, match_type: &dyn std::fmt::Display, id_type: &dyn std::fmt::Display, score: f32) -> String {
+    let cid = cid.map(|cid| cid.to_string()).unwrap_or_default();
+    format!("\"{}\",{},\"{}\",{},{},{},{:.3}\n", key, cid, context.replace('"', "\\\"").replace('\n', "\\n"), paper_id, match_type, id_type, score)
+}
+
+/// Rows written for each document of a run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    /// One row per match, by generate_report
+    Match,
+    /// One row per document listing its CIDs, by generate_paper_report
+    Paper,
+}
+
+impl std::str::FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aggregate, String> {
+        match s {
+            "match" => Ok(Aggregate::Match),
+            "paper" => Ok(Aggregate::Paper),
+            _ => Err(format!("unknown aggregate: {}", s)),
+        }
+    }
+}
+
+impl Aggregate {
+    /// Write the rows of a document
+    pub fn write(self, search_results: SearchResults, writer: &mut impl Write, paper_id: &str) {
+        match self {
+            Aggregate::Match => generate_report(search_results, writer, paper_id),
+            Aggregate::Paper => generate_paper_report(&search_results, writer, paper_id),
+        }
+    }
+}
+
+/// Format of the output of a run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Rows of matches or papers, as chosen by Aggregate
+    Csv,
+    /// Token-per-line BIO tags of the paragraphs with matches, by conll::write_conll
+    Conll,
+    /// One ner_json record per document with matches
+    NerJson,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "conll" => Ok(OutputFormat::Conll),
+            "ner-json" => Ok(OutputFormat::NerJson),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Write one `paper_id,cid:mentions;cid:mentions` row for a document, most mentioned CIDs first;
+/// nothing when no match has a CID
+pub fn generate_paper_report(search_results: &SearchResults, writer: &mut impl Write, paper_id: &str) {
+    let mut mentions: HashMap<Id, u64> = HashMap::new();
+    for cid in search_results.iter().filter_map(|found| found.cid.as_ref()) {
+        *mentions.entry(cid.clone()).or_default() += 1;
+    }
+    if mentions.is_empty() {
+        return;
+    }
+    let cids: Vec<String> = ranked(&mentions, usize::MAX).iter().map(|(cid, count)| format!("{}:{}", cid, count)).collect();
+    writeln!(writer, "{},{}", paper_id, cids.join(";")).unwrap();
+}
+
+/// A line of a result file written with `--aggregate paper`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperRow {
+    /// corpusid of the document, empty for text files
+    pub paper_id: String,
+    /// CIDs with their mentions, most mentioned first
+    pub cids: Vec<(Id, u64)>,
+}
+
+/// Parse a line written by generate_paper_report
+pub fn parse_paper_line(line: &str) -> Result<PaperRow, String> {
+    let (paper_id, cids) = line.rsplit_once(',').ok_or("not a paper line")?;
+    let cids = cids
+        .split(';')
+        .map(|entry| {
+            let (cid, count) = entry.split_once(':').ok_or(format!("bad entry: {}", entry))?;
+            Ok((cid.parse().map_err(|e| format!("bad cid: {}", e))?, count.parse().map_err(|e| format!("bad count: {}", e))?))
+        })
+        .collect::<Result<Vec<(Id, u64)>, String>>()?;
+    Ok(PaperRow { paper_id: paper_id.to_string(), cids })
+}
+
+/// A line of a result file, as written by generate_report
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResultRow {
+    pub key: String,
+    pub cid: Option<Id>,
+    pub context: String,
+    /// corpusid of the document, empty for text files
+    pub paper_id: String,
+    pub match_type: String,
+    pub id_type: String,
+    pub score: f32,
+}
+
+impl ResultRow {
+    /// The row as a result line, with its newline
+    pub fn to_line(&self) -> String {
+        result_line(&self.key, self.cid.as_ref(), &self.context, &self.paper_id, &self.match_type, &self.id_type, self.score)
+    }
+}
+
+/// Parse a result line. Keys are written unescaped, so one containing `",<id>,"` is misread.
+pub fn parse_result_line(line: &str) -> Result<ResultRow, String> {
+    static LINE_RE: OnceLock<Regex> = OnceLock::new();
+    let line_re = LINE_RE.get_or_init(|| Regex::new(r#"^"(.*?)",([^,"]*),"(.*)",([^,]*),([^,]*),([^,]*),([^,]*)$"#).unwrap());
+    let captures = line_re.captures(line).ok_or("not a result line")?;
+    let cid = match &captures[2] {
+        "" => None,
+        cid => Some(cid.parse().map_err(|e| format!("bad cid: {}", e))?),
+    };
+    Ok(ResultRow {
+        key: captures[1].to_string(),
+        cid,
+        context: captures[3].replace("\\n", "\n").replace("\\\"", "\""),
+        paper_id: captures[4].to_string(),
+        match_type: captures[5].to_string(),
+        id_type: captures[6].to_string(),
+        score: captures[7].parse().map_err(|e| format!("bad score: {}", e))?,
+    })
+}
+
+// Upper bounds of the context length buckets, in characters
+const CONTEXT_BUCKETS: &[usize] = &[50, 100, 200, 500, 1000, 2000, usize::MAX];
+
+/// Counts over result files, printed by the stats subcommand
+#[derive(Debug, PartialEq)]
+pub struct ResultStats {
+    pub matches: u64,
+    pub per_cid: HashMap<Id, u64>,
+    /// Matches of each synonym of a CID
+    pub cid_synonyms: HashMap<Id, HashMap<String, u64>>,
+    /// Matches such as CAS numbers without a known CID
+    pub without_cid: u64,
+    pub per_synonym: HashMap<String, u64>,
+    pub per_paper: HashMap<String, u64>,
+    /// (bucket upper bound, number of matches) by context length in characters
+    pub context_lengths: Vec<(usize, u64)>,
+    /// Entries listed of each ranking
+    pub top: usize,
+}
+
+impl ResultStats {
+    pub fn new(top: usize) -> ResultStats {
+        ResultStats {
+            matches: 0,
+            per_cid: HashMap::new(),
+            cid_synonyms: HashMap::new(),
+            without_cid: 0,
+            per_synonym: HashMap::new(),
+            per_paper: HashMap::new(),
+            context_lengths: CONTEXT_BUCKETS.iter().map(|&bound| (bound, 0)).collect(),
+            top,
+        }
+    }
+
+    pub fn add(&mut self, row: &ResultRow) {
+        self.matches += 1;
+        match &row.cid {
+            Some(cid) => {
+                *self.per_cid.entry(cid.clone()).or_default() += 1;
+                *self.cid_synonyms.entry(cid.clone()).or_default().entry(row.key.clone()).or_default() += 1;
+            }
+            None => self.without_cid += 1,
+        }
+        *self.per_synonym.entry(row.key.clone()).or_default() += 1;
+        *self.per_paper.entry(row.paper_id.clone()).or_default() += 1;
+        let length = row.context.chars().count();
+        self.context_lengths.iter_mut().find(|(bound, _)| length < *bound).unwrap().1 += 1;
+    }
+
+    /// Add every line of a result file
+    pub fn add_file(&mut self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        let reader = io::BufReader::new(File::open(file_path)?);
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if !line.is_empty() {
+                let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+                self.add(&row);
+            }
+        }
+        Ok(())
+    }
+
+    /// The top CIDs, most matched first
+    pub fn top_cids(&self) -> Vec<(Id, u64)> {
+        ranked(&self.per_cid, self.top)
+    }
+
+    /// Readable list of the top CIDs with their synonyms and up to a few contexts each from examples,
+    /// e.g. from sample_contexts, to spot dictionary entries prone to false positives
+    pub fn molecule_report(&self, examples: &HashMap<Id, Vec<String>>) -> String {
+        let mut report = String::new();
+        for (rank, (cid, count)) in self.top_cids().into_iter().enumerate() {
+            let synonyms: Vec<String> = ranked(&self.cid_synonyms[&cid], usize::MAX)
+                .into_iter()
+                .map(|(synonym, count)| format!("{} ({})", synonym, count))
+                .collect();
+            report += &format!("{}. CID {}, {} matches: {}\n", rank + 1, cid, count, synonyms.join(", "));
+            for context in examples.get(&cid).into_iter().flatten() {
+                report += &format!("    {}\n", context.replace('\n', " "));
+            }
+        }
+        report
+    }
+}
+
+/// Up to k distinct contexts of each of cids in result files. Contexts are picked by their hash,
+/// so the sample is spread over the files yet the same on every run.
+pub fn sample_contexts(files: &[String], cids: &HashSet<Id>, k: usize) -> Result<HashMap<Id, Vec<String>>, Box<dyn Error>> {
+    // lowest hashes seen for each cid, in order
+    let mut samples: HashMap<Id, Vec<(u64, String)>> = HashMap::new();
+    for file_path in files {
+        for (i, line) in io::BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+            let Some(cid) = row.cid.filter(|cid| cids.contains(cid)) else { continue };
+            let sample = samples.entry(cid).or_default();
+            let hash = hash_strings([&row.context]);
+            if let Err(position) = sample.binary_search_by(|(kept, context)| kept.cmp(&hash).then(context.cmp(&row.context))) {
+                if position < k {
+                    sample.insert(position, (hash, row.context));
+                    sample.truncate(k);
+                }
+            }
+        }
+    }
+    Ok(samples.into_iter().map(|(cid, sample)| (cid, sample.into_iter().map(|(_, context)| context).collect())).collect())
+}
+
+// SplitMix64, enough for reproducible sampling without a random number crate
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+// Up to k of the items offered, each as likely to be kept as any other (algorithm R)
+struct Reservoir<T> {
+    seen: u64,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    fn offer(&mut self, item: T, k: usize, rng: &mut SplitMix64) {
+        self.seen += 1;
+        if self.items.len() < k {
+            self.items.push(item);
+        } else {
+            let slot = rng.next() % self.seen;
+            if slot < k as u64 {
+                self.items[slot as usize] = item;
+            }
+        }
+    }
+}
+
+/// Up to k lines of result files picked uniformly at random from seed, or with per_cid up to k
+/// lines of every CID (lines without one counting as a CID of their own). Only the sample is
+/// held in memory; lines come back in file order, grouped by CID with per_cid.
+pub fn sample_results(files: &[String], k: usize, per_cid: bool, seed: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut rng = SplitMix64(seed);
+    // lines kept with their (file, line) position, by CID
+    let mut reservoirs = BTreeMap::new();
+    for (f, file_path) in files.iter().enumerate() {
+        for (i, line) in io::BufReader::new(File::open(file_path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let row = parse_result_line(&line).map_err(|e| format!("{}:{}: {}", file_path, i + 1, e))?;
+            let stratum = if per_cid { row.cid } else { None };
+            reservoirs.entry(stratum).or_insert(Reservoir { seen: 0, items: Vec::new() }).offer(((f, i), line), k, &mut rng);
+        }
+    }
+    let mut lines = Vec::new();
+    for (_, mut reservoir) in reservoirs {
+        reservoir.items.sort();
+        lines.extend(reservoir.items.into_iter().map(|(_, line)| line));
+    }
+    Ok(lines)
+}
+
+// The top entries of counts, most frequent first
+fn ranked<K: Ord + Clone>(counts: &HashMap<K, u64>, top: usize) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.iter().map(|(key, count)| (key.clone(), *count)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(top);
+    counts
+}
+
+impl std::fmt::Display for ResultStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "matches\t{}", self.matches)?;
+        writeln!(f, "papers with a match\t{}", self.per_paper.len())?;
+        writeln!(f, "unique cids\t{}", self.per_cid.len())?;
+        writeln!(f, "matches without cid\t{}", self.without_cid)?;
+        writeln!(f, "unique synonyms\t{}", self.per_synonym.len())?;
+        for (cid, count) in self.top_cids() {
+            writeln!(f, "cid\t{}\t{}", cid, count)?;
+        }
+        for (synonym, count) in ranked(&self.per_synonym, self.top) {
+            writeln!(f, "synonym\t{}\t{}", synonym, count)?;
+        }
+        for (paper, count) in ranked(&self.per_paper, self.top) {
+            writeln!(f, "paper\t{}\t{}", paper, count)?;
+        }
+        let mut lower = 0;
+        for (bound, count) in &self.context_lengths {
+            if *bound == usize::MAX {
+                writeln!(f, "context length {}+\t{}", lower, count)?;
+            } else {
+                writeln!(f, "context length {}-{}\t{}", lower, bound - 1, count)?;
+            }
+            lower = *bound;
+        }
+        Ok(())
+    }
+}
+
+/// Writes lines to <output>.part-0001, <output>.part-0002, ..., starting a new part once the current
+/// one holds max_rows lines or max_bytes bytes. <output>.manifest lists each part with its rows and bytes.
+/// A new part is written as <part>.tmp and renamed by flush. Lines added to a listed part count once
+/// flush rewrites the manifest, and resuming truncates each part back to its listed bytes, so the
+/// manifest only ever names complete files and rows.
+pub struct RotatingWriter {
+    output: String,
+    max_rows: Option<u64>,
+    max_bytes: Option<u64>,
+    // (file, rows, bytes) of each part so far
+    parts: Vec<(String, u64, u64)>,
+    // the last part, open as <part>.tmp when new or under its listed name when reopened
+    writer: Option<BufWriter<File>>,
+    // whether the open part is new, and so written as <part>.tmp
+    new_part: bool,
+}
+
+impl RotatingWriter {
+    /// Start new parts, or with resume keep adding to the parts listed in an existing manifest
+    pub fn new(output: &str, max_rows: Option<u64>, max_bytes: Option<u64>, resume: bool) -> Result<RotatingWriter, Box<dyn Error>> {
+        let mut parts = Vec::new();
+        let manifest = format!("{}.manifest", output);
+        if resume && fs::metadata(&manifest).is_ok() {
+            for line in fs::read_to_string(&manifest)?.lines() {
+                let fields: Vec<&str> = line.split('\t').collect();
+                if let [file, rows, bytes] = fields[..] {
+                    parts.push((file.to_string(), rows.parse()?, bytes.parse()?));
+                } else {
+                    return Err(format!("malformed line in {}: {}", manifest, line).into());
+                }
+            }
+            // lines an interrupted run added after the manifest was last written
+            for (file, _, bytes) in &parts {
+                let part = OpenOptions::new().write(true).open(file).map_err(|e| format!("cannot resume {}: {}", file, e))?;
+                if part.metadata()?.len() > *bytes {
+                    part.set_len(*bytes)?;
+                }
+            }
+        }
+        Ok(RotatingWriter { output: output.to_string(), max_rows, max_bytes, parts, writer: None, new_part: false })
+    }
+
+    // finish the open part under its final name
+    fn close_part(&mut self) -> io::Result<()> {
+        if let (Some(mut writer), Some((file, _, _))) = (self.writer.take(), self.parts.last()) {
+            writer.flush()?;
+            if self.new_part {
+                fs::rename(format!("{}.tmp", file), file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_full(&self, line_bytes: u64) -> bool {
+        match self.parts.last() {
+            Some(&(_, rows, bytes)) => {
+                rows > 0 && (self.max_rows.is_some_and(|max| rows >= max) || self.max_bytes.is_some_and(|max| bytes + line_bytes > max))
+            }
+            None => true,
+        }
+    }
+
+    /// Write one line, including its newline
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.is_full(line.len() as u64) {
+            self.close_part()?;
+            let file = format!("{}.part-{:04}", self.output, self.parts.len() + 1);
+            self.writer = Some(BufWriter::new(File::create(format!("{}.tmp", file))?));
+            self.new_part = true;
+            self.parts.push((file, 0, 0));
+        } else if self.writer.is_none() {
+            // reopen the last part, flushed earlier or by a previous run, where it is listed
+            let file = &self.parts.last().unwrap().0;
+            self.writer = Some(BufWriter::new(OpenOptions::new().append(true).open(file)?));
+            self.new_part = false;
+        }
+        self.writer.as_mut().unwrap().write_all(line)?;
+        let (_, rows, bytes) = self.parts.last_mut().unwrap();
+        *rows += 1;
+        *bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Write every line of reader
+    pub fn copy_lines(&mut self, mut reader: impl BufRead) -> io::Result<()> {
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            self.write_line(&line)?;
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Finish the current part and rewrite the manifest
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.close_part()?;
+        let manifest = format!("{}.manifest", self.output);
+        let mut writer = BufWriter::new(File::create(format!("{}.tmp", manifest))?);
+        for (file, rows, bytes) in &self.parts {
+            writeln!(writer, "{}\t{}\t{}", file, rows, bytes)?;
+        }
+        writer.flush()?;
+        fs::rename(format!("{}.tmp", manifest), manifest)
+    }
+}
+
+/// Column the rows of a run are partitioned by
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionBy {
+    /// One file per CID, or per bucket of hashed CIDs
+    Cid,
+}
+
+impl std::str::FromStr for PartitionBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PartitionBy, String> {
+        match s {
+            "cid" => Ok(PartitionBy::Cid),
+            _ => Err(format!("unknown partition column: {}", s)),
+        }
+    }
+}
+
+/// Writes result lines to `<dir>/cid-<cid>.csv`, or with buckets to `<dir>/bucket-<n>.csv` by the
+/// hash of the CID, and lines without a CID to `<dir>/no-cid.csv`. At most max_open files are open
+/// at once; the least recently written is closed to open another, and reopened for appending.
+pub struct PartitionWriter {
+    dir: std::path::PathBuf,
+    buckets: Option<u64>,
+    max_open: usize,
+    // open files with the write count when they were last written
+    open: HashMap<String, (BufWriter<File>, u64)>,
+    // files written by this writer, appended to when reopened
+    created: HashSet<String>,
+    append: bool,
+    writes: u64,
+}
+
+impl PartitionWriter {
+    /// Partition into dir, which is created; with resume, lines are appended to existing files
+    pub fn new(dir: &Path, buckets: Option<u64>, max_open: usize, resume: bool) -> io::Result<PartitionWriter> {
+        fs::create_dir_all(dir)?;
+        Ok(PartitionWriter {
+            dir: dir.to_path_buf(),
+            buckets: buckets.filter(|buckets| *buckets > 0),
+            max_open: max_open.max(1),
+            open: HashMap::new(),
+            created: HashSet::new(),
+            append: resume,
+            writes: 0,
+        })
+    }
+
+    /// Name of the file holding rows of cid
+    pub fn file_name(&self, cid: Option<&Id>) -> String {
+        match (cid, self.buckets) {
+            (None, _) => "no-cid.csv".to_string(),
+            (Some(cid), None) => format!("cid-{}.csv", cid),
+            (Some(cid), Some(buckets)) => format!("bucket-{:04}.csv", hash_strings([cid.to_string()]) % buckets),
+        }
+    }
+
+    /// Write one result line, including its newline
+    pub fn write_line(&mut self, line: &[u8]) -> Result<(), Box<dyn Error>> {
+        let text = String::from_utf8_lossy(line);
+        let row = parse_result_line(text.trim_end_matches(['\n', '\r'])).map_err(|e| format!("cannot partition {:?}: {}", text, e))?;
+        let name = self.file_name(row.cid.as_ref());
+        self.writes += 1;
+        if !self.open.contains_key(&name) {
+            if self.open.len() == self.max_open {
+                let oldest = self.open.iter().min_by_key(|(_, (_, written))| *written).map(|(name, _)| name.clone()).unwrap();
+                self.open.remove(&oldest).unwrap().0.flush()?;
+            }
+            let path = self.dir.join(&name);
+            let file = if self.append || self.created.contains(&name) {
+                OpenOptions::new().create(true).append(true).open(path)?
+            } else {
+                File::create(path)?
+            };
+            self.created.insert(name.clone());
+            self.open.insert(name.clone(), (BufWriter::new(file), 0));
+        }
+        let (writer, written) = self.open.get_mut(&name).unwrap();
+        writer.write_all(line)?;
+        *written = self.writes;
+        Ok(())
+    }
+
+    /// Write every line of reader
+    pub fn copy_lines(&mut self, mut reader: impl BufRead) -> Result<(), Box<dyn Error>> {
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            self.write_line(&line)?;
+            line.clear();
+        }
+        Ok(())
+    }
+
+    /// Flush the open files
+    pub fn flush(&mut self) -> io::Result<()> {
+        for (writer, _) in self.open.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{IdType, MatchType};
+    use std::io::BufReader;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_result_stats() {
+        let tmp_dir = TempDir::new("result_stats").unwrap();
+        let path = tmp_dir.path().join("out.csv");
+        let mut writer = BufWriter::new(File::create(&path).unwrap());
+        let results = |key: &str, cid: Option<u32>, context: &str| {
+            let (match_type, id_type) = (MatchType::Exact, IdType::Name);
+            vec![Match { key: key.to_string(), cid: cid.map(Id::Cid), context: context.to_string(), match_type, id_type, score: 1.0, start: 0, end: 0 }]
+        };
+        generate_report(results("Aspirin", Some(2244), "<|MOLECULE|> \"daily\"\nthen"), &mut writer, "7");
+        generate_report(results("2,4-Dinitrophenol", Some(1493), "a <|MOLECULE|>"), &mut writer, "7");
+        generate_report(results("Aspirin", Some(2244), "more <|MOLECULE|>"), &mut writer, "8");
+        generate_report(results("50-00-0", None, "x"), &mut writer, "");
+        writer.flush().unwrap();
+
+        let row = parse_result_line(fs::read_to_string(&path).unwrap().lines().next().unwrap()).unwrap();
+        assert_eq!((row.key.as_str(), row.cid, row.paper_id.as_str()), ("Aspirin", Some(Id::Cid(2244)), "7"));
+        assert_eq!(row.context, "<|MOLECULE|> \"daily\"\nthen");
+        let other = parse_result_line("\"Water\",CHEBI:15377,\"in <|MOLECULE|>\",7,exact,name,1.000").unwrap();
+        assert_eq!(other.cid, Some(Id::Other("CHEBI:15377".into())));
+        assert_eq!(other.to_line(), "\"Water\",CHEBI:15377,\"in <|MOLECULE|>\",7,exact,name,1.000\n");
+
+        let mut stats = ResultStats::new(1);
+        stats.add_file(path.to_str().unwrap()).unwrap();
+        assert_eq!((stats.matches, stats.without_cid, stats.per_paper.len()), (4, 1, 3));
+        assert_eq!(stats.per_synonym["2,4-Dinitrophenol"], 1);
+        let text = stats.to_string();
+        assert!(text.contains("cid\t2244\t2\n"));
+        assert!(!text.contains("cid\t1493"));
+        assert!(text.contains("context length 0-49\t4\n"));
+
+        let files = [path.to_str().unwrap().to_string()];
+        let aspirin = Id::Cid(2244);
+        let examples = sample_contexts(&files, &[aspirin.clone()].into_iter().collect(), 1).unwrap();
+        assert_eq!(examples[&aspirin].len(), 1);
+        assert_eq!(examples, sample_contexts(&files, &[aspirin.clone()].into_iter().collect(), 1).unwrap());
+        let all = sample_contexts(&files, &[aspirin.clone()].into_iter().collect(), 5).unwrap();
+        assert_eq!(all[&aspirin].len(), 2);
+        assert!(all[&aspirin].contains(&examples[&aspirin][0]));
+        let report = stats.molecule_report(&all);
+        assert!(report.starts_with("1. CID 2244, 2 matches: Aspirin (2)\n    "));
+        assert_eq!(report.lines().count(), 3);
+        assert!(report.contains("    more <|MOLECULE|>\n"));
+
+        fs::write(&path, "Aspirin,2244\n").unwrap();
+        assert!(ResultStats::new(1).add_file(path.to_str().unwrap()).unwrap_err().to_string().contains("out.csv:1"));
+    }
+
+    #[test]
+    fn test_sample_results() {
+        let tmp_dir = TempDir::new("sample_results").unwrap();
+        let path = tmp_dir.path().join("out.csv");
+        let line = |cid: u32, paper: usize| format!("\"x\",{},\"c\",{},exact,name,1.000", cid, paper);
+        let lines: Vec<String> = (0..1000).map(|paper| line(if paper % 10 == 0 { 962 } else { 2244 }, paper)).collect();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let files = [path.to_str().unwrap().to_string()];
+
+        let sample = sample_results(&files, 20, false, 1).unwrap();
+        assert_eq!(sample.len(), 20);
+        assert!(sample.iter().all(|line| lines.contains(line)));
+        // in file order, and the same for the same seed
+        let positions: Vec<usize> = sample.iter().map(|line| lines.iter().position(|l| l == line).unwrap()).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(sample, sample_results(&files, 20, false, 1).unwrap());
+        assert_ne!(sample, sample_results(&files, 20, false, 2).unwrap());
+        // a uniform sample reaches past the start of the file
+        assert!(positions.iter().any(|position| *position > 500));
+
+        let stratified = sample_results(&files, 5, true, 1).unwrap();
+        assert_eq!(stratified.len(), 10);
+        assert!(stratified[..5].iter().all(|line| line.contains(",962,")));
+        assert_eq!(sample_results(&files, 2000, false, 1).unwrap(), lines);
+    }
+
+    #[test]
+    fn test_paper_report() {
+        let tmp_dir = TempDir::new("paper_report").unwrap();
+        let path = tmp_dir.path().join("out.csv");
+        let mut writer = BufWriter::new(File::create(&path).unwrap());
+        let found = |cid: Option<u32>| Match {
+            key: "x".to_string(),
+            cid: cid.map(Id::Cid),
+            context: String::new(),
+            match_type: MatchType::Exact,
+            id_type: IdType::Name,
+            score: 1.0,
+            start: 0,
+            end: 0,
+        };
+        let aggregate: Aggregate = "paper".parse().unwrap();
+        aggregate.write(vec![found(Some(962)), found(Some(2244)), found(None), found(Some(2244))], &mut writer, "7");
+        aggregate.write(vec![found(None)], &mut writer, "8");
+        writer.flush().unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "7,2244:2;962:1\n");
+        let row = parse_paper_line(written.trim_end()).unwrap();
+        assert_eq!(row, PaperRow { paper_id: "7".to_string(), cids: vec![(Id::Cid(2244), 2), (Id::Cid(962), 1)] });
+        assert!(parse_paper_line("7,2244").is_err());
+    }
+
+    #[test]
+    fn test_ner_json() {
+        let found = Match {
+            key: "Aspirin".to_string(),
+            cid: Some(Id::Cid(2244)),
+            context: String::new(),
+            match_type: MatchType::Exact,
+            id_type: IdType::Name,
+            score: 1.0,
+            start: 5,
+            end: 12,
+        };
+        let unknown = Match { cid: None, start: 16, end: 23, ..found.clone() };
+        assert_eq!(
+            ner_json("7", "Épi aspirin or 50-00-0", &[found, unknown]),
+            serde_json::json!({"id": "7", "text": "Épi aspirin or 50-00-0", "entities": [[4, 11, "CHEMICAL", 2244], [15, 22, "CHEMICAL", null]]})
+        );
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let mut stats = InputStats { records_read: 3, ..Default::default() };
+        stats.skip("invalid-json");
+        let mut summary = RunSummary::new(serde_json::json!({"stop": 0}));
+        summary.add_input("a.json.gz", stats.clone());
+        stats.cids.insert(Id::Cid(2244));
+        stats.matches = 2;
+        summary.add_input("b.json.gz", stats);
+        assert_eq!((summary.records_read, summary.matches, summary.unique_cids), (6, 2, 1));
+        assert_eq!(summary.records_skipped["invalid-json"], 2);
+
+        let tmp_dir = TempDir::new("run_summary").unwrap();
+        let path = tmp_dir.path().join("out.csv.summary.json");
+        summary.write(path.to_str().unwrap()).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["inputs_processed"], serde_json::json!(["a.json.gz", "b.json.gz"]));
+        assert_eq!(written["config"]["stop"], 0);
+        assert!(written.get("cids").is_none());
+    }
+
+    #[test]
+    fn test_merge_results() {
+        let tmp_dir = TempDir::new("merge_results").unwrap();
+        let (a, b) = (tmp_dir.path().join("a.csv"), tmp_dir.path().join("b.csv"));
+        let line = |cid: &str, context: &str, paper: &str| format!("\"Aspirin\",{},\"{}\",{},exact,name,1.000\n", cid, context, paper);
+        fs::write(&a, line("2244", "take <|MOLECULE|>", "1") + &line("2244", "more <|MOLECULE|>", "1")).unwrap();
+        // the same match written by a rerun, plus one of another paper and one without a CID
+        fs::write(&b, line("2244", "take <|MOLECULE|>", "1") + &line("2244", "take <|MOLECULE|>", "2") + &line("", "x", "2")).unwrap();
+        let summary_a = serde_json::json!({"records_read": 5, "records_skipped": {"empty": 1}});
+        fs::write(tmp_dir.path().join("a.csv.summary.json"), summary_a.to_string()).unwrap();
+
+        let output = tmp_dir.path().join("merged.csv");
+        let files = [a, b].map(|path| path.to_str().unwrap().to_string());
+        let mut summary = RunSummary::new(serde_json::json!({}));
+        merge_results(&files, output.to_str().unwrap(), &mut summary).unwrap();
+        let merged = fs::read_to_string(&output).unwrap();
+        assert_eq!(merged.lines().count(), 4);
+        assert_eq!(merged.matches("take <|MOLECULE|>\",1,").count(), 1);
+        assert_eq!((summary.records_read, summary.matches, summary.unique_cids), (5, 4, 1));
+        assert_eq!(summary.records_skipped["duplicate-row"], 1);
+        assert_eq!(summary.records_skipped["empty"], 1);
+        assert_eq!(summary.inputs_processed, files.to_vec());
+    }
+
+    #[test]
+    fn test_rotating_writer() {
+        let tmp_dir = TempDir::new("rotating_writer").unwrap();
+        let output = tmp_dir.path().join("out.csv");
+        let output = output.to_str().unwrap();
+        let mut writer = RotatingWriter::new(output, Some(2), None, false).unwrap();
+        writer.copy_lines(BufReader::new("a\nb\nc\n".as_bytes())).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}.part-0001", output)).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_to_string(format!("{}.part-0002", output)).unwrap(), "c\n");
+        assert!(fs::metadata(format!("{}.part-0002.tmp", output)).is_err());
+
+        // resuming fills the last part before starting another
+        let mut writer = RotatingWriter::new(output, Some(2), None, true).unwrap();
+        writer.copy_lines(BufReader::new("d\ne\n".as_bytes())).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}.part-0002", output)).unwrap(), "c\nd\n");
+        let manifest = fs::read_to_string(format!("{}.manifest", output)).unwrap();
+        let rows: Vec<&str> = manifest.lines().map(|line| line.split('\t').nth(1).unwrap()).collect();
+        assert_eq!(rows, vec!["2", "2", "1"]);
+
+        // a line longer than max_bytes still gets written, alone in its part
+        let mut writer = RotatingWriter::new(output, None, Some(5), false).unwrap();
+        writer.copy_lines(BufReader::new("ab\nc\nlonger\nd\n".as_bytes())).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}.part-0001", output)).unwrap(), "ab\nc\n");
+        assert_eq!(fs::read_to_string(format!("{}.part-0002", output)).unwrap(), "longer\n");
+        assert_eq!(fs::read_to_string(format!("{}.part-0003", output)).unwrap(), "d\n");
+
+        // a run killed partway through an input resumes from the manifest
+        let mut writer = RotatingWriter::new(output, None, Some(1 << 20), false).unwrap();
+        writer.copy_lines(BufReader::new("first\n".as_bytes())).unwrap();
+        writer.flush().unwrap();
+        let second = "second\n".repeat(5000);
+        writer.copy_lines(BufReader::new(second.as_bytes())).unwrap();
+        // killed: part of the second input's lines reached the part, but not the manifest
+        std::mem::forget(writer);
+        assert!(fs::metadata(format!("{}.part-0001", output)).unwrap().len() > 6);
+        let mut writer = RotatingWriter::new(output, None, Some(1 << 20), true).unwrap();
+        writer.copy_lines(BufReader::new(second.as_bytes())).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(format!("{}.part-0001", output)).unwrap(), format!("first\n{}", second));
+        assert_eq!(fs::read_to_string(format!("{}.manifest", output)).unwrap(), format!("{}.part-0001\t5001\t35006\n", output));
+    }
+
+    #[test]
+    fn test_partition_writer() {
+        let tmp_dir = TempDir::new("partition_writer").unwrap();
+        let line = |cid: &str, paper: usize| format!("\"x\",{},\"c\",{},exact,name,1.000\n", cid, paper);
+        let mut lines = String::new();
+        for paper in 0..20 {
+            lines += &line(&(paper % 5).to_string(), paper);
+        }
+        lines += &line("", 20);
+        // two open files for five CIDs, so files are closed and reopened along the way
+        let dir = tmp_dir.path().join("by-cid");
+        let mut writer = PartitionWriter::new(&dir, None, 2, false).unwrap();
+        writer.copy_lines(BufReader::new(lines.as_bytes())).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cid-3.csv")).unwrap(), line("3", 3) + &line("3", 8) + &line("3", 13) + &line("3", 18));
+        assert_eq!(fs::read_to_string(dir.join("no-cid.csv")).unwrap(), line("", 20));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 6);
+
+        // resuming appends, a new run starts the files over
+        let mut writer = PartitionWriter::new(&dir, None, 2, true).unwrap();
+        writer.write_line(line("3", 21).as_bytes()).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cid-3.csv")).unwrap().lines().count(), 5);
+        let mut writer = PartitionWriter::new(&dir, None, 2, false).unwrap();
+        writer.write_line(line("3", 22).as_bytes()).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cid-3.csv")).unwrap(), line("3", 22));
+
+        let dir = tmp_dir.path().join("buckets");
+        let mut writer = PartitionWriter::new(&dir, Some(2), 1, false).unwrap();
+        writer.copy_lines(BufReader::new(lines.as_bytes())).unwrap();
+        writer.flush().unwrap();
+        let rows: usize = fs::read_dir(&dir).unwrap().map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap().lines().count()).sum();
+        assert_eq!(rows, 21);
+        assert!(fs::read_dir(&dir).unwrap().count() <= 3);
+        assert!(writer.write_line(b"not a row\n").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! HTTP server answering match requests with a dictionary loaded once, and exposing metrics.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use crate::matcher::Matcher;
+use crate::metrics::{Metrics, WorkerMetrics};
+use crate::report::match_json;
+
+struct ServerState {
+    matcher: Arc<Matcher>,
+    property: String,
+    worker: Arc<WorkerMetrics>,
+}
+
+/// Response to a POST /match body. The body is raw text, or with a JSON content type a document
+/// whose text is in "text" or, as in the corpus files, "content".property; its corpusid is echoed.
+/// The search is counted in worker.
+pub fn match_body(matcher: &Matcher, property: &str, content_type: Option<&str>, body: &str, worker: &WorkerMetrics) -> Result<Value, String> {
+    let mut response = serde_json::Map::new();
+    let text = if content_type.is_some_and(|content_type| content_type.starts_with("application/json")) {
+        let document: Value = serde_json::from_str(body).map_err(|e| format!("invalid JSON: {}", e))?;
+        if let Some(corpus_id) = document.get("corpusid") {
+            response.insert("corpusid".to_string(), corpus_id.clone());
+        }
+        let text = document["text"]
+            .as_str()
+            .or_else(|| document["content"][property].as_str())
+            .ok_or_else(|| format!("document has no \"text\" or \"content\".\"{}\" string", property))?;
+        text.to_string()
+    } else {
+        body.to_string()
+    };
+    let (matches, paragraphs) = matcher.search_counted(&text);
+    worker.record(paragraphs, matches.len());
+    response.insert("matches".to_string(), matches.iter().map(match_json).collect());
+    Ok(Value::Object(response))
+}
+
+async fn post_match(State(state): State<Arc<ServerState>>, headers: HeaderMap, body: String) -> (StatusCode, Json<Value>) {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(str::to_string);
+    // matching is CPU bound, keep it off the request threads
+    let response = tokio::task::spawn_blocking(move || match_body(&state.matcher, &state.property, content_type.as_deref(), &body, &state.worker)).await;
+    match response {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)),
+        Ok(Err(e)) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))),
+    }
+}
+
+fn metrics_routes(metrics: Arc<Metrics>) -> Router {
+    Router::new().route("/metrics", get(|| async move { ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics.prometheus()) }))
+}
+
+/// Answer POST /match on address until the process stops, counting searches as the "http"
+/// worker of metrics, which GET /metrics reports. JSON documents take their text from
+/// "content".property when they have no "text" field.
+pub async fn serve(matcher: Arc<Matcher>, property: String, address: SocketAddr, metrics: Arc<Metrics>) -> Result<(), Box<dyn Error>> {
+    let state = Arc::new(ServerState { matcher, property, worker: metrics.worker("http") });
+    let app = Router::new().route("/match", post(post_match)).with_state(state).merge(metrics_routes(metrics));
+    axum::Server::bind(&address).serve(app.into_make_service()).await?;
+    Ok(())
+}
+
+/// Answer GET /metrics on address, in the Prometheus text format, until the process stops
+pub async fn serve_metrics(metrics: Arc<Metrics>, address: SocketAddr) -> Result<(), Box<dyn Error>> {
+    axum::Server::bind(&address).serve(metrics_routes(metrics).into_make_service()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::dictionary::Id;
+    use crate::matcher::SearchOptions;
+
+    #[test]
+    fn test_match_body() {
+        let map: HashMap<String, Id> = [("Aspirin".to_string(), Id::Cid(2244))].into_iter().collect();
+        let matcher = Matcher::new(map, SearchOptions::default());
+        let metrics = Metrics::new();
+        let worker = metrics.worker("http");
+
+        let response = match_body(&matcher, "text", Some("text/plain"), "Take aspirin daily", &worker).unwrap();
+        assert_eq!(response["matches"][0]["cid"], 2244);
+        assert_eq!(response["matches"][0]["start"], 5);
+        assert_eq!(response["matches"][0]["end"], 12);
+
+        let document = r#"{"corpusid": 7, "content": {"abstract": "Aspirin"}}"#;
+        let response = match_body(&matcher, "abstract", Some("application/json"), document, &worker).unwrap();
+        assert_eq!(response["corpusid"], 7);
+        assert_eq!(response["matches"][0]["key"], "Aspirin");
+        let response = match_body(&matcher, "abstract", Some("application/json; charset=utf-8"), r#"{"text": "no match"}"#, &worker).unwrap();
+        assert_eq!(response["matches"], json!([]));
+
+        assert!(match_body(&matcher, "abstract", Some("application/json"), "Aspirin", &worker).is_err());
+        assert!(match_body(&matcher, "text", Some("application/json"), document, &worker).is_err());
+        assert_eq!(worker.counts(), [3, 3, 2]);
+    }
+}
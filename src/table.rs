@@ -0,0 +1,71 @@
+//! Documents of tabular corpora, e.g. spreadsheet exports: one document per row of a CSV or TSV
+//! file with a header naming its text and id columns.
+
+use std::error::Error;
+use std::io::Read;
+
+/// Call f with the id and text of each row of a CSV (delimiter b',') or TSV (b'\t') table whose
+/// header names text_column and, when given, id_column; rows are numbered from 1 without one,
+/// and so are rows whose id is empty.
+/// Quoted fields may span lines. f returns false to stop.
+pub fn read_table<R: Read>(reader: R, delimiter: u8, text_column: &str, id_column: Option<&str>, mut f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new().delimiter(delimiter).flexible(true).from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers.iter().position(|header| header.trim() == name).ok_or_else(|| format!("no {} column among {}", name, headers.iter().collect::<Vec<&str>>().join(", ")))
+    };
+    let text_index = column(text_column)?;
+    let id_index = id_column.map(column).transpose()?;
+    let mut record = csv::StringRecord::new();
+    let mut row = 0;
+    while reader.read_record(&mut record)? {
+        row += 1;
+        let text = record.get(text_index).unwrap_or_default();
+        let keep_going = match id_index {
+            // rows with an empty id are numbered like rows without an id column
+            Some(id_index) => match record.get(id_index).unwrap_or_default().trim() {
+                "" => f(&row.to_string(), text),
+                id => f(id, text),
+            },
+            None => f(&row.to_string(), text),
+        };
+        if !keep_going {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_table() {
+        let csv = "doc_id,title,body\nd1,First,\"Aspirin, then\n\"\"water\"\"\"\nd2,Second\nd3,Third,Ethanol\n";
+        let mut documents = Vec::new();
+        read_table(csv.as_bytes(), b',', "body", Some("doc_id"), |id, text| {
+            documents.push((id.to_string(), text.to_string()));
+            documents.len() < 2
+        })
+        .unwrap();
+        assert_eq!(documents, vec![("d1".to_string(), "Aspirin, then\n\"water\"".to_string()), ("d2".to_string(), String::new())]);
+
+        let mut documents = Vec::new();
+        read_table("text\tid\nAspirin\t7\nWater\t8\n".as_bytes(), b'\t', "text", None, |id, text| {
+            documents.push(format!("{}:{}", id, text));
+            true
+        })
+        .unwrap();
+        assert_eq!(documents, vec!["1:Aspirin".to_string(), "2:Water".to_string()]);
+        documents.clear();
+        read_table("text\tid\nAspirin\t \nWater\t8\n".as_bytes(), b'\t', "text", Some("id"), |id, text| {
+            documents.push(format!("{}:{}", id, text));
+            true
+        })
+        .unwrap();
+        assert_eq!(documents, vec!["1:Aspirin".to_string(), "8:Water".to_string()]);
+        let missing = read_table(csv.as_bytes(), b',', "text", None, |_, _| true).unwrap_err();
+        assert_eq!(missing.to_string(), "no text column among doc_id, title, body");
+    }
+}
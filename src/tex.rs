@@ -0,0 +1,262 @@
+//! Prose of LaTeX sources (e.g. arXiv submissions): comments, math, citations and other
+//! commands are stripped, leaving the text of sections, paragraphs and captions.
+
+// Environments left out with everything inside them
+const DROPPED_ENVIRONMENTS: [&str; 17] = [
+    "equation", "equation*", "align", "align*", "eqnarray", "eqnarray*", "gather", "gather*", "multline", "multline*", "displaymath", "math", "tabular",
+    "verbatim", "lstlisting", "tikzpicture", "thebibliography",
+];
+
+// Commands left out with their arguments
+const DROPPED_COMMANDS: [&str; 29] = [
+    "cite", "citep", "citet", "citealp", "citeauthor", "citeyear", "ref", "eqref", "autoref", "cref", "Cref", "pageref", "label", "url", "href",
+    "includegraphics", "bibliography", "bibliographystyle", "usepackage", "documentclass", "input", "include", "newcommand", "renewcommand",
+    "vspace", "hspace", "setlength", "footnote", "thanks",
+];
+
+// Commands whose argument is a paragraph of its own
+const HEADING_COMMANDS: [&str; 8] = ["title", "chapter", "section", "subsection", "subsubsection", "paragraph", "subparagraph", "caption"];
+
+// Source without comments: from an unescaped % to the end of the line, dropping lines that are
+// only a comment so they do not break paragraphs
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    for line in source.lines() {
+        let mut escaped = false;
+        let end = line
+            .char_indices()
+            .find(|&(_, c)| {
+                let comment = c == '%' && !escaped;
+                escaped = c == '\\' && !escaped;
+                comment
+            })
+            .map(|(i, _)| i);
+        match end {
+            Some(0) => continue,
+            Some(end) if line[..end].trim().is_empty() => continue,
+            Some(end) => stripped.push_str(&line[..end]),
+            None => stripped.push_str(line),
+        }
+        stripped.push('\n');
+    }
+    stripped
+}
+
+struct Stripper<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    mhchem: bool,
+    text: &'a mut String,
+}
+
+impl Stripper<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c == ' ' || c == '\t') {
+            self.pos += 1;
+        }
+    }
+
+    // Contents of the balanced group opening at pos with open, e.g. '{' or '['
+    fn group(&mut self, open: char, close: char) -> Option<String> {
+        if self.peek() != Some(open) {
+            return None;
+        }
+        self.pos += 1;
+        let (start, mut depth) = (self.pos, 1);
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => self.pos += 1,
+                _ if c == open => depth += 1,
+                _ if c == close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(self.chars[start..self.pos - 1].iter().collect());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(self.chars[start..].iter().collect())
+    }
+
+    // Skip the optional [..] arguments and a star of a command
+    fn skip_options(&mut self) {
+        if self.peek() == Some('*') {
+            self.pos += 1;
+        }
+        self.skip_spaces();
+        while self.group('[', ']').is_some() {
+            self.skip_spaces();
+        }
+    }
+
+    // Skip past the first occurrence of end
+    fn skip_past(&mut self, end: &str) {
+        let end: Vec<char> = end.chars().collect();
+        while self.pos < self.chars.len() && !self.chars[self.pos..].starts_with(&end) {
+            // escaped characters, e.g. \$ in math, cannot end it
+            self.pos += if self.chars[self.pos] == '\\' && end[0] != '\\' { 2 } else { 1 };
+        }
+        self.pos = (self.pos + end.len()).min(self.chars.len());
+    }
+
+    fn push_paragraph(&mut self, source: &str) {
+        self.text.push_str("\n\n");
+        strip_into(source, self.mhchem, self.text);
+        self.text.push_str("\n\n");
+    }
+
+    fn command(&mut self) {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if name.is_empty() {
+            // a control symbol: \%, \&, \\, \(, \[, ...
+            let Some(symbol) = self.peek() else { return };
+            self.pos += 1;
+            match symbol {
+                '(' => self.skip_past("\\)"),
+                '[' => self.skip_past("\\]"),
+                '\\' => self.text.push('\n'),
+                '%' | '&' | '$' | '#' | '_' | '{' | '}' => self.text.push(symbol),
+                _ => self.text.push(' '),
+            }
+            return;
+        }
+        match name.as_str() {
+            "begin" => {
+                self.skip_spaces();
+                let environment = self.group('{', '}').unwrap_or_default();
+                if DROPPED_ENVIRONMENTS.contains(&environment.as_str()) {
+                    self.skip_past(&format!("\\end{{{}}}", environment));
+                } else {
+                    self.skip_options();
+                    self.text.push_str("\n\n");
+                }
+            }
+            "end" => {
+                self.skip_spaces();
+                self.group('{', '}');
+                self.text.push_str("\n\n");
+            }
+            "ce" if self.mhchem => {
+                self.skip_spaces();
+                let formula = self.group('{', '}').unwrap_or_default();
+                self.text.push_str(formula.trim());
+            }
+            "ce" => {
+                self.skip_spaces();
+                self.group('{', '}');
+            }
+            _ if DROPPED_COMMANDS.contains(&name.as_str()) => {
+                self.skip_options();
+                while self.group('{', '}').is_some() {
+                    self.skip_options();
+                }
+            }
+            _ if HEADING_COMMANDS.contains(&name.as_str()) => {
+                self.skip_options();
+                if let Some(heading) = self.group('{', '}') {
+                    self.push_paragraph(&heading);
+                }
+            }
+            // formatting commands such as \textbf and \emph leave their arguments as text
+            _ => {
+                self.skip_options();
+                // the dropped name still separates the words around it
+                if self.peek() != Some('{') && !self.text.ends_with(char::is_whitespace) {
+                    self.text.push(' ');
+                }
+            }
+        }
+    }
+
+    fn strip(&mut self) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => self.command(),
+                '$' if self.peek() == Some('$') => {
+                    self.pos += 1;
+                    self.skip_past("$$");
+                }
+                '$' => self.skip_past("$"),
+                '{' | '}' => {}
+                '~' => self.text.push(' '),
+                _ => self.text.push(c),
+            }
+        }
+    }
+}
+
+fn strip_into(source: &str, mhchem: bool, text: &mut String) {
+    Stripper { chars: source.chars().collect(), pos: 0, mhchem, text }.strip();
+}
+
+/// Prose of a LaTeX source: the body of its document environment (or all of it) without
+/// comments, math, dropped environments (equations, tables, verbatim, bibliography) and
+/// commands such as \cite and \label. Headings and captions become paragraphs, blank lines
+/// separate paragraphs and other line breaks become spaces. With mhchem the formulas of \ce{}
+/// are kept as text, otherwise they go with the math.
+pub fn strip_latex(source: &str, mhchem: bool) -> String {
+    let source = strip_comments(source);
+    let body = match (source.find("\\begin{document}"), source.rfind("\\end{document}")) {
+        (Some(start), Some(end)) if start < end => &source[start + "\\begin{document}".len()..end],
+        (Some(start), None) => &source[start + "\\begin{document}".len()..],
+        _ => &source,
+    };
+    let mut text = String::with_capacity(body.len());
+    strip_into(body, mhchem, &mut text);
+    let mut paragraphs = vec![Vec::new()];
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match paragraphs.last_mut() {
+            Some(paragraph) if words.is_empty() && !paragraph.is_empty() => paragraphs.push(Vec::new()),
+            Some(paragraph) => paragraph.extend(words),
+            None => {}
+        }
+    }
+    let paragraphs: Vec<String> = paragraphs.iter().filter(|words| !words.is_empty()).map(|words| words.join(" ")).collect();
+    paragraphs.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_latex() {
+        let source = r#"\documentclass{article}
+\usepackage[version=4]{mhchem}
+\begin{document}
+\title{Aspirin in \emph{water}}
+\maketitle
+% a comment about benzene
+\section{Methods}\label{sec:methods}
+Aspirin~\cite{smith2020} was dissolved in 50\% ethanol % and toluene
+with $x^2 + \alpha$ and \ce{H2O} as in Eq.~\eqref{eq:1}.
+\begin{equation}
+  E = mc^2 \label{eq:1}
+\end{equation}
+
+\begin{figure}[h]
+\includegraphics[width=\linewidth]{fig1.pdf}
+\caption{Spectra of \textbf{caffeine}.}
+\end{figure}
+\end{document}
+Ignored after the document."#;
+        assert_eq!(
+            strip_latex(source, false),
+            "Aspirin in water\n\nMethods\n\nAspirin was dissolved in 50% ethanol with and as in Eq. .\n\nSpectra of caffeine."
+        );
+        assert!(strip_latex(source, true).contains("with and H2O as in"));
+        assert_eq!(strip_latex("Plain \\LaTeX{} text.\n\nNext", false), "Plain text.\n\nNext");
+    }
+}
@@ -0,0 +1,566 @@
+//! Normalization, case handling and tokenization shared by dictionaries and matching.
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use unicode_normalization::char::canonical_combining_class;
+use unicode_normalization::{is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+/// Characters separating words
+pub const WORD_SPLITS: &[char] = &[' ', '\t', '\n', '\r', ',', '.', ';', ':', '!', '?', '(', ')', '[', ']', '{', '}', '<', '>', '"', '\''];
+
+/// Default minimum length, in bytes, of dictionary keys
+pub const MIN_WORD_LENGTH: usize = 5;
+
+// Prefixes whose hyphen is part of the name, so a line break after them keeps the hyphen
+const HYPHEN_PREFIXES: &[&str] = &["cis", "trans", "tert", "sec", "iso", "neo", "ortho", "meta", "para", "alpha", "beta", "gamma", "delta", "omega"];
+
+/// How dictionary keys and text are compared
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseMode {
+    // only the first letter is case-insensitive
+    Title,
+    Exact,
+    Fold,
+    // fold case except for acronyms and mixed-case tokens such as "THF" or "pH"
+    Smart,
+}
+
+impl std::str::FromStr for CaseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CaseMode, String> {
+        match s {
+            "title" => Ok(CaseMode::Title),
+            "exact" => Ok(CaseMode::Exact),
+            "fold" => Ok(CaseMode::Fold),
+            "smart" => Ok(CaseMode::Smart),
+            _ => Err(format!("unknown case mode: {}", s)),
+        }
+    }
+}
+
+/// English stemmer used to compare words against the banned list
+pub struct StemmerWrapper {
+    stemmer: Stemmer,
+}
+
+impl Default for StemmerWrapper {
+    fn default() -> StemmerWrapper {
+        StemmerWrapper::new()
+    }
+}
+
+impl StemmerWrapper{
+    /// English (Snowball) stemmer
+    pub fn new() -> StemmerWrapper {
+        StemmerWrapper {
+            stemmer: Stemmer::create(Algorithm::English),
+        }
+    }
+
+    /// Lowercased stem of a word
+    pub fn standardize(&self, word: &str) -> String {
+        self.stemmer.stem(word.trim().to_lowercase().as_str()).to_string()
+    }
+}
+
+/// Word with its first (ASCII) letter uppercased
+pub fn to_ascii_titlecase(s: &str) -> String {
+    let mut titlecased = s.to_owned();
+    if let Some(r) = titlecased.get_mut(0..1) {
+        r.make_ascii_uppercase();
+    }
+    titlecased
+}
+
+/// Acronyms ("THF", "DDT") and words with inner capitals ("pH", "NaCl", "mRNA") keep their case in smart mode
+pub fn is_case_sensitive(word: &str) -> bool {
+    let upper = word.chars().filter(|c| c.is_uppercase()).count();
+    let lower = word.chars().filter(|c| c.is_lowercase()).count();
+    let inner_upper = word.chars().skip(1).any(|c| c.is_uppercase());
+    (lower == 0 && upper >= 2 && word.chars().count() <= 5) || (lower > 0 && inner_upper)
+}
+
+/// Word as compared against dictionary keys under a case mode
+pub fn case_word(word: &str, mode: CaseMode) -> String {
+    match mode {
+        CaseMode::Title | CaseMode::Exact => word.to_string(),
+        CaseMode::Fold => word.to_lowercase(),
+        CaseMode::Smart if is_case_sensitive(word) => word.to_string(),
+        CaseMode::Smart => word.to_lowercase(),
+    }
+}
+
+/// The form a dictionary key or text n-gram takes for lookup under the given case mode
+pub fn case_key(key: &str, mode: CaseMode) -> String {
+    match mode {
+        CaseMode::Title => to_ascii_titlecase(key),
+        _ => key.split(' ').map(|word| case_word(word, mode)).collect::<Vec<String>>().join(" "),
+    }
+}
+
+/// Word with its first (ASCII) letter lowercased
+pub fn from_ascii_titlecase(s: &str) -> String {
+    let mut titlecased = s.to_owned();
+    if let Some(r) = titlecased.get_mut(0..1) {
+        r.make_ascii_lowercase();
+    }
+    titlecased
+}
+
+fn is_prime(c: char) -> bool {
+    matches!(c, '\'' | '′' | '″' | '‴' | 'ʹ')
+}
+
+// Letters of a typographic ligature, which PDF extraction leaves in words like "ﬂuorine"
+fn ligature_letters(c: char) -> Option<&'static str> {
+    let letters = match c {
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        _ => return None,
+    };
+    Some(letters)
+}
+
+fn greek_name(c: char) -> Option<&'static str> {
+    let name = match c {
+        'α' => "alpha", 'β' | 'ϐ' => "beta", 'γ' => "gamma", 'δ' => "delta", 'ε' | 'ϵ' => "epsilon",
+        'ζ' => "zeta", 'η' => "eta", 'θ' | 'ϑ' => "theta", 'ι' => "iota", 'κ' | 'ϰ' => "kappa",
+        'λ' => "lambda", 'μ' | 'µ' => "mu", 'ν' => "nu", 'ξ' => "xi", 'ο' => "omicron", 'π' | 'ϖ' => "pi",
+        'ρ' | 'ϱ' => "rho", 'σ' | 'ς' => "sigma", 'τ' => "tau", 'υ' => "upsilon", 'φ' | 'ϕ' => "phi",
+        'χ' => "chi", 'ψ' => "psi", 'ω' => "omega",
+        'Α' => "Alpha", 'Β' => "Beta", 'Γ' => "Gamma", 'Δ' => "Delta", 'Ε' => "Epsilon", 'Ζ' => "Zeta",
+        'Η' => "Eta", 'Θ' => "Theta", 'Ι' => "Iota", 'Κ' => "Kappa", 'Λ' => "Lambda", 'Μ' => "Mu",
+        'Ν' => "Nu", 'Ξ' => "Xi", 'Ο' => "Omicron", 'Π' => "Pi", 'Ρ' => "Rho", 'Σ' => "Sigma",
+        'Τ' => "Tau", 'Υ' => "Upsilon", 'Φ' => "Phi", 'Χ' => "Chi", 'Ψ' => "Psi", 'Ω' => "Omega",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Byte offsets of a rewritten text (dehyphenated, NFKC normalized) back into its source
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OffsetMap {
+    // (start, end) in the rewritten text and (start, end) in the source of each rewritten span,
+    // in order; text between spans is unchanged
+    spans: Vec<(usize, usize, usize, usize)>,
+}
+
+impl OffsetMap {
+    // record that source[from..from_end] was rewritten as rewritten[to..to_end]
+    fn push(&mut self, to: usize, to_end: usize, from: usize, from_end: usize) {
+        self.spans.push((to, to_end, from, from_end));
+    }
+
+    /// Source offset of an offset in the rewritten text. Offsets inside a rewritten span map to
+    /// its start, or to its end when round_up is set, so ranges always cover whole spans; a
+    /// range ending where a span starts, e.g. of dropped characters, leaves it out.
+    pub fn source(&self, offset: usize, round_up: bool) -> usize {
+        let i = self.spans.partition_point(|&(to, ..)| if round_up { to < offset } else { to <= offset });
+        if i == 0 {
+            return offset;
+        }
+        let (to, to_end, from, from_end) = self.spans[i - 1];
+        if offset >= to_end {
+            from_end + (offset - to_end)
+        } else if round_up && offset > to {
+            from_end
+        } else {
+            from
+        }
+    }
+
+    /// Source range of a range in the rewritten text
+    pub fn source_range(&self, start: usize, end: usize) -> (usize, usize) {
+        (self.source(start, false), self.source(end, true))
+    }
+}
+
+/// Unicode NFKC folds ligatures, full-width and other compatibility forms found in PDF-derived text
+pub fn to_nfkc(text: &str) -> Cow<'_, str> {
+    to_nfkc_mapped(text).0
+}
+
+/// to_nfkc, with the offsets of the normalized text in text. Characters are normalized in
+/// runs that start at a character which cannot combine with the one before it.
+pub fn to_nfkc_mapped(text: &str) -> (Cow<'_, str>, OffsetMap) {
+    let mut offsets = OffsetMap::default();
+    if is_nfkc_quick(text.chars()) == IsNormalized::Yes {
+        return (Cow::Borrowed(text), offsets);
+    }
+    let mut normalized = String::with_capacity(text.len());
+    let mut run_start = 0;
+    let boundaries = text
+        .char_indices()
+        .skip(1)
+        .filter(|&(_, c)| canonical_combining_class(c) == 0 && is_nfkc_quick(std::iter::once(c)) != IsNormalized::Maybe)
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()));
+    for run_end in boundaries {
+        let run = &text[run_start..run_end];
+        let start = normalized.len();
+        normalized.extend(run.nfkc());
+        if normalized[start..] != *run {
+            offsets.push(start, normalized.len(), run_start, run_end);
+        }
+        run_start = run_end;
+    }
+    (Cow::Owned(normalized), offsets)
+}
+
+/// Rejoin words broken across lines by PDF extraction. The hyphen is kept when it is likely part
+/// of a chemical name, e.g. after a locant ("2-\nchloro") or a stereo prefix ("tert-\nbutyl").
+pub fn dehyphenate(text: &str) -> Cow<'_, str> {
+    dehyphenate_mapped(text).0
+}
+
+/// dehyphenate, with the offsets of the rejoined text in text
+pub fn dehyphenate_mapped(text: &str) -> (Cow<'_, str>, OffsetMap) {
+    static LINE_BREAK: OnceLock<regex::Regex> = OnceLock::new();
+    let re = LINE_BREAK.get_or_init(|| regex::Regex::new(r"([\p{L}\p{N}]+)-[ \t]*\r?\n[ \t]*([\p{L}\p{N}])").unwrap());
+    let mut offsets = OffsetMap::default();
+    let mut joined = String::new();
+    let mut last = 0;
+    for caps in re.captures_iter(text) {
+        let (left, right) = (caps.get(1).unwrap(), caps.get(2).unwrap());
+        let keep = left.as_str().ends_with(|c: char| c.is_numeric())
+            || right.as_str().starts_with(|c: char| c.is_numeric() || c.is_uppercase())
+            || left.as_str().chars().count() == 1
+            || HYPHEN_PREFIXES.contains(&left.as_str().to_lowercase().as_str());
+        joined.push_str(&text[last..left.end()]);
+        let start = joined.len();
+        if keep {
+            joined.push('-');
+        }
+        offsets.push(start, joined.len(), left.end(), right.start());
+        last = right.start();
+    }
+    if last == 0 {
+        return (Cow::Borrowed(text), offsets);
+    }
+    joined.push_str(&text[last..]);
+    (Cow::Owned(joined), offsets)
+}
+
+/// Spell out Greek letters and ligatures and unify primes and middle dots, so "β-carotene" and
+/// "beta-carotene" (or "2′-" and "2'-", "ﬂuorine" and "fluorine") look the same to the
+/// dictionary, with or without NFKC normalization
+pub fn normalize(word: &str) -> String {
+    if word.is_ascii() {
+        return word.to_string();
+    }
+    let mut normalized = String::with_capacity(word.len() + 8);
+    for c in word.chars() {
+        match c {
+            '′' | 'ʹ' => normalized.push('\''),
+            '″' => normalized.push_str("''"),
+            '‴' => normalized.push_str("'''"),
+            '⋅' | '•' | '∙' | '・' => normalized.push('·'),
+            _ => match greek_name(c).or_else(|| ligature_letters(c)) {
+                Some(name) => normalized.push_str(name),
+                None => normalized.push(c),
+            },
+        }
+    }
+    normalized
+}
+
+// Character of a named HTML entity common in scientific text
+fn named_entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "minus" => '−',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "prime" => '′',
+        "Prime" => '″',
+        "middot" => '·',
+        "hellip" => '…',
+        "deg" => '°',
+        "plusmn" => '±',
+        "times" => '×',
+        "micro" => 'µ',
+        "rarr" => '→',
+        "harr" => '↔',
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ε',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "pi" => 'π',
+        "sigma" => 'σ',
+        "omega" => 'ω',
+        "Delta" => 'Δ',
+        _ => return None,
+    })
+}
+
+// Character of the HTML character reference text starts with, and the reference's length
+fn entity_at(text: &str) -> Option<(char, usize)> {
+    let end = text.get(1..)?.find(';').filter(|&end| end <= 32)? + 1;
+    let reference = &text[1..end];
+    let c = match reference.strip_prefix('#') {
+        Some(number) => char::from_u32(match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        })?,
+        None => named_entity(reference)?,
+    };
+    Some((c, end + 1))
+}
+
+// What a control, zero-width or separator character is cleaned into, None to keep it
+fn cleaned_control(c: char) -> Option<&'static str> {
+    match c {
+        // form feeds separate pages, and paragraphs with --paragraph-delimiter '\f'
+        '\n' | '\r' | '\t' | '\u{c}' => None,
+        '\u{b}' | '\u{85}' | '\u{2028}' => Some("\n"),
+        '\u{2029}' => Some("\n\n"),
+        // soft hyphen, zero-width spaces and joiners, word joiner and byte order mark
+        '\u{ad}' | '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}' => Some(""),
+        _ if c.is_control() => Some(""),
+        _ => None,
+    }
+}
+
+/// Decode HTML character references: numeric ones (`&#8217;`, `&#x2019;`) and the named ones
+/// common in scientific text (`&amp;`, `&beta;`, ...). Unknown or malformed references are kept.
+pub fn decode_entities(text: &str) -> Cow<'_, str> {
+    clean_text_mapped(text, true, false).0
+}
+
+/// Text cleaned up before matching, with the offsets of the cleaned text in text: with entities
+/// HTML character references decoded (see decode_entities), with controls zero-width characters,
+/// soft hyphens and control characters other than newlines, tabs and form feeds dropped, and
+/// vertical tabs and Unicode line and paragraph separators turned into line breaks
+pub fn clean_text_mapped(text: &str, entities: bool, controls: bool) -> (Cow<'_, str>, OffsetMap) {
+    let mut offsets = OffsetMap::default();
+    let mut cleaned = String::new();
+    // end of the text copied to cleaned
+    let mut copied = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rewrite = match c {
+            '&' if entities => entity_at(&text[i..]).map(|(c, len)| (Cow::Owned(c.to_string()), len)),
+            _ if controls => cleaned_control(c).map(|replacement| (Cow::Borrowed(replacement), c.len_utf8())),
+            _ => None,
+        };
+        let Some((replacement, len)) = rewrite else {
+            i += c.len_utf8();
+            continue;
+        };
+        cleaned.push_str(&text[copied..i]);
+        let start = cleaned.len();
+        cleaned.push_str(&replacement);
+        offsets.push(start, cleaned.len(), i, i + len);
+        i += len;
+        copied = i;
+    }
+    if copied == 0 {
+        return (Cow::Borrowed(text), offsets);
+    }
+    cleaned.push_str(&text[copied..]);
+    (Cow::Owned(cleaned), offsets)
+}
+
+fn is_delimiter(c: char, splits: &[char]) -> bool {
+    c.is_whitespace() || splits.contains(&c)
+}
+
+pub(crate) fn closing_bracket(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+// Index of the bracket closing the group opened at `open`, if the group is balanced and
+// contains no whitespace or sentence punctuation (e.g. the "(±)" in "(±)-ibuprofen")
+fn bracket_group_end(chars: &[(usize, char)], open: usize, splits: &[char]) -> Option<usize> {
+    let mut stack = vec![closing_bracket(chars[open].1)?];
+    for (i, &(_, c)) in chars.iter().enumerate().skip(open + 1) {
+        if let Some(close) = closing_bracket(c) {
+            stack.push(close);
+        } else if stack.last() == Some(&c) {
+            stack.pop();
+            if stack.is_empty() {
+                return if i > open + 1 { Some(i) } else { None };
+            }
+        } else if c != ',' && is_delimiter(c, splits) {
+            return None;
+        }
+    }
+    None
+}
+
+// Locants look like "1", "4a", "3'" or "N" and are joined by commas in names such as "1,2-dichloroethane"
+fn is_locant(segment: &[(usize, char)]) -> bool {
+    let primes = segment.iter().rev().take_while(|(_, c)| is_prime(*c)).count();
+    let segment = &segment[..segment.len() - primes];
+    let digits = segment.iter().take_while(|(_, c)| c.is_ascii_digit()).count();
+    match segment.len() - digits {
+        0 => digits > 0,
+        1 if digits > 0 => segment[digits].1.is_ascii_lowercase(),
+        1 => segment[0].1.is_ascii_uppercase(),
+        _ => false,
+    }
+}
+
+/// Split text into (byte offset, token) pairs, keeping locant commas, hyphens and attached
+/// bracket groups inside tokens so IUPAC-style names survive tokenization
+pub fn tokenize(text: &str) -> Vec<(usize, &str)> {
+    tokenize_with(text, WORD_SPLITS)
+}
+
+/// tokenize, splitting words on whitespace and the given characters instead of WORD_SPLITS
+pub fn tokenize_with<'a>(text: &'a str, splits: &[char]) -> Vec<(usize, &'a str)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut segment: usize = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        if let Some(end) = closing_bracket(c).and_then(|_| bracket_group_end(&chars, i, splits)) {
+            let attached_after = chars.get(end + 1).is_some_and(|&(_, next)| !is_delimiter(next, splits));
+            if start.is_some() || attached_after {
+                start.get_or_insert(i);
+                i = end + 1;
+                segment = i;
+                continue;
+            }
+        } else if c == ',' && start.is_some() && is_delimiter(c, splits) {
+            let next_is_locant = chars.get(i + 1).is_some_and(|&(_, next)| next.is_ascii_digit() || next.is_ascii_uppercase());
+            if next_is_locant && is_locant(&chars[segment..i]) {
+                i += 1;
+                segment = i;
+                continue;
+            }
+        } else if c == '\'' && i > 0 && start.is_some() && chars[i - 1].1.is_ascii_digit() {
+            // a prime on a locant, as in "2'-deoxyadenosine"
+            i += 1;
+            continue;
+        } else if !is_delimiter(c, splits) {
+            if start.is_none() {
+                start = Some(i);
+                segment = i;
+            } else if c == '-' {
+                segment = i + 1;
+            }
+            i += 1;
+            continue;
+        }
+        if let Some(s) = start.take() {
+            tokens.push((chars[s].0, &text[chars[s].0..chars[i].0]));
+        }
+        i += 1;
+    }
+    if let Some(s) = start {
+        tokens.push((chars[s].0, &text[chars[s].0..]));
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_iupac_names() {
+        let words = |text| tokenize(text).into_iter().map(|(_, word)| word).collect::<Vec<&str>>();
+
+        assert_eq!(words("I like apples, pears (and plums)."), vec!["I", "like", "apples", "pears", "and", "plums"]);
+        assert_eq!(words("we used 1,2-dichloroethane, then 2,4-dinitrophenol."), vec!["we", "used", "1,2-dichloroethane", "then", "2,4-dinitrophenol"]);
+        assert_eq!(words("(±)-ibuprofen and (E)-stilbene"), vec!["(±)-ibuprofen", "and", "(E)-stilbene"]);
+        assert_eq!(words("bis(2-ethylhexyl) phthalate"), vec!["bis(2-ethylhexyl)", "phthalate"]);
+        assert_eq!(words("N,N-dimethylformamide and 1,1,1-trichloroethane"), vec!["N,N-dimethylformamide", "and", "1,1,1-trichloroethane"]);
+        assert_eq!(words("4a,8a-dihydronaphthalene"), vec!["4a,8a-dihydronaphthalene"]);
+        assert_eq!(words("tris[2-(dimethylamino)ethyl]amine"), vec!["tris[2-(dimethylamino)ethyl]amine"]);
+        assert_eq!(words("(2,4-dichlorophenoxy)acetic acid"), vec!["(2,4-dichlorophenoxy)acetic", "acid"]);
+        assert_eq!(words("unbalanced (2-ethyl word"), vec!["unbalanced", "2-ethyl", "word"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("α-pinene"), "alpha-pinene");
+        assert_eq!(normalize("Β-carotene"), "Beta-carotene");
+        assert_eq!(normalize("2′-deoxyadenosine"), "2'-deoxyadenosine");
+        assert_eq!(normalize("CuSO4•5H2O"), "CuSO4·5H2O");
+        assert_eq!(normalize("acetaminophen"), "acetaminophen");
+        assert_eq!(normalize("con\u{fb01}guration of \u{fb02}uorine"), "configuration of fluorine");
+        let words = |text| tokenize(text).into_iter().map(|(_, word)| word).collect::<Vec<&str>>();
+        assert_eq!(words("the 3',5'-cyclic 'quoted' form"), vec!["the", "3',5'-cyclic", "quoted", "form"]);
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("no references"), "no references");
+        assert_eq!(decode_entities("Na&#43;&amp;K&#x207A; &beta;-carotene&#8217;s &foo; &amp x&"), "Na+&K⁺ β-carotene’s &foo; &amp x&");
+        assert_eq!(decode_entities("&#xD800; &#99999999;"), "&#xD800; &#99999999;");
+    }
+
+    #[test]
+    fn test_clean_text() {
+        let source = "ben\u{200b}zene&amp;\u{7}tol\u{ad}uene\u{b}x\ty\u{c}";
+        let (cleaned, offsets) = clean_text_mapped(source, true, true);
+        assert_eq!(cleaned, "benzene&toluene\nx\ty\u{c}");
+        assert_eq!(offsets.source_range(0, 7), (0, 10));
+        assert_eq!(&source[offsets.source(8, false)..offsets.source(15, true)], "tol\u{ad}uene");
+        assert_eq!(clean_text_mapped(source, false, true).0, "benzene&amp;toluene\nx\ty\u{c}");
+        assert!(matches!(clean_text_mapped("plain & text", true, true).0, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_dehyphenate() {
+        assert_eq!(dehyphenate("took acetami-\nnophen daily"), "took acetaminophen daily");
+        assert_eq!(dehyphenate("a 2-\nchloroethanol and tert-\nbutanol"), "a 2-chloroethanol and tert-butanol");
+        assert_eq!(dehyphenate("an N-\nmethyl and p-\r\n  cresol"), "an N-methyl and p-cresol");
+        assert_eq!(dehyphenate("well-known - \n list"), "well-known - \n list");
+    }
+
+    #[test]
+    fn test_offset_maps() {
+        let source = "took acetami-\nnophen and 2-\nchloroethanol";
+        let (joined, offsets) = dehyphenate_mapped(source);
+        let start = joined.find("acetaminophen").unwrap();
+        let (from, to) = offsets.source_range(start, start + "acetaminophen".len());
+        assert_eq!(&source[from..to], "acetami-\nnophen");
+        let start = joined.find("2-chloroethanol").unwrap();
+        let (from, to) = offsets.source_range(start, start + "2-chloroethanol".len());
+        assert_eq!(&source[from..to], "2-\nchloroethanol");
+
+        let source = "ﬁve ｂｅｎｚｅｎｅ and e\u{301}ther";
+        let (normalized, offsets) = to_nfkc_mapped(source);
+        assert_eq!(normalized, "five benzene and \u{e9}ther");
+        let (from, to) = offsets.source_range(0, 4);
+        assert_eq!(&source[from..to], "ﬁve");
+        let (from, to) = offsets.source_range(5, 12);
+        assert_eq!(&source[from..to], "ｂｅｎｚｅｎｅ");
+        let (from, to) = offsets.source_range(17, normalized.len());
+        assert_eq!(&source[from..to], "e\u{301}ther");
+        // part of a ligature maps to the whole ligature
+        assert_eq!(offsets.source_range(1, 2), (0, 3));
+        assert_eq!(to_nfkc_mapped("plain"), (Cow::Borrowed("plain"), OffsetMap::default()));
+    }
+}
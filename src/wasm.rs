@@ -0,0 +1,36 @@
+//! wasm-bindgen bindings for matching in the browser with a precompiled dictionary.
+
+use wasm_bindgen::prelude::*;
+
+use crate::dictionary::decode_compiled_dict;
+use crate::matcher::{Matcher, MatcherBuilder};
+use crate::report::match_json;
+
+/// A matcher over a dictionary written by compile-dict
+#[wasm_bindgen]
+pub struct WasmMatcher {
+    matcher: Matcher,
+}
+
+#[wasm_bindgen]
+impl WasmMatcher {
+    /// Load the bytes of a compiled dictionary, searched with the --case-mode it was compiled
+    /// with. case_mode is omitted, or that mode's name, which is checked.
+    #[wasm_bindgen(constructor)]
+    pub fn new(dict: &[u8], case_mode: Option<String>) -> Result<WasmMatcher, JsError> {
+        let (header, (map, conflicts)) = decode_compiled_dict(dict).map_err(|e| JsError::new(&e.to_string()))?;
+        let case_mode = header.case_mode(case_mode.as_deref()).map_err(|e| JsError::new(&e))?;
+        let matcher = MatcherBuilder::new()
+            .case_mode(case_mode)
+            .conflicts(&conflicts)
+            .build(map)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(WasmMatcher { matcher })
+    }
+
+    /// Matches in text, as a JSON array of objects
+    pub fn search(&self, text: &str) -> String {
+        let matches: Vec<serde_json::Value> = self.matcher.search(text).iter().map(match_json).collect();
+        serde_json::Value::Array(matches).to_string()
+    }
+}
@@ -0,0 +1,493 @@
+//! Documents of XML corpora, streamed out of the files with their ids: PubMed/MEDLINE baseline
+//! citations, JATS full texts of PMC, TEI full texts GROBID extracts out of PDFs and USPTO
+//! patents.
+
+use std::error::Error;
+use std::io::BufRead;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+// Text of a text or CDATA event
+fn event_text(event: &Event) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(match event {
+        Event::Text(text) => Some(text.unescape()?.into_owned()),
+        Event::CData(data) => Some(String::from_utf8_lossy(data).into_owned()),
+        _ => None,
+    })
+}
+
+/// Call f with the PMID and text of each PubmedArticle of a PubMed baseline or update file: the
+/// article title, then each AbstractText as a paragraph of its own, separated by blank lines.
+/// Articles without a title or abstract are passed with an empty text; f returns false to stop.
+pub fn read_pubmed<R: BufRead>(reader: R, mut f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // names of the open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let (mut pmid, mut title, mut abstracts) = (String::new(), String::new(), Vec::<String>::new());
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(element) => {
+                let name = element.name().as_ref().to_vec();
+                match name.as_slice() {
+                    b"PubmedArticle" => (pmid, title, abstracts) = (String::new(), String::new(), Vec::new()),
+                    b"AbstractText" => abstracts.push(String::new()),
+                    _ => {}
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                if path.pop().is_some_and(|name| name == b"PubmedArticle") {
+                    let paragraphs: Vec<&str> = std::iter::once(&title).chain(&abstracts).map(|text| text.trim()).filter(|text| !text.is_empty()).collect();
+                    if !f(&pmid, &paragraphs.join("\n\n")) {
+                        break;
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {
+                if let Some(text) = event_text(&event)? {
+                    let inside = |element: &[u8]| path.iter().any(|name| name == element);
+                    // the citation's own PMID, not those of the articles it comments on
+                    if path.len() >= 2 && path[path.len() - 1] == b"PMID" && path[path.len() - 2] == b"MedlineCitation" {
+                        pmid = text.trim().to_string();
+                    } else if inside(b"ArticleTitle") {
+                        title.push_str(&text);
+                    } else if let (true, Some(paragraph)) = (inside(b"AbstractText"), abstracts.last_mut()) {
+                        paragraph.push_str(&text);
+                    }
+                }
+            }
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+/// Name of the first element of an XML document, e.g. to tell its format, None when it has none
+pub fn root_element<R: BufRead>(reader: R) -> Result<Option<String>, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(element) | Event::Empty(element) => return Ok(Some(String::from_utf8_lossy(element.local_name().as_ref()).into_owned())),
+            Event::Eof => return Ok(None),
+            _ => buf.clear(),
+        }
+    }
+}
+
+// Text of the paragraph elements of a document, those nested in others (e.g. in lists) continuing
+// them
+#[derive(Default)]
+struct Paragraphs {
+    paragraphs: Vec<String>,
+    // paragraph elements open, the outermost of which text goes to
+    open: usize,
+}
+
+impl Paragraphs {
+    fn start(&mut self) {
+        match self.paragraphs.last_mut() {
+            Some(paragraph) if self.open > 0 => paragraph.push(' '),
+            _ => self.paragraphs.push(String::new()),
+        }
+        self.open += 1;
+    }
+
+    fn end(&mut self) {
+        self.open -= 1;
+    }
+
+    fn push_str(&mut self, text: &str) {
+        if let (true, Some(paragraph)) = (self.open > 0, self.paragraphs.last_mut()) {
+            paragraph.push_str(text);
+        }
+    }
+
+    // The paragraphs with markup whitespace collapsed, separated by blank lines
+    fn text(&self) -> String {
+        let paragraphs: Vec<String> =
+            self.paragraphs.iter().map(|paragraph| paragraph.split_whitespace().collect::<Vec<&str>>().join(" ")).filter(|paragraph| !paragraph.is_empty()).collect();
+        paragraphs.join("\n\n")
+    }
+}
+
+// Whether an element opening in path (its ancestors) starts a paragraph of a JATS article: the
+// article's title, or a title or paragraph of its abstract, body or back matter but the references
+fn jats_paragraph(path: &[Vec<u8>], name: &[u8]) -> bool {
+    let inside = |element: &[u8]| path.iter().any(|name| name == element);
+    match name {
+        b"article-title" => path.last().is_some_and(|parent| parent == b"title-group"),
+        b"title" | b"p" => (inside(b"abstract") || inside(b"body") || inside(b"back")) && !inside(b"ref-list"),
+        _ => false,
+    }
+}
+
+/// Call f with the PMCID and text of each article of a JATS file, e.g. of the PMC open access
+/// subset: its title, then the titles and paragraphs of its abstract, sections and back matter,
+/// leaving out the references. Paragraphs nested in others (e.g. in lists) continue them, and
+/// markup is dropped with whitespace collapsed. Citations of the references are left out with the
+/// superscript holding them, so "aspirin<sup><xref>2</xref></sup>" reads "aspirin". The PMCID is
+/// empty when the article has none; f returns false to stop.
+pub fn read_jats<R: BufRead>(reader: R, mut f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // names of the open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let (mut pmcid, mut paragraphs) = (String::new(), Paragraphs::default());
+    let mut in_pmcid = false;
+    // depth of the open citation of a reference, whose text is left out
+    let mut citation = None;
+    // depth of the open superscript with its text and whether it holds a citation, kept back
+    // until it ends as it is left out when it holds nothing but citations and punctuation
+    let mut sup: Option<(usize, String, bool)> = None;
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(element) => {
+                let name = element.name().as_ref().to_vec();
+                if name == b"article" {
+                    (pmcid, paragraphs) = (String::new(), Paragraphs::default());
+                } else if name == b"article-id" && path.last().is_some_and(|parent| parent == b"article-meta") {
+                    let id_type = element.try_get_attribute("pub-id-type")?.map(|id_type| id_type.unescape_value().map(|value| value.into_owned())).transpose()?;
+                    in_pmcid = matches!(id_type.as_deref(), Some("pmc" | "pmcid"));
+                } else if name == b"xref" && citation.is_none() {
+                    let ref_type = element.try_get_attribute("ref-type")?.map(|ref_type| ref_type.unescape_value().map(|value| value.into_owned())).transpose()?;
+                    if ref_type.as_deref() == Some("bibr") {
+                        citation = Some(path.len());
+                        match &mut sup {
+                            Some((_, _, held)) => *held = true,
+                            None => paragraphs.push_str(" "),
+                        }
+                    }
+                } else if name == b"sup" && sup.is_none() {
+                    sup = Some((path.len(), String::new(), false));
+                } else if jats_paragraph(&path, &name) {
+                    paragraphs.start();
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                if let Some(name) = path.pop() {
+                    if name == b"article-id" {
+                        in_pmcid = false;
+                    } else if citation == Some(path.len()) {
+                        citation = None;
+                    } else if sup.as_ref().is_some_and(|(depth, _, _)| *depth == path.len()) {
+                        let (_, text, held) = sup.take().unwrap();
+                        if held && !text.chars().any(char::is_alphanumeric) {
+                            paragraphs.push_str(" ");
+                        } else {
+                            paragraphs.push_str(&text);
+                        }
+                    } else if jats_paragraph(&path, &name) {
+                        paragraphs.end();
+                    } else if name == b"article" && !f(&pmcid, &paragraphs.text()) {
+                        break;
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {
+                if let Some(text) = event_text(&event)? {
+                    if in_pmcid {
+                        // given with or without its prefix
+                        let id = text.trim();
+                        pmcid = if id.starts_with("PMC") { id.to_string() } else { format!("PMC{}", id) };
+                    } else if citation.is_none() {
+                        match &mut sup {
+                            Some((_, sup_text, _)) => sup_text.push_str(&text),
+                            None => paragraphs.push_str(&text),
+                        }
+                    }
+                }
+            }
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+// Whether an element opening in path starts a paragraph of a TEI document: a section head or
+// paragraph of its abstract or body, but not of a figure or table
+fn tei_paragraph(path: &[Vec<u8>], name: &[u8]) -> bool {
+    let inside = |element: &[u8]| path.iter().any(|name| name == element);
+    matches!(name, b"head" | b"p") && (inside(b"abstract") || inside(b"body")) && !inside(b"figure")
+}
+
+/// The xml:id and text of a TEI document, such as GROBID writes for a PDF: the heads and
+/// paragraphs of its abstract and body sections, without those of figures and tables or the
+/// references of the back matter. Markup is dropped with whitespace collapsed; the id is empty
+/// when the TEI element has none, as GROBID's usually do.
+pub fn read_tei<R: BufRead>(reader: R) -> Result<(String, String), Box<dyn Error>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // local names of the open elements, as TEI comes with and without a prefix
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let (mut id, mut paragraphs) = (String::new(), Paragraphs::default());
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(element) => {
+                let name = element.local_name().as_ref().to_vec();
+                if path.is_empty() {
+                    if name != b"TEI" {
+                        return Err(format!("not a TEI document: {}", String::from_utf8_lossy(&name)).into());
+                    }
+                    if let Some(xml_id) = element.try_get_attribute("xml:id")? {
+                        id = xml_id.unescape_value()?.trim().to_string();
+                    }
+                } else if tei_paragraph(&path, &name) {
+                    paragraphs.start();
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                if let Some(name) = path.pop() {
+                    if tei_paragraph(&path, &name) {
+                        paragraphs.end();
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {
+                if let Some(text) = event_text(&event)? {
+                    paragraphs.push_str(&text);
+                }
+            }
+        }
+        buf.clear();
+    }
+    Ok((id, paragraphs.text()))
+}
+
+// Whether an element opening in path starts a paragraph of a patent: a heading or paragraph of
+// its description or a claim, whose nested claim-texts continue it
+fn uspto_paragraph(path: &[Vec<u8>], name: &[u8]) -> bool {
+    let inside = |element: &[u8]| path.iter().any(|name| name == element);
+    match name {
+        b"heading" | b"p" => inside(b"description"),
+        b"claim" => inside(b"claims"),
+        _ => false,
+    }
+}
+
+/// Call f with the publication number (e.g. US11234567B2) and text of each patent grant or
+/// application of a USPTO full-text file, which concatenates one XML document per patent: the
+/// headings and paragraphs of its description, then its claims, one paragraph each. Math is
+/// left out, markup dropped and whitespace collapsed; f returns false to stop.
+pub fn read_uspto<R: BufRead>(reader: R, mut f: impl FnMut(&str, &str) -> bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    // names of the open elements
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    // country, doc-number and kind of the publication
+    let (mut number, mut paragraphs) = (<[String; 3]>::default(), Paragraphs::default());
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        match &event {
+            Event::Start(element) => {
+                let name = element.name().as_ref().to_vec();
+                if path.is_empty() {
+                    (number, paragraphs) = (Default::default(), Paragraphs::default());
+                } else if uspto_paragraph(&path, &name) {
+                    paragraphs.start();
+                }
+                path.push(name);
+            }
+            Event::End(_) => {
+                if let Some(name) = path.pop() {
+                    if uspto_paragraph(&path, &name) {
+                        paragraphs.end();
+                    } else if path.is_empty() && (name == b"us-patent-grant" || name == b"us-patent-application") && !f(&number.concat(), &paragraphs.text()) {
+                        break;
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {
+                if let Some(text) = event_text(&event)? {
+                    match &path[..] {
+                        [.., reference, id, field] if reference == b"publication-reference" && id == b"document-id" => {
+                            if let Some(i) = [&b"country"[..], b"doc-number", b"kind"].iter().position(|name| name == field) {
+                                number[i] = text.trim().to_string();
+                            }
+                        }
+                        _ if !path.iter().any(|name| name == b"maths") => paragraphs.push_str(&text),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        buf.clear();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_pubmed() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE PubmedArticleSet PUBLIC "-//NLM//DTD PubMedArticle, 1st January 2024//EN" "https://dtd.nlm.nih.gov/ncbi/pubmed/out/pubmed_240101.dtd">
+<PubmedArticleSet>
+<PubmedArticle>
+ <MedlineCitation Status="MEDLINE" Owner="NLM">
+  <PMID Version="1">31</PMID>
+  <Article PubModel="Print">
+   <ArticleTitle>Effects of <i>aspirin</i> &amp; water.</ArticleTitle>
+   <Abstract>
+    <AbstractText Label="BACKGROUND">Aspirin was given.</AbstractText>
+    <AbstractText Label="RESULTS">Water <sup>2</sup> helped.</AbstractText>
+   </Abstract>
+  </Article>
+  <CommentsCorrectionsList><CommentsCorrections RefType="CommentOn"><PMID Version="1">7</PMID></CommentsCorrections></CommentsCorrectionsList>
+ </MedlineCitation>
+ <PubmedData><ArticleIdList><ArticleId IdType="pubmed">31</ArticleId></ArticleIdList></PubmedData>
+</PubmedArticle>
+<PubmedArticle>
+ <MedlineCitation><PMID Version="1">32</PMID><Article><ArticleTitle>No abstract.</ArticleTitle></Article></MedlineCitation>
+</PubmedArticle>
+<PubmedArticle>
+ <MedlineCitation><PMID Version="1">33</PMID><Article><ArticleTitle>Not read.</ArticleTitle></Article></MedlineCitation>
+</PubmedArticle>
+</PubmedArticleSet>"#;
+        let mut articles = Vec::new();
+        read_pubmed(xml.as_bytes(), |pmid, text| {
+            articles.push((pmid.to_string(), text.to_string()));
+            articles.len() < 2
+        })
+        .unwrap();
+        assert_eq!(
+            articles,
+            vec![
+                ("31".to_string(), "Effects of aspirin & water.\n\nAspirin was given.\n\nWater 2 helped.".to_string()),
+                ("32".to_string(), "No abstract.".to_string()),
+            ]
+        );
+        assert!(read_pubmed("<PubmedArticleSet><PubmedArticle></Other>".as_bytes(), |_, _| true).is_err());
+    }
+
+    #[test]
+    fn test_read_jats() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE article PUBLIC "-//NLM//DTD JATS (Z39.96) Journal Archiving and Interchange DTD v1.3 20210610//EN" "JATS-archivearticle1-3.dtd">
+<article xmlns:xlink="http://www.w3.org/1999/xlink" article-type="research-article">
+ <front>
+  <journal-meta><journal-title-group><journal-title>J Chem</journal-title></journal-title-group></journal-meta>
+  <article-meta>
+   <article-id pub-id-type="pmid">31</article-id>
+   <article-id pub-id-type="pmc">1234567</article-id>
+   <title-group><article-title>Aspirin in
+     <italic>water</italic></article-title></title-group>
+   <author-notes><fn><p>Corresponding author.</p></fn></author-notes>
+   <abstract><p>We dissolved aspirin.</p></abstract>
+  </article-meta>
+ </front>
+ <body>
+  <sec><title>Methods</title>
+   <p>Aspirin<xref ref-type="bibr" rid="B1">1</xref> was mixed with H<sub>2</sub>O<sup><xref ref-type="bibr" rid="B1">1</xref>,<xref ref-type="bibr" rid="B2">2</xref></sup>
+    and caffeine<sup>2+</sup> as in Table<xref ref-type="table" rid="T1">1</xref>:</p>
+   <p>Steps <list><list-item><p>stir</p></list-item></list></p>
+  </sec>
+ </body>
+ <back>
+  <ack><p>We thank ethanol.</p></ack>
+  <ref-list><title>References</title><ref id="B1"><element-citation><article-title>Benzene</article-title></element-citation></ref></ref-list>
+ </back>
+</article>"#;
+        let mut articles = Vec::new();
+        read_jats(xml.as_bytes(), |pmcid, text| {
+            articles.push((pmcid.to_string(), text.to_string()));
+            true
+        })
+        .unwrap();
+        assert_eq!(
+            articles,
+            vec![(
+                "PMC1234567".to_string(),
+                "Aspirin in water\n\nWe dissolved aspirin.\n\nMethods\n\nAspirin was mixed with H2O and caffeine2+ as in Table1:\n\nSteps stir\n\nWe thank ethanol.".to_string()
+            )]
+        );
+        let mut ids = Vec::new();
+        read_jats("<pmc-articleset><article><front><article-meta><article-id pub-id-type=\"pmcid\">PMC7</article-id></article-meta></front></article><article/></pmc-articleset>".as_bytes(), |pmcid, _| {
+            ids.push(pmcid.to_string());
+            true
+        })
+        .unwrap();
+        assert_eq!(ids, vec!["PMC7".to_string()]);
+    }
+
+    #[test]
+    fn test_read_tei() {
+        let xml = r##"<?xml version="1.0" encoding="UTF-8"?>
+<TEI xml:space="preserve" xmlns="http://www.tei-c.org/ns/1.0" xmlns:xlink="http://www.w3.org/1999/xlink">
+ <teiHeader xml:lang="en">
+  <fileDesc><titleStmt><title level="a" type="main">Aspirin</title></titleStmt></fileDesc>
+  <profileDesc><abstract><div xmlns="http://www.tei-c.org/ns/1.0"><p>We dissolved aspirin.</p></div></abstract></profileDesc>
+ </teiHeader>
+ <text xml:lang="en">
+  <body>
+   <div xmlns="http://www.tei-c.org/ns/1.0"><head n="1">Methods</head>
+    <p>Aspirin <ref type="bibr" target="#b0">[1]</ref> was mixed with
+     water.</p>
+   </div>
+   <figure xmlns="http://www.tei-c.org/ns/1.0"><head>Fig. 1</head><figDesc>Benzene</figDesc></figure>
+  </body>
+  <back><div type="references"><listBibl><biblStruct xml:id="b0"><analytic><title>Ethanol</title></analytic></biblStruct></listBibl></div></back>
+ </text>
+</TEI>"##;
+        assert_eq!(read_tei(xml.as_bytes()).unwrap(), (String::new(), "We dissolved aspirin.\n\nMethods\n\nAspirin [1] was mixed with water.".to_string()));
+        let (id, _) = read_tei(r#"<tei:TEI xmlns:tei="http://www.tei-c.org/ns/1.0" xml:id="paper-7"></tei:TEI>"#.as_bytes()).unwrap();
+        assert_eq!(id, "paper-7");
+        assert!(read_tei("<article><p>x</p></article>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_read_uspto() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE us-patent-grant SYSTEM "us-patent-grant-v47-2022-02-17.dtd" [ ]>
+<us-patent-grant lang="EN" dtd-version="v4.7 2022-02-17" file="US11234567-20220201.XML">
+<us-bibliographic-data-grant>
+<publication-reference><document-id><country>US</country><doc-number>11234567</doc-number><kind>B2</kind><date>20220201</date></document-id></publication-reference>
+<application-reference appl-type="utility"><document-id><country>US</country><doc-number>16999999</doc-number></document-id></application-reference>
+<invention-title id="d2e43">Aspirin tablets</invention-title>
+</us-bibliographic-data-grant>
+<abstract id="abstract"><p id="p-0001" num="0000">Not read.</p></abstract>
+<description id="description">
+<heading id="h-0001" level="1">BACKGROUND</heading>
+<p id="p-0002" num="0001">Aspirin is dissolved in <i>water</i> <maths id="MATH-US-00001" num="00001"><math><mi>x</mi></math></maths>.</p>
+</description>
+<us-claim-statement>What is claimed is:</us-claim-statement>
+<claims id="claims">
+<claim id="CLM-00001" num="00001"><claim-text>1. A tablet comprising:
+<claim-text>aspirin; and</claim-text>
+<claim-text>starch.</claim-text></claim-text></claim>
+</claims>
+</us-patent-grant>
+<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE us-patent-application SYSTEM "us-patent-application-v46-2022-02-17.dtd" [ ]>
+<us-patent-application><us-bibliographic-data-application><publication-reference><document-id><country>US</country><doc-number>20220000001</doc-number><kind>A1</kind></document-id></publication-reference></us-bibliographic-data-application>
+<description><p>Ethanol.</p></description></us-patent-application>
+"#;
+        let mut patents = Vec::new();
+        read_uspto(xml.as_bytes(), |number, text| {
+            patents.push((number.to_string(), text.to_string()));
+            true
+        })
+        .unwrap();
+        assert_eq!(
+            patents,
+            vec![
+                ("US11234567B2".to_string(), "BACKGROUND\n\nAspirin is dissolved in water .\n\n1. A tablet comprising: aspirin; and starch.".to_string()),
+                ("US20220000001A1".to_string(), "Ethanol.".to_string()),
+            ]
+        );
+        assert_eq!(root_element(xml.as_bytes()).unwrap().as_deref(), Some("us-patent-grant"));
+        assert_eq!(root_element("<?xml version=\"1.0\"?>".as_bytes()).unwrap(), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Identifiers of compounds in other databases (ChEBI, MeSH, DrugBank) by PubChem CID, for
+//! knowledge graphs not keyed by CIDs.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use crate::dictionary::Id;
+
+/// Databases mapped to, in the order of their columns
+pub const XREF_DATABASES: [&str; 3] = ["chebi", "mesh", "drugbank"];
+
+/// Identifiers in each of XREF_DATABASES by CID
+#[derive(Debug, Default, PartialEq)]
+pub struct Xrefs {
+    ids: HashMap<u32, [BTreeSet<String>; 3]>,
+}
+
+impl Xrefs {
+    /// Read a tab-separated mapping whose header names a `cid` column and any of the chebi, mesh
+    /// and drugbank columns, e.g. `cid<TAB>chebi<TAB>drugbank`. A cell may list several ids
+    /// separated by `|`, and a CID may have several lines.
+    pub fn read(path: &str) -> Result<Xrefs, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or(format!("{}: empty mapping", path))?;
+        let names: Vec<String> = header.split('\t').map(|name| name.trim().to_lowercase()).collect();
+        let cid_column = names.iter().position(|name| name == "cid").ok_or(format!("{}: no cid column", path))?;
+        let columns: Vec<(usize, usize)> =
+            names.iter().enumerate().filter_map(|(i, name)| Some((i, XREF_DATABASES.iter().position(|database| database == name)?))).collect();
+        if columns.is_empty() {
+            return Err(format!("{}: none of the {} columns", path, XREF_DATABASES.join(", ")).into());
+        }
+        let mut xrefs = Xrefs::default();
+        for (i, line) in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let cid = fields.get(cid_column).and_then(|cid| cid.trim().parse::<u32>().ok()).ok_or(format!("{}:{}: bad cid", path, i + 1))?;
+            let ids = xrefs.ids.entry(cid).or_default();
+            for &(column, database) in &columns {
+                let cell = fields.get(column).copied().unwrap_or_default();
+                ids[database].extend(cell.split('|').map(str::trim).filter(|id| !id.is_empty()).map(str::to_string));
+            }
+        }
+        Ok(xrefs)
+    }
+
+    /// Identifiers of cid in each of XREF_DATABASES, several joined by `|`, empty when unmapped
+    pub fn columns(&self, cid: Option<u32>) -> [String; 3] {
+        match cid.and_then(|cid| self.ids.get(&cid)) {
+            Some(ids) => ids.clone().map(|ids| ids.into_iter().collect::<Vec<String>>().join("|")),
+            None => Default::default(),
+        }
+    }
+
+    /// CID of each identifier, e.g. to read dictionaries keyed by DrugBank ids
+    pub fn by_id(&self) -> HashMap<String, Id> {
+        self.ids.iter().flat_map(|(cid, ids)| ids.iter().flatten().map(move |id| (id.clone(), Id::Cid(*cid)))).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_xrefs() {
+        let tmp_dir = TempDir::new("xrefs").unwrap();
+        let path = tmp_dir.path().join("xrefs.tsv");
+        fs::write(&path, "CID\tname\tChEBI\tDrugBank\n2244\taspirin\tCHEBI:15365\tDB00945\n\n2244\tacetylsalicylic acid\tCHEBI:15365|CHEBI:9999\t\n962\twater\tCHEBI:15377\n").unwrap();
+        let xrefs = Xrefs::read(path.to_str().unwrap()).unwrap();
+        assert_eq!(xrefs.len(), 2);
+        assert_eq!(xrefs.columns(Some(2244)), ["CHEBI:15365|CHEBI:9999".to_string(), String::new(), "DB00945".to_string()]);
+        assert_eq!(xrefs.columns(Some(962))[0], "CHEBI:15377");
+        assert_eq!(xrefs.columns(Some(1)), <[String; 3]>::default());
+        assert_eq!(xrefs.columns(None), <[String; 3]>::default());
+        assert_eq!((&xrefs.by_id()["CHEBI:9999"], &xrefs.by_id()["DB00945"], xrefs.by_id().len()), (&Id::Cid(2244), &Id::Cid(2244), 4));
+
+        fs::write(&path, "cid\tname\n1\tx\n").unwrap();
+        assert!(Xrefs::read(path.to_str().unwrap()).is_err());
+        fs::write(&path, "cid\tmesh\nx\tD1\n").unwrap();
+        assert!(Xrefs::read(path.to_str().unwrap()).unwrap_err().to_string().ends_with(":2: bad cid"));
+    }
+}